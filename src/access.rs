@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::warn;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{Header, Method, Status};
+use rocket::{Data, Request, Response};
+
+use crate::data;
+
+/// Length of the window that the rate limit counts requests in.
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Number of tracked clients after which clients with expired windows are dropped.
+const RATE_LIMIT_PRUNE_THRESHOLD: usize = 1024;
+
+/// Path that rate limited requests are sent to instead, so that no route handles them.
+const RATE_LIMITED_PATH: &str = "/__anifunnel/rate-limited";
+
+/// Headers that browsers may send on cross-origin API requests.
+const CORS_ALLOWED_HEADERS: &str = "Authorization, Content-Type, If-Match";
+
+/// Whether a request goes to the API or the webhook endpoint, relative to the base
+/// path that the routes are mounted at.
+fn is_api_path(method: Method, path: &str, base_path: &str) -> bool {
+    let path = match path.strip_prefix(base_path) {
+        Some(path) => path,
+        None => return false,
+    };
+    return path.starts_with("/api/")
+        || (method == Method::Post && (path.is_empty() || path == "/"));
+}
+
+/// Whether a request counts towards the rate limit. CORS preflights are not counted,
+/// since browsers send them on top of the actual requests.
+fn is_rate_limited(method: Method, path: &str, base_path: &str) -> bool {
+    return method != Method::Options && is_api_path(method, path, base_path);
+}
+
+fn base_path<'a>(request: &'a Request<'_>) -> &'a str {
+    return request
+        .rocket()
+        .state::<Arc<data::state::Global>>()
+        .map_or("", |x| x.base_path.as_str());
+}
+
+/// Fairing that adds CORS headers for the configured origins and answers preflight
+/// requests. Without origins, no CORS headers are sent and browsers only allow
+/// same-origin requests.
+pub struct Cors {
+    allowed_origins: Vec<String>,
+}
+
+impl Cors {
+    pub fn new(allowed_origins: Vec<String>) -> Self {
+        Self { allowed_origins }
+    }
+
+    /// Value for the Access-Control-Allow-Origin header of a request from an origin.
+    fn allow_origin(self: &Self, origin: &str) -> Option<String> {
+        if self.allowed_origins.iter().any(|x| x == "*") {
+            return Some(String::from("*"));
+        }
+        return self
+            .allowed_origins
+            .iter()
+            .any(|x| x.trim_end_matches('/') == origin)
+            .then(|| origin.to_string());
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info {
+            name: "CORS",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let origin = match request.headers().get_one("Origin") {
+            Some(origin) => origin,
+            None => return,
+        };
+        let allow_origin = match self.allow_origin(origin) {
+            Some(allow_origin) => allow_origin,
+            None => return,
+        };
+        response.set_header(Header::new("Access-Control-Allow-Origin", allow_origin));
+        response.set_header(Header::new("Vary", "Origin"));
+        let is_preflight = request.method() == Method::Options
+            && request.headers().contains("Access-Control-Request-Method");
+        if is_preflight && request.route().is_none() {
+            response.set_status(Status::NoContent);
+            response.set_header(Header::new("Access-Control-Allow-Methods", "GET, POST"));
+            response.set_header(Header::new(
+                "Access-Control-Allow-Headers",
+                CORS_ALLOWED_HEADERS,
+            ));
+            response.set_header(Header::new("Access-Control-Max-Age", "86400"));
+            response.set_sized_body(0, std::io::Cursor::new(""));
+        }
+    }
+}
+
+/// Requests of a client in the current window.
+#[derive(Debug)]
+struct ClientWindow {
+    start: Instant,
+    requests: u32,
+}
+
+/// Fairing that limits how many API and webhook requests each client address can
+/// make per minute. Requests over the limit get HTTP 429 without reaching a route.
+pub struct RateLimit {
+    requests_per_minute: u32,
+    clients: Mutex<HashMap<IpAddr, ClientWindow>>,
+}
+
+/// Marks a request that was rejected by the rate limit, with the time until the
+/// client can make requests again.
+struct RateLimited(Option<Duration>);
+
+impl RateLimit {
+    pub fn new(requests_per_minute: u32) -> Self {
+        Self {
+            requests_per_minute,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count a request from an address. Returns the time until the window resets if the
+    /// address is over the limit.
+    fn check(self: &Self, address: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= RATE_LIMIT_PRUNE_THRESHOLD {
+            clients.retain(|_, x| now.duration_since(x.start) < RATE_LIMIT_WINDOW);
+        }
+        let window = clients.entry(address).or_insert(ClientWindow {
+            start: now,
+            requests: 0,
+        });
+        if now.duration_since(window.start) >= RATE_LIMIT_WINDOW {
+            window.start = now;
+            window.requests = 0;
+        }
+        if window.requests >= self.requests_per_minute {
+            return Err(RATE_LIMIT_WINDOW - now.duration_since(window.start));
+        }
+        window.requests += 1;
+        return Ok(());
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for RateLimit {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate limit",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        if !is_rate_limited(
+            request.method(),
+            request.uri().path().as_str(),
+            base_path(request),
+        ) {
+            return;
+        }
+        let address = match data::guards::client_ip(request).0 {
+            Some(address) => address,
+            None => return,
+        };
+        if let Err(retry_after) = self.check(address, Instant::now()) {
+            warn!("Rate limited a request from {}", address);
+            request.local_cache(|| RateLimited(Some(retry_after)));
+            request.set_method(Method::Get);
+            request.set_uri(Origin::parse(RATE_LIMITED_PATH).unwrap());
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let retry_after = match request.local_cache(|| RateLimited(None)).0 {
+            Some(retry_after) => retry_after,
+            None => return,
+        };
+        let body = "Too many requests.";
+        response.set_status(Status::TooManyRequests);
+        response.set_header(Header::new(
+            "Retry-After",
+            retry_after.as_secs().max(1).to_string(),
+        ));
+        response.set_sized_body(body.len(), std::io::Cursor::new(body));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_case::test_case;
+
+    #[test_case(Method::Get, "/api/user", "", true ; "api")]
+    #[test_case(Method::Post, "/", "", true ; "webhook")]
+    #[test_case(Method::Get, "/", "", false ; "management redirect")]
+    #[test_case(Method::Get, "/admin", "", false ; "management")]
+    #[test_case(Method::Post, "/anifunnel", "/anifunnel", true ; "webhook under base path")]
+    #[test_case(Method::Get, "/anifunnel/api/user", "/anifunnel", true ; "api under base path")]
+    #[test_case(Method::Get, "/api/user", "/anifunnel", false ; "outside base path")]
+    fn api_path(method: Method, path: &str, base_path: &str, expected: bool) {
+        assert_eq!(is_api_path(method, path, base_path), expected);
+    }
+
+    #[test_case(Method::Get, "/api/user", true ; "api")]
+    #[test_case(Method::Options, "/api/user", false ; "preflight")]
+    #[test_case(Method::Get, "/admin", false ; "management")]
+    fn rate_limited(method: Method, path: &str, expected: bool) {
+        assert_eq!(is_rate_limited(method, path, ""), expected);
+    }
+
+    #[test]
+    fn rate_limit_window() {
+        let rate_limit = RateLimit::new(2);
+        let address: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        let start = Instant::now();
+        assert!(rate_limit.check(address, start).is_ok());
+        assert!(rate_limit.check(address, start).is_ok());
+        assert_eq!(
+            rate_limit.check(address, start + Duration::from_secs(20)),
+            Err(Duration::from_secs(40))
+        );
+        assert!(rate_limit.check(other, start).is_ok());
+        assert!(rate_limit.check(address, start + RATE_LIMIT_WINDOW).is_ok());
+    }
+
+    #[test_case(&["https://example.com"], "https://example.com", Some("https://example.com") ; "allowed")]
+    #[test_case(&["https://example.com/"], "https://example.com", Some("https://example.com") ; "trailing slash")]
+    #[test_case(&["https://example.com"], "https://example.org", None ; "not allowed")]
+    #[test_case(&["*"], "https://example.org", Some("*") ; "any")]
+    #[test_case(&[], "https://example.com", None ; "none")]
+    fn cors_allow_origin(allowed: &[&str], origin: &str, expected: Option<&str>) {
+        let cors = Cors::new(allowed.iter().map(|x| x.to_string()).collect());
+        assert_eq!(cors.allow_origin(origin).as_deref(), expected);
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use serde::Deserialize;
+use tokio::sync::{oneshot, RwLock};
+
+/// Mapping file from the Fribb/anime-lists project, which links AniDB IDs to Anilist IDs.
+pub const DEFAULT_MAPPING_URL: &str =
+    "https://raw.githubusercontent.com/Fribb/anime-lists/master/anime-list-full.json";
+
+/// How often the mapping file is loaded again.
+pub const MAPPING_REFRESH_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// GUID prefixes that are followed by an AniDB ID, as set by the HAMA agent
+/// (`com.plexapp.agents.hama://anidb-1234`) and as external IDs (`anidb://1234`).
+const ANIDB_GUID_PREFIXES: [&str; 3] = [
+    "com.plexapp.agents.hama://anidb-",
+    "com.plexapp.agents.hama://anidb2-",
+    "anidb://",
+];
+
+#[derive(Debug)]
+pub enum MappingError {
+    Connection,
+    Read,
+    Parsing,
+}
+
+#[derive(Debug, Deserialize)]
+struct MappingEntry {
+    anidb_id: Option<i32>,
+    anilist_id: Option<i32>,
+}
+
+/// Anilist media IDs keyed by AniDB IDs.
+#[derive(Debug, Default)]
+pub struct AnidbMapping {
+    inner: HashMap<i32, i32>,
+}
+
+impl AnidbMapping {
+    pub fn new() -> Self {
+        Self {
+            inner: HashMap::new(),
+        }
+    }
+
+    /// Parse a mapping file in the anime-lists JSON format. Entries without both IDs
+    /// are ignored.
+    pub fn parse(data: &str) -> Result<Self, MappingError> {
+        let entries: Vec<MappingEntry> = serde_json::from_str(data).map_err(|error| {
+            debug!("{}", error);
+            MappingError::Parsing
+        })?;
+        return Ok(Self {
+            inner: entries
+                .into_iter()
+                .filter_map(|x| Some((x.anidb_id?, x.anilist_id?)))
+                .collect(),
+        });
+    }
+
+    /// Anilist media ID of the first GUID that contains a known AniDB ID.
+    pub fn get<'a>(self: &Self, guids: impl IntoIterator<Item = &'a String>) -> Option<i32> {
+        return guids
+            .into_iter()
+            .filter_map(|x| anidb_id(x))
+            .find_map(|x| self.inner.get(&x).copied());
+    }
+
+    pub fn len(self: &Self) -> usize {
+        return self.inner.len();
+    }
+}
+
+/// AniDB ID from a Plex GUID, if the GUID has one.
+pub fn anidb_id(guid: &str) -> Option<i32> {
+    let id = ANIDB_GUID_PREFIXES
+        .iter()
+        .find_map(|prefix| guid.strip_prefix(prefix))?;
+    let end = id.find(|x: char| !x.is_ascii_digit()).unwrap_or(id.len());
+    return id[..end].parse().ok();
+}
+
+/// Load the mapping file from a URL or a local path.
+pub async fn load(source: &str) -> Result<AnidbMapping, MappingError> {
+    let data = if source.starts_with("http://") || source.starts_with("https://") {
+        reqwest::get(source)
+            .await
+            .and_then(|x| x.error_for_status())
+            .map_err(|_| MappingError::Connection)?
+            .text()
+            .await
+            .map_err(|_| MappingError::Connection)?
+    } else {
+        tokio::fs::read_to_string(source)
+            .await
+            .map_err(|_| MappingError::Read)?
+    };
+    return AnidbMapping::parse(&data);
+}
+
+/// Load the mapping file and keep loading it again periodically. A failed refresh
+/// keeps the previously loaded mapping.
+pub async fn refresh(
+    source: String,
+    mapping: Arc<RwLock<AnidbMapping>>,
+    mut first_load: Option<oneshot::Sender<()>>,
+) {
+    loop {
+        match load(&source).await {
+            Ok(loaded) => {
+                info!("Loaded {} AniDB ID mappings", loaded.len());
+                *mapping.write().await = loaded;
+            }
+            Err(error) => warn!("Could not load the AniDB ID mapping: {:?}", error),
+        }
+        // Signal that the first attempt is done, whether or not it succeeded.
+        if let Some(first_load) = first_load.take() {
+            let _ = first_load.send(());
+        }
+        tokio::time::sleep(MAPPING_REFRESH_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_case::test_case;
+
+    #[test_case("com.plexapp.agents.hama://anidb-17832?lang=en", Some(17832) ; "hama")]
+    #[test_case("com.plexapp.agents.hama://anidb2-17832/1?lang=en", Some(17832) ; "hama anidb2")]
+    #[test_case("anidb://17832", Some(17832) ; "external")]
+    #[test_case("com.plexapp.agents.hama://tvdb-414977?lang=en", None ; "hama tvdb")]
+    #[test_case("plex://show/5d9c086c46115600200aa2fe", None ; "plex")]
+    #[test_case("anidb://", None ; "missing id")]
+    fn anidb_id_parse(guid: &str, expected: Option<i32>) {
+        assert_eq!(anidb_id(guid), expected);
+    }
+
+    #[test]
+    fn mapping_get() {
+        let mapping = AnidbMapping::parse(
+            "[{\"anidb_id\": 17832, \"anilist_id\": 153800, \"type\": \"TV\"}, \
+            {\"anidb_id\": 17833, \"type\": \"TV\"}, {\"anilist_id\": 1, \"type\": \"TV\"}]",
+        )
+        .unwrap();
+        assert_eq!(mapping.len(), 1);
+        let guids = [
+            String::from("plex://show/5d9c086c46115600200aa2fe"),
+            String::from("anidb://17832"),
+        ];
+        assert_eq!(mapping.get(&guids), Some(153800));
+        assert_eq!(mapping.get(&[String::from("anidb://17833")]), None);
+    }
+}
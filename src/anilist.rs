@@ -1,9 +1,20 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-
-use log::{debug, info};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use icu_normalizer::ComposingNormalizer;
+use log::{debug, info, warn};
 use regex::Regex;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use strsim::normalized_levenshtein;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{romaji, trace};
 
 const MEDIALIST_MUTATION: &str = "
 mutation($id: Int, $progress: Int) {
@@ -12,14 +23,56 @@ mutation($id: Int, $progress: Int) {
   }
 }
 ";
+const MEDIALIST_COMPLETE_MUTATION: &str = "
+mutation($id: Int, $progress: Int) {
+  SaveMediaListEntry(id: $id, progress: $progress, status: COMPLETED) {
+    progress
+  }
+}
+";
+const MEDIALIST_START_MUTATION: &str = "
+mutation($id: Int, $progress: Int) {
+  SaveMediaListEntry(id: $id, progress: $progress, status: CURRENT) {
+    progress
+  }
+}
+";
+const MEDIALIST_SCORE_MUTATION: &str = "
+mutation($id: Int, $scoreRaw: Int) {
+  SaveMediaListEntry(id: $id, scoreRaw: $scoreRaw) {
+    progress
+    score(format: POINT_100)
+  }
+}
+";
+const MEDIALIST_REPEAT_COMPLETE_MUTATION: &str = "
+mutation($id: Int, $progress: Int, $repeat: Int) {
+  SaveMediaListEntry(id: $id, progress: $progress, status: COMPLETED, repeat: $repeat) {
+    progress
+  }
+}
+";
+const MEDIALIST_PROGRESS_QUERY: &str = "
+query($id: Int) {
+  MediaList(id: $id) {
+    progress
+  }
+}
+";
 const MEDIALIST_QUERY: &str = "
-query MediaListCollection($user_id: Int) {
-    MediaListCollection(userId: $user_id, status_in: [CURRENT, REPEATING], type: ANIME) {
+query MediaListCollection($user_id: Int, $status_in: [MediaListStatus]) {
+    MediaListCollection(userId: $user_id, status_in: $status_in, type: ANIME) {
         lists {
             entries {
                 id
                 progress
+                status
+                repeat
                 media {
+                    id
+                    format
+                    episodes
+                    seasonYear
                     title {
                         romaji
                         english
@@ -33,50 +86,802 @@ query MediaListCollection($user_id: Int) {
     }
 }
 ";
+const MEDIA_RELATIONS_QUERY: &str = "
+query($id: Int) {
+    Media(id: $id) {
+        relations {
+            edges {
+                relationType
+                node {
+                    id
+                    type
+                    format
+                    episodes
+                }
+            }
+        }
+    }
+}
+";
+const MEDIA_DETAILS_QUERY: &str = "
+query($id: Int) {
+    Media(id: $id, type: ANIME) {
+        id
+        format
+        episodes
+        season
+        seasonYear
+        status
+        coverImage {
+            large
+            color
+        }
+        title {
+            romaji
+            english
+            native
+            userPreferred
+        }
+    }
+}
+";
+const MEDIA_SEARCH_QUERY: &str = "
+query($search: String) {
+    Page(perPage: 10) {
+        media(search: $search, type: ANIME) {
+            id
+            format
+            episodes
+            title {
+                romaji
+                english
+                native
+                userPreferred
+            }
+        }
+    }
+}
+";
 const USER_QUERY: &str = "
 query {
     Viewer {
         id
         name
+        avatar {
+            large
+        }
+        options {
+            profileColor
+        }
+        mediaListOptions {
+            scoreFormat
+        }
     }
 }
 ";
-const MINIMUM_CONFIDENCE: f64 = 0.8;
+/// Fuzzy match confidence that a title needs to be considered a match, unless
+/// configured otherwise.
+pub const DEFAULT_MINIMUM_CONFIDENCE: f64 = 0.8;
+
+/// How titles are fuzzy matched to the list entries.
+#[derive(Clone, Copy, Debug)]
+pub struct MatchSettings {
+    /// Minimum confidence for entries without their own minimum.
+    pub minimum_confidence: f64,
+    /// Whether kana titles are also matched as transliterated to romaji.
+    pub transliterate_native: bool,
+}
 
-#[derive(Debug)]
+impl Default for MatchSettings {
+    fn default() -> Self {
+        return Self {
+            minimum_confidence: DEFAULT_MINIMUM_CONFIDENCE,
+            transliterate_native: false,
+        };
+    }
+}
+
+/// Formats that count as seasons when following prequels. Movies, OVAs and specials
+/// are usually not part of the absolute episode numbering.
+const SEASON_FORMATS: [&str; 3] = ["TV", "TV_SHORT", "ONA"];
+/// Longest chain of prequels that is followed.
+const MAX_PREQUELS: usize = 10;
+/// Fuzzy matches this close to the minimum confidence are logged as warnings.
+const BORDERLINE_MARGIN: f64 = 0.05;
+/// Matches whose confidence is this close to the best match make the match ambiguous.
+const AMBIGUITY_MARGIN: f64 = 0.01;
+
+/// Watching lists with at least this many entries are fuzzy matched in parallel.
+const PARALLEL_MATCHING_THRESHOLD: usize = 200;
+
+/// Common title suffixes removed for fallback fuzzy matching, compiled once.
+static MASSAGING_REGEXES: OnceLock<[Regex; 6]> = OnceLock::new();
+
+/// How many times a rate limited request is retried before giving up.
+const RATE_LIMIT_RETRIES: u32 = 3;
+/// Longest time to wait before retrying a rate limited request.
+const RATE_LIMIT_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Remaining requests in the current rate limit window, or -1 if unknown.
+static RATE_LIMIT_REMAINING: AtomicI64 = AtomicI64::new(-1);
+/// Number of requests that Anilist has rejected due to rate limiting.
+static RATE_LIMITED_REQUESTS: AtomicU64 = AtomicU64::new(0);
+
+/// Anilist GraphQL API used unless configured otherwise.
+pub const DEFAULT_API_URL: &str = "https://graphql.anilist.co/";
+
+/// Anilist OAuth endpoints for the authorization code grant.
+const OAUTH_URL: &str = "https://anilist.co/api/v2/oauth/";
+
+/// How often the schema probe checks that the queried fields still exist.
+pub const SCHEMA_PROBE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Fields of each GraphQL type that anifunnel queries, checked by the schema probe.
+const EXPECTED_FIELDS: [(&str, &[&str]); 7] = [
+    ("MediaListCollection", &["lists"]),
+    ("MediaListGroup", &["entries"]),
+    (
+        "MediaList",
+        &["id", "progress", "status", "repeat", "media"],
+    ),
+    (
+        "Media",
+        &[
+            "id",
+            "type",
+            "format",
+            "episodes",
+            "seasonYear",
+            "title",
+            "synonyms",
+            "relations",
+        ],
+    ),
+    (
+        "MediaTitle",
+        &["romaji", "english", "native", "userPreferred"],
+    ),
+    ("MediaEdge", &["relationType", "node"]),
+    (
+        "User",
+        &["id", "name", "avatar", "options", "mediaListOptions"],
+    ),
+];
+/// Schema problems that have already been logged, so that each is only logged once.
+static SCHEMA_WARNINGS: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+
+/// Client name used in the User-Agent unless configured otherwise.
+pub const DEFAULT_CLIENT_NAME: &str = "anifunnel";
+
+/// GraphQL API that requests are sent to, e.g. anifunnel-mock-anilist, and the
+/// User-Agent that anifunnel identifies itself to Anilist with.
+#[derive(Clone, Debug)]
+pub struct AnilistApi {
+    pub url: String,
+    pub user_agent: String,
+}
+
+impl AnilistApi {
+    /// Build the User-Agent from the client name and the anifunnel version, with
+    /// optional contact details (e.g. an email address or URL) for Anilist to reach
+    /// the user.
+    pub fn new(url: &str, client_name: &str, contact: Option<&str>) -> Self {
+        let user_agent = format!("{}/{}", client_name, env!("CARGO_PKG_VERSION"));
+        return Self {
+            url: url.to_string(),
+            user_agent: match contact {
+                Some(contact) => format!("{} ({})", user_agent, contact),
+                None => user_agent,
+            },
+        };
+    }
+}
+
+impl Default for AnilistApi {
+    fn default() -> Self {
+        return Self::new(DEFAULT_API_URL, DEFAULT_CLIENT_NAME, None);
+    }
+}
+
+#[derive(Clone, Debug)]
 pub enum AnilistError {
     RequestDataError,
     ConnectionError,
     ParsingError,
     InvalidToken,
+    RateLimited,
+}
+
+/// Rate limit state reported by Anilist.
+#[derive(Debug, PartialEq)]
+pub struct RateLimitStats {
+    /// Remaining requests in the current window, if Anilist has reported it.
+    pub remaining: Option<i64>,
+    pub rate_limited_requests: u64,
+}
+
+pub fn rate_limit_stats() -> RateLimitStats {
+    let remaining = RATE_LIMIT_REMAINING.load(Ordering::Relaxed);
+    RateLimitStats {
+        remaining: if remaining >= 0 {
+            Some(remaining)
+        } else {
+            None
+        },
+        rate_limited_requests: RATE_LIMITED_REQUESTS.load(Ordering::Relaxed),
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Media {
+    pub id: i32,
+    pub format: Option<String>,
+    pub episodes: Option<i32>,
+    #[serde(rename = "seasonYear", default)]
+    pub season_year: Option<i32>,
     pub title: MediaTitle,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub synonyms: Vec<String>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct MediaList {
     pub id: i32,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub progress: i32,
+    pub status: Option<String>,
+    /// Number of completed rewatches.
+    pub repeat: Option<i32>,
     pub media: Media,
 }
 
 impl MediaList {
-    pub async fn update(self: &Self, token: &String) -> Result<bool, AnilistError> {
+    /// Increment the progress. Finishing a rewatch also completes the entry, and
+    /// planned or paused entries are moved to the watching list.
+    pub async fn update(
+        self: &Self,
+        mutations: &MutationQueue,
+        token: &str,
+    ) -> Result<Saved, AnilistError> {
+        if (self.is_repeating() || self.is_inactive())
+            && self.media.episodes == Some(self.progress + 1)
+        {
+            return self.complete(mutations, token).await;
+        }
+        if self.is_inactive() {
+            return mutations
+                .save_progress(
+                    token,
+                    MEDIALIST_START_MUTATION,
+                    self.id,
+                    self.progress + 1,
+                    None,
+                    false,
+                )
+                .await;
+        }
+        return mutations
+            .save_progress(
+                token,
+                MEDIALIST_MUTATION,
+                self.id,
+                self.progress + 1,
+                None,
+                false,
+            )
+            .await;
+    }
+
+    /// Increment the progress and mark the entry as completed, counting a rewatch if
+    /// the entry was being rewatched.
+    pub async fn complete(
+        self: &Self,
+        mutations: &MutationQueue,
+        token: &str,
+    ) -> Result<Saved, AnilistError> {
+        if let Some(repeat) = self.completed_repeat() {
+            return mutations
+                .save_progress(
+                    token,
+                    MEDIALIST_REPEAT_COMPLETE_MUTATION,
+                    self.id,
+                    self.progress + 1,
+                    Some(repeat),
+                    false,
+                )
+                .await;
+        }
+        return mutations
+            .save_progress(
+                token,
+                MEDIALIST_COMPLETE_MUTATION,
+                self.id,
+                self.progress + 1,
+                None,
+                false,
+            )
+            .await;
+    }
+
+    pub fn is_repeating(self: &Self) -> bool {
+        return self.status.as_deref() == Some("REPEATING");
+    }
+
+    /// Whether the entry is on the planning or paused list instead of being watched.
+    pub fn is_inactive(self: &Self) -> bool {
+        return matches!(self.status.as_deref(), Some("PLANNING") | Some("PAUSED"));
+    }
+
+    /// Rewatch count after completing the entry, if the entry is being rewatched.
+    fn completed_repeat(self: &Self) -> Option<i32> {
+        if !self.is_repeating() {
+            return None;
+        }
+        return Some(self.repeat.unwrap_or(0) + 1);
+    }
+}
+
+/// Set the progress of a media list entry to an arbitrary value. With force, the
+/// progress is set even if it moves the progress backwards, e.g. when the user
+/// corrects a progress that was counted too far.
+pub async fn set_progress(
+    mutations: &MutationQueue,
+    token: &str,
+    id: i32,
+    progress: i32,
+    force: bool,
+) -> Result<Saved, AnilistError> {
+    return mutations
+        .save_progress(token, MEDIALIST_MUTATION, id, progress, None, force)
+        .await;
+}
+
+/// Set the score of a media list entry on the 100 point scale, which Anilist converts
+/// to the scoring system of the user.
+pub async fn set_score(
+    mutations: &MutationQueue,
+    token: &str,
+    id: i32,
+    score: i32,
+) -> Result<bool, AnilistError> {
+    let variables = MediaListCollectionMutateVariables {
+        id,
+        progress: None,
+        repeat: None,
+        score_raw: Some(score),
+    };
+    let saved = mutations
+        .save(token, MEDIALIST_SCORE_MUTATION, variables, false)
+        .await?;
+    return Ok(saved.score.map(|x| x.round() as i32) == Some(score));
+}
+
+/// Result of a queued progress update.
+#[derive(Debug, PartialEq)]
+pub enum Saved {
+    Updated,
+    /// The entry was already past the progress, so nothing was sent. Earlier
+    /// mutations in the queue or another client have counted the episode.
+    AlreadyCounted,
+    /// Anilist did not save the progress.
+    Failed,
+}
+
+/// Mutation waiting in the MutationQueue.
+struct Mutation {
+    token: String,
+    /// Trace of the scrobble that queued the mutation, since the worker runs outside it.
+    trace: Option<trace::TraceContext>,
+    mutation: &'static str,
+    variables: MediaListCollectionMutateVariables,
+    /// Send the mutation even if it moves the progress backwards.
+    force: bool,
+    result: oneshot::Sender<Result<SaveMediaListEntry, AnilistError>>,
+}
+
+/// Queue that sends all mutations to Anilist one at a time, so concurrent scrobbles
+/// cannot race each other and a rate limited mutation holds back the rest instead of
+/// competing with them. The worker task is started on first use.
+#[derive(Debug, Default)]
+pub struct MutationQueue {
+    api: AnilistApi,
+    sender: OnceLock<mpsc::UnboundedSender<Mutation>>,
+    /// Mutations that have been queued but not yet sent.
+    pending: Arc<AtomicUsize>,
+}
+
+impl MutationQueue {
+    pub fn new(api: AnilistApi) -> Self {
+        Self {
+            api,
+            ..Default::default()
+        }
+    }
+
+    pub fn pending(self: &Self) -> usize {
+        return self.pending.load(Ordering::SeqCst);
+    }
+
+    async fn save(
+        self: &Self,
+        token: &str,
+        mutation: &'static str,
+        variables: MediaListCollectionMutateVariables,
+        force: bool,
+    ) -> Result<SaveMediaListEntry, AnilistError> {
+        let sender = self.sender.get_or_init(|| {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            tokio::spawn(run_mutations(
+                self.api.clone(),
+                receiver,
+                self.pending.clone(),
+            ));
+            sender
+        });
+        let (result, receiver) = oneshot::channel();
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        sender
+            .send(Mutation {
+                token: token.to_string(),
+                trace: trace::current(),
+                mutation,
+                variables,
+                force,
+                result,
+            })
+            .map_err(|_| {
+                self.pending.fetch_sub(1, Ordering::SeqCst);
+                AnilistError::ConnectionError
+            })?;
+        return receiver.await.map_err(|_| AnilistError::ConnectionError)?;
+    }
+
+    async fn save_progress(
+        self: &Self,
+        token: &str,
+        mutation: &'static str,
+        id: i32,
+        progress: i32,
+        repeat: Option<i32>,
+        force: bool,
+    ) -> Result<Saved, AnilistError> {
         let variables = MediaListCollectionMutateVariables {
-            id: self.id,
-            progress: self.progress + 1,
+            id,
+            progress: Some(progress),
+            repeat,
+            score_raw: None,
         };
-        let query = Query::<MediaListCollectionMutateVariables> {
-            query: MEDIALIST_MUTATION,
-            variables: Some(variables),
+        let saved = self.save(token, mutation, variables, force).await?;
+        if saved.already_counted {
+            return Ok(Saved::AlreadyCounted);
+        }
+        if saved.progress < progress {
+            return Ok(Saved::Failed);
+        }
+        return Ok(Saved::Updated);
+    }
+}
+
+/// Largest number of queued mutations that are sent to Anilist in a single request.
+const MUTATION_BATCH_SIZE: usize = 10;
+
+/// Worker that sends queued mutations in order until the queue is dropped. Mutations
+/// that are already waiting when the worker gets to them, e.g. during a backfill, are
+/// sent together in a batch, unless they are for another account or for an entry that
+/// is already in the batch.
+async fn run_mutations(
+    api: AnilistApi,
+    mut receiver: mpsc::UnboundedReceiver<Mutation>,
+    pending: Arc<AtomicUsize>,
+) {
+    let mut next = None;
+    loop {
+        let first = match next.take() {
+            Some(mutation) => mutation,
+            None => match receiver.recv().await {
+                Some(mutation) => mutation,
+                None => break,
+            },
         };
-        let response = send_query(token, query).await?;
-        let data = QueryResponse::<SaveMediaListEntryData>::parse(response).await?;
-        Ok(data.SaveMediaListEntry.progress == self.progress + 1)
+        let mut batch = vec![first];
+        while batch.len() < MUTATION_BATCH_SIZE {
+            let mutation = match receiver.try_recv() {
+                Ok(mutation) => mutation,
+                Err(_) => break,
+            };
+            if mutation.token != batch[0].token
+                || batch
+                    .iter()
+                    .any(|x| x.variables.id == mutation.variables.id)
+            {
+                next = Some(mutation);
+                break;
+            }
+            batch.push(mutation);
+        }
+        let results = match batch.as_slice() {
+            [mutation] => {
+                debug!(
+                    "Sending queued mutation for {} ({} waiting)",
+                    mutation.variables.id,
+                    receiver.len()
+                );
+                vec![send_traced(&api, mutation).await]
+            }
+            _ => {
+                debug!(
+                    "Sending {} queued mutations in a batch ({} waiting)",
+                    batch.len(),
+                    receiver.len()
+                );
+                send_batch(&api, &batch).await
+            }
+        };
+        for (mutation, result) in batch.into_iter().zip(results) {
+            pending.fetch_sub(1, Ordering::SeqCst);
+            let _ = mutation.result.send(result);
+        }
+    }
+}
+
+/// Send a queued mutation on its own, in the trace of the scrobble that queued it.
+async fn send_traced(
+    api: &AnilistApi,
+    mutation: &Mutation,
+) -> Result<SaveMediaListEntry, AnilistError> {
+    return trace::scope(
+        mutation.trace.clone(),
+        send_mutation(
+            api,
+            &mutation.token,
+            mutation.mutation,
+            &mutation.variables,
+            mutation.force,
+        ),
+    )
+    .await;
+}
+
+/// Send a batch of queued mutations with GraphQL aliases. Mutations that the batch
+/// did not save, e.g. because Anilist rejected one of them, are sent again on their
+/// own so that the error only affects the mutation that caused it.
+async fn send_batch(
+    api: &AnilistApi,
+    batch: &[Mutation],
+) -> Vec<Result<SaveMediaListEntry, AnilistError>> {
+    let saved = trace::scope(batch[0].trace.clone(), save_batch(api, batch)).await;
+    let saved = match saved {
+        Ok(saved) => saved,
+        // Errors that are not about any one mutation would fail them all anyway.
+        Err(
+            error @ (AnilistError::ConnectionError
+            | AnilistError::InvalidToken
+            | AnilistError::RateLimited),
+        ) => return batch.iter().map(|_| Err(error.clone())).collect(),
+        Err(error) => {
+            warn!(
+                "Could not send {} mutations in a batch, sending them separately: {:?}",
+                batch.len(),
+                error
+            );
+            batch.iter().map(|_| None).collect()
+        }
+    };
+    let mut results = Vec::new();
+    for (mutation, saved) in batch.iter().zip(saved) {
+        results.push(match saved {
+            Some(saved) => Ok(saved),
+            None => send_traced(api, mutation).await,
+        });
+    }
+    return results;
+}
+
+/// Send a batch of mutations the same way as send_mutation sends them one at a time:
+/// the progress of their entries is read again in one request, and the mutations that
+/// do not move the progress backwards are saved in another. Returns the saved entry of
+/// each mutation, or None for those that were not saved.
+async fn save_batch(
+    api: &AnilistApi,
+    batch: &[Mutation],
+) -> Result<Vec<Option<SaveMediaListEntry>>, AnilistError> {
+    let token = &batch[0].token;
+    let progress_queries: Vec<(usize, MediaQueryVariables)> = batch
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| x.variables.progress.is_some() && !x.force)
+        .map(|(index, x)| (index, MediaQueryVariables { id: x.variables.id }))
+        .collect();
+    let mut current = HashMap::new();
+    if !progress_queries.is_empty() {
+        let document = batch_document(
+            "query",
+            "p",
+            progress_queries
+                .iter()
+                .map(|(index, _)| (*index, MEDIALIST_PROGRESS_QUERY)),
+        );
+        let query = Query {
+            query: &document,
+            variables: Some(batch_variables(&progress_queries)),
+        };
+        let response = send_query(api, token, query).await?;
+        current =
+            QueryResponse::<HashMap<String, Option<MediaListProgress>>>::parse(response).await?;
+    }
+    let mut results: Vec<Option<SaveMediaListEntry>> = batch.iter().map(|_| None).collect();
+    let mut saves = Vec::new();
+    for (index, mutation) in batch.iter().enumerate() {
+        let progress = match mutation.variables.progress.filter(|_| !mutation.force) {
+            Some(progress) => progress,
+            None => {
+                saves.push((index, &mutation.variables));
+                continue;
+            }
+        };
+        match current.get(&format!("p{}", index)) {
+            Some(Some(entry)) if entry.progress > progress => {
+                info!(
+                    "Not saving progress {} for {}, which is already at {}",
+                    progress, mutation.variables.id, entry.progress
+                );
+                results[index] = Some(SaveMediaListEntry {
+                    progress: entry.progress,
+                    score: None,
+                    already_counted: true,
+                });
+            }
+            Some(Some(_)) => saves.push((index, &mutation.variables)),
+            // The progress could not be read, so the mutation is sent on its own.
+            _ => {}
+        }
+    }
+    if saves.is_empty() {
+        return Ok(results);
+    }
+    let document = batch_document(
+        "mutation",
+        "m",
+        saves
+            .iter()
+            .map(|(index, _)| (*index, batch[*index].mutation)),
+    );
+    let query = Query {
+        query: &document,
+        variables: Some(batch_variables(&saves)),
+    };
+    let response = send_query(api, token, query).await?;
+    let mut saved =
+        QueryResponse::<HashMap<String, Option<SaveMediaListEntry>>>::parse(response).await?;
+    for (index, _) in saves {
+        results[index] = saved.remove(&format!("m{}", index)).flatten();
+    }
+    return Ok(results);
+}
+
+/// Combine GraphQL documents of the same operation type, each with a single field,
+/// into one document. The field of each document is aliased as the alias followed by
+/// the index, and the index is appended to its variables.
+fn batch_document<'a>(
+    operation: &str,
+    alias: &str,
+    documents: impl Iterator<Item = (usize, &'a str)>,
+) -> String {
+    let mut declarations = Vec::new();
+    let mut fields = Vec::new();
+    for (index, document) in documents {
+        let document = document.trim();
+        let document = document.strip_prefix(operation).unwrap_or(document);
+        let (declaration, field) = document.split_once('{').unwrap_or(("", document));
+        let declaration = declaration
+            .trim()
+            .trim_start_matches('(')
+            .trim_end_matches(')');
+        let field = field.trim();
+        let field = field.strip_suffix('}').unwrap_or(field).trim();
+        let suffix = format!("_{}", index);
+        declarations.push(suffix_variables(declaration, &suffix));
+        fields.push(format!(
+            "  {}{}: {}",
+            alias,
+            index,
+            suffix_variables(field, &suffix)
+        ));
+    }
+    return format!(
+        "{}({}) {{\n{}\n}}",
+        operation,
+        declarations.join(", "),
+        fields.join("\n")
+    );
+}
+
+/// Append a suffix to the names of the variables in a GraphQL document.
+fn suffix_variables(text: &str, suffix: &str) -> String {
+    let mut result = String::new();
+    let mut in_variable = false;
+    for chr in text.chars() {
+        if in_variable && !(chr.is_alphanumeric() || chr == '_') {
+            result.push_str(suffix);
+            in_variable = false;
+        }
+        if chr == '$' {
+            in_variable = true;
+        }
+        result.push(chr);
+    }
+    if in_variable {
+        result.push_str(suffix);
+    }
+    return result;
+}
+
+/// Variables of the documents combined by batch_document.
+fn batch_variables<T: Serialize>(
+    variables: &[(usize, T)],
+) -> serde_json::Map<String, serde_json::Value> {
+    let mut batch = serde_json::Map::new();
+    for (index, variables) in variables {
+        if let Ok(serde_json::Value::Object(variables)) = serde_json::to_value(variables) {
+            for (name, value) in variables {
+                batch.insert(format!("{}_{}", name, index), value);
+            }
+        }
+    }
+    return batch;
+}
+
+/// Send a queued mutation, unless it would move the progress of the entry backwards
+/// without being forced. The progress is read again right before sending, since
+/// mutations are built from the progress that the scrobble saw, which earlier
+/// mutations in the queue may have already moved past.
+async fn send_mutation(
+    api: &AnilistApi,
+    token: &String,
+    mutation: &'static str,
+    variables: &MediaListCollectionMutateVariables,
+    force: bool,
+) -> Result<SaveMediaListEntry, AnilistError> {
+    if let Some(progress) = variables.progress.filter(|_| !force) {
+        let current = get_progress(api, token, variables.id).await?;
+        if current > progress {
+            info!(
+                "Not saving progress {} for {}, which is already at {}",
+                progress, variables.id, current
+            );
+            return Ok(SaveMediaListEntry {
+                progress: current,
+                score: None,
+                already_counted: true,
+            });
+        }
     }
+    return save_entry(api, token, mutation, variables).await;
+}
+
+/// Current progress of a media list entry.
+async fn get_progress(api: &AnilistApi, token: &String, id: i32) -> Result<i32, AnilistError> {
+    let query = Query::<MediaQueryVariables> {
+        query: MEDIALIST_PROGRESS_QUERY,
+        variables: Some(MediaQueryVariables { id }),
+    };
+    let response = send_query(api, token, query).await?;
+    let data = QueryResponse::<MediaListProgressData>::parse(response).await?;
+    return Ok(data.MediaList.progress);
+}
+
+async fn save_entry(
+    api: &AnilistApi,
+    token: &String,
+    mutation: &'static str,
+    variables: &MediaListCollectionMutateVariables,
+) -> Result<SaveMediaListEntry, AnilistError> {
+    let query = Query {
+        query: mutation,
+        variables: Some(variables),
+    };
+    let response = send_query(api, token, query).await?;
+    let data = QueryResponse::<SaveMediaListEntryData>::parse(response).await?;
+    Ok(data.SaveMediaListEntry)
 }
 
 impl fmt::Display for MediaList {
@@ -96,73 +901,325 @@ struct MediaListCollectionData {
     MediaListCollection: MediaListCollection,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize)]
 struct MediaListCollectionQueryVariables {
     user_id: i32,
+    status_in: &'static [&'static str],
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct MediaListCollectionMutateVariables {
     id: i32,
-    progress: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    progress: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repeat: Option<i32>,
+    #[serde(rename = "scoreRaw", skip_serializing_if = "Option::is_none")]
+    score_raw: Option<i32>,
+}
+
+/// Result of matching a Plex title against the watching list.
+#[derive(Debug)]
+pub enum TitleMatch<'a> {
+    Found(&'a MediaList),
+    /// Several entries matched equally well, so picking one would be a guess.
+    Ambiguous(Vec<&'a MediaList>),
+    NotFound,
+}
+
+impl TitleMatch<'_> {
+    /// Pick the only ambiguous candidate that aired in the given year, if there is one.
+    pub fn narrow_by_year(self: Self, year: i32) -> Self {
+        let candidates = match self {
+            TitleMatch::Ambiguous(candidates) => candidates,
+            title_match => return title_match,
+        };
+        let same_year: Vec<&MediaList> = candidates
+            .iter()
+            .copied()
+            .filter(|x| x.media.season_year == Some(year))
+            .collect();
+        if same_year.len() == 1 {
+            return TitleMatch::Found(same_year[0]);
+        }
+        return TitleMatch::Ambiguous(candidates);
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct MediaListGroup {
+    #[serde(deserialize_with = "lenient_entries")]
     entries: Vec<MediaList>,
+    #[serde(skip)]
+    index: OnceLock<Arc<TitleIndex>>,
+}
+
+/// Log a schema problem, unless the same problem has been logged before.
+fn warn_once(message: String) {
+    let mut warnings = SCHEMA_WARNINGS
+        .get_or_init(|| Mutex::new(HashSet::new()))
+        .lock()
+        .unwrap();
+    if warnings.insert(message.clone()) {
+        warn!("{}", message);
+    }
+}
+
+/// Deserialize a null value the same as a missing one.
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    return Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default());
+}
+
+/// Deserialize list entries, skipping the ones that cannot be parsed instead of
+/// failing the whole list.
+fn lenient_entries<'de, D>(deserializer: D) -> Result<Vec<MediaList>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let entries = Vec::<serde_json::Value>::deserialize(deserializer)?;
+    return Ok(entries
+        .into_iter()
+        .filter_map(|x| match serde_json::from_value(x) {
+            Ok(entry) => Some(entry),
+            Err(error) => {
+                warn_once(format!(
+                    "Skipping Anilist list entries that could not be parsed ({}), the Anilist API may have changed",
+                    error
+                ));
+                None
+            }
+        })
+        .collect());
 }
 
 impl MediaListGroup {
     pub fn find_id(self: &Self, id: &i32) -> Option<&MediaList> {
         debug!("Matching ID \"{}\"", &id);
-        for media_list in self.entries.iter() {
-            if &media_list.id == id {
-                return Some(media_list);
-            }
+        return self.entries.iter().find(|media_list| &media_list.id == id);
+    }
+
+    /// Find the entry for an Anilist media ID, as opposed to a list entry ID.
+    pub fn find_media_id(self: &Self, id: &i32) -> Option<&MediaList> {
+        return self
+            .entries
+            .iter()
+            .find(|media_list| &media_list.media.id == id);
+    }
+
+    /// Fuzzy match score of every entry that matches at all. The minimum confidence of
+    /// each entry decides whether its titles are also compared without suffixes. Large
+    /// lists are scored in parallel to keep webhook latency flat.
+    fn score_entries<'a>(
+        self: &'a Self,
+        searches: &[SearchTitle],
+        minimum_confidence: &impl Fn(&MediaList) -> f64,
+    ) -> Vec<(TitleScore, &'a MediaList)> {
+        let titles = &self.index().titles;
+        let minimum_confidences: Vec<f64> = self.entries.iter().map(minimum_confidence).collect();
+        let threads = std::thread::available_parallelism().map_or(1, |x| x.get());
+        if self.entries.len() < PARALLEL_MATCHING_THRESHOLD || threads == 1 {
+            return score_chunk(&self.entries, titles, &minimum_confidences, searches);
         }
-        return None;
+        let chunk_size = self.entries.len().div_ceil(threads);
+        return off_worker(|| {
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = self
+                    .entries
+                    .chunks(chunk_size)
+                    .zip(titles.chunks(chunk_size))
+                    .zip(minimum_confidences.chunks(chunk_size))
+                    .map(|((entries, titles), minimum_confidences)| {
+                        scope.spawn(move || {
+                            score_chunk(entries, titles, minimum_confidences, searches)
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .flat_map(|handle| handle.join().unwrap())
+                    .collect()
+            })
+        });
     }
 
-    pub fn find_match(self: &Self, title: &String) -> Option<&MediaList> {
-        let match_title = title.to_lowercase();
-        debug!("Matching title \"{}\"", &match_title);
-        let mut best_match: (f64, Option<&MediaList>) = (0.0, None);
-        for media_list in self.entries.iter() {
-            let confidence = media_list.media.title.find_match(&match_title);
-            if confidence == 1.0 {
-                info!(
+    /// Best fuzzy match candidates for a title with their scores, for debugging. The
+    /// scores are the same as when matching with the given minimum confidences.
+    pub fn candidates(
+        self: &Self,
+        title: &str,
+        count: usize,
+        transliterate: bool,
+        minimum_confidence: impl Fn(&MediaList) -> f64,
+    ) -> Vec<MatchCandidate> {
+        let searches = search_titles(title, transliterate);
+        let mut candidates = self.score_entries(&searches, &minimum_confidence);
+        candidates.sort_by(|a, b| b.0.confidence.total_cmp(&a.0.confidence));
+        return candidates
+            .into_iter()
+            .take(count)
+            .map(|(score, media_list)| MatchCandidate {
+                anilist_id: media_list.id,
+                title: media_list.media.title.to_string(),
+                confidence: score.confidence,
+                variant: score.variant,
+                massaged: score.massaged,
+                transliterated: score.transliterated,
+            })
+            .collect();
+    }
+
+    /// Match a title using the given minimum confidence for each entry. The minimum of
+    /// the best matching entry decides whether it is considered a match. Returns the
+    /// confidence of the best matching entry along with the match, which is full
+    /// confidence for exact matches.
+    pub fn find_scored_match(
+        self: &Self,
+        title: &String,
+        transliterate: bool,
+        minimum_confidence: impl Fn(&MediaList) -> f64,
+    ) -> (TitleMatch<'_>, Option<f64>) {
+        let searches = search_titles(title, transliterate);
+        // Exact matches are cheap to find and always win, so skip fuzzy matching for them.
+        let exact: Vec<&MediaList> = match searches
+            .iter()
+            .find_map(|search| self.index().exact.get(&search.title))
+        {
+            Some(positions) => positions.iter().map(|x| &self.entries[*x]).collect(),
+            None => Vec::new(),
+        };
+        match exact.len() {
+            0 => {}
+            1 => {
+                debug!(
                     "{} was an exact match for {:?}",
-                    media_list.media.title, title
+                    exact[0].media.title, title
                 );
-                return Some(media_list);
-            }
-            if confidence > best_match.0 {
-                best_match = (confidence, Some(media_list));
+                return (TitleMatch::Found(exact[0]), Some(1.0));
             }
+            _ => return (TitleMatch::Ambiguous(exact), Some(1.0)),
         }
-        if let Some(media_list) = best_match.1 {
+        let mut candidates: Vec<(f64, &MediaList)> = self
+            .score_entries(&searches, &minimum_confidence)
+            .into_iter()
+            .map(|(score, media_list)| (score.confidence, media_list))
+            .collect();
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let (best_confidence, best_match) = match candidates.first() {
+            Some(candidate) => *candidate,
+            None => return (TitleMatch::NotFound, None),
+        };
+        let minimum_confidence = minimum_confidence(best_match);
+        if best_confidence < minimum_confidence {
             info!(
                 "{} was the best match for \"{}\" ({})",
-                media_list.media.title, title, best_match.0
+                best_match.media.title, title, best_confidence
+            );
+            return (TitleMatch::NotFound, Some(best_confidence));
+        }
+        let ambiguous: Vec<&MediaList> = candidates
+            .iter()
+            .filter(|(confidence, _)| best_confidence - confidence <= AMBIGUITY_MARGIN)
+            .map(|(_, media_list)| *media_list)
+            .collect();
+        if ambiguous.len() > 1 {
+            return (TitleMatch::Ambiguous(ambiguous), Some(best_confidence));
+        }
+        if best_confidence < minimum_confidence + BORDERLINE_MARGIN {
+            let runner_up = match candidates.get(1) {
+                Some((confidence, media_list)) => {
+                    format!("{} ({})", media_list.media.title, confidence)
+                }
+                None => String::from("none"),
+            };
+            warn!(
+                "{} was a borderline match for \"{}\" ({}), next best: {}",
+                best_match.media.title, title, best_confidence, runner_up
+            );
+        } else {
+            info!(
+                "{} was the best match for \"{}\" ({})",
+                best_match.media.title, title, best_confidence
             );
-            if best_match.0 >= MINIMUM_CONFIDENCE {
-                return Some(media_list);
-            }
         }
-        return None;
+        return (TitleMatch::Found(best_match), Some(best_confidence));
+    }
+
+    /// Set the progress of an entry after updating it on Anilist. Returns whether the
+    /// entry is in the group.
+    pub fn set_progress(self: &mut Self, id: i32, progress: i32) -> bool {
+        return match self.entries.iter_mut().find(|x| x.id == id) {
+            Some(media_list) => {
+                media_list.progress = progress;
+                true
+            }
+            None => false,
+        };
+    }
+
+    /// Group containing only the single-episode movie entries.
+    pub fn movies(self: &Self) -> Self {
+        let entries = self
+            .entries
+            .iter()
+            .filter(|x| x.media.format.as_deref() == Some("MOVIE"))
+            .filter(|x| x.media.episodes.unwrap_or(1) == 1)
+            .cloned()
+            .collect();
+        return Self::new(entries);
+    }
+
+    pub fn iter(self: &Self) -> impl Iterator<Item = &MediaList> {
+        return self.entries.iter();
     }
 
-    pub fn empty() -> Self {
+    pub fn media_ids<'a>(self: &'a Self) -> impl Iterator<Item = i32> + 'a {
+        return self.entries.iter().map(|x| x.media.id);
+    }
+
+    pub fn new(entries: Vec<MediaList>) -> Self {
         Self {
-            entries: Vec::new(),
+            entries,
+            index: OnceLock::new(),
         }
     }
 
-    pub fn get_context_values<'a>(self: &'a Self) -> impl Iterator<Item = (i32, String)> + 'a {
+    /// Normalized title index, built on first use.
+    fn index(self: &Self) -> &Arc<TitleIndex> {
+        return self
+            .index
+            .get_or_init(|| Arc::new(TitleIndex::build(&self.entries)));
+    }
+
+    /// Normalized title index for caching it between refreshes of the list.
+    pub fn title_index(self: &Self) -> Arc<TitleIndex> {
+        return self.index().clone();
+    }
+
+    /// Use a title index built for an earlier copy of the list instead of building
+    /// it again. Returns false if the titles of the list have changed since.
+    pub fn use_title_index(self: &Self, index: Arc<TitleIndex>) -> bool {
+        if index.fingerprint != title_fingerprint(&self.entries) {
+            return false;
+        }
+        let _ = self.index.set(index);
+        return true;
+    }
+
+    /// Fingerprint of the titles of the entries, which identifies the title index of
+    /// the list.
+    pub fn title_fingerprint(self: &Self) -> u64 {
+        return title_fingerprint(&self.entries);
+    }
+
+    pub fn get_context_values<'a>(self: &'a Self) -> impl Iterator<Item = (i32, String, i32)> + 'a {
         return self
             .entries
             .iter()
-            .map(|x| (x.id, x.media.title.userPreferred.clone()));
+            .map(|x| (x.id, x.media.title.userPreferred.clone(), x.progress));
     }
 }
 
@@ -175,180 +1232,718 @@ pub struct MediaTitle {
     userPreferred: String,
 }
 
+/// Confidence of a title match and where it came from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TitleScore {
+    confidence: f64,
+    variant: &'static str,
+    massaged: bool,
+    transliterated: bool,
+}
+
+/// Fuzzy match candidate for a title, explaining which title variant produced the
+/// confidence.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MatchCandidate {
+    pub anilist_id: i32,
+    pub title: String,
+    pub confidence: f64,
+    /// Title variant that matched best: romaji, english, native or synonym.
+    pub variant: &'static str,
+    /// Whether the confidence came from comparing the titles without season, part and
+    /// year suffixes.
+    pub massaged: bool,
+    /// Whether the confidence came from the romaji transliteration of a Japanese title.
+    pub transliterated: bool,
+}
+
+/// Fuzzy match score of every entry of a chunk of the list that matches at all.
+fn score_chunk<'a>(
+    entries: &'a [MediaList],
+    titles: &[Vec<NormalizedTitle>],
+    minimum_confidences: &[f64],
+    searches: &[SearchTitle],
+) -> Vec<(TitleScore, &'a MediaList)> {
+    return entries
+        .iter()
+        .zip(titles)
+        .zip(minimum_confidences)
+        .filter_map(|((media_list, titles), minimum_confidence)| {
+            let score = searches
+                .iter()
+                .map(|search| score_titles(titles, search, *minimum_confidence))
+                .reduce(|a, b| if b.confidence > a.confidence { b } else { a })?;
+            Some((score, media_list))
+        })
+        .filter(|(score, _)| score.confidence > 0.0)
+        .collect();
+}
+
+/// Run blocking work without holding up the other tasks of the async worker, which
+/// the multi-threaded runtime hands over to another worker in the meantime. Outside
+/// of one, e.g. in synchronous tests, the work runs as is.
+fn off_worker<T>(work: impl FnOnce() -> T) -> T {
+    return match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(work)
+        }
+        _ => work(),
+    };
+}
+
 fn remove_special_surrounding_characters(value: &str) -> &str {
-    let mut start_pos = 0;
-    let mut end_pos = 0;
-    for (pos, chr) in value.char_indices() {
-        start_pos = pos;
-        if chr.is_alphanumeric() || chr == '(' {
-            break;
+    let start_pos = value.find(|chr: char| chr.is_alphanumeric() || chr == '(');
+    let end_pos = value
+        .char_indices()
+        .rev()
+        .find(|(_, chr)| chr.is_alphanumeric() || *chr == ')')
+        .map(|(pos, chr)| pos + chr.len_utf8());
+    return match (start_pos, end_pos) {
+        (Some(start_pos), Some(end_pos)) if start_pos < end_pos => &value[start_pos..end_pos],
+        // Nothing to keep, e.g. a title of only symbols.
+        _ => value,
+    };
+}
+
+/// Remove the season, part and year suffixes that often differ between Plex and
+/// Anilist titles, and the special characters around the title.
+fn massage_title(title: &str) -> String {
+    let massaging_regexes = MASSAGING_REGEXES.get_or_init(|| {
+        [
+            Regex::new(r" \(?20[2-4]\d\)?$").unwrap(), // XXX (2023)
+            Regex::new(r" \d+(st|nd|rd|th) season$").unwrap(), // XXX 2nd Season
+            Regex::new(r" \(?cour \d\)?$").unwrap(),   // XXX Cour 2, XXX (Cour 2)
+            Regex::new(r" \(?season \d\)?$").unwrap(), // XXX Season 2, XXX (Season 2)
+            Regex::new(r" \(?part \d\)?$").unwrap(),   // XXX Part 2, XXX (Part 2)
+            Regex::new(r" \d$").unwrap(),              // XXX 2
+        ]
+    });
+    let massaged_title = remove_regexes(massaging_regexes, title);
+    return remove_special_surrounding_characters(&massaged_title).to_string();
+}
+
+/// Title to match as normalized for matching, and as transliterated to romaji if it
+/// is written in kana.
+fn search_titles(title: &str, transliterate: bool) -> Vec<SearchTitle> {
+    let title = normalize_title(title);
+    debug!("Matching title \"{}\"", &title);
+    let mut searches = vec![SearchTitle::new(title, false)];
+    if transliterate {
+        if let Some(romaji) = romaji::transliterate(&searches[0].title) {
+            debug!("Matching transliterated title \"{}\"", &romaji);
+            searches.push(SearchTitle::new(romaji, true));
         }
     }
-    for (pos, chr) in value.char_indices().rev() {
-        end_pos = pos;
-        if chr.is_alphanumeric() || chr == ')' {
-            break;
+    return searches;
+}
+
+/// Title being matched, normalized and massaged once for all entries.
+#[derive(Clone, Debug)]
+struct SearchTitle {
+    title: String,
+    massaged: String,
+    transliterated: bool,
+}
+
+impl SearchTitle {
+    fn new(title: String, transliterated: bool) -> Self {
+        Self {
+            massaged: massage_title(&title),
+            title,
+            transliterated,
         }
     }
-    while !value.is_char_boundary(end_pos + 1) {
-        end_pos += 1;
-    }
-    return &value[start_pos..=end_pos];
 }
 
-impl MediaTitle {
-    fn find_match(self: &Self, string: &String) -> f64 {
-        let mut titles: Vec<String> = Vec::new();
-        for title in [&self.romaji, &self.english, &self.native] {
-            if let Some(title) = title {
-                titles.push(title.to_lowercase());
+/// Compatibility decompose and compose a title (NFKC) and lowercase it, so that e.g.
+/// full-width and half-width forms of the same characters compare as equal.
+fn normalize_title(title: &str) -> String {
+    return ComposingNormalizer::new_nfkc()
+        .normalize(title)
+        .to_lowercase();
+}
+
+/// Title variant of an entry, normalized and massaged ahead of matching.
+#[derive(Clone, Debug)]
+struct NormalizedTitle {
+    variant: &'static str,
+    title: String,
+    massaged: String,
+}
+
+/// Normalized titles of the entries of a list, built once per fetched list so that
+/// matching does not lowercase and massage every title on every webhook. The index
+/// can be reused for later copies of the list with the same titles.
+#[derive(Clone, Debug, Default)]
+pub struct TitleIndex {
+    /// Fingerprint of the titles that the index was built from.
+    fingerprint: u64,
+    /// Normalized title variants of each entry, in the order of the entries.
+    titles: Vec<Vec<NormalizedTitle>>,
+    /// Positions of the entries by their lowercased title variants.
+    exact: HashMap<String, Vec<usize>>,
+}
+
+impl TitleIndex {
+    pub fn fingerprint(self: &Self) -> u64 {
+        return self.fingerprint;
+    }
+
+    fn build(entries: &[MediaList]) -> Self {
+        let mut index = Self {
+            fingerprint: title_fingerprint(entries),
+            ..Default::default()
+        };
+        for (position, media_list) in entries.iter().enumerate() {
+            let title = &media_list.media.title;
+            let variants = [
+                ("romaji", &title.romaji),
+                ("english", &title.english),
+                ("native", &title.native),
+            ]
+            .into_iter()
+            .filter_map(|(variant, title)| Some((variant, title.as_ref()?)))
+            .chain(
+                media_list
+                    .media
+                    .synonyms
+                    .iter()
+                    .map(|synonym| ("synonym", synonym)),
+            );
+            let mut titles = Vec::new();
+            for (variant, title) in variants {
+                let title = normalize_title(title);
+                let positions = index.exact.entry(title.clone()).or_default();
+                if positions.last() != Some(&position) {
+                    positions.push(position);
+                }
+                titles.push(NormalizedTitle {
+                    variant,
+                    massaged: massage_title(&title),
+                    title,
+                });
             }
+            index.titles.push(titles);
         }
+        return index;
+    }
+}
 
-        // Try an exact match first..
-        for title in titles.iter() {
-            if title == string {
-                return 1.0;
-            }
+/// Hash of the title variants of the entries in order, since the index refers to the
+/// entries by their position.
+fn title_fingerprint(entries: &[MediaList]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for media_list in entries {
+        let media = &media_list.media;
+        media.id.hash(&mut hasher);
+        media.title.romaji.hash(&mut hasher);
+        media.title.english.hash(&mut hasher);
+        media.title.native.hash(&mut hasher);
+        media.synonyms.hash(&mut hasher);
+    }
+    return hasher.finish();
+}
+
+/// Score the normalized title variants of an entry against a lowercased title. Titles
+/// are only compared without suffixes if the regular comparison does not reach the
+/// minimum confidence of the entry.
+fn score_titles(
+    titles: &[NormalizedTitle],
+    search: &SearchTitle,
+    minimum_confidence: f64,
+) -> TitleScore {
+    let string = search.title.as_str();
+    let massaged_string = search.massaged.as_str();
+    // Try an exact match first..
+    for title in titles.iter() {
+        if title.title == string {
+            return TitleScore {
+                confidence: 1.0,
+                variant: title.variant,
+                massaged: false,
+                transliterated: search.transliterated,
+            };
         }
+    }
 
-        let mut best_match: f64 = 0.0;
+    let mut best_match = TitleScore {
+        confidence: 0.0,
+        variant: "",
+        massaged: false,
+        transliterated: search.transliterated,
+    };
 
-        // Regular case insensitive Levenshtein-based fuzzy matching.
-        for title in titles.iter() {
-            let confidence = normalized_levenshtein(string, &title);
-            debug!("~ {} = {}", &title, &confidence);
-            if confidence > best_match {
-                best_match = confidence;
-            }
+    // Regular case insensitive Levenshtein-based fuzzy matching.
+    for title in titles.iter() {
+        let confidence = normalized_levenshtein(string, &title.title);
+        debug!("~ {} = {}", &title.title, &confidence);
+        if confidence > best_match.confidence {
+            best_match = TitleScore {
+                confidence,
+                variant: title.variant,
+                massaged: false,
+                transliterated: search.transliterated,
+            };
         }
+    }
+
+    if best_match.confidence >= minimum_confidence {
+        return best_match;
+    }
 
-        if best_match >= MINIMUM_CONFIDENCE {
-            return best_match;
+    // Levenshtein distance with cleaned up comparison to get rid of common
+    // suffixes that might alter between AniDB and local libraries.
+    for title in titles.iter() {
+        let confidence = (normalized_levenshtein(massaged_string, &title.massaged) - 0.05).max(0.0);
+        debug!("~ {} = {}", &title.massaged, &confidence);
+        if confidence > best_match.confidence {
+            best_match = TitleScore {
+                confidence,
+                variant: title.variant,
+                massaged: true,
+                transliterated: search.transliterated,
+            };
         }
+    }
 
-        // Levenshtein distance with cleaned up comparison to get rid of common
-        // suffixes that might alter between AniDB and local libraries.
-        let massaging_regexes = [
-            Regex::new(r" \(?20[2-4]\d\)?$").unwrap(), // XXX (2023)
-            Regex::new(r" \d+(st|nd|rd|th) season$").unwrap(), // XXX 2nd Season
-            Regex::new(r" \(?cour \d\)?$").unwrap(),   // XXX Cour 2, XXX (Cour 2)
-            Regex::new(r" \(?season \d\)?$").unwrap(), // XXX Season 2, XXX (Season 2)
-            Regex::new(r" \(?part \d\)?$").unwrap(),   // XXX Part 2, XXX (Part 2)
-            Regex::new(r" \d$").unwrap(),              // XXX 2
-        ];
-        let massaged_string = remove_regexes(&massaging_regexes, string);
-        let massaged_string = remove_special_surrounding_characters(&massaged_string);
-        debug!("Matching fallback title \"{}\"", &massaged_string);
-        for title in titles.iter() {
-            let massaged_title = remove_regexes(&massaging_regexes, &title);
-            let massaged_title = remove_special_surrounding_characters(&massaged_title);
-            let confidence =
-                (normalized_levenshtein(&massaged_string, &massaged_title) - 0.05).max(0.0);
-            debug!("~ {} = {}", &massaged_title, &confidence);
-            if confidence > best_match {
-                best_match = confidence;
+    return best_match;
+}
+
+impl fmt::Display for MediaTitle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.userPreferred)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Query<'a, T> {
+    query: &'a str,
+    variables: Option<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueryResponse<T> {
+    data: T,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    errors: Option<Vec<Error>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Error {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SaveMediaListEntry {
+    progress: i32,
+    score: Option<f64>,
+    /// Whether the mutation was not sent because the progress was already past it.
+    #[serde(skip)]
+    already_counted: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct MediaListProgress {
+    progress: i32,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+struct MediaListProgressData {
+    MediaList: MediaListProgress,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+struct SaveMediaListEntryData {
+    SaveMediaListEntry: SaveMediaListEntry,
+}
+
+impl<T> QueryResponse<T> {
+    async fn parse(response: reqwest::Response) -> Result<T, AnilistError>
+    where
+        T: for<'a> Deserialize<'a>,
+    {
+        let status_code = response.status();
+        let response_body = response
+            .text()
+            .await
+            .map_err(|_| AnilistError::RequestDataError)?;
+        if status_code == 400 {
+            if let Ok(error_response) = serde_json::from_str::<ErrorResponse>(&response_body) {
+                for error in error_response.errors.iter().flatten() {
+                    if error.message == "Invalid token" {
+                        return Err(AnilistError::InvalidToken);
+                    }
+                }
             }
         }
+        let query_response: QueryResponse<T> = match serde_json::from_str(&response_body) {
+            Ok(response) => response,
+            Err(error) => {
+                debug!("{}", &response_body);
+                debug!("{}", error);
+                // Queries for fields that no longer exist are rejected with an error.
+                if let Ok(error_response) = serde_json::from_str::<ErrorResponse>(&response_body) {
+                    for error in error_response.errors.iter().flatten() {
+                        warn_once(format!("Anilist rejected a query: {}", error.message));
+                    }
+                }
+                return Err(AnilistError::ParsingError);
+            }
+        };
+        return Ok(query_response.data);
+    }
+}
 
-        return best_match;
+#[allow(non_snake_case)]
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct User {
+    pub id: i32,
+    pub name: String,
+    pub avatar: Option<UserAvatar>,
+    pub options: Option<UserOptions>,
+    pub mediaListOptions: Option<MediaListOptions>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct UserAvatar {
+    pub large: Option<String>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct UserOptions {
+    pub profileColor: Option<String>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct MediaListOptions {
+    pub scoreFormat: Option<String>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+struct ViewerData {
+    Viewer: User,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenClaims {
+    exp: Option<u64>,
+}
+
+/// Expiry time of an Anilist API token in seconds since the Unix epoch. Anilist
+/// tokens are JWTs, so this only requires decoding the claims.
+pub fn token_expiry(token: &str) -> Option<u64> {
+    let claims = token.split('.').nth(1)?;
+    let claims = URL_SAFE_NO_PAD.decode(claims.trim_end_matches('=')).ok()?;
+    let claims: TokenClaims = serde_json::from_slice(&claims).ok()?;
+    return claims.exp;
+}
+
+#[derive(Debug, Deserialize)]
+struct RelatedMedia {
+    id: i32,
+    r#type: Option<String>,
+    format: Option<String>,
+    episodes: Option<i32>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+struct MediaRelationEdge {
+    relationType: Option<String>,
+    node: RelatedMedia,
+}
+
+#[derive(Debug, Deserialize)]
+struct MediaRelationConnection {
+    edges: Vec<MediaRelationEdge>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MediaRelations {
+    relations: MediaRelationConnection,
+}
+
+impl MediaRelations {
+    /// Previous season of the show.
+    fn prequel(self: &Self) -> Option<&RelatedMedia> {
+        return self
+            .relations
+            .edges
+            .iter()
+            .filter(|x| x.relationType.as_deref() == Some("PREQUEL"))
+            .map(|x| &x.node)
+            .find(|x| {
+                x.r#type.as_deref() == Some("ANIME")
+                    && SEASON_FORMATS.contains(&x.format.as_deref().unwrap_or_default())
+            });
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+struct MediaRelationsData {
+    Media: MediaRelations,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MediaQueryVariables {
+    id: i32,
+}
+
+/// Position of a media in the chain of seasons of its franchise.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FranchisePosition {
+    /// Media ID of the first season.
+    pub root: i32,
+    /// Number of episodes in the preceding seasons.
+    pub offset: i32,
+}
+
+/// Franchise positions of media, for mapping absolute episode numbers to seasons.
+#[derive(Debug, Default)]
+pub struct Relations {
+    inner: HashMap<i32, FranchisePosition>,
+}
+
+impl Relations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(self: &Self) -> usize {
+        return self.inner.len();
+    }
+
+    pub fn set(self: &mut Self, media_id: i32, position: FranchisePosition) {
+        self.inner.insert(media_id, position);
+    }
+
+    /// Find the watching list entry and its episode number for an absolute episode
+    /// number of the franchise that the matched entry belongs to.
+    pub fn resolve<'a>(
+        self: &Self,
+        media_list_group: &'a MediaListGroup,
+        media_list: &MediaList,
+        episode: i32,
+    ) -> Option<(&'a MediaList, i32)> {
+        let root = self.inner.get(&media_list.media.id)?.root;
+        return media_list_group
+            .entries
+            .iter()
+            .filter_map(|x| match self.inner.get(&x.media.id) {
+                Some(position) if position.root == root => Some((x, position.offset)),
+                _ => None,
+            })
+            .find(|(x, offset)| {
+                episode > *offset && x.media.episodes.is_none_or(|e| episode <= offset + e)
+            })
+            .map(|(x, offset)| (x, episode - offset));
     }
 }
 
-impl fmt::Display for MediaTitle {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.userPreferred)
+/// Follow the prequels of a media to find its position in the franchise. Returns
+/// None if a prequel has an unknown number of episodes.
+pub async fn get_franchise_position(
+    api: &AnilistApi,
+    token: &String,
+    media_id: i32,
+) -> Result<Option<FranchisePosition>, AnilistError> {
+    let mut root = media_id;
+    let mut offset = 0;
+    for _ in 0..MAX_PREQUELS {
+        let query = Query::<MediaQueryVariables> {
+            query: MEDIA_RELATIONS_QUERY,
+            variables: Some(MediaQueryVariables { id: root }),
+        };
+        let response = send_query(api, token, query).await?;
+        let data = QueryResponse::<MediaRelationsData>::parse(response).await?;
+        let prequel = match data.Media.prequel() {
+            Some(prequel) => prequel,
+            None => return Ok(Some(FranchisePosition { root, offset })),
+        };
+        match prequel.episodes {
+            Some(episodes) => offset += episodes,
+            None => return Ok(None),
+        }
+        root = prequel.id;
     }
+    return Ok(None);
 }
 
-#[derive(Debug, Serialize)]
-struct Query<'a, T> {
-    query: &'a str,
-    variables: Option<T>,
+#[derive(Clone, Debug, Deserialize)]
+pub struct CoverImage {
+    pub large: Option<String>,
+    /// Average colour of the cover as a hex code.
+    pub color: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
-struct QueryResponse<T> {
-    data: T,
+/// Metadata of a media that is not part of the watching list query.
+#[allow(non_snake_case)]
+#[derive(Clone, Debug, Deserialize)]
+pub struct MediaDetails {
+    pub id: i32,
+    pub format: Option<String>,
+    pub episodes: Option<i32>,
+    pub season: Option<String>,
+    pub seasonYear: Option<i32>,
+    /// Airing status, e.g. RELEASING or FINISHED.
+    pub status: Option<String>,
+    pub coverImage: Option<CoverImage>,
+    pub title: MediaTitle,
 }
 
+#[allow(non_snake_case)]
 #[derive(Debug, Deserialize)]
-struct ErrorResponse {
-    errors: Option<Vec<Error>>,
+struct MediaDetailsData {
+    Media: Option<MediaDetails>,
 }
 
-#[derive(Debug, Deserialize)]
-struct Error {
-    message: String,
+/// Fetch the metadata of a media. Returns None if Anilist has no media with the ID.
+pub async fn get_media_details(
+    api: &AnilistApi,
+    token: &String,
+    id: i32,
+) -> Result<Option<MediaDetails>, AnilistError> {
+    let query = Query::<MediaQueryVariables> {
+        query: MEDIA_DETAILS_QUERY,
+        variables: Some(MediaQueryVariables { id }),
+    };
+    let response = send_query(api, token, query).await?;
+    let data = QueryResponse::<MediaDetailsData>::parse(response).await?;
+    return Ok(data.Media);
 }
 
 #[derive(Debug, Deserialize)]
-struct SaveMediaListEntry {
-    progress: i32,
+struct MediaPage {
+    media: Vec<Media>,
 }
 
 #[allow(non_snake_case)]
 #[derive(Debug, Deserialize)]
-struct SaveMediaListEntryData {
-    SaveMediaListEntry: SaveMediaListEntry,
+struct MediaSearchData {
+    Page: MediaPage,
 }
 
-impl<T> QueryResponse<T> {
-    async fn parse(response: reqwest::Response) -> Result<T, AnilistError>
-    where
-        T: for<'a> Deserialize<'a>,
-    {
-        let status_code = response.status();
-        let response_body = response
-            .text()
-            .await
-            .map_err(|_| AnilistError::RequestDataError)?;
-        if status_code == 400 {
-            if let Ok(error_response) = serde_json::from_str::<ErrorResponse>(&response_body) {
-                for error in error_response.errors.iter().flatten() {
-                    if error.message == "Invalid token" {
-                        return Err(AnilistError::InvalidToken);
-                    }
-                }
-            }
-        }
-        let query_response: QueryResponse<T> = match serde_json::from_str(&response_body) {
-            Ok(response) => response,
-            Err(error) => {
-                debug!("{}", &response_body);
-                debug!("{}", error);
-                return Err(AnilistError::ParsingError);
-            }
-        };
-        return Ok(query_response.data);
-    }
+#[derive(Debug, Serialize, Deserialize)]
+struct MediaSearchQueryVariables {
+    search: String,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct User {
-    pub id: i32,
-    pub name: String,
+/// Search Anilist for anime by title, best matches first.
+pub async fn search_media(
+    api: &AnilistApi,
+    token: &String,
+    search: &str,
+) -> Result<Vec<Media>, AnilistError> {
+    let query = Query::<MediaSearchQueryVariables> {
+        query: MEDIA_SEARCH_QUERY,
+        variables: Some(MediaSearchQueryVariables {
+            search: search.to_string(),
+        }),
+    };
+    let response = send_query(api, token, query).await?;
+    let data = QueryResponse::<MediaSearchData>::parse(response).await?;
+    return Ok(data.Page.media);
 }
 
-#[allow(non_snake_case)]
-#[derive(Debug, Deserialize)]
-struct ViewerData {
-    Viewer: User,
+/// Remove parts of a given string using a collection of regular expressions.
+fn remove_regexes(regexes: &[Regex], string: &str) -> String {
+    return regexes.iter().fold(string.to_string(), |s, regex| {
+        regex.replace(&s, "").to_string()
+    });
 }
 
-/// Remove parts of a given string using a collection of regular expressions.
-fn remove_regexes(regexes: &[Regex], string: &String) -> String {
-    return regexes
-        .iter()
-        .fold(string.clone(), |s, regex| regex.replace(&s, "").to_string());
+/// Anilist API client registered by the user, for authorizing anifunnel without
+/// copying a token by hand.
+#[derive(Debug)]
+pub struct OAuthClient {
+    pub id: String,
+    pub secret: String,
+    /// Callback URL, which must match the redirect URL of the API client exactly.
+    pub redirect_url: String,
+}
+
+#[derive(Serialize)]
+struct OAuthTokenRequest<'a> {
+    grant_type: &'static str,
+    client_id: &'a str,
+    client_secret: &'a str,
+    redirect_uri: &'a str,
+    code: &'a str,
+}
+
+#[derive(Deserialize)]
+struct OAuthTokenResponse {
+    access_token: String,
+}
+
+impl OAuthClient {
+    /// Anilist URL that asks the user to authorize anifunnel. The state is passed back
+    /// to the callback unchanged.
+    pub fn authorize_url(self: &Self, state: &str) -> String {
+        return reqwest::Url::parse_with_params(
+            &format!("{}authorize", OAUTH_URL),
+            [
+                ("client_id", self.id.as_str()),
+                ("redirect_uri", self.redirect_url.as_str()),
+                ("response_type", "code"),
+                ("state", state),
+            ],
+        )
+        .unwrap()
+        .to_string();
+    }
+
+    /// Exchange an authorization code from the callback for an access token.
+    pub async fn exchange_code(
+        self: &Self,
+        api: &AnilistApi,
+        code: &str,
+    ) -> Result<String, AnilistError> {
+        let body = serde_json::to_string(&OAuthTokenRequest {
+            grant_type: "authorization_code",
+            client_id: &self.id,
+            client_secret: &self.secret,
+            redirect_uri: &self.redirect_url,
+            code,
+        })
+        .map_err(|_| AnilistError::RequestDataError)?;
+        let response = reqwest::Client::new()
+            .post(format!("{}token", OAUTH_URL))
+            .header("Content-Type", "application/json")
+            .header("Accept", "application/json")
+            .header("User-Agent", &api.user_agent)
+            .body(body)
+            .send()
+            .await
+            .map_err(|_| AnilistError::ConnectionError)?;
+        if response.status().is_client_error() {
+            return Err(AnilistError::InvalidToken);
+        }
+        let response: OAuthTokenResponse = response
+            .error_for_status()
+            .map_err(|_| AnilistError::ConnectionError)?
+            .json()
+            .await
+            .map_err(|_| AnilistError::ParsingError)?;
+        return Ok(response.access_token);
+    }
 }
 
-pub async fn get_user(token: &String) -> Result<User, AnilistError> {
+pub async fn get_user(api: &AnilistApi, token: &String) -> Result<User, AnilistError> {
     let query = Query::<()> {
         query: USER_QUERY,
         variables: None,
     };
-    let response = send_query(token, query).await?;
+    let response = send_query(api, token, query).await?;
     let viewer_data = QueryResponse::<ViewerData>::parse(response).await?;
     debug!(
         "Found user {} ({})",
@@ -358,25 +1953,128 @@ pub async fn get_user(token: &String) -> Result<User, AnilistError> {
 }
 
 pub async fn get_watching_list(
+    api: &AnilistApi,
+    token: &String,
+    user: &User,
+) -> Result<MediaListGroup, AnilistError> {
+    return get_media_list(api, token, user, &["CURRENT", "REPEATING"]).await;
+}
+
+/// Get the planning and paused lists, which can be matched when nothing in the
+/// watching list matches.
+pub async fn get_inactive_list(
+    api: &AnilistApi,
+    token: &String,
+    user: &User,
+) -> Result<MediaListGroup, AnilistError> {
+    return get_media_list(api, token, user, &["PLANNING", "PAUSED"]).await;
+}
+
+/// Get every list that a rated show or movie can be in, including completed and
+/// dropped entries.
+pub async fn get_rated_list(
+    api: &AnilistApi,
+    token: &String,
+    user: &User,
+) -> Result<MediaListGroup, AnilistError> {
+    return get_media_list(
+        api,
+        token,
+        user,
+        &["CURRENT", "REPEATING", "COMPLETED", "PAUSED", "DROPPED"],
+    )
+    .await;
+}
+
+async fn get_media_list(
+    api: &AnilistApi,
     token: &String,
     user: &User,
+    status_in: &'static [&'static str],
 ) -> Result<MediaListGroup, AnilistError> {
-    let variables = MediaListCollectionQueryVariables { user_id: user.id };
+    let variables = MediaListCollectionQueryVariables {
+        user_id: user.id,
+        status_in,
+    };
     let query = Query::<MediaListCollectionQueryVariables> {
         query: MEDIALIST_QUERY,
         variables: Some(variables),
     };
-    let response = send_query(token, query).await?;
+    let response = send_query(api, token, query).await?;
     let media_list_collection_data =
         QueryResponse::<MediaListCollectionData>::parse(response).await?;
-    let mut collected_list = MediaListGroup::empty();
+    let mut entries = Vec::new();
     for mut list in media_list_collection_data.MediaListCollection.lists {
-        collected_list.entries.append(&mut list.entries);
+        entries.append(&mut list.entries);
+    }
+    Ok(MediaListGroup::new(entries))
+}
+
+#[derive(Debug, Deserialize)]
+struct SchemaField {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SchemaType {
+    fields: Option<Vec<SchemaField>>,
+}
+
+/// Introspection query for the fields of every type in EXPECTED_FIELDS, aliased by
+/// their position.
+fn schema_probe_query() -> String {
+    let types: Vec<String> = EXPECTED_FIELDS
+        .iter()
+        .enumerate()
+        .map(|(index, (name, _))| {
+            format!(
+                "t{}: __type(name: \"{}\") {{ fields {{ name }} }}",
+                index, name
+            )
+        })
+        .collect();
+    return format!("query {{ {} }}", types.join(" "));
+}
+
+/// Expected fields that are missing from the introspected types, formatted as
+/// `Type.field`, or just `Type` if the whole type is missing.
+fn missing_fields(types: &HashMap<String, Option<SchemaType>>) -> Vec<String> {
+    let mut missing = Vec::new();
+    for (index, (name, fields)) in EXPECTED_FIELDS.iter().enumerate() {
+        let schema_type = match types.get(&format!("t{}", index)) {
+            Some(Some(schema_type)) => schema_type,
+            _ => {
+                missing.push(name.to_string());
+                continue;
+            }
+        };
+        let existing: HashSet<&str> = schema_type
+            .fields
+            .iter()
+            .flatten()
+            .map(|x| x.name.as_str())
+            .collect();
+        for field in fields.iter().filter(|x| !existing.contains(*x)) {
+            missing.push(format!("{}.{}", name, field));
+        }
     }
-    Ok(collected_list)
+    return missing;
+}
+
+/// Check that the fields anifunnel queries still exist in the Anilist schema.
+pub async fn probe_schema(api: &AnilistApi, token: &String) -> Result<Vec<String>, AnilistError> {
+    let query = schema_probe_query();
+    let query = Query::<()> {
+        query: &query,
+        variables: None,
+    };
+    let response = send_query(api, token, query).await?;
+    let types = QueryResponse::<HashMap<String, Option<SchemaType>>>::parse(response).await?;
+    return Ok(missing_fields(&types));
 }
 
 async fn send_query<T>(
+    api: &AnilistApi,
     token: &String,
     query: Query<'_, T>,
 ) -> Result<reqwest::Response, AnilistError>
@@ -385,15 +2083,104 @@ where
 {
     let body = serde_json::to_string(&query).map_err(|_| AnilistError::RequestDataError)?;
     let client = reqwest::Client::new();
-    return Ok(client
-        .post("https://graphql.anilist.co/")
-        .header("Content-Type", "application/json")
-        .header("Accept", "application/json")
-        .header("Authorization", format!("Bearer {}", token))
-        .body(body)
-        .send()
-        .await
-        .map_err(|_| AnilistError::ConnectionError)?);
+    let traceparent = trace::current().map(|x| x.header());
+    for attempt in 0..=RATE_LIMIT_RETRIES {
+        let mut request = client
+            .post(&api.url)
+            .header("Content-Type", "application/json")
+            .header("Accept", "application/json")
+            .header("User-Agent", &api.user_agent)
+            .header("Authorization", format!("Bearer {}", token));
+        if let Some(traceparent) = &traceparent {
+            request = request.header("traceparent", traceparent);
+        }
+        let response = request
+            .body(body.clone())
+            .send()
+            .await
+            .map_err(|_| AnilistError::ConnectionError)?;
+        if let Some(remaining) = header_value(&response, "X-RateLimit-Remaining") {
+            RATE_LIMIT_REMAINING.store(remaining as i64, Ordering::Relaxed);
+        }
+        if response.status() != reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return Ok(response);
+        }
+        RATE_LIMITED_REQUESTS.fetch_add(1, Ordering::Relaxed);
+        if attempt == RATE_LIMIT_RETRIES {
+            break;
+        }
+        let delay = retry_delay(header_value(&response, "Retry-After"), attempt);
+        warn!(
+            "Anilist rate limit reached, retrying in {} seconds",
+            delay.as_secs()
+        );
+        tokio::time::sleep(delay).await;
+    }
+    warn!("Anilist rate limit retries exhausted");
+    return Err(AnilistError::RateLimited);
+}
+
+/// Parse a numeric header value from a response.
+fn header_value(response: &reqwest::Response, name: &str) -> Option<u64> {
+    return response.headers().get(name)?.to_str().ok()?.parse().ok();
+}
+
+/// Time to wait before retrying a rate limited request. Uses the Retry-After value
+/// when available and falls back to exponential backoff.
+fn retry_delay(retry_after: Option<u64>, attempt: u32) -> Duration {
+    let delay = match retry_after {
+        Some(seconds) => Duration::from_secs(seconds),
+        None => Duration::from_secs(2u64.pow(attempt + 1)),
+    };
+    return delay.min(RATE_LIMIT_MAX_DELAY);
+}
+
+/// Fake Anilist API for tests, which answers each GraphQL request with the response
+/// built from the request body and keeps the bodies in the order they were received.
+#[cfg(test)]
+pub mod fake {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    use super::AnilistApi;
+
+    pub fn serve(
+        respond: impl Fn(&str) -> String + Send + 'static,
+    ) -> (AnilistApi, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut length = 0;
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 && line.trim_end() != "" {
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap_or(0);
+                        }
+                    }
+                    line.clear();
+                }
+                let mut body = vec![0; length];
+                let _ = reader.read_exact(&mut body);
+                let body = String::from_utf8_lossy(&body).to_string();
+                let response = respond(&body);
+                received.lock().unwrap().push(body);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                    Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    response.len(),
+                    response
+                );
+            }
+        });
+        return (AnilistApi::new(&url, "anifunnel", None), requests);
+    }
 }
 
 #[cfg(test)]
@@ -407,17 +2194,162 @@ mod tests {
         return MediaList {
             id,
             progress: 3,
+            status: Some(String::from("CURRENT")),
+            repeat: Some(0),
             media: Media {
+                id,
+                format: Some(String::from("TV")),
+                episodes: Some(12),
+                season_year: Some(2018),
                 title: MediaTitle {
                     romaji: Some(title.clone()),
                     english: Some(title.clone()),
                     native: Some(title.clone()),
                     userPreferred: title.clone(),
                 },
+                synonyms: vec![],
             },
         };
     }
 
+    #[test_case("ＹＵＲＵ　ＣＡＭＰ", Some("yuru camp"), false ; "full-width")]
+    #[test_case("ﾕﾙｷｬﾝ", Some("ユルキャン"), false ; "half-width katakana")]
+    #[test_case("ユルキャン", Some("yurukyan"), true ; "transliterated katakana")]
+    #[test_case("無職転生", None, true ; "kanji is not transliterated")]
+    fn search_titles_normalize(title: &str, expected: Option<&str>, transliterate: bool) {
+        let searches = search_titles(title, transliterate);
+        let expected = expected.map(String::from);
+        if transliterate {
+            assert_eq!(searches.get(1).map(|x| x.title.clone()), expected);
+        } else {
+            assert_eq!(searches.len(), 1);
+            assert_eq!(Some(searches[0].title.clone()), expected);
+        }
+    }
+
+    #[test_case("Laid-Back Camp", Some(1), "synonym" ; "exact synonym")]
+    #[test_case("Laid Back Camps", Some(1), "synonym" ; "fuzzy synonym")]
+    #[test_case("Mushoku Tensei", Some(2), "romaji" ; "exact title")]
+    #[test_case("Shoushimin", None, "" ; "no match")]
+    fn find_match_synonyms(title: &str, expected_id: Option<i32>, variant: &str) {
+        let mut yuru_camp = fake_media_list(1, "Yuru Camp");
+        yuru_camp.media.synonyms = vec![String::from("Laid-Back Camp")];
+        let media_list_group =
+            MediaListGroup::new(vec![yuru_camp, fake_media_list(2, "Mushoku Tensei")]);
+        let title = String::from(title);
+        let matched = match media_list_group.find_match(&title, |_| DEFAULT_MINIMUM_CONFIDENCE) {
+            TitleMatch::Found(media_list) => Some(media_list.media.id),
+            _ => None,
+        };
+        assert_eq!(matched, expected_id);
+        if expected_id.is_some() {
+            assert_eq!(
+                media_list_group.candidates(&title, 1, false, |_| DEFAULT_MINIMUM_CONFIDENCE)[0]
+                    .variant,
+                variant
+            );
+        }
+    }
+
+    #[test]
+    fn find_match_large_list() {
+        let mut entries: Vec<MediaList> = (0..PARALLEL_MATCHING_THRESHOLD as i32 * 2)
+            .map(|id| fake_media_list(id, &format!("Unrelated Anime {}", id)))
+            .collect();
+        entries.push(fake_media_list(1000, "Yuru Camp△"));
+        entries.push(fake_media_list(1001, "Yuru Camp△ Season 2"));
+        let media_list_group = MediaListGroup::new(entries);
+        for title in ["Yuru Camp△ Season 2", "Yuru Camp△ Season2"] {
+            let matched =
+                media_list_group.find_match(&String::from(title), |_| DEFAULT_MINIMUM_CONFIDENCE);
+            assert_eq!(matched.media_list().map(|x| x.id), Some(1001));
+        }
+    }
+
+    #[test_case(false ; "synchronous")]
+    #[test_case(true ; "async worker")]
+    fn score_entries_parallel(on_worker: bool) {
+        let mut entries: Vec<MediaList> = (0..PARALLEL_MATCHING_THRESHOLD as i32 * 3)
+            .map(|id| fake_media_list(id, &format!("Unrelated Anime {}", id % 7)))
+            .collect();
+        entries.push(fake_media_list(1000, "Yuru Camp△ Season 2"));
+        let media_list_group = MediaListGroup::new(entries);
+        let searches = search_titles("Unrelated Anime 3", false);
+        let minimum_confidence = |x: &MediaList| if x.id % 2 == 0 { 0.5 } else { 0.9 };
+        let scored = |scores: Vec<(TitleScore, &MediaList)>| -> Vec<(i32, f64, bool)> {
+            return scores
+                .into_iter()
+                .map(|(score, media_list)| (media_list.id, score.confidence, score.massaged))
+                .collect();
+        };
+        let parallel = match on_worker {
+            true => rocket::async_run(
+                async { scored(media_list_group.score_entries(&searches, &minimum_confidence)) },
+                2,
+                1,
+                true,
+                "score_entries_parallel",
+            ),
+            false => scored(media_list_group.score_entries(&searches, &minimum_confidence)),
+        };
+        let minimum_confidences: Vec<f64> = media_list_group
+            .entries
+            .iter()
+            .map(minimum_confidence)
+            .collect();
+        let sequential = scored(score_chunk(
+            &media_list_group.entries,
+            &media_list_group.index().titles,
+            &minimum_confidences,
+            &searches,
+        ));
+        assert!(!sequential.is_empty());
+        assert_eq!(parallel, sequential);
+    }
+
+    #[test]
+    fn use_title_index() {
+        let media_list_group = MediaListGroup::new(vec![fake_media_list(1, "Yuru Camp△")]);
+        let index = media_list_group.title_index();
+        let mut refreshed = MediaListGroup::new(vec![fake_media_list(1, "Yuru Camp△")]);
+        refreshed.set_progress(1, 4);
+        assert!(refreshed.use_title_index(index.clone()));
+        assert!(Arc::ptr_eq(&refreshed.title_index(), &index));
+        let renamed = MediaListGroup::new(vec![fake_media_list(1, "Yuru Camp")]);
+        assert!(!renamed.use_title_index(index));
+        assert!(renamed
+            .find_match(&String::from("Yuru Camp"), |_| 0.8)
+            .media_list()
+            .is_some());
+    }
+
+    #[test]
+    fn set_progress() {
+        let mut media_list_group = MediaListGroup::new(vec![fake_media_list(1, "Yuru Camp△")]);
+        assert!(media_list_group.set_progress(1, 4));
+        assert!(!media_list_group.set_progress(2, 4));
+        assert_eq!(media_list_group.find_id(&1).unwrap().progress, 4);
+    }
+
+    impl MediaListGroup {
+        fn find_match(
+            self: &Self,
+            title: &String,
+            minimum_confidence: impl Fn(&MediaList) -> f64,
+        ) -> TitleMatch<'_> {
+            return self.find_scored_match(title, false, minimum_confidence).0;
+        }
+    }
+
+    impl<'a> TitleMatch<'a> {
+        fn media_list(self: Self) -> Option<&'a MediaList> {
+            return match self {
+                TitleMatch::Found(media_list) => Some(media_list),
+                _ => None,
+            };
+        }
+    }
+
     impl PartialEq for &MediaList {
         fn eq(&self, other: &Self) -> bool {
             self.media.title.romaji == other.media.title.romaji
@@ -427,21 +2359,118 @@ mod tests {
         }
     }
 
+    #[test_case("CURRENT", Some(0), None ; "current")]
+    #[test_case("REPEATING", Some(0), Some(1) ; "first rewatch")]
+    #[test_case("REPEATING", Some(2), Some(3) ; "third rewatch")]
+    #[test_case("REPEATING", None, Some(1) ; "unknown repeat count")]
+    fn media_list_completed_repeat(status: &str, repeat: Option<i32>, expected: Option<i32>) {
+        let mut media_list = fake_media_list(1234, "Yuru Camp");
+        media_list.status = Some(String::from(status));
+        media_list.repeat = repeat;
+        assert_eq!(media_list.completed_repeat(), expected);
+    }
+
+    #[test_case("CURRENT", false ; "current")]
+    #[test_case("REPEATING", false ; "repeating")]
+    #[test_case("PLANNING", true ; "planning")]
+    #[test_case("PAUSED", true ; "paused")]
+    fn media_list_is_inactive(status: &str, expected: bool) {
+        let mut media_list = fake_media_list(1234, "Yuru Camp");
+        media_list.status = Some(String::from(status));
+        assert_eq!(media_list.is_inactive(), expected);
+    }
+
+    #[test]
+    fn media_relations_prequel() {
+        let relations: MediaRelations = serde_json::from_str(
+            "{\"relations\": {\"edges\": [\
+            {\"relationType\": \"SEQUEL\", \"node\": {\"id\": 3, \"type\": \"ANIME\", \"format\": \"TV\"}}, \
+            {\"relationType\": \"PREQUEL\", \"node\": {\"id\": 2, \"type\": \"ANIME\", \"format\": \"OVA\"}}, \
+            {\"relationType\": \"PREQUEL\", \"node\": {\"id\": 1, \"type\": \"ANIME\", \"format\": \"TV\", \"episodes\": 12}}\
+            ]}}",
+        )
+        .unwrap();
+        let prequel = relations.prequel().unwrap();
+        assert_eq!(prequel.id, 1);
+        assert_eq!(prequel.episodes, Some(12));
+    }
+
+    #[test]
+    fn media_list_group_lenient() {
+        let group: MediaListGroup = serde_json::from_str(
+            "{\"entries\": [\
+            {\"id\": 1, \"progress\": null, \"newField\": true, \"media\": {\"id\": 10, \
+            \"title\": {\"userPreferred\": \"Frieren\"}}}, \
+            {\"id\": 2, \"progress\": 3, \"media\": null}\
+            ]}",
+        )
+        .unwrap();
+        assert_eq!(group.entries.len(), 1);
+        assert_eq!(group.entries[0].id, 1);
+        assert_eq!(group.entries[0].progress, 0);
+    }
+
+    #[test]
+    fn schema_missing_fields() {
+        let mut types: HashMap<String, Option<SchemaType>> = serde_json::from_str(
+            &serde_json::json!(EXPECTED_FIELDS
+                .iter()
+                .enumerate()
+                .map(|(index, (_, fields))| (
+                    format!("t{}", index),
+                    serde_json::json!({
+                        "fields": fields.iter().map(|x| serde_json::json!({"name": x})).collect::<Vec<_>>()
+                    })
+                ))
+                .collect::<serde_json::Map<String, serde_json::Value>>())
+            .to_string(),
+        )
+        .unwrap();
+        assert_eq!(missing_fields(&types), Vec::<String>::new());
+        types
+            .get_mut("t2")
+            .unwrap()
+            .as_mut()
+            .unwrap()
+            .fields
+            .as_mut()
+            .unwrap()
+            .retain(|x| x.name != "repeat");
+        types.insert(String::from("t4"), None);
+        assert_eq!(missing_fields(&types), ["MediaList.repeat", "MediaTitle"]);
+    }
+
+    #[test_case(12, Some((1, 12)) ; "first season")]
+    #[test_case(13, Some((2, 1)) ; "second season")]
+    #[test_case(25, Some((3, 1)) ; "third season without episode count")]
+    fn relations_resolve(episode: i32, expected: Option<(i32, i32)>) {
+        let first_season = fake_media_list(1, "Mushoku Tensei");
+        let second_season = fake_media_list(2, "Mushoku Tensei Part 2");
+        let mut third_season = fake_media_list(3, "Mushoku Tensei II");
+        third_season.media.episodes = None;
+        let media_list_group =
+            MediaListGroup::new(vec![first_season.clone(), second_season, third_season]);
+        let mut relations = Relations::new();
+        for (id, offset) in [(1, 0), (2, 12), (3, 24)] {
+            relations.set(id, FranchisePosition { root: 1, offset });
+        }
+        let resolved = relations.resolve(&media_list_group, &first_season, episode);
+        assert_eq!(resolved.map(|(x, episode)| (x.id, episode)), expected);
+    }
+
     #[test]
     fn media_list_group_get_context_values() {
-        let media_list_group = MediaListGroup {
-            entries: vec![
-                fake_media_list(146065, "Mushoku Tensei II"),
-                fake_media_list(163132, "Horimiya -piece-"),
-            ],
-        };
+        let media_list_group = MediaListGroup::new(vec![
+            fake_media_list(146065, "Mushoku Tensei II"),
+            fake_media_list(163132, "Horimiya -piece-"),
+        ]);
 
-        let values: Vec<(i32, String)> = media_list_group.get_context_values().collect();
+        let values: Vec<(i32, String, i32)> = media_list_group.get_context_values().collect();
         assert_eq!(
             values,
             vec![
-                (146065, String::from("Mushoku Tensei II")),
-                (163132, String::from("Horimiya -piece-"))
+                (146065, String::from("Mushoku Tensei II"), 3),
+                (163132, String::from("Horimiya -piece-"), 3)
             ]
         );
     }
@@ -452,9 +2481,10 @@ mod tests {
     fn media_list_group_get_id(id: i32, expected: Option<&str>) {
         let correct_media_list = fake_media_list(146065, "Mushoku Tensei II");
         let incorrect_media_list = fake_media_list(163132, "Horimiya -piece-");
-        let media_list_group = MediaListGroup {
-            entries: vec![incorrect_media_list.clone(), correct_media_list.clone()],
-        };
+        let media_list_group = MediaListGroup::new(vec![
+            incorrect_media_list.clone(),
+            correct_media_list.clone(),
+        ]);
 
         let matched = media_list_group.find_id(&id);
         assert_eq!(
@@ -463,6 +2493,29 @@ mod tests {
         );
     }
 
+    #[test]
+    fn media_list_group_movies() {
+        let mut movie = fake_media_list(21519, "Kimi no Na wa.");
+        movie.media.format = Some(String::from("MOVIE"));
+        movie.media.episodes = Some(1);
+        let mut multi_part_movie = fake_media_list(21127, "Kizumonogatari");
+        multi_part_movie.media.format = Some(String::from("MOVIE"));
+        multi_part_movie.media.episodes = Some(3);
+        let media_list_group = MediaListGroup::new(vec![
+            fake_media_list(146065, "Mushoku Tensei II"),
+            movie,
+            multi_part_movie,
+        ]);
+
+        let ids: Vec<i32> = media_list_group
+            .movies()
+            .entries
+            .iter()
+            .map(|x| x.id)
+            .collect();
+        assert_eq!(ids, vec![21519]);
+    }
+
     #[test]
     // Test that an exact match is picked over a very close match.
     fn media_list_group_close_match_exact_match() {
@@ -472,11 +2525,15 @@ mod tests {
 
         let correct_media_list = fake_media_list(146065, correct_title);
         let incorrect_media_list = fake_media_list(5678, incorrect_title);
-        let media_list_group = MediaListGroup {
-            entries: vec![incorrect_media_list.clone(), correct_media_list.clone()],
-        };
-
-        let matched = media_list_group.find_match(&search_title).unwrap();
+        let media_list_group = MediaListGroup::new(vec![
+            incorrect_media_list.clone(),
+            correct_media_list.clone(),
+        ]);
+
+        let matched = media_list_group
+            .find_match(&search_title, |_| DEFAULT_MINIMUM_CONFIDENCE)
+            .media_list()
+            .unwrap();
         assert_eq!(matched, &correct_media_list);
     }
 
@@ -490,11 +2547,15 @@ mod tests {
 
         let correct_media_list = fake_media_list(1234, correct_title);
         let incorrect_media_list = fake_media_list(5678, incorrect_title);
-        let media_list_group = MediaListGroup {
-            entries: vec![incorrect_media_list.clone(), correct_media_list.clone()],
-        };
-
-        let matched = media_list_group.find_match(&search_title).unwrap();
+        let media_list_group = MediaListGroup::new(vec![
+            incorrect_media_list.clone(),
+            correct_media_list.clone(),
+        ]);
+
+        let matched = media_list_group
+            .find_match(&search_title, |_| DEFAULT_MINIMUM_CONFIDENCE)
+            .media_list()
+            .unwrap();
         assert_eq!(matched, &correct_media_list);
     }
 
@@ -506,11 +2567,15 @@ mod tests {
 
         let correct_media_list = fake_media_list(1234, correct_title);
         let incorrect_media_list = fake_media_list(5678, incorrect_title);
-        let media_list_group = MediaListGroup {
-            entries: vec![incorrect_media_list.clone(), correct_media_list.clone()],
-        };
-
-        let matched = media_list_group.find_match(&search_title).unwrap();
+        let media_list_group = MediaListGroup::new(vec![
+            incorrect_media_list.clone(),
+            correct_media_list.clone(),
+        ]);
+
+        let matched = media_list_group
+            .find_match(&search_title, |_| DEFAULT_MINIMUM_CONFIDENCE)
+            .media_list()
+            .unwrap();
         assert_eq!(matched, &correct_media_list);
     }
 
@@ -520,11 +2585,12 @@ mod tests {
         let search_title = String::from("\"Oshi no Ko\" (2024)");
 
         let media_list = fake_media_list(1234, anidb_title);
-        let media_list_group = MediaListGroup {
-            entries: vec![media_list.clone()],
-        };
+        let media_list_group = MediaListGroup::new(vec![media_list.clone()]);
 
-        let matched = media_list_group.find_match(&search_title).unwrap();
+        let matched = media_list_group
+            .find_match(&search_title, |_| DEFAULT_MINIMUM_CONFIDENCE)
+            .media_list()
+            .unwrap();
         assert_eq!(matched, &media_list);
     }
 
@@ -537,11 +2603,15 @@ mod tests {
 
         let correct_media_list = fake_media_list(1234, correct_title);
         let incorrect_media_list = fake_media_list(5678, incorrect_title);
-        let media_list_group = MediaListGroup {
-            entries: vec![incorrect_media_list.clone(), correct_media_list.clone()],
-        };
-
-        let matched = media_list_group.find_match(&search_title).unwrap();
+        let media_list_group = MediaListGroup::new(vec![
+            incorrect_media_list.clone(),
+            correct_media_list.clone(),
+        ]);
+
+        let matched = media_list_group
+            .find_match(&search_title, |_| DEFAULT_MINIMUM_CONFIDENCE)
+            .media_list()
+            .unwrap();
         assert_eq!(matched, &correct_media_list);
     }
 
@@ -552,12 +2622,179 @@ mod tests {
         let search_title = String::from("Soredemo Machi wa Mawatteiru");
 
         let incorrect_media_list = fake_media_list(1234, incorrect_title);
-        let media_list_group = MediaListGroup {
-            entries: vec![incorrect_media_list.clone()],
-        };
+        let media_list_group = MediaListGroup::new(vec![incorrect_media_list.clone()]);
+
+        let matched = media_list_group.find_match(&search_title, |_| DEFAULT_MINIMUM_CONFIDENCE);
+        assert!(matched.media_list().is_none());
+    }
+
+    #[test]
+    // Test that the minimum confidence of the best matching entry is used.
+    fn media_list_group_minimum_confidence() {
+        let search_title = String::from("Soredemo Machi wa Mawatteiru");
+        let media_list = fake_media_list(1234, "Soredemo Ayumu wa Yosetekuru");
+        let media_list_group = MediaListGroup::new(vec![media_list.clone()]);
+
+        let matched = media_list_group.find_match(&search_title, |_| 0.1);
+        assert_eq!(matched.media_list(), Some(&media_list));
+        let matched = media_list_group.find_match(&search_title, |x| match x.id {
+            1234 => 0.99,
+            _ => 0.1,
+        });
+        assert!(matched.media_list().is_none());
+    }
+
+    #[test]
+    // Test that no guess is made when several entries match equally well.
+    fn media_list_group_ambiguous_match() {
+        let title = "Yuru Camp";
+        let search_title = String::from("Yuru Camp");
+
+        let tv_media_list = fake_media_list(1234, title);
+        let mut ona_media_list = fake_media_list(5678, title);
+        ona_media_list.media.format = Some(String::from("ONA"));
+        let media_list_group =
+            MediaListGroup::new(vec![tv_media_list.clone(), ona_media_list.clone()]);
+
+        match media_list_group.find_match(&search_title, |_| DEFAULT_MINIMUM_CONFIDENCE) {
+            TitleMatch::Ambiguous(candidates) => {
+                assert_eq!(candidates, vec![&tv_media_list, &ona_media_list])
+            }
+            matched => panic!("Expected an ambiguous match, got {:?}", matched),
+        }
+    }
 
-        let matched = media_list_group.find_match(&search_title);
-        assert!(matched.is_none());
+    #[test_case("Laid-Back Camp", 1.0, "english", false ; "exact english")]
+    #[test_case("Yuru Camps", 0.9, "romaji", false ; "fuzzy romaji")]
+    #[test_case("Yuru Camp 2nd Season", 0.95, "romaji", true ; "massaged")]
+    fn media_list_group_candidates(
+        title: &str,
+        expected_confidence: f64,
+        expected_variant: &str,
+        expected_massaged: bool,
+    ) {
+        let mut media_list = fake_media_list(1234, "Yuru Camp");
+        media_list.media.title.english = Some(String::from("Laid-Back Camp"));
+        media_list.media.title.native = None;
+        let media_list_group =
+            MediaListGroup::new(vec![media_list, fake_media_list(5678, "Mushoku Tensei")]);
+        let candidates =
+            media_list_group.candidates(title, 1, false, |_| DEFAULT_MINIMUM_CONFIDENCE);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].anilist_id, 1234);
+        assert!((candidates[0].confidence - expected_confidence).abs() < 0.001);
+        assert_eq!(candidates[0].variant, expected_variant);
+        assert_eq!(candidates[0].massaged, expected_massaged);
+    }
+
+    #[test_case(DEFAULT_MINIMUM_CONFIDENCE, true ; "global minimum")]
+    #[test_case(0.4, false ; "lowered entry minimum")]
+    fn media_list_group_candidates_entry_minimum(minimum_confidence: f64, expected_massaged: bool) {
+        let media_list_group = MediaListGroup::new(vec![
+            fake_media_list(1234, "Yuru Camp"),
+            fake_media_list(5678, "Mushoku Tensei"),
+        ]);
+        let candidates = media_list_group.candidates("Yuru Camp 2nd Season", 1, false, |x| {
+            if x.id == 1234 {
+                minimum_confidence
+            } else {
+                DEFAULT_MINIMUM_CONFIDENCE
+            }
+        });
+        assert_eq!(candidates[0].anilist_id, 1234);
+        assert_eq!(candidates[0].massaged, expected_massaged);
+    }
+
+    #[test_case(2018, Some(1234) ; "one candidate in year")]
+    #[test_case(2021, None ; "no candidate in year")]
+    fn title_match_narrow_by_year(year: i32, expected: Option<i32>) {
+        let tv_media_list = fake_media_list(1234, "Yuru Camp");
+        let mut movie_media_list = fake_media_list(5678, "Yuru Camp");
+        movie_media_list.media.season_year = Some(2022);
+        let title_match = TitleMatch::Ambiguous(vec![&tv_media_list, &movie_media_list]);
+        let title_match = title_match.narrow_by_year(year);
+        assert_eq!(title_match.media_list().map(|x| x.id), expected);
+    }
+
+    #[test_case(Some(30), 0, Duration::from_secs(30) ; "retry after")]
+    #[test_case(Some(600), 0, Duration::from_secs(60) ; "retry after over maximum")]
+    #[test_case(None, 0, Duration::from_secs(2) ; "first backoff")]
+    #[test_case(None, 2, Duration::from_secs(8) ; "third backoff")]
+    fn rate_limit_retry_delay(retry_after: Option<u64>, attempt: u32, expected: Duration) {
+        assert_eq!(retry_delay(retry_after, attempt), expected);
+    }
+
+    #[test]
+    fn batch_document_aliases() {
+        let document = batch_document(
+            "mutation",
+            "m",
+            [(0, MEDIALIST_MUTATION), (2, MEDIALIST_SCORE_MUTATION)].into_iter(),
+        );
+        assert_eq!(
+            document,
+            "mutation($id_0: Int, $progress_0: Int, $id_2: Int, $scoreRaw_2: Int) {\n  \
+            m0: SaveMediaListEntry(id: $id_0, progress: $progress_0) {\n    progress\n  }\n  \
+            m2: SaveMediaListEntry(id: $id_2, scoreRaw: $scoreRaw_2) {\n    progress\n    \
+            score(format: POINT_100)\n  }\n}"
+        );
+        let document = batch_document("query", "p", [(1, MEDIALIST_PROGRESS_QUERY)].into_iter());
+        assert_eq!(
+            document,
+            "query($id_1: Int) {\n  p1: MediaList(id: $id_1) {\n    progress\n  }\n}"
+        );
+    }
+
+    #[test]
+    fn batch_variables_suffix() {
+        let variables = batch_variables(&[
+            (
+                0,
+                MediaListCollectionMutateVariables {
+                    id: 1,
+                    progress: Some(3),
+                    repeat: None,
+                    score_raw: None,
+                },
+            ),
+            (
+                2,
+                MediaListCollectionMutateVariables {
+                    id: 2,
+                    progress: None,
+                    repeat: None,
+                    score_raw: Some(80),
+                },
+            ),
+        ]);
+        assert_eq!(
+            serde_json::Value::Object(variables),
+            serde_json::json!({"id_0": 1, "progress_0": 3, "id_2": 2, "scoreRaw_2": 80})
+        );
+    }
+
+    #[test_case("anifunnel", None, "anifunnel/{}" ; "default")]
+    #[test_case("anifunnel-yukikaze", Some("yukikaze@example.com"), "anifunnel-yukikaze/{} (yukikaze@example.com)" ; "with contact")]
+    fn user_agent_format(client_name: &str, contact: Option<&str>, expected: &str) {
+        assert_eq!(
+            AnilistApi::new(DEFAULT_API_URL, client_name, contact).user_agent,
+            expected.replace("{}", env!("CARGO_PKG_VERSION"))
+        );
+    }
+
+    #[test]
+    fn oauth_authorize_url() {
+        let client = OAuthClient {
+            id: String::from("1234"),
+            secret: String::from("secret"),
+            redirect_url: String::from("https://anifunnel.example.com/auth/anilist/callback"),
+        };
+        assert_eq!(
+            client.authorize_url("abc"),
+            "https://anilist.co/api/v2/oauth/authorize?client_id=1234&redirect_uri=\
+            https%3A%2F%2Fanifunnel.example.com%2Fauth%2Fanilist%2Fcallback\
+            &response_type=code&state=abc"
+        );
     }
 
     #[test]
@@ -582,8 +2819,11 @@ mod tests {
     #[test_case("Girlfriend (Kari)", "Girlfriend (Kari)" ; "trailing parenthesis")]
     #[test_case("らき☆すた", "らき☆すた" ; "special character between Japanese")]
     #[test_case("【推しの子】", "推しの子" ; "surrounding quotes Japanese")]
+    #[test_case("☆", "☆" ; "only special characters")]
+    #[test_case(")(", ")(" ; "reversed parentheses")]
+    #[test_case("", "" ; "empty")]
     fn special_surrounding_characters_removal(input: &str, expected: &str) {
-        let output = remove_special_surrounding_characters(&input);
+        let output = remove_special_surrounding_characters(input);
         assert_eq!(output, expected);
     }
 }
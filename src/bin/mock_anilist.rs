@@ -0,0 +1,357 @@
+//! Fake Anilist GraphQL API for testing anifunnel setups without touching a real
+//! Anilist list. Serves a watching list from a JSON file and records mutations.
+
+#[macro_use]
+extern crate rocket;
+
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use clap::Parser;
+use regex::Regex;
+use rocket::http::Status;
+use rocket::serde::json::{json, Json, Value};
+use serde::{Deserialize, Serialize};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct MockArgs {
+    /// JSON file with the watching list entries in the Anilist MediaList format.
+    list: PathBuf,
+
+    /// IP address to bind the server to.
+    #[clap(long, default_value_t = Ipv4Addr::new(127, 0, 0, 1))]
+    bind_address: Ipv4Addr,
+
+    /// Port to bind the server to.
+    #[clap(long, default_value_t = 8100)]
+    port: u16,
+
+    /// Name of the fake Anilist user.
+    #[clap(long, default_value = "anifunnel")]
+    user_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlRequest {
+    query: String,
+    variables: Option<Value>,
+}
+
+/// Mutation received from anifunnel.
+#[derive(Clone, Debug, Serialize)]
+struct Mutation {
+    query: String,
+    variables: Value,
+}
+
+struct MockState {
+    user_name: String,
+    entries: Mutex<Vec<Value>>,
+    mutations: Mutex<Vec<Mutation>>,
+}
+
+impl MockState {
+    /// Respond to a GraphQL request based on the query anifunnel sent.
+    fn respond(self: &Self, request: GraphqlRequest) -> Option<Value> {
+        let variables = request.variables.unwrap_or(Value::Null);
+        // Schema introspection is not mocked, so the schema probe is skipped.
+        if request.query.contains("__type") {
+            return None;
+        }
+        if let Some(response) = self.respond_batch(&request.query, &variables) {
+            return Some(response);
+        }
+        if request.query.contains("SaveMediaListEntry") {
+            return Some(self.save_media_list_entry(&request.query, variables));
+        }
+        if request.query.contains("MediaListCollection") {
+            let entries = self.entries.lock().unwrap();
+            let entries: Vec<&Value> = match variables["status_in"].as_array() {
+                Some(statuses) => entries
+                    .iter()
+                    .filter(|x| statuses.contains(&x["status"]))
+                    .collect(),
+                None => entries.iter().collect(),
+            };
+            return Some(json!({
+                "data": {"MediaListCollection": {"lists": [{"entries": entries}]}}
+            }));
+        }
+        if request.query.contains("MediaList(") {
+            return Some(json!({"data": {"MediaList": self.media_list(&variables)}}));
+        }
+        if request.query.contains("Viewer") {
+            return Some(json!({
+                "data": {"Viewer": {"id": 1, "name": self.user_name}}
+            }));
+        }
+        if request.query.contains("media(search") {
+            let search = variables["search"]
+                .as_str()
+                .unwrap_or_default()
+                .to_lowercase();
+            let entries = self.entries.lock().unwrap();
+            let media: Vec<&Value> = entries
+                .iter()
+                .map(|x| &x["media"])
+                .filter(|x| {
+                    x["title"]["userPreferred"]
+                        .as_str()
+                        .is_some_and(|x| x.to_lowercase().contains(&search))
+                })
+                .collect();
+            return Some(json!({"data": {"Page": {"media": media}}}));
+        }
+        if request.query.contains("coverImage") {
+            let entries = self.entries.lock().unwrap();
+            let media = entries
+                .iter()
+                .map(|x| &x["media"])
+                .find(|x| x["id"] == variables["id"]);
+            return Some(json!({"data": {"Media": media}}));
+        }
+        if request.query.contains("Media(") {
+            return Some(json!({"data": {"Media": {"relations": {"edges": []}}}}));
+        }
+        return None;
+    }
+
+    /// Respond to the aliased fields of a batch, e.g. m0: SaveMediaListEntry(...), each
+    /// with the variables that have its index as the suffix.
+    fn respond_batch(self: &Self, query: &str, variables: &Value) -> Option<Value> {
+        let field =
+            Regex::new(r"(?m)^\s*(\w+?(\d+)): (SaveMediaListEntry|MediaList)\(([^)]*)\)").unwrap();
+        let mut data = serde_json::Map::new();
+        for captures in field.captures_iter(query) {
+            let suffix = format!("_{}", &captures[2]);
+            let field_variables: serde_json::Map<String, Value> = variables
+                .as_object()
+                .into_iter()
+                .flatten()
+                .filter_map(|(name, value)| {
+                    let name = name.strip_suffix(&suffix)?;
+                    Some((name.to_string(), value.clone()))
+                })
+                .collect();
+            let field_variables = Value::Object(field_variables);
+            let response = match &captures[3] {
+                "SaveMediaListEntry" => self.save_media_list_entry(&captures[0], field_variables)
+                    ["data"]["SaveMediaListEntry"]
+                    .take(),
+                _ => self.media_list(&field_variables),
+            };
+            data.insert(captures[1].to_string(), response);
+        }
+        if data.is_empty() {
+            return None;
+        }
+        return Some(json!({"data": data}));
+    }
+
+    /// Progress of a served entry, or null if the list does not have it.
+    fn media_list(self: &Self, variables: &Value) -> Value {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.iter().find(|x| x["id"] == variables["id"]);
+        return entry.map_or(Value::Null, |x| json!({"progress": x["progress"]}));
+    }
+
+    /// Record the mutation and apply it to the served watching list.
+    fn save_media_list_entry(self: &Self, query: &str, variables: Value) -> Value {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.iter_mut().find(|x| x["id"] == variables["id"]);
+        let mut progress = entry
+            .as_ref()
+            .map_or(Value::Null, |x| x["progress"].clone());
+        let score = variables["scoreRaw"].clone();
+        if let Some(entry) = entry {
+            if !variables["progress"].is_null() {
+                progress = variables["progress"].clone();
+                entry["progress"] = progress.clone();
+            }
+            if !score.is_null() {
+                entry["score"] = score.clone();
+            }
+            if query.contains("status: COMPLETED") {
+                entry["status"] = json!("COMPLETED");
+            } else if query.contains("status: CURRENT") {
+                entry["status"] = json!("CURRENT");
+            }
+            if !variables["repeat"].is_null() {
+                entry["repeat"] = variables["repeat"].clone();
+            }
+        }
+        info!("Received mutation {}", variables);
+        self.mutations.lock().unwrap().push(Mutation {
+            query: query.to_string(),
+            variables,
+        });
+        let mut saved = json!({"progress": progress});
+        if !score.is_null() {
+            saved["score"] = score;
+        }
+        return json!({"data": {"SaveMediaListEntry": saved}});
+    }
+}
+
+#[post("/", data = "<request>")]
+fn graphql(
+    request: Json<GraphqlRequest>,
+    state: &rocket::State<MockState>,
+) -> Result<Json<Value>, (Status, Json<Value>)> {
+    return match state.respond(request.into_inner()) {
+        Some(response) => Ok(Json(response)),
+        None => Err((
+            Status::BadRequest,
+            Json(json!({"errors": [{"message": "Unsupported query"}]})),
+        )),
+    };
+}
+
+#[get("/mutations")]
+fn mutations(state: &rocket::State<MockState>) -> Json<Vec<Mutation>> {
+    Json(state.mutations.lock().unwrap().clone())
+}
+
+#[rocket::main]
+async fn main() {
+    let args = MockArgs::parse();
+    let list = std::fs::read_to_string(&args.list).expect("Could not read the list file");
+    let entries: Vec<Value> = serde_json::from_str(&list).expect("Could not parse the list file");
+    let state = MockState {
+        user_name: args.user_name,
+        entries: Mutex::new(entries),
+        mutations: Mutex::new(Vec::new()),
+    };
+    let config = rocket::Config {
+        address: args.bind_address.into(),
+        port: args.port,
+        ..rocket::Config::release_default()
+    };
+    let _ = rocket::custom(config)
+        .manage(state)
+        .mount("/", routes![graphql, mutations])
+        .launch()
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_state() -> MockState {
+        return MockState {
+            user_name: String::from("yukikaze"),
+            entries: Mutex::new(vec![json!({
+                "id": 1234, "progress": 1, "status": "CURRENT", "repeat": 0,
+                "media": {"id": 146065, "format": "TV", "episodes": 12, "title": {
+                    "romaji": "Onii-chan wa Oshimai!", "english": null,
+                    "native": null, "userPreferred": "Onii-chan wa Oshimai!"}, "synonyms": []}
+            })]),
+            mutations: Mutex::new(Vec::new()),
+        };
+    }
+
+    #[test]
+    fn respond_save_media_list_entry() {
+        let state = build_state();
+        let response = state.respond(GraphqlRequest {
+            query: String::from("mutation { SaveMediaListEntry(status: COMPLETED) }"),
+            variables: Some(json!({"id": 1234, "progress": 12})),
+        });
+        assert_eq!(
+            response,
+            Some(json!({"data": {"SaveMediaListEntry": {"progress": 12}}}))
+        );
+        let entries = state.entries.lock().unwrap();
+        assert_eq!(entries[0]["progress"], 12);
+        assert_eq!(entries[0]["status"], "COMPLETED");
+        assert_eq!(state.mutations.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn respond_batch() {
+        let state = build_state();
+        let response = state.respond(GraphqlRequest {
+            query: String::from(
+                "mutation($id_0: Int, $progress_0: Int, $id_1: Int, $progress_1: Int) {\n  \
+                m0: SaveMediaListEntry(id: $id_0, progress: $progress_0, status: COMPLETED) \
+                {\n    progress\n  }\n  \
+                m1: SaveMediaListEntry(id: $id_1, progress: $progress_1) {\n    progress\n  }\n}",
+            ),
+            variables: Some(json!({"id_0": 1234, "progress_0": 12, "id_1": 99, "progress_1": 3})),
+        });
+        assert_eq!(
+            response,
+            Some(json!({"data": {"m0": {"progress": 12}, "m1": {"progress": null}}}))
+        );
+        assert_eq!(state.entries.lock().unwrap()[0]["status"], "COMPLETED");
+        assert_eq!(state.mutations.lock().unwrap().len(), 2);
+        let response = state.respond(GraphqlRequest {
+            query: String::from(
+                "query($id_0: Int) {\n  p0: MediaList(id: $id_0) {\n    progress\n  }\n}",
+            ),
+            variables: Some(json!({"id_0": 1234})),
+        });
+        assert_eq!(response, Some(json!({"data": {"p0": {"progress": 12}}})));
+    }
+
+    #[test]
+    fn respond_media_list_collection() {
+        let state = build_state();
+        let respond = |statuses: Value| {
+            let response = state
+                .respond(GraphqlRequest {
+                    query: String::from("query { MediaListCollection { lists } }"),
+                    variables: Some(json!({"user_id": 1, "status_in": statuses})),
+                })
+                .unwrap();
+            return response["data"]["MediaListCollection"]["lists"][0]["entries"]
+                .as_array()
+                .unwrap()
+                .len();
+        };
+        assert_eq!(respond(json!(["CURRENT", "REPEATING"])), 1);
+        assert_eq!(respond(json!(["PLANNING", "PAUSED"])), 0);
+    }
+
+    #[test]
+    fn respond_media_list() {
+        let state = build_state();
+        let response = state.respond(GraphqlRequest {
+            query: String::from("query { MediaList(id: $id) { progress } }"),
+            variables: Some(json!({"id": 1234})),
+        });
+        assert_eq!(
+            response,
+            Some(json!({"data": {"MediaList": {"progress": 1}}}))
+        );
+    }
+
+    #[test]
+    fn respond_media_search() {
+        let state = build_state();
+        let respond = |search: &str| {
+            let response = state
+                .respond(GraphqlRequest {
+                    query: String::from("query { Page { media(search: $search) { id } } }"),
+                    variables: Some(json!({"search": search})),
+                })
+                .unwrap();
+            return response["data"]["Page"]["media"].as_array().unwrap().len();
+        };
+        assert_eq!(respond("oshimai"), 1);
+        assert_eq!(respond("Yuru Camp"), 0);
+    }
+
+    #[test]
+    fn respond_unsupported() {
+        let state = build_state();
+        let response = state.respond(GraphqlRequest {
+            query: String::from("query { Page { id } }"),
+            variables: None,
+        });
+        assert_eq!(response, None);
+    }
+}
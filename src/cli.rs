@@ -0,0 +1,380 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+use serde::Deserialize;
+
+use crate::replication;
+
+/// Subcommands of anifunnel. Everything except serve manages a running instance
+/// through its API, so anifunnel can be managed without the management interface.
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the server. This is the default when no subcommand is given.
+    Serve,
+    /// Manage the Anilist tokens of a running instance.
+    Token {
+        #[command(subcommand)]
+        command: TokenCommand,
+        #[command(flatten)]
+        client: ClientArgs,
+    },
+    /// Manage the title overrides of a running instance.
+    Override {
+        #[command(subcommand)]
+        command: OverrideCommand,
+        #[command(flatten)]
+        client: ClientArgs,
+    },
+    /// Print the overrides of a running instance as JSON.
+    Export {
+        #[command(flatten)]
+        client: ClientArgs,
+    },
+    /// Replay the Plex watch history through a running instance to catch up on
+    /// episodes watched while it was down.
+    Catchup {
+        /// Only replay items watched after this Unix timestamp.
+        #[arg(long)]
+        since: Option<u64>,
+        #[command(flatten)]
+        client: ClientArgs,
+    },
+    /// Import overrides from a JSON export into a running instance.
+    Import {
+        /// Export file to import.
+        file: PathBuf,
+        /// Replace existing overrides that conflict with the imported ones.
+        #[arg(long)]
+        replace: bool,
+        #[command(flatten)]
+        client: ClientArgs,
+    },
+    /// Copy the overrides of another running instance into a running instance, e.g.
+    /// from staging to production or when moving to a new host.
+    SyncFrom {
+        /// URL of the instance to copy the overrides from.
+        source: String,
+        /// Admin API key of the source instance, if it has an admin password.
+        #[arg(long, env = "ANIFUNNEL_SOURCE_API_KEY")]
+        source_api_key: Option<String>,
+        /// Replace existing overrides that conflict with the copied ones.
+        #[arg(long)]
+        replace: bool,
+        #[command(flatten)]
+        client: ClientArgs,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TokenCommand {
+    /// Store an Anilist token and make it the active one.
+    Set {
+        token: String,
+        /// Label to store the token under.
+        #[arg(long, default_value = "cli")]
+        label: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum OverrideCommand {
+    /// List the title overrides.
+    List,
+    /// Point a Plex title at an Anilist entry given by its ID or URL.
+    Set {
+        title: String,
+        target: String,
+        /// Only apply the override to the active Anilist account.
+        #[arg(long)]
+        account: bool,
+    },
+    /// Remove the title override of a Plex title.
+    #[command(alias = "delete")]
+    Remove {
+        title: String,
+        /// Remove the override of the active Anilist account.
+        #[arg(long)]
+        account: bool,
+    },
+}
+
+/// Connection to the running instance.
+#[derive(Args, Debug)]
+pub struct ClientArgs {
+    /// URL of the running anifunnel instance, including the base path if one is used.
+    #[arg(
+        long,
+        global = true,
+        default_value = "http://127.0.0.1:8000",
+        env = "ANIFUNNEL_URL"
+    )]
+    url: String,
+
+    /// Admin API key of the instance, if it has an admin password.
+    #[arg(long, global = true, env = "ANIFUNNEL_API_KEY")]
+    api_key: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum CliError {
+    Connection(String),
+    /// The instance rejected the request with the status and response body.
+    Rejected(reqwest::StatusCode, String),
+    Parsing,
+    File(std::io::Error),
+    /// The overrides could not be fetched from the source instance of sync-from.
+    Source(replication::ReplicationError),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::Connection(error) => write!(f, "Could not connect to anifunnel: {}", error),
+            CliError::Rejected(status, body) => {
+                write!(f, "anifunnel returned {}: {}", status, body)
+            }
+            CliError::Parsing => write!(f, "Could not parse the response of anifunnel"),
+            CliError::File(error) => write!(f, "Could not read the file: {}", error),
+            CliError::Source(error) => write!(
+                f,
+                "Could not fetch the overrides of the source instance: {:?}",
+                error
+            ),
+        }
+    }
+}
+
+/// Stored token in the response of the token API.
+#[derive(Debug, Deserialize)]
+struct Token {
+    id: u32,
+    user: String,
+}
+
+/// Title overrides in the export of an instance.
+#[derive(Debug, Deserialize)]
+struct Export {
+    title_overrides: BTreeMap<String, i32>,
+    /// Title overrides of single accounts by the Anilist user ID.
+    #[serde(default)]
+    account_title_overrides: BTreeMap<i32, BTreeMap<String, i32>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TitleOverride {
+    title: String,
+    anilist_id: i32,
+    anilist_title: String,
+}
+
+struct Client {
+    http: reqwest::Client,
+    url: String,
+    api_key: Option<String>,
+}
+
+impl Client {
+    fn new(args: ClientArgs) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            url: args.url.trim_end_matches('/').to_string(),
+            api_key: args.api_key,
+        }
+    }
+
+    fn request(self: &Self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let request = self.http.request(method, format!("{}{}", self.url, path));
+        return match &self.api_key {
+            Some(api_key) => request.bearer_auth(api_key),
+            None => request,
+        };
+    }
+
+    /// Send a request and return the response body if the request succeeded.
+    async fn send(self: &Self, request: reqwest::RequestBuilder) -> Result<String, CliError> {
+        let response = request
+            .send()
+            .await
+            .map_err(|error| CliError::Connection(error.to_string()))?;
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .map_err(|error| CliError::Connection(error.to_string()))?;
+        if !status.is_success() {
+            return Err(CliError::Rejected(status, body));
+        }
+        return Ok(body);
+    }
+}
+
+fn parse<T: for<'a> Deserialize<'a>>(body: &str) -> Result<T, CliError> {
+    return serde_json::from_str(body).map_err(|_| CliError::Parsing);
+}
+
+/// Title overrides of an export as tab-separated lines of the title and Anilist ID,
+/// followed by the Anilist user ID for overrides that only apply to one account.
+fn format_title_overrides(export: &str) -> Result<String, CliError> {
+    let export: Export = parse(export)?;
+    let shared = export
+        .title_overrides
+        .iter()
+        .map(|(title, id)| format!("{}\t{}", title, id));
+    let accounts = export
+        .account_title_overrides
+        .iter()
+        .flat_map(|(user_id, overrides)| {
+            overrides
+                .iter()
+                .map(move |(title, id)| format!("{}\t{}\t{}", title, id, user_id))
+        });
+    return Ok(shared.chain(accounts).collect::<Vec<String>>().join("\n"));
+}
+
+async fn token_set(client: &Client, token: &str, label: &str) -> Result<String, CliError> {
+    let request = client
+        .request(reqwest::Method::POST, "/api/tokens")
+        .form(&[("label", label), ("token", token)]);
+    let token: Token = parse(&client.send(request).await?)?;
+    let path = format!("/api/tokens/{}/activate", token.id);
+    client
+        .send(client.request(reqwest::Method::POST, &path))
+        .await?;
+    return Ok(format!("Activated the token of {}", token.user));
+}
+
+async fn run_override(client: &Client, command: OverrideCommand) -> Result<String, CliError> {
+    return match command {
+        OverrideCommand::List => {
+            let export = client
+                .send(client.request(reqwest::Method::GET, "/api/export"))
+                .await?;
+            format_title_overrides(&export)
+        }
+        OverrideCommand::Set {
+            title,
+            target,
+            account,
+        } => {
+            let request = client
+                .request(reqwest::Method::POST, "/api/overrides")
+                .form(&[
+                    ("title", title.as_str()),
+                    ("target", target.as_str()),
+                    ("account", if account { "true" } else { "false" }),
+                ]);
+            let created: TitleOverride = parse(&client.send(request).await?)?;
+            Ok(format!(
+                "{} -> {} ({})",
+                created.title, created.anilist_id, created.anilist_title
+            ))
+        }
+        OverrideCommand::Remove { title, account } => {
+            let request = client
+                .request(reqwest::Method::POST, "/api/overrides/delete")
+                .form(&[
+                    ("title", title.as_str()),
+                    ("account", if account { "true" } else { "false" }),
+                ]);
+            client.send(request).await?;
+            Ok(format!("Removed the title override of {}", title))
+        }
+    };
+}
+
+async fn import(client: &Client, export: String, replace: bool) -> Result<String, CliError> {
+    let conflict = if replace { "replace" } else { "skip" };
+    let request = client
+        .request(
+            reqwest::Method::POST,
+            &format!("/api/import?conflict={}", conflict),
+        )
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(export);
+    return client.send(request).await;
+}
+
+/// Run a management subcommand and print its output. Returns the exit code.
+pub async fn run(command: Command) -> i32 {
+    let result = match command {
+        Command::Serve => Ok(String::new()),
+        Command::Token { command, client } => match command {
+            TokenCommand::Set { token, label } => {
+                token_set(&Client::new(client), &token, &label).await
+            }
+        },
+        Command::Override { command, client } => run_override(&Client::new(client), command).await,
+        Command::Export { client } => {
+            let client = Client::new(client);
+            client
+                .send(client.request(reqwest::Method::GET, "/api/export"))
+                .await
+        }
+        Command::Catchup { since, client } => {
+            let client = Client::new(client);
+            let path = match since {
+                Some(since) => format!("/api/sync/catchup?since={}", since),
+                None => String::from("/api/sync/catchup"),
+            };
+            client
+                .send(client.request(reqwest::Method::POST, &path))
+                .await
+        }
+        Command::Import {
+            file,
+            replace,
+            client,
+        } => match std::fs::read_to_string(file) {
+            Ok(export) => import(&Client::new(client), export, replace).await,
+            Err(error) => Err(CliError::File(error)),
+        },
+        Command::SyncFrom {
+            source,
+            source_api_key,
+            replace,
+            client,
+        } => match replication::fetch(&source, source_api_key.as_deref()).await {
+            Ok(export) => import(&Client::new(client), export, replace).await,
+            Err(error) => Err(CliError::Source(error)),
+        },
+    };
+    return match result {
+        Ok(output) => {
+            if !output.is_empty() {
+                println!("{}", output);
+            }
+            0
+        }
+        Err(error) => {
+            eprintln!("{}", error);
+            1
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn title_overrides_format() {
+        let export = "{\"version\": \"1.4.0\", \"title_overrides\": \
+            {\"Yuru Camp\": 98444, \"Mushoku Tensei S2\": 146065}, \"log_only\": []}";
+        assert_eq!(
+            format_title_overrides(export).unwrap(),
+            "Mushoku Tensei S2\t146065\nYuru Camp\t98444"
+        );
+    }
+
+    #[test]
+    fn title_overrides_format_accounts() {
+        let export = "{\"title_overrides\": {\"Yuru Camp\": 98444}, \
+            \"account_title_overrides\": {\"5678\": {\"Laid-Back Camp\": 104460}}}";
+        assert_eq!(
+            format_title_overrides(export).unwrap(),
+            "Yuru Camp\t98444\nLaid-Back Camp\t104460\t5678"
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use clap::error::ErrorKind;
+use clap::{Command, CommandFactory, FromArgMatches};
+use rocket::figment::providers::{Format, Toml};
+use rocket::figment::Figment;
+use serde::Deserialize;
+
+use crate::notifiers;
+
+/// Name of the argument that holds the config file path.
+const CONFIG_ARGUMENT: &str = "config";
+
+/// Value of a single option in the config file.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+enum ConfigValue {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    List(Vec<String>),
+}
+
+impl fmt::Display for ConfigValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigValue::Bool(value) => write!(f, "{}", value),
+            ConfigValue::Integer(value) => write!(f, "{}", value),
+            ConfigValue::Float(value) => write!(f, "{}", value),
+            ConfigValue::String(value) => write!(f, "{}", value),
+            ConfigValue::List(values) => write!(f, "{}", values.join(",")),
+        }
+    }
+}
+
+/// Contents of the config file. Besides the options, the file can have a
+/// `notifications` table of notifiers, which have no matching argument.
+#[derive(Debug, Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    notifications: Vec<notifiers::Notifier>,
+    #[serde(flatten)]
+    options: BTreeMap<String, ConfigValue>,
+}
+
+fn load_file(path: &Path) -> Result<ConfigFile, String> {
+    return Figment::from(Toml::file_exact(path))
+        .extract()
+        .map_err(|error| format!("Could not load config file: {}", error));
+}
+
+/// Read the options from a TOML config file. Keys are the argument names with
+/// underscores, e.g. `bind_address`.
+fn load(path: &Path) -> Result<BTreeMap<String, ConfigValue>, String> {
+    return load_file(path).map(|x| x.options);
+}
+
+/// Read the notifiers from the `notifications` table of a TOML config file.
+pub fn notifiers(path: &Path) -> Result<Vec<notifiers::Notifier>, String> {
+    return load_file(path).map(|x| x.notifications);
+}
+
+/// Use the config file values as argument defaults so that both command line
+/// arguments and environment variables override them.
+fn apply_defaults(
+    mut command: Command,
+    values: BTreeMap<String, ConfigValue>,
+) -> Result<Command, String> {
+    for (key, value) in values {
+        let is_known = key != CONFIG_ARGUMENT
+            && command
+                .get_arguments()
+                .any(|arg| arg.get_id() == key.as_str());
+        if !is_known {
+            return Err(format!("Unknown option in config file: {}", key));
+        }
+        command = command.mut_arg(key, |arg| {
+            let arg = arg.required(false);
+            match value {
+                ConfigValue::List(values) => arg.default_values(values),
+                value => arg.default_value(value.to_string()),
+            }
+        });
+    }
+    return Ok(command);
+}
+
+/// Parse the arguments, taking defaults from the config file given with the config
+/// argument. Exits the process on errors like regular argument parsing does.
+pub fn parse<T: CommandFactory + FromArgMatches>() -> T {
+    let mut command = T::command();
+    let preliminary = command.clone().ignore_errors(true).get_matches();
+    if let Some(path) = preliminary.get_one::<PathBuf>(CONFIG_ARGUMENT) {
+        command = match load(path).and_then(|values| apply_defaults(command, values)) {
+            Ok(command) => command,
+            Err(message) => T::command().error(ErrorKind::InvalidValue, message).exit(),
+        };
+    }
+    let mut matches = command.get_matches();
+    return T::from_arg_matches_mut(&mut matches).unwrap_or_else(|error| error.exit());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use clap::{Arg, ArgAction};
+    use test_case::test_case;
+
+    fn build_command() -> Command {
+        Command::new("anifunnel")
+            .arg(Arg::new("anilist_token").required(true))
+            .arg(Arg::new("port").long("port").default_value("8000"))
+            .arg(
+                Arg::new("multi_season")
+                    .long("multi-season")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(Arg::new("keys").long("keys").value_delimiter(','))
+            .arg(Arg::new(CONFIG_ARGUMENT).long("config"))
+    }
+
+    fn values(options: &[(&str, ConfigValue)]) -> BTreeMap<String, ConfigValue> {
+        return options
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect();
+    }
+
+    #[test]
+    fn config_defaults() {
+        let command = apply_defaults(
+            build_command(),
+            values(&[
+                ("anilist_token", ConfigValue::String(String::from("A"))),
+                ("port", ConfigValue::Integer(8001)),
+                ("multi_season", ConfigValue::Bool(true)),
+                (
+                    "keys",
+                    ConfigValue::List(vec![String::from("a"), String::from("b")]),
+                ),
+            ]),
+        )
+        .unwrap();
+        let matches = command.get_matches_from(["anifunnel"]);
+        assert_eq!(
+            matches.get_one::<String>("anilist_token"),
+            Some(&String::from("A"))
+        );
+        assert_eq!(
+            matches.get_one::<String>("port"),
+            Some(&String::from("8001"))
+        );
+        assert!(matches.get_flag("multi_season"));
+        assert_eq!(
+            matches
+                .get_many::<String>("keys")
+                .unwrap()
+                .collect::<Vec<&String>>(),
+            vec!["a", "b"]
+        );
+    }
+
+    #[test]
+    fn config_arguments_take_precedence() {
+        let command = apply_defaults(
+            build_command(),
+            values(&[("port", ConfigValue::Integer(8001))]),
+        )
+        .unwrap();
+        let matches = command.get_matches_from(["anifunnel", "A", "--port", "8002"]);
+        assert_eq!(
+            matches.get_one::<String>("port"),
+            Some(&String::from("8002"))
+        );
+    }
+
+    #[test_case("unknown" ; "unknown option")]
+    #[test_case("config" ; "config option")]
+    fn config_invalid_option(key: &str) {
+        let result = apply_defaults(build_command(), values(&[(key, ConfigValue::Bool(true))]));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn config_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("anifunnel.toml");
+        std::fs::write(
+            &path,
+            "anilist_token = \"A\"\nport = 8001\nmulti_season = true\nkeys = [\"a\"]\n",
+        )
+        .unwrap();
+        let values = load(&path).unwrap();
+        assert_eq!(
+            values.get("anilist_token"),
+            Some(&ConfigValue::String(String::from("A")))
+        );
+        assert_eq!(values.get("port"), Some(&ConfigValue::Integer(8001)));
+        assert_eq!(values.get("multi_season"), Some(&ConfigValue::Bool(true)));
+        assert_eq!(
+            values.get("keys"),
+            Some(&ConfigValue::List(vec![String::from("a")]))
+        );
+    }
+
+    #[test]
+    fn config_load_notifiers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("anifunnel.toml");
+        std::fs::write(
+            &path,
+            "port = 8001\n\n[[notifications]]\nkind = \"ntfy\"\n\
+            url = \"https://ntfy.sh/anifunnel\"\nevents = [\"error\", \"token_expiring\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            load(&path).unwrap().into_keys().collect::<Vec<String>>(),
+            vec!["port"]
+        );
+        assert_eq!(
+            notifiers(&path).unwrap(),
+            vec![notifiers::Notifier {
+                kind: notifiers::NotifierKind::Ntfy,
+                url: String::from("https://ntfy.sh/anifunnel"),
+                token: None,
+                events: vec![
+                    notifiers::NotifierEvent::Error,
+                    notifiers::NotifierEvent::TokenExpiring
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn config_load_missing() {
+        assert!(load(Path::new("/nonexistent/anifunnel.toml")).is_err());
+    }
+}
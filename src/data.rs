@@ -1,152 +1,4010 @@
-pub mod context {
-    use serde::Serialize;
+pub mod api {
+    use regex::Regex;
+    use rocket::http::{Header, Status};
+    use rocket::serde::json::Json;
+    use rocket::time::{format_description, Date};
+    use serde::{Deserialize, Serialize};
+    use std::collections::{BTreeMap, BTreeSet};
+    use strsim::normalized_levenshtein;
 
-    use crate::anilist;
-    use crate::data::state;
+    use crate::data::{forms, state};
+    use crate::{anilist, logging, notifiers, plex};
 
-    #[derive(Serialize)]
-    pub struct Anime {
+    #[derive(Debug, PartialEq, Serialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum HealthStatus {
+        Ok,
+        Error,
+    }
+
+    /// Response body for the health check and readiness endpoints.
+    #[derive(Debug, Serialize)]
+    pub struct Health {
+        pub status: HealthStatus,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub reason: Option<&'static str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub user: Option<String>,
+    }
+
+    impl Health {
+        pub fn ok() -> Self {
+            Self {
+                status: HealthStatus::Ok,
+                reason: None,
+                user: None,
+            }
+        }
+
+        pub fn error(reason: &'static str) -> Self {
+            Self {
+                status: HealthStatus::Error,
+                reason: Some(reason),
+                user: None,
+            }
+        }
+    }
+
+    /// Connected Anilist account.
+    #[derive(Debug, Serialize)]
+    pub struct User {
         pub id: i32,
+        pub name: String,
+        pub avatar: Option<String>,
+        pub profile_color: Option<String>,
+        pub score_format: Option<String>,
+    }
+
+    impl User {
+        pub fn build(user: &anilist::User) -> Self {
+            Self {
+                id: user.id,
+                name: user.name.clone(),
+                avatar: user.avatar.as_ref().and_then(|x| x.large.clone()),
+                profile_color: user.options.as_ref().and_then(|x| x.profileColor.clone()),
+                score_format: user
+                    .mediaListOptions
+                    .as_ref()
+                    .and_then(|x| x.scoreFormat.clone()),
+            }
+        }
+    }
+
+    /// Stored Anilist token, without the token itself.
+    #[derive(Debug, Serialize)]
+    pub struct Token {
+        pub id: u32,
+        pub label: String,
+        pub user: String,
+        pub active: bool,
+        /// Seconds until the token expires, negative if it already has.
+        pub expires_in: Option<i64>,
+    }
+
+    impl Token {
+        pub fn build(account: &state::Account, active: bool) -> Self {
+            Self {
+                id: account.id,
+                label: account.label.clone(),
+                user: account.user.name.clone(),
+                active,
+                expires_in: anilist::token_expiry(&account.token)
+                    .map(|x| x as i64 - state::unix_timestamp() as i64),
+            }
+        }
+    }
+
+    /// Configured notifier, without the token itself.
+    #[derive(Debug, Serialize)]
+    pub struct Notifier {
+        pub id: u32,
+        pub kind: notifiers::NotifierKind,
+        pub url: String,
+        pub token_set: bool,
+        pub events: Vec<notifiers::NotifierEvent>,
+    }
+
+    impl Notifier {
+        pub fn build(id: u32, notifier: &notifiers::Notifier) -> Self {
+            Self {
+                id,
+                kind: notifier.kind,
+                url: notifier.url.clone(),
+                token_set: notifier.token.is_some(),
+                events: notifier.events.clone(),
+            }
+        }
+    }
+
+    /// Seconds that webhook senders are asked to wait when anifunnel is too busy.
+    const BUSY_RETRY_AFTER: u64 = 30;
+
+    /// Response to Plex webhooks.
+    #[derive(Responder)]
+    pub enum ScrobbleResponse {
+        #[response(status = 200)]
+        Processed(&'static str),
+        #[response(status = 503)]
+        Busy(&'static str, Header<'static>),
+        /// Details of the decision for clients that asked for JSON.
+        #[response(status = 200)]
+        Report(Json<ScrobbleReport>),
+    }
+
+    /// How a webhook was processed: the plain text response as the decision, along with
+    /// the codes of the reasons for it and the matched Anilist entry.
+    #[derive(Debug, Default, Serialize)]
+    pub struct ScrobbleReport {
+        pub decision: &'static str,
+        pub reasons: Vec<&'static str>,
+        /// History outcome of a matched scrobble or rating.
+        pub outcome: Option<state::HistoryOutcome>,
+        pub media: Option<ScrobbleMedia>,
+        /// Title match confidence. Not set for matches from overrides and mappings.
+        pub confidence: Option<f64>,
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct ScrobbleMedia {
+        pub anilist_id: i32,
+        pub title: String,
+        /// Anilist episode after offsets and mappings. Not set for ratings.
+        pub episode: Option<i32>,
+    }
+
+    impl ScrobbleResponse {
+        pub fn busy() -> Self {
+            return ScrobbleResponse::Busy(
+                "BUSY",
+                Header::new("Retry-After", BUSY_RETRY_AFTER.to_string()),
+            );
+        }
+    }
+
+    /// Response with the override version of an entry as its entity tag.
+    #[derive(Responder)]
+    pub struct Versioned<R> {
+        pub inner: R,
+        pub etag: Header<'static>,
+    }
+
+    impl<R> Versioned<R> {
+        pub fn new(inner: R, version: &state::OverrideVersion) -> Self {
+            Self {
+                inner,
+                etag: Header::new("ETag", format!("\"{}\"", version.version)),
+            }
+        }
+    }
+
+    /// Anilist metadata of an anime.
+    #[derive(Debug, Serialize)]
+    pub struct AnimeDetails {
+        pub anilist_id: i32,
         pub title: String,
+        pub format: Option<String>,
+        pub episodes: Option<i32>,
+        pub season: Option<String>,
+        pub season_year: Option<i32>,
+        pub status: Option<String>,
+        pub cover_image: Option<String>,
+        pub cover_color: Option<String>,
+    }
+
+    impl AnimeDetails {
+        pub fn build(details: &anilist::MediaDetails) -> Self {
+            let cover = details.coverImage.as_ref();
+            Self {
+                anilist_id: details.id,
+                title: details.title.to_string(),
+                format: details.format.clone(),
+                episodes: details.episodes,
+                season: details.season.clone(),
+                season_year: details.seasonYear,
+                status: details.status.clone(),
+                cover_image: cover.and_then(|x| x.large.clone()),
+                cover_color: cover.and_then(|x| x.color.clone()),
+            }
+        }
+    }
+
+    /// Current overrides of a single entry.
+    #[derive(Debug, Serialize)]
+    pub struct Overrides {
+        pub anilist_id: i32,
+        pub title: Option<String>,
+        pub guid: Option<String>,
+        pub pattern: Option<String>,
         pub episode_offset: Option<i32>,
-        pub title_override: Option<String>,
+        pub muted_until: Option<String>,
+        pub minimum_confidence: Option<f64>,
+        pub log_only: bool,
+        pub ignore_ratings: bool,
+        pub ignored: bool,
+        #[serde(flatten)]
+        pub version: state::OverrideVersion,
     }
 
-    impl Anime {
+    /// Overrides of a single entry in a bulk edit, replacing all of its current ones.
+    #[derive(Debug, Deserialize)]
+    pub struct OverrideEdit {
+        pub anilist_id: i32,
+        pub title: Option<String>,
+        pub guid: Option<String>,
+        pub pattern: Option<String>,
+        pub episode_offset: Option<i32>,
+        pub muted_until: Option<String>,
+        pub minimum_confidence: Option<f64>,
+        #[serde(default)]
+        pub log_only: bool,
+        #[serde(default)]
+        pub ignore_ratings: bool,
+        #[serde(default)]
+        pub ignored: bool,
+        /// Version of the overrides that the edit is based on.
+        pub version: Option<u64>,
+    }
+
+    impl OverrideEdit {
+        /// Validate the edit the same way as the management interface form.
+        pub fn to_form(self: &Self) -> Result<forms::AnimeOverride<'_>, &'static str> {
+            let pattern = self.pattern.as_deref().filter(|x| !x.is_empty());
+            if pattern.is_some_and(|x| Regex::new(x).is_err()) {
+                return Err("invalid title pattern");
+            }
+            if self
+                .minimum_confidence
+                .is_some_and(|x| !(0.0..=1.0).contains(&x))
+            {
+                return Err("minimum confidence must be 0-1");
+            }
+            let date_format = format_description::parse("[year]-[month]-[day]").unwrap();
+            let muted_until = match self.muted_until.as_deref().filter(|x| !x.is_empty()) {
+                Some(muted_until) => match Date::parse(muted_until, &date_format) {
+                    Ok(muted_until) => Some(muted_until),
+                    Err(_) => return Err("invalid mute date"),
+                },
+                None => None,
+            };
+            return Ok(forms::AnimeOverride {
+                episode_offset: self.episode_offset,
+                title: self.title.as_deref(),
+                guid: self.guid.as_deref(),
+                pattern,
+                muted_until,
+                minimum_confidence: self.minimum_confidence,
+                log_only: self.log_only,
+                ignore_ratings: self.ignore_ratings,
+                ignored: self.ignored,
+                version: self.version,
+            });
+        }
+    }
+
+    /// Edit in a bulk edit that could not be applied.
+    #[derive(Debug, Serialize)]
+    pub struct BulkEditError {
+        /// Position of the edit in the request.
+        pub index: usize,
+        pub anilist_id: i32,
+        pub error: String,
+    }
+
+    /// Response to a bulk edit. Nothing is applied if any of the edits is invalid.
+    #[derive(Responder)]
+    pub enum BulkEditResponse {
+        #[response(status = 200)]
+        Applied(Json<Vec<BulkEditResult>>),
+        #[response(status = 422)]
+        Invalid(Json<Vec<BulkEditError>>),
+    }
+
+    /// New override version of an entry after a bulk edit.
+    #[derive(Debug, Serialize)]
+    pub struct BulkEditResult {
+        pub anilist_id: i32,
+        #[serde(flatten)]
+        pub version: state::OverrideVersion,
+    }
+
+    /// Response body for failed requests.
+    #[derive(Debug, Serialize)]
+    pub struct Error {
+        pub status: u16,
+        pub error: &'static str,
+        /// Likely causes of the error, e.g. a misconfigured reverse proxy.
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        pub hints: Vec<&'static str>,
+    }
+
+    impl Error {
+        pub fn new(status: Status) -> Self {
+            Self {
+                status: status.code,
+                error: status.reason_lossy(),
+                hints: Vec::new(),
+            }
+        }
+
+        pub fn with_hints(self: Self, hints: Vec<&'static str>) -> Self {
+            Self { hints, ..self }
+        }
+    }
+
+    /// Effective settings with secrets replaced by whether they are set.
+    #[derive(Debug, Serialize)]
+    pub struct Settings {
+        pub multi_season: bool,
+        pub movies: bool,
+        pub inactive_lists: bool,
+        pub sync_ratings: bool,
+        pub remember_resolved: bool,
+        pub trakt: bool,
+        pub discord: bool,
+        pub plex_user: Option<String>,
+        pub plex_servers: Vec<String>,
+        pub plex_libraries: Vec<String>,
+        pub account_filter: plex::AccountFilter,
+        pub rewatch_policy: state::RewatchPolicy,
+        pub conflict_policy: state::ConflictPolicy,
+        pub scrobble_debounce: Option<u64>,
+        pub minimum_watch_time: Option<u8>,
+        pub max_pending_webhooks: Option<usize>,
+        pub minimum_confidence: f64,
+        pub webhook_token_set: bool,
+        pub admin_password_set: bool,
+        pub admin_api_keys: usize,
+        pub read_only_api_keys: usize,
+    }
+
+    impl Settings {
+        pub fn build(state: &state::Global) -> Self {
+            Self {
+                multi_season: state.multi_season,
+                movies: state.movies,
+                inactive_lists: state.inactive_lists,
+                sync_ratings: state.sync_ratings,
+                remember_resolved: state.remember_resolved,
+                trakt: state.trakt.is_some(),
+                discord: state.discord.is_some(),
+                plex_user: state.plex_user.clone(),
+                plex_servers: state.plex_servers.clone(),
+                plex_libraries: state.plex_libraries.clone(),
+                account_filter: state.account_filter,
+                rewatch_policy: state.rewatch_policy,
+                conflict_policy: state.conflict_policy,
+                scrobble_debounce: state.scrobble_debounce.map(|x| x.as_secs()),
+                minimum_watch_time: state.minimum_watch_time,
+                max_pending_webhooks: state.webhook_limit.limit(),
+                minimum_confidence: state.match_settings.minimum_confidence,
+                webhook_token_set: state.webhook_token.is_some(),
+                admin_password_set: state.admin_password.is_some(),
+                admin_api_keys: state.admin_api_keys.len(),
+                read_only_api_keys: state.read_only_api_keys.len(),
+            }
+        }
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct RelationsRefresh {
+        /// Number of watching list entries with a known position in their franchise.
+        pub entries: usize,
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct Maintenance {
+        pub enabled: bool,
+        pub queued: usize,
+    }
+
+    impl Maintenance {
+        pub fn build(maintenance: &state::Maintenance) -> Self {
+            Self {
+                enabled: maintenance.enabled,
+                queued: maintenance.queued(),
+            }
+        }
+    }
+
+    /// Overview of whether anifunnel is receiving webhooks and updating Anilist.
+    #[derive(Debug, Serialize)]
+    pub struct SystemStatus {
+        pub uptime_seconds: u64,
+        pub last_webhook: Option<u64>,
+        pub last_update: Option<u64>,
+        /// Seconds until the Anilist token expires, negative if it already has.
+        pub token_expires_in: Option<i64>,
+        pub watch_sessions: usize,
+        pub history_entries: usize,
+        /// Number of history entries from each source.
+        pub history_sources: BTreeMap<String, usize>,
+        pub sync_paused: bool,
+        /// When syncing was paused, if it is.
+        pub sync_paused_since: Option<u64>,
+    }
+
+    impl SystemStatus {
+        pub fn build(
+            accounts: &state::Accounts,
+            activity: &state::Activity,
+            sessions: &state::WatchSessions,
+            history: &state::History,
+            sync_pause: &state::SyncPause,
+        ) -> Self {
+            Self {
+                uptime_seconds: activity.started.elapsed().as_secs(),
+                last_webhook: activity.last_webhook,
+                last_update: activity.last_update,
+                token_expires_in: anilist::token_expiry(&accounts.active().token)
+                    .map(|x| x as i64 - state::unix_timestamp() as i64),
+                watch_sessions: sessions.iter().count(),
+                history_entries: history.iter().count(),
+                history_sources: history.source_counts(),
+                sync_paused: sync_pause.is_paused(),
+                sync_paused_since: sync_pause.paused_since,
+            }
+        }
+    }
+
+    /// Step of a replayed webhook and what was decided in it.
+    #[derive(Debug, Serialize)]
+    pub struct ReplayStep {
+        pub step: &'static str,
+        pub detail: String,
+    }
+
+    /// Number of episodes synced to Anilist on a single day.
+    #[derive(Debug, Serialize)]
+    pub struct ActivityDay {
+        pub date: String,
+        pub episodes: usize,
+    }
+
+    impl ActivityDay {
+        /// Episode counts for every day of the last year up to today, including days
+        /// without any episodes.
+        pub fn build_year(history: &state::History) -> Vec<Self> {
+            let today = state::today();
+            let mut date = today - rocket::time::Duration::days(364);
+            let counts = history.daily_updates(date);
+            let mut result = Vec::new();
+            while date <= today {
+                result.push(Self {
+                    date: date.to_string(),
+                    episodes: counts.get(&date).copied().unwrap_or(0),
+                });
+                date = date.next_day().unwrap();
+            }
+            return result;
+        }
+    }
+
+    /// Number of watch history items replayed by a catch-up sync per result.
+    #[derive(Debug, Default, Serialize)]
+    pub struct CatchUp {
+        pub processed: usize,
+        /// Items rejected by the Plex filters or that did not change anything.
+        pub ignored: usize,
+        pub failed: usize,
+    }
+
+    /// Number of stored unmatched scrobbles replayed for an entry per result.
+    #[derive(Debug, Default, Serialize)]
+    pub struct AppliedUnmatched {
+        pub processed: usize,
+        /// Scrobbles that did not change anything, e.g. because they still did not match.
+        pub ignored: usize,
+        pub failed: usize,
+    }
+
+    /// Result of a self-test scrobble.
+    #[derive(Debug, Serialize)]
+    pub struct SelfTest {
+        pub anilist_id: i32,
+        pub title: String,
+        /// Plex episode number of the fabricated scrobble.
+        pub episode: i32,
+        /// Entry that the scrobble was matched to.
+        pub matched_id: Option<i32>,
+        pub outcome: Option<state::HistoryOutcome>,
+        /// Whether the scrobble matched the entry and updated its progress.
+        pub passed: bool,
+    }
+
+    /// Trace of how a webhook would be processed.
+    #[derive(Debug, Default, Serialize)]
+    pub struct Replay {
+        pub steps: Vec<ReplayStep>,
+        /// Scores of the best fuzzy match candidates, if fuzzy matching was reached.
+        pub candidates: Vec<anilist::MatchCandidate>,
+        /// What would have been done with the webhook.
+        pub action: String,
+    }
+
+    /// Result of matching a title against the watching list without a scrobble.
+    #[derive(Debug, Serialize)]
+    pub struct MatchReport {
+        pub title: String,
+        /// Title override or pattern that decided the match instead of fuzzy matching.
+        pub override_id: Option<i32>,
+        pub outcome: &'static str,
+        pub matched_id: Option<i32>,
+        pub candidates: Vec<anilist::MatchCandidate>,
+    }
+
+    impl MatchReport {
+        pub fn build(
+            title: &str,
+            override_id: Option<i32>,
+            title_match: &anilist::TitleMatch,
+            candidates: Vec<anilist::MatchCandidate>,
+        ) -> Self {
+            let (outcome, matched_id) = match title_match {
+                anilist::TitleMatch::Found(media_list) => ("found", Some(media_list.id)),
+                anilist::TitleMatch::Ambiguous(_) => ("ambiguous", None),
+                anilist::TitleMatch::NotFound => ("not_found", None),
+            };
+            Self {
+                title: title.to_string(),
+                override_id,
+                outcome,
+                matched_id,
+                candidates,
+            }
+        }
+    }
+
+    impl Replay {
+        pub fn step(self: &mut Self, step: &'static str, detail: String) {
+            self.steps.push(ReplayStep { step, detail });
+        }
+    }
+
+    /// Startup configuration for logging and attaching to issues.
+    #[derive(Debug, Serialize)]
+    pub struct ConfigSummary {
+        pub version: &'static str,
+        pub address: String,
+        pub port: u16,
+        /// Where overrides and history are stored.
+        pub storage: &'static str,
+        pub anilist_user: String,
+        /// Seconds until the Anilist token expires, negative if it already has.
+        pub token_expires_in: Option<i64>,
+        pub settings: Settings,
+    }
+
+    impl ConfigSummary {
+        pub fn build(
+            config: &rocket::Config,
+            state: &state::Global,
+            accounts: &state::Accounts,
+        ) -> Self {
+            Self {
+                version: env!("CARGO_PKG_VERSION"),
+                address: config.address.to_string(),
+                port: config.port,
+                storage: "memory",
+                anilist_user: accounts.active().user.name.clone(),
+                token_expires_in: anilist::token_expiry(&accounts.active().token)
+                    .map(|x| x as i64 - state::unix_timestamp() as i64),
+                settings: Settings::build(state),
+            }
+        }
+    }
+
+    /// Anilist progress of a matched show.
+    #[derive(Debug, PartialEq, Serialize)]
+    pub struct TrackerProgress {
+        pub progress: i32,
+        pub status: Option<String>,
+    }
+
+    /// Local and tracker state of a show that has been matched by a scrobble.
+    #[derive(Debug, Serialize)]
+    pub struct SyncStatus {
+        pub anilist_id: i32,
+        pub title: String,
+        /// Plex episode number of the latest scrobble, before any episode offset.
+        pub last_scrobbled_episode: i32,
+        pub last_scrobbled_at: u64,
+        pub last_outcome: state::HistoryOutcome,
+        pub episode_offset: Option<i32>,
+        /// Progress reported by each tracker, none if the show is not on the
+        /// watching list or the tracker could not be reached.
+        pub trackers: BTreeMap<&'static str, Option<TrackerProgress>>,
+        /// Webhooks for the show waiting in the maintenance queue.
+        pub queued: usize,
+    }
+
+    impl SyncStatus {
+        pub fn build(
+            history: &state::History,
+            media_list_group: Option<&anilist::MediaListGroup>,
+            episode_offsets: &state::EpisodeOverrides,
+            maintenance: &state::Maintenance,
+        ) -> Vec<Self> {
+            let mut latest: BTreeMap<i32, &state::HistoryEntry> = BTreeMap::new();
+            for entry in history.iter() {
+                if let Some(anilist_id) = entry.anilist_id {
+                    latest.insert(anilist_id, entry);
+                }
+            }
+            let queued: Vec<plex::Webhook> = maintenance
+                .iter_queued()
+                .filter_map(|x| serde_json::from_str(x).ok())
+                .collect();
+            return latest
+                .into_iter()
+                .map(|(anilist_id, entry)| {
+                    let anilist = media_list_group
+                        .and_then(|x| x.find_id(&anilist_id))
+                        .map(|x| TrackerProgress {
+                            progress: x.progress,
+                            status: x.status.clone(),
+                        });
+                    let titles: Vec<&String> = history
+                        .iter()
+                        .filter(|x| x.anilist_id == Some(anilist_id))
+                        .map(|x| &x.title)
+                        .collect();
+                    Self {
+                        anilist_id,
+                        title: entry.title.clone(),
+                        last_scrobbled_episode: entry.episode_number,
+                        last_scrobbled_at: entry.timestamp,
+                        last_outcome: entry.outcome.clone(),
+                        episode_offset: episode_offsets.get(&anilist_id),
+                        trackers: BTreeMap::from([("anilist", anilist)]),
+                        queued: queued
+                            .iter()
+                            .filter(|x| titles.contains(&&x.metadata.title))
+                            .count(),
+                    }
+                })
+                .collect();
+        }
+    }
+
+    /// How the Anilist progress of an entry compares to the last episode seen from Plex.
+    #[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum ProgressAlignment {
+        InSync,
+        AnilistAhead,
+        AnilistBehind,
+        /// No scrobble for the entry is in the history.
+        NotSeen,
+    }
+
+    /// Watching list entry with the last episode that anifunnel saw from Plex.
+    #[derive(Debug, Serialize)]
+    pub struct ProgressOverview {
+        pub anilist_id: i32,
+        pub title: String,
+        pub progress: i32,
+        pub episodes: Option<i32>,
+        /// Latest scrobbled episode with the episode offset applied, so that it can be
+        /// compared to the Anilist progress.
+        pub last_seen_episode: Option<i32>,
+        pub last_seen_at: Option<u64>,
+        /// Anilist progress minus the last seen episode.
+        pub delta: Option<i32>,
+        pub alignment: ProgressAlignment,
+    }
+
+    impl ProgressOverview {
         pub fn build(
             media_list_group: &anilist::MediaListGroup,
-            title_overrides: &state::TitleOverrides,
+            history: &state::History,
             episode_offsets: &state::EpisodeOverrides,
         ) -> Vec<Self> {
-            let mut result: Vec<Self> = Vec::new();
-            for (id, title) in media_list_group.get_context_values() {
-                let title_override = title_overrides.get_key(&id);
-                let episode_offset = episode_offsets.get(&id);
-                result.push(Self {
-                    id,
-                    title,
-                    episode_offset,
-                    title_override,
-                });
+            let mut latest: BTreeMap<i32, &state::HistoryEntry> = BTreeMap::new();
+            for entry in history.iter() {
+                if let Some(anilist_id) = entry.anilist_id {
+                    latest.insert(anilist_id, entry);
+                }
             }
-            result.sort_by(|a, b| a.title.cmp(&b.title));
+            return media_list_group
+                .iter()
+                .map(|media_list| {
+                    let entry = latest.get(&media_list.id);
+                    let last_seen_episode = entry.map(|x| {
+                        x.episode_number + episode_offsets.get(&media_list.id).unwrap_or(0)
+                    });
+                    let delta = last_seen_episode.map(|x| media_list.progress - x);
+                    let alignment = match delta {
+                        None => ProgressAlignment::NotSeen,
+                        Some(0) => ProgressAlignment::InSync,
+                        Some(delta) if delta > 0 => ProgressAlignment::AnilistAhead,
+                        Some(_) => ProgressAlignment::AnilistBehind,
+                    };
+                    Self {
+                        anilist_id: media_list.id,
+                        title: media_list.media.title.to_string(),
+                        progress: media_list.progress,
+                        episodes: media_list.media.episodes,
+                        last_seen_episode,
+                        last_seen_at: entry.map(|x| x.timestamp),
+                        delta,
+                        alignment,
+                    }
+                })
+                .collect();
+        }
+    }
+
+    /// Information for attaching to bug reports.
+    #[derive(Debug, Serialize)]
+    pub struct DebugBundle {
+        pub version: &'static str,
+        pub settings: Settings,
+        pub logs: Vec<logging::LogRecord>,
+        pub history: Vec<state::HistoryEntry>,
+        pub failed_payloads: Vec<String>,
+    }
+
+    /// Overrides for backing up or moving to another anifunnel instance.
+    #[derive(Debug, Serialize)]
+    pub struct Export {
+        pub version: &'static str,
+        /// Settings for reference. They come from the arguments and are not imported.
+        pub settings: Settings,
+        pub title_overrides: BTreeMap<String, i32>,
+        /// Title overrides of single accounts by the Anilist user ID.
+        pub account_title_overrides: BTreeMap<i32, BTreeMap<String, i32>>,
+        pub guid_overrides: BTreeMap<String, i32>,
+        pub title_patterns: BTreeMap<String, i32>,
+        pub episode_offsets: BTreeMap<i32, i32>,
+        pub mutes: BTreeMap<i32, String>,
+        pub minimum_confidences: BTreeMap<i32, f64>,
+        pub log_only: BTreeSet<i32>,
+        pub ignored_ratings: BTreeSet<i32>,
+        pub ignored: BTreeSet<i32>,
+        pub special_overrides: BTreeMap<i32, state::SpecialOverride>,
+        pub season_mappings: Vec<state::SeasonMapping>,
+    }
+
+    impl Export {
+        #[allow(clippy::too_many_arguments)]
+        pub fn build(
+            state: &state::Global,
+            title_overrides: &state::TitleOverrides,
+            account_title_overrides: &state::AccountTitleOverrides,
+            guid_overrides: &state::GuidOverrides,
+            title_patterns: &state::TitlePatterns,
+            episode_offsets: &state::EpisodeOverrides,
+            mutes: &state::Mutes,
+            minimum_confidences: &state::MinimumConfidences,
+            log_only: &state::LogOnly,
+            ignored_ratings: &state::IgnoredRatings,
+            ignored: &state::IgnoredEntries,
+            special_overrides: &state::SpecialOverrides,
+            season_mappings: &state::SeasonMappings,
+        ) -> Self {
+            Self {
+                version: env!("CARGO_PKG_VERSION"),
+                settings: Settings::build(state),
+                title_overrides: title_overrides
+                    .iter()
+                    .map(|(k, v)| (k.clone(), *v))
+                    .collect(),
+                account_title_overrides: account_title_overrides
+                    .iter()
+                    .map(|(user_id, overrides)| {
+                        let overrides = overrides.iter().map(|(k, v)| (k.clone(), *v));
+                        (*user_id, overrides.collect())
+                    })
+                    .collect(),
+                guid_overrides: guid_overrides
+                    .iter()
+                    .map(|(k, v)| (k.clone(), *v))
+                    .collect(),
+                title_patterns: title_patterns
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), *v))
+                    .collect(),
+                episode_offsets: episode_offsets.iter().map(|(k, v)| (*k, *v)).collect(),
+                mutes: mutes.iter().map(|(k, v)| (*k, v.to_string())).collect(),
+                minimum_confidences: minimum_confidences.iter().map(|(k, v)| (*k, *v)).collect(),
+                log_only: log_only.iter().copied().collect(),
+                ignored_ratings: ignored_ratings.iter().copied().collect(),
+                ignored: ignored.iter().copied().collect(),
+                special_overrides: special_overrides
+                    .iter()
+                    .map(|(k, v)| (*k, v.clone()))
+                    .collect(),
+                season_mappings: season_mappings.iter().collect(),
+            }
+        }
+    }
+
+    /// Overrides from an export. Unknown fields such as the settings are ignored.
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    pub struct Import {
+        pub title_overrides: BTreeMap<String, i32>,
+        pub account_title_overrides: BTreeMap<i32, BTreeMap<String, i32>>,
+        pub guid_overrides: BTreeMap<String, i32>,
+        pub title_patterns: BTreeMap<String, i32>,
+        pub episode_offsets: BTreeMap<i32, i32>,
+        pub mutes: BTreeMap<i32, String>,
+        pub minimum_confidences: BTreeMap<i32, f64>,
+        pub log_only: BTreeSet<i32>,
+        pub ignored_ratings: BTreeSet<i32>,
+        pub ignored: BTreeSet<i32>,
+        pub special_overrides: BTreeMap<i32, state::SpecialOverride>,
+        pub season_mappings: Vec<state::SeasonMapping>,
+    }
+
+    /// Anilist tokens and runtime settings of a primary instance, which standby
+    /// instances replicate along with the overrides export. Unlike the export, this
+    /// includes secrets.
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct ReplicationSnapshot {
+        pub accounts: Vec<ReplicatedAccount>,
+        pub discord_events: state::DiscordEvents,
+        pub notifiers: Vec<notifiers::Notifier>,
+        pub sync_pause: state::SyncPause,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct ReplicatedAccount {
+        pub label: String,
+        pub token: String,
+        pub active: bool,
+    }
+
+    impl ReplicationSnapshot {
+        pub fn build(
+            accounts: &state::Accounts,
+            discord_events: &state::DiscordEvents,
+            notifiers: &state::Notifiers,
+            sync_pause: &state::SyncPause,
+        ) -> Self {
+            let active = accounts.active().id;
+            Self {
+                accounts: accounts
+                    .iter()
+                    .filter(|x| !x.token.is_empty())
+                    .map(|x| ReplicatedAccount {
+                        label: x.label.clone(),
+                        token: x.token.clone(),
+                        active: x.id == active,
+                    })
+                    .collect(),
+                discord_events: discord_events.clone(),
+                notifiers: notifiers.iter().map(|(_, x)| x.clone()).collect(),
+                sync_pause: sync_pause.clone(),
+            }
+        }
+    }
+
+    /// Number of imported overrides per outcome.
+    #[derive(Debug, Default, PartialEq, Serialize)]
+    pub struct ImportSummary {
+        pub imported: usize,
+        /// Overrides that conflicted with existing ones and were not imported.
+        pub skipped: usize,
+        pub invalid: usize,
+    }
+
+    impl Import {
+        /// IDs of the entries that the import has overrides for.
+        pub fn ids(self: &Self) -> BTreeSet<i32> {
+            return self
+                .title_overrides
+                .values()
+                .chain(
+                    self.account_title_overrides
+                        .values()
+                        .flat_map(|x| x.values()),
+                )
+                .chain(self.guid_overrides.values())
+                .chain(self.title_patterns.values())
+                .chain(self.episode_offsets.keys())
+                .chain(self.mutes.keys())
+                .chain(self.minimum_confidences.keys())
+                .chain(self.log_only.iter())
+                .chain(self.ignored_ratings.iter())
+                .chain(self.ignored.iter())
+                .chain(self.special_overrides.keys())
+                .chain(self.season_mappings.iter().map(|x| &x.anilist_id))
+                .copied()
+                .collect();
+        }
+
+        pub fn apply(
+            self: Self,
+            conflict: forms::ImportConflict,
+            overrides: &mut state::OverridesMut<'_>,
+        ) -> ImportSummary {
+            for id in self.ids() {
+                overrides.override_versions.bump(id);
+            }
+            let state::OverridesMut {
+                title_overrides,
+                account_title_overrides,
+                guid_overrides,
+                title_patterns,
+                episode_offsets,
+                mutes,
+                minimum_confidences,
+                log_only,
+                ignored_ratings,
+                ignored,
+                special_overrides,
+                season_mappings,
+                ..
+            } = overrides;
+            let replace = conflict == forms::ImportConflict::Replace;
+            let mut summary = ImportSummary::default();
+            for (key, id) in self.title_overrides {
+                import_override(title_overrides, key, id, replace, &mut summary);
+            }
+            for (user_id, overrides) in self.account_title_overrides {
+                let account_overrides = account_title_overrides.account_mut(user_id);
+                for (key, id) in overrides {
+                    import_override(account_overrides, key, id, replace, &mut summary);
+                }
+            }
+            for (key, id) in self.guid_overrides {
+                import_override(guid_overrides, key, id, replace, &mut summary);
+            }
+            for (pattern, id) in self.title_patterns {
+                let pattern = match Regex::new(&pattern) {
+                    Ok(pattern) => pattern,
+                    Err(_) => {
+                        summary.invalid += 1;
+                        continue;
+                    }
+                };
+                if !replace
+                    && title_patterns
+                        .get_key(&id)
+                        .is_some_and(|x| x != pattern.as_str())
+                {
+                    summary.skipped += 1;
+                } else {
+                    title_patterns.set(pattern, id);
+                    summary.imported += 1;
+                }
+            }
+            for (id, episode_offset) in self.episode_offsets {
+                if episode_offset == 0 {
+                    summary.invalid += 1;
+                } else if !replace
+                    && episode_offsets
+                        .get(&id)
+                        .is_some_and(|x| x != episode_offset)
+                {
+                    summary.skipped += 1;
+                } else {
+                    episode_offsets.set(id, episode_offset);
+                    summary.imported += 1;
+                }
+            }
+            let date_format = format_description::parse("[year]-[month]-[day]").unwrap();
+            for (id, muted_until) in self.mutes {
+                let muted_until = match Date::parse(&muted_until, &date_format) {
+                    Ok(muted_until) => muted_until,
+                    Err(_) => {
+                        summary.invalid += 1;
+                        continue;
+                    }
+                };
+                if !replace && mutes.get(&id).is_some_and(|x| x != muted_until) {
+                    summary.skipped += 1;
+                } else {
+                    mutes.set(id, muted_until);
+                    summary.imported += 1;
+                }
+            }
+            for (id, minimum_confidence) in self.minimum_confidences {
+                if !(0.0..=1.0).contains(&minimum_confidence) {
+                    summary.invalid += 1;
+                } else if !replace
+                    && minimum_confidences
+                        .get(&id)
+                        .is_some_and(|x| x != minimum_confidence)
+                {
+                    summary.skipped += 1;
+                } else {
+                    minimum_confidences.set(id, minimum_confidence);
+                    summary.imported += 1;
+                }
+            }
+            for id in self.log_only {
+                log_only.set(id, true);
+                summary.imported += 1;
+            }
+            for id in self.ignored_ratings {
+                ignored_ratings.set(id, true);
+                summary.imported += 1;
+            }
+            for id in self.ignored {
+                ignored.set(id, true);
+                summary.imported += 1;
+            }
+            for (id, special_override) in self.special_overrides {
+                if !special_override.is_valid() {
+                    summary.invalid += 1;
+                } else if !replace
+                    && special_overrides
+                        .get(&id)
+                        .is_some_and(|x| x != &special_override)
+                {
+                    summary.skipped += 1;
+                } else {
+                    special_overrides.set(id, special_override);
+                    summary.imported += 1;
+                }
+            }
+            for season_mapping in self.season_mappings {
+                if !season_mapping.is_valid() {
+                    summary.invalid += 1;
+                } else if !replace
+                    && season_mappings
+                        .get(&season_mapping.title, season_mapping.season)
+                        .is_some_and(|x| x != season_mapping.anilist_id)
+                {
+                    summary.skipped += 1;
+                } else {
+                    season_mappings.set(season_mapping);
+                    summary.imported += 1;
+                }
+            }
+            return summary;
+        }
+    }
+
+    /// Import a title or GUID override. The override conflicts if the key is already
+    /// used for another ID or the ID already has an override with another key.
+    fn import_override(
+        overrides: &mut state::TitleOverrides,
+        key: String,
+        id: i32,
+        replace: bool,
+        summary: &mut ImportSummary,
+    ) {
+        let conflicts = overrides.get(&key).is_some_and(|x| x != id)
+            || overrides.get_key(&id).is_some_and(|x| x != key);
+        if conflicts && !replace {
+            summary.skipped += 1;
+            return;
+        }
+        overrides.remove_key(&key);
+        overrides.set(key, id);
+        summary.imported += 1;
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct Rewatch {
+        pub id: i32,
+        pub episode: i32,
+        pub count: u32,
+    }
+
+    impl Rewatch {
+        pub fn build(rewatches: &state::Rewatches) -> Vec<Self> {
+            let mut result: Vec<Self> = rewatches
+                .iter()
+                .map(|(&(id, episode), &count)| Self { id, episode, count })
+                .collect();
+            result.sort_by_key(|x| (x.id, x.episode));
             return result;
         }
-    }
-}
+    }
+
+    /// Minimum score for an override to be included in search results.
+    const OVERRIDE_SEARCH_MINIMUM_SCORE: f64 = 0.5;
+
+    /// Title override created from an Anilist ID or URL.
+    #[derive(Debug, Serialize)]
+    pub struct TitleOverride {
+        pub title: String,
+        pub anilist_id: i32,
+        pub anilist_title: String,
+        /// Anilist user ID of the account that the override is limited to.
+        pub anilist_user_id: Option<i32>,
+    }
+
+    /// Anilist anime matching a search query, for picking the target of an override.
+    #[derive(Debug, Serialize)]
+    pub struct AnilistSearchResult {
+        pub anilist_id: i32,
+        pub title: String,
+        pub format: Option<String>,
+        pub episodes: Option<i32>,
+    }
+
+    impl AnilistSearchResult {
+        pub fn build(media: &[anilist::Media]) -> Vec<Self> {
+            return media
+                .iter()
+                .map(|x| Self {
+                    anilist_id: x.id,
+                    title: x.title.to_string(),
+                    format: x.format.clone(),
+                    episodes: x.episodes,
+                })
+                .collect();
+        }
+    }
+
+    /// Title override matching a search query.
+    #[derive(Debug, Serialize)]
+    pub struct OverrideSearchResult {
+        pub title: String,
+        pub anilist_id: i32,
+        pub anilist_title: Option<String>,
+        pub score: f64,
+    }
+
+    impl OverrideSearchResult {
+        /// Search the title overrides by their Plex title and, when the watching list
+        /// is available, by the Anilist title. Best matches first.
+        pub fn search(
+            title_overrides: &state::TitleOverrides,
+            media_list_group: Option<&anilist::MediaListGroup>,
+            query: &str,
+        ) -> Vec<Self> {
+            let query = query.trim().to_lowercase();
+            let mut results: Vec<Self> = title_overrides
+                .iter()
+                .map(|(title, &anilist_id)| {
+                    let anilist_title = media_list_group
+                        .and_then(|x| x.find_id(&anilist_id))
+                        .map(|x| x.media.title.to_string());
+                    let score = std::iter::once(title)
+                        .chain(anilist_title.as_ref())
+                        .map(|x| search_score(&query, x))
+                        .fold(0.0, f64::max);
+                    Self {
+                        title: title.clone(),
+                        anilist_id,
+                        anilist_title,
+                        score,
+                    }
+                })
+                .filter(|x| x.score >= OVERRIDE_SEARCH_MINIMUM_SCORE)
+                .collect();
+            results.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.title.cmp(&b.title)));
+            return results;
+        }
+    }
+
+    /// How well a lowercase query matches a title. Titles containing the query are
+    /// always a full match.
+    fn search_score(query: &str, title: &str) -> f64 {
+        let title = title.to_lowercase();
+        if title.contains(query) {
+            return 1.0;
+        }
+        return normalized_levenshtein(query, &title);
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct Session {
+        pub key: String,
+        pub title: String,
+        pub season_number: i32,
+        pub episode_number: i32,
+        pub playing: bool,
+        pub watched_seconds: u64,
+        pub progress: Option<f64>,
+        pub duration_seconds: Option<u64>,
+    }
+
+    impl Session {
+        pub fn build(sessions: &state::WatchSessions) -> Vec<Self> {
+            let mut result: Vec<Self> = sessions
+                .iter()
+                .map(|(key, session)| Self {
+                    key: key.clone(),
+                    title: session.title.clone(),
+                    season_number: session.season_number,
+                    episode_number: session.episode_number,
+                    playing: session.is_playing(),
+                    watched_seconds: session.watched().as_secs(),
+                    progress: session.progress(),
+                    duration_seconds: session.duration.map(|x| x.as_secs()),
+                })
+                .collect();
+            result.sort_by(|a, b| a.key.cmp(&b.key));
+            return result;
+        }
+    }
+
+    /// Currently playing episode for dashboards.
+    #[derive(Debug, Serialize)]
+    pub struct NowWatching {
+        pub account: String,
+        pub title: String,
+        pub season_number: i32,
+        pub episode_number: i32,
+        pub progress: Option<f64>,
+    }
+
+    impl NowWatching {
+        pub fn build(sessions: &state::WatchSessions) -> Vec<Self> {
+            let mut result: Vec<Self> = sessions
+                .iter()
+                .filter(|(_, session)| session.is_playing())
+                .map(|(_, session)| Self {
+                    account: session.account.clone(),
+                    title: session.title.clone(),
+                    season_number: session.season_number,
+                    episode_number: session.episode_number,
+                    progress: session.progress(),
+                })
+                .collect();
+            result.sort_by(|a, b| a.account.cmp(&b.account).then(a.title.cmp(&b.title)));
+            return result;
+        }
+    }
+}
+
+pub mod context {
+    use serde::Serialize;
+
+    use crate::anilist;
+    use crate::data::state;
+
+    #[derive(Serialize)]
+    pub struct Anime {
+        pub id: i32,
+        pub title: String,
+        pub progress: i32,
+        pub episode_offset: Option<i32>,
+        pub title_override: Option<String>,
+        pub guid_override: Option<String>,
+        pub title_pattern: Option<String>,
+        pub muted_until: Option<String>,
+        pub minimum_confidence: Option<f64>,
+        pub log_only: bool,
+        pub ignore_ratings: bool,
+        pub ignored: bool,
+        pub version: u64,
+    }
+
+    impl Anime {
+        #[allow(clippy::too_many_arguments)]
+        pub fn build(
+            media_list_group: &anilist::MediaListGroup,
+            title_overrides: &state::TitleOverrides,
+            guid_overrides: &state::GuidOverrides,
+            title_patterns: &state::TitlePatterns,
+            episode_offsets: &state::EpisodeOverrides,
+            mutes: &state::Mutes,
+            minimum_confidences: &state::MinimumConfidences,
+            log_only: &state::LogOnly,
+            ignored_ratings: &state::IgnoredRatings,
+            ignored: &state::IgnoredEntries,
+            override_versions: &state::OverrideVersions,
+        ) -> Vec<Self> {
+            let mut result: Vec<Self> = Vec::new();
+            for (id, title, progress) in media_list_group.get_context_values() {
+                let title_override = title_overrides.get_key(&id);
+                let guid_override = guid_overrides.get_key(&id);
+                let episode_offset = episode_offsets.get(&id);
+                let title_pattern = title_patterns.get_key(&id);
+                let muted_until = mutes.get(&id).map(|x| x.to_string());
+                result.push(Self {
+                    id,
+                    title,
+                    progress,
+                    episode_offset,
+                    title_override,
+                    guid_override,
+                    title_pattern,
+                    muted_until,
+                    minimum_confidence: minimum_confidences.get(&id),
+                    log_only: log_only.contains(&id),
+                    ignore_ratings: ignored_ratings.contains(&id),
+                    ignored: ignored.contains(&id),
+                    version: override_versions.get(&id).version,
+                });
+            }
+            result.sort_by(|a, b| a.title.cmp(&b.title));
+            return result;
+        }
+    }
+}
+
+pub mod forms {
+    use regex::Regex;
+    use rocket::data::{self, Data, FromData, ToByteUnit};
+    use rocket::form::{self, Form};
+    use rocket::http::Status;
+    use rocket::outcome::Outcome;
+    use rocket::request::Request;
+    use rocket::time::Date;
+
+    use crate::notifiers;
+
+    #[derive(Debug, FromForm)]
+    pub struct Scrobble<'r> {
+        pub payload: &'r str,
+    }
+
+    /// Plex payload of a webhook, either in the `payload` field of a form as sent by
+    /// Plex or as the whole body of an `application/json` request.
+    #[derive(Debug)]
+    pub struct ScrobblePayload(pub String);
+
+    #[rocket::async_trait]
+    impl<'r> FromData<'r> for ScrobblePayload {
+        type Error = form::Errors<'r>;
+
+        async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+            if !request.content_type().is_some_and(|x| x.is_json()) {
+                return Form::<Scrobble<'r>>::from_data(request, data)
+                    .await
+                    .map(|x| ScrobblePayload(x.payload.to_string()));
+            }
+            let limit = request.limits().get("json").unwrap_or(1.mebibytes());
+            return match data.open(limit).into_string().await {
+                Ok(payload) if payload.is_complete() => {
+                    Outcome::Success(ScrobblePayload(payload.into_inner()))
+                }
+                Ok(_) => Outcome::Error((
+                    Status::PayloadTooLarge,
+                    form::Error::from((None, Some(limit))).into(),
+                )),
+                Err(error) => {
+                    Outcome::Error((Status::BadRequest, form::Error::custom(error).into()))
+                }
+            };
+        }
+    }
+
+    #[derive(Debug, FromForm)]
+    pub struct Login<'r> {
+        pub password: &'r str,
+    }
+
+    #[derive(Debug, FromForm)]
+    pub struct AnimeOverride<'r> {
+        pub episode_offset: Option<i32>,
+        pub title: Option<&'r str>,
+        pub guid: Option<&'r str>,
+        #[field(validate = valid_pattern())]
+        pub pattern: Option<&'r str>,
+        /// Last day (UTC) on which scrobbles for the entry are ignored.
+        pub muted_until: Option<Date>,
+        /// Fuzzy match confidence needed for the entry to be matched.
+        #[field(validate = valid_confidence())]
+        pub minimum_confidence: Option<f64>,
+        /// Only log scrobbles for the entry instead of updating Anilist.
+        pub log_only: bool,
+        /// Do not sync Plex ratings of the entry to its Anilist score.
+        pub ignore_ratings: bool,
+        /// Never update the entry from Plex, e.g. for shows tracked manually.
+        pub ignored: bool,
+        /// Version of the overrides that the edit is based on. Same as If-Match.
+        pub version: Option<u64>,
+    }
+
+    #[derive(Debug, FromForm)]
+    pub struct Maintenance {
+        pub enabled: bool,
+    }
+
+    /// Discord events to enable or disable. Events that are left out are not changed.
+    #[derive(Debug, FromForm)]
+    pub struct DiscordEvents {
+        pub updated: Option<bool>,
+        pub unmatched: Option<bool>,
+        pub failed: Option<bool>,
+    }
+
+    #[derive(Debug, FromForm)]
+    pub struct Progress {
+        #[field(validate = range(0..))]
+        pub progress: i32,
+    }
+
+    /// Plex specials to track as an Anilist entry. Empty episodes remove the override.
+    #[derive(Debug, FromForm)]
+    pub struct SpecialOverride<'r> {
+        /// Plex title of the show that the specials are in.
+        pub title: &'r str,
+        /// Comma-separated Plex episode numbers in season 0, e.g. 3,5.
+        #[field(validate = valid_special_episodes())]
+        pub episodes: &'r str,
+    }
+
+    impl SpecialOverride<'_> {
+        pub fn episodes(self: &Self) -> Vec<i32> {
+            return self
+                .episodes
+                .split(',')
+                .filter_map(|x| x.trim().parse().ok())
+                .collect();
+        }
+    }
+
+    /// Plex season of a show to track as an Anilist entry.
+    #[derive(Debug, FromForm)]
+    pub struct SeasonMapping<'r> {
+        #[field(validate = len(1..))]
+        pub title: &'r str,
+        #[field(validate = range(1..))]
+        pub season: i32,
+        #[field(validate = valid_target())]
+        pub target: &'r str,
+    }
+
+    impl SeasonMapping<'_> {
+        /// Anilist media ID of the validated target.
+        pub fn get_anilist_id(self: &Self) -> i32 {
+            return parse_target(self.target).unwrap_or_default();
+        }
+    }
+
+    #[derive(Debug, FromForm)]
+    pub struct SeasonMappingRemove<'r> {
+        pub title: &'r str,
+        pub season: i32,
+    }
+
+    /// Check that special episodes are a comma-separated list of positive numbers.
+    fn valid_special_episodes<'v>(episodes: &str) -> form::Result<'v, ()> {
+        if episodes.trim().is_empty() {
+            return Ok(());
+        }
+        let valid = episodes
+            .split(',')
+            .all(|x| x.trim().parse::<i32>().is_ok_and(|x| x >= 1));
+        if !valid {
+            return Err(form::Error::validation("invalid special episode numbers").into());
+        }
+        return Ok(());
+    }
+
+    /// Check that a title pattern is a valid regular expression.
+    fn valid_pattern<'v>(pattern: &Option<&str>) -> form::Result<'v, ()> {
+        if let Some(pattern) = pattern {
+            if Regex::new(pattern).is_err() {
+                return Err(form::Error::validation("invalid title pattern").into());
+            }
+        }
+        return Ok(());
+    }
+
+    /// Check that a minimum match confidence is between 0 and 1.
+    fn valid_confidence<'v>(confidence: &Option<f64>) -> form::Result<'v, ()> {
+        if let Some(confidence) = confidence {
+            if !(0.0..=1.0).contains(confidence) {
+                return Err(form::Error::validation("minimum confidence must be 0-1").into());
+            }
+        }
+        return Ok(());
+    }
+
+    /// What to do with imported overrides that conflict with existing ones.
+    #[derive(Clone, Copy, Debug, Default, FromFormField, PartialEq)]
+    pub enum ImportConflict {
+        #[default]
+        Skip,
+        Replace,
+    }
+
+    #[derive(Debug, FromForm)]
+    pub struct UnmatchedResolve {
+        /// Anilist entry to resolve to, the best fuzzy match candidate if not given.
+        pub anilist_id: Option<i32>,
+        /// Keep the title override afterwards, the --remember-resolved setting if not
+        /// given.
+        pub remember: Option<bool>,
+    }
+
+    /// Title override pointing at an Anilist entry given by its ID or URL.
+    #[derive(Debug, FromForm)]
+    pub struct TitleOverride<'r> {
+        #[field(validate = len(1..))]
+        pub title: &'r str,
+        #[field(validate = valid_target())]
+        pub target: &'r str,
+        /// Only apply the override to the active Anilist account.
+        pub account: bool,
+    }
+
+    impl TitleOverride<'_> {
+        /// Anilist media ID of the validated target.
+        pub fn get_anilist_id(self: &Self) -> i32 {
+            return parse_target(self.target).unwrap_or_default();
+        }
+    }
+
+    #[derive(Debug, FromForm)]
+    pub struct TitleOverrideRemove<'r> {
+        pub title: &'r str,
+        /// Remove the override of the active Anilist account instead of the shared one.
+        pub account: bool,
+    }
+
+    /// Parse an Anilist media ID from the ID itself or from the URL of the anime page,
+    /// e.g. https://anilist.co/anime/98444/Yuru-Camp.
+    pub fn parse_target(target: &str) -> Option<i32> {
+        let target = target.trim();
+        let id = match target.split_once("anilist.co/anime/") {
+            Some((_, path)) => path.split(['/', '?', '#']).next()?,
+            None => target,
+        };
+        return id.parse().ok().filter(|x| *x > 0);
+    }
+
+    fn valid_target<'v>(target: &str) -> form::Result<'v, ()> {
+        if parse_target(target).is_none() {
+            return Err(form::Error::validation("expected an Anilist ID or anime URL").into());
+        }
+        return Ok(());
+    }
+
+    /// Query of the Anilist authorization callback.
+    #[derive(Debug, FromForm)]
+    pub struct OAuthCallback<'r> {
+        pub code: &'r str,
+        pub state: &'r str,
+    }
+
+    #[derive(Debug, FromForm)]
+    pub struct TokenAdd<'r> {
+        pub label: &'r str,
+        pub token: &'r str,
+    }
+
+    #[derive(Debug, FromForm)]
+    pub struct Notifier<'r> {
+        pub kind: notifiers::NotifierKind,
+        #[field(validate = with(|x| x.starts_with("http://") || x.starts_with("https://"), "invalid URL"))]
+        pub url: &'r str,
+        pub token: Option<&'r str>,
+        /// Events to fire on, or all events if none are given.
+        pub events: Vec<notifiers::NotifierEvent>,
+    }
+
+    impl Notifier<'_> {
+        pub fn build(self: &Self) -> notifiers::Notifier {
+            notifiers::Notifier {
+                kind: self.kind,
+                url: self.url.to_string(),
+                token: self.token.filter(|x| !x.is_empty()).map(String::from),
+                events: match self.events.is_empty() {
+                    true => notifiers::ALL_EVENTS.to_vec(),
+                    false => self.events.clone(),
+                },
+            }
+        }
+    }
+
+    #[derive(Debug, FromForm)]
+    pub struct SelfTest {
+        /// Throwaway watching list entry whose progress the self-test increments.
+        pub anilist_id: i32,
+    }
+
+    impl AnimeOverride<'_> {
+        /// Retrieve a usable episode offset value.
+        pub fn get_episode_offset(self: &Self) -> Option<i32> {
+            if let Some(episode_offset) = self.episode_offset {
+                if episode_offset != 0 {
+                    return Some(episode_offset);
+                }
+            }
+            return None;
+        }
+
+        /// Retrieve a usable title value.
+        pub fn get_title(self: &Self) -> Option<&str> {
+            if let Some(title) = self.title {
+                if !title.is_empty() {
+                    return Some(title);
+                }
+            }
+            return None;
+        }
+
+        /// Retrieve a usable title pattern.
+        pub fn get_pattern(self: &Self) -> Option<Regex> {
+            return self
+                .pattern
+                .filter(|x| !x.is_empty())
+                .and_then(|x| Regex::new(x).ok());
+        }
+
+        /// Retrieve a usable Plex GUID value.
+        pub fn get_guid(self: &Self) -> Option<&str> {
+            return self.guid.map(|x| x.trim()).filter(|x| !x.is_empty());
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use crate::data::forms::{parse_target, AnimeOverride};
+
+        use test_case::test_case;
+
+        #[test_case(Some(0), None ; "zero episode offset")]
+        #[test_case(Some(1), Some(1) ; "positive episode offset")]
+        #[test_case(Some(-12), Some(-12) ; "negative episode offset")]
+        #[test_case(None, None ; "no episode offset")]
+        fn episode_offset(value: Option<i32>, expected: Option<i32>) {
+            let anime_override = AnimeOverride {
+                episode_offset: value,
+                title: None,
+                guid: None,
+                pattern: None,
+                muted_until: None,
+                minimum_confidence: None,
+                log_only: false,
+                ignore_ratings: false,
+                ignored: false,
+                version: None,
+            };
+            assert_eq!(anime_override.get_episode_offset(), expected);
+        }
+
+        #[test_case("98444", Some(98444) ; "id")]
+        #[test_case(" 98444 ", Some(98444) ; "id with whitespace")]
+        #[test_case("https://anilist.co/anime/98444/Yuru-Camp/", Some(98444) ; "url")]
+        #[test_case("anilist.co/anime/98444", Some(98444) ; "url without scheme or slug")]
+        #[test_case("https://anilist.co/anime/98444?tab=staff", Some(98444) ; "url with query")]
+        #[test_case("https://anilist.co/manga/98444", None ; "manga url")]
+        #[test_case("Yuru Camp", None ; "title")]
+        #[test_case("0", None ; "zero")]
+        fn target(value: &str, expected: Option<i32>) {
+            assert_eq!(parse_target(value), expected);
+        }
+
+        #[test_case(Some(""), None ; "empty title")]
+        #[test_case(Some("title"), Some("title") ; "valid title")]
+        #[test_case(None, None ; "no title")]
+        fn title(value: Option<&str>, expected: Option<&str>) {
+            let anime_override = AnimeOverride {
+                episode_offset: None,
+                title: value,
+                guid: None,
+                pattern: None,
+                muted_until: None,
+                minimum_confidence: None,
+                log_only: false,
+                ignore_ratings: false,
+                ignored: false,
+                version: None,
+            };
+            assert_eq!(anime_override.get_title(), expected);
+        }
+
+        #[test_case(Some(""), None ; "empty pattern")]
+        #[test_case(Some("^Yuru Camp( \\(\\d+\\))?$"), Some("^Yuru Camp( \\(\\d+\\))?$") ; "valid pattern")]
+        #[test_case(None, None ; "no pattern")]
+        fn pattern(value: Option<&str>, expected: Option<&str>) {
+            let anime_override = AnimeOverride {
+                episode_offset: None,
+                title: None,
+                guid: None,
+                pattern: value,
+                muted_until: None,
+                minimum_confidence: None,
+                log_only: false,
+                ignore_ratings: false,
+                ignored: false,
+                version: None,
+            };
+            assert_eq!(
+                anime_override.get_pattern().as_ref().map(|x| x.as_str()),
+                expected
+            );
+        }
+
+        #[test_case(Some(" "), None ; "blank GUID")]
+        #[test_case(Some("plex://show/1 "), Some("plex://show/1") ; "valid GUID")]
+        #[test_case(None, None ; "no GUID")]
+        fn guid(value: Option<&str>, expected: Option<&str>) {
+            let anime_override = AnimeOverride {
+                episode_offset: None,
+                title: None,
+                guid: value,
+                pattern: None,
+                muted_until: None,
+                minimum_confidence: None,
+                log_only: false,
+                ignore_ratings: false,
+                ignored: false,
+                version: None,
+            };
+            assert_eq!(anime_override.get_guid(), expected);
+        }
+    }
+}
+
+pub mod guards {
+    use log::warn;
+    use rocket::http::{Method, Status};
+    use rocket::request::{FromRequest, Outcome, Request};
+    use std::fmt;
+    use std::net::IpAddr;
+    use std::sync::Arc;
+
+    use crate::data::state;
+    use crate::trace;
+
+    /// Header that can be used instead of the query parameter for the webhook token.
+    const WEBHOOK_TOKEN_HEADER: &str = "X-Anifunnel-Token";
+
+    /// Cookie holding the admin session identifier.
+    pub const ADMIN_SESSION_COOKIE: &str = "anifunnel_session";
+
+    /// Compare two strings in constant time with regard to their contents.
+    pub fn constant_time_eq(a: &str, b: &str) -> bool {
+        if a.len() != b.len() {
+            return false;
+        }
+        return a
+            .bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0;
+    }
+
+    /// Request guard ensuring that the webhook token matches if one is configured.
+    pub struct WebhookAuthorized;
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for WebhookAuthorized {
+        type Error = ();
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            let state = request.rocket().state::<Arc<state::Global>>().unwrap();
+            let expected = match &state.webhook_token {
+                Some(token) => token,
+                None => return Outcome::Success(WebhookAuthorized),
+            };
+            let provided = request
+                .query_value::<&str>("token")
+                .and_then(|x| x.ok())
+                .or_else(|| request.headers().get_one(WEBHOOK_TOKEN_HEADER));
+            match provided {
+                Some(token) if constant_time_eq(token, expected) => {
+                    Outcome::Success(WebhookAuthorized)
+                }
+                _ => {
+                    warn!(
+                        "Rejecting webhook from {} with a missing or invalid token",
+                        client_ip(request)
+                    );
+                    Outcome::Error((Status::Unauthorized, ()))
+                }
+            }
+        }
+    }
+
+    const HINT_ADMIN_PATH: &str = "Plex webhooks are received at the root path (/), not at the \
+        management interface (/admin).";
+    const HINT_SUB_PATH: &str = "Plex webhooks are received at the root path (/). If anifunnel is \
+        behind a reverse proxy under a sub-path, make the proxy strip the sub-path or set the \
+        sub-path with --base-path.";
+    const HINT_QUERY_STRING: &str =
+        "No webhook token was received. If the webhook URL in Plex has \
+        a ?token= parameter, check that the reverse proxy forwards the query string.";
+    const HINT_FORWARDED_PROTO: &str =
+        "The request came through a reverse proxy that does not set \
+        X-Forwarded-Proto, so its forwarding headers may not be configured.";
+    const HINT_FORWARDED_HOST: &str = "The reverse proxy changes the Host header. If the proxy \
+        routes by host name, check that the webhook URL in Plex uses the expected host name.";
+
+    /// Check whether the request looks like a Plex webhook, even if it was sent to the
+    /// wrong place.
+    fn is_plex_webhook(request: &Request<'_>) -> bool {
+        let user_agent = request.headers().get_one("User-Agent").unwrap_or_default();
+        return request.method() == Method::Post
+            && (user_agent.starts_with("PlexMediaServer")
+                || request.content_type().is_some_and(|x| x.is_form_data()));
+    }
+
+    /// Likely causes for a failed request, based on common reverse proxy and webhook
+    /// URL misconfigurations.
+    pub fn request_hints(request: &Request<'_>, status: Status) -> Vec<&'static str> {
+        let headers = request.headers();
+        let base_path = request
+            .rocket()
+            .state::<Arc<state::Global>>()
+            .map_or("", |x| x.base_path.as_str());
+        let path = request.uri().path().as_str();
+        let path = match path.strip_prefix(base_path) {
+            Some("") => "/",
+            Some(path) if path.starts_with('/') => path,
+            _ => path,
+        };
+        let mut hints = Vec::new();
+        if status == Status::NotFound && is_plex_webhook(request) {
+            if path.starts_with("/admin") {
+                hints.push(HINT_ADMIN_PATH);
+            } else {
+                hints.push(HINT_SUB_PATH);
+            }
+        }
+        if status == Status::Unauthorized
+            && path == "/"
+            && request.query_value::<&str>("token").is_none()
+            && !headers.contains(WEBHOOK_TOKEN_HEADER)
+        {
+            hints.push(HINT_QUERY_STRING);
+        }
+        if headers.contains("X-Forwarded-For") && !headers.contains("X-Forwarded-Proto") {
+            hints.push(HINT_FORWARDED_PROTO);
+        }
+        if let (Some(host), Some(forwarded_host)) =
+            (headers.get_one("Host"), headers.get_one("X-Forwarded-Host"))
+        {
+            if host != forwarded_host {
+                hints.push(HINT_FORWARDED_HOST);
+            }
+        }
+        return hints;
+    }
+
+    /// Access level granted to API keys.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum ApiRole {
+        ReadOnly,
+        Admin,
+    }
+
+    /// Check whether the request has a valid admin login session.
+    async fn has_admin_session(request: &Request<'_>, state: &state::Global) -> bool {
+        return match request.cookies().get(ADMIN_SESSION_COOKIE) {
+            Some(cookie) => state.admin_sessions.read().await.is_valid(cookie.value()),
+            None => false,
+        };
+    }
+
+    /// Resolve the role of the API key given in the Authorization header.
+    fn api_key_role(request: &Request<'_>, state: &state::Global) -> Option<ApiRole> {
+        let provided = request
+            .headers()
+            .get_one("Authorization")?
+            .strip_prefix("Bearer ")?;
+        for (keys, role) in [
+            (&state.admin_api_keys, ApiRole::Admin),
+            (&state.read_only_api_keys, ApiRole::ReadOnly),
+        ] {
+            if keys.iter().any(|key| constant_time_eq(provided, key)) {
+                return Some(role);
+            }
+        }
+        return None;
+    }
+
+    /// Authorize an API request with either a login session or an API key.
+    async fn authorize_api(request: &Request<'_>, required: ApiRole) -> Outcome<(), ()> {
+        let state = request.rocket().state::<Arc<state::Global>>().unwrap();
+        if state.admin_password.is_none() || has_admin_session(request, state).await {
+            return Outcome::Success(());
+        }
+        return match api_key_role(request, state) {
+            Some(ApiRole::Admin) => Outcome::Success(()),
+            Some(ApiRole::ReadOnly) if required == ApiRole::ReadOnly => Outcome::Success(()),
+            Some(ApiRole::ReadOnly) => Outcome::Error((Status::Forbidden, ())),
+            None => Outcome::Error((Status::Unauthorized, ())),
+        };
+    }
+
+    /// Request guard ensuring that the admin is logged in if a password is configured.
+    /// Forwards to lower-ranked routes when not logged in.
+    pub struct AdminAuthorized;
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for AdminAuthorized {
+        type Error = ();
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            let state = request.rocket().state::<Arc<state::Global>>().unwrap();
+            if state.admin_password.is_none() || has_admin_session(request, state).await {
+                return Outcome::Success(AdminAuthorized);
+            }
+            return Outcome::Forward(Status::Unauthorized);
+        }
+    }
+
+    /// W3C trace context from the traceparent header of the request, if there is one.
+    pub struct Trace(pub Option<trace::TraceContext>);
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for Trace {
+        type Error = ();
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            let context = request.local_cache(|| {
+                request
+                    .headers()
+                    .get_one("traceparent")
+                    .and_then(trace::TraceContext::parse)
+            });
+            return Outcome::Success(Trace(context.clone()));
+        }
+    }
+
+    /// Whether the client asked for a JSON report instead of the plain text response,
+    /// with an Accept header preferring JSON or the format=json query parameter.
+    pub struct ReportFormat(pub bool);
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for ReportFormat {
+        type Error = ();
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            let format_json = request
+                .query_value::<&str>("format")
+                .is_some_and(|x| x == Ok("json"));
+            let accept_json = request
+                .accept()
+                .is_some_and(|x| x.preferred().media_type().is_json());
+            return Outcome::Success(ReportFormat(format_json || accept_json));
+        }
+    }
+
+    /// Request guard rejecting changes while anifunnel is a standby that has not been
+    /// promoted, since the changes would be overwritten by the next replication.
+    pub struct Writable;
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for Writable {
+        type Error = ();
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            let state = request.rocket().state::<Arc<state::Global>>().unwrap();
+            if state.replication.read().await.is_read_only() {
+                return Outcome::Error((Status::ServiceUnavailable, ()));
+            }
+            return Outcome::Success(Writable);
+        }
+    }
+
+    /// Request guard for API routes that only read data. Accepts both read-only and
+    /// admin API keys.
+    pub struct ApiReader;
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for ApiReader {
+        type Error = ();
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            return authorize_api(request, ApiRole::ReadOnly)
+                .await
+                .map(|_| ApiReader);
+        }
+    }
+
+    /// Version given in the If-Match header, parsed from an entity tag such as `"3"`.
+    /// None when the header is missing or matches any version (`*`).
+    pub struct IfMatch(pub Option<u64>);
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for IfMatch {
+        type Error = ();
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            let value = match request.headers().get_one("If-Match") {
+                Some(value) => value.trim(),
+                None => return Outcome::Success(IfMatch(None)),
+            };
+            if value == "*" {
+                return Outcome::Success(IfMatch(None));
+            }
+            let tag = value.strip_prefix("W/").unwrap_or(value).trim_matches('"');
+            return match tag.parse() {
+                Ok(version) => Outcome::Success(IfMatch(Some(version))),
+                Err(_) => Outcome::Error((Status::BadRequest, ())),
+            };
+        }
+    }
+
+    /// Request guard for routes that modify data. Only accepts admin API keys.
+    pub struct ApiAdmin;
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for ApiAdmin {
+        type Error = ();
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            return authorize_api(request, ApiRole::Admin)
+                .await
+                .map(|_| ApiAdmin);
+        }
+    }
+
+    /// Find the client in the X-Forwarded-For addresses of a request that came from a
+    /// trusted proxy. Each proxy appends the address it received the request from, so
+    /// the client is the last address that is not another trusted proxy.
+    pub fn forwarded_client(remote: IpAddr, forwarded_for: &str, trusted: &[IpAddr]) -> IpAddr {
+        let mut client = remote;
+        if !trusted.contains(&remote) {
+            return client;
+        }
+        let addresses = forwarded_for.split(',').map(|x| x.trim().parse::<IpAddr>());
+        for address in addresses.rev() {
+            match address {
+                Ok(address) => {
+                    client = address;
+                    if !trusted.contains(&address) {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+        return client;
+    }
+
+    /// Address of the client that made the request, looking past trusted proxies.
+    pub fn client_ip(request: &Request<'_>) -> ClientIp {
+        let remote = match request.remote() {
+            Some(remote) => remote.ip(),
+            None => return ClientIp(None),
+        };
+        let trusted = request
+            .rocket()
+            .state::<Arc<state::Global>>()
+            .map_or(&[][..], |x| x.trusted_proxies.as_slice());
+        let forwarded_for = request
+            .headers()
+            .get("X-Forwarded-For")
+            .collect::<Vec<_>>()
+            .join(",");
+        return ClientIp(Some(forwarded_client(remote, &forwarded_for, trusted)));
+    }
+
+    /// Request guard for the address of the client.
+    pub struct ClientIp(pub Option<IpAddr>);
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for ClientIp {
+        type Error = ();
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            return Outcome::Success(client_ip(request));
+        }
+    }
+
+    impl fmt::Display for ClientIp {
+        fn fmt(self: &Self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            return match self.0 {
+                Some(address) => write!(f, "{}", address),
+                None => write!(f, "unknown address"),
+            };
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{constant_time_eq, forwarded_client};
+
+        use std::net::IpAddr;
+        use test_case::test_case;
+
+        #[test_case("secret", "secret", true ; "equal")]
+        #[test_case("secret", "secreT", false ; "different")]
+        #[test_case("secret", "secrets", false ; "different length")]
+        #[test_case("", "", true ; "empty")]
+        fn constant_time_comparison(a: &str, b: &str, expected: bool) {
+            assert_eq!(constant_time_eq(a, b), expected);
+        }
+
+        #[test_case("10.0.0.1", "203.0.113.7", "10.0.0.1" ; "untrusted remote")]
+        #[test_case("172.17.0.1", "203.0.113.7", "203.0.113.7" ; "trusted proxy")]
+        #[test_case("172.17.0.1", "198.51.100.2, 203.0.113.7", "203.0.113.7" ; "spoofed address")]
+        #[test_case("172.17.0.1", "203.0.113.7, 172.17.0.2", "203.0.113.7" ; "proxy chain")]
+        #[test_case("172.17.0.1", "", "172.17.0.1" ; "no header")]
+        #[test_case("172.17.0.1", "203.0.113.7, unknown", "172.17.0.1" ; "invalid address")]
+        fn forwarded_client_address(remote: &str, forwarded_for: &str, expected: &str) {
+            let trusted: Vec<IpAddr> =
+                vec!["172.17.0.1".parse().unwrap(), "172.17.0.2".parse().unwrap()];
+            assert_eq!(
+                forwarded_client(remote.parse().unwrap(), forwarded_for, &trusted),
+                expected.parse::<IpAddr>().unwrap()
+            );
+        }
+    }
+}
+
+pub mod state {
+    use crate::{anidb, anilist, discord, notifiers, plex, trakt};
+    use log::warn;
+    use rand::distributions::{Alphanumeric, DistString};
+    use regex::Regex;
+    use rocket::time::{Date, OffsetDateTime};
+    use serde::{Deserialize, Serialize};
+    use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+    use std::net::IpAddr;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+    use tokio::sync::{broadcast, RwLock, RwLockWriteGuard};
+
+    /// Number of processed scrobbles kept in the history.
+    const HISTORY_CAPACITY: usize = 500;
+
+    /// Number of history entries buffered for each live event stream before a slow
+    /// stream starts missing entries.
+    const HISTORY_EVENT_CAPACITY: usize = 64;
+
+    /// Number of days that the daily update counts are kept for, enough for the
+    /// activity heatmap of the last year.
+    const DAILY_UPDATE_DAYS: i64 = 366;
+
+    /// Number of notifications kept before the oldest are discarded.
+    const NOTIFICATION_CAPACITY: usize = 100;
+
+    /// How long before the Anilist token expires a notification is created.
+    const TOKEN_EXPIRY_WARNING: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+    /// Number of webhooks queued during maintenance before the oldest are dropped.
+    const MAINTENANCE_QUEUE_CAPACITY: usize = 1000;
+
+    /// Number of unmatched scrobbles kept for manual resolution.
+    const UNMATCHED_CAPACITY: usize = 100;
+
+    /// Seconds after the first notification for an unmatched title before the next one.
+    /// Doubles with every notification.
+    const UNMATCHED_NOTIFICATION_BACKOFF: u64 = 60;
+
+    /// Longest time between notifications for the same unmatched title.
+    const UNMATCHED_NOTIFICATION_MAX_BACKOFF: u64 = 24 * 60 * 60;
+
+    /// Number of failing webhook payloads kept for debugging.
+    const FAILED_PAYLOAD_CAPACITY: usize = 10;
+
+    /// Webhook payload fields that could identify the user or their network.
+    const SENSITIVE_PAYLOAD_FIELDS: [&str; 3] = ["publicAddress", "thumb", "uuid"];
+
+    /// How long a session can go without events before it is discarded.
+    const SESSION_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+    /// How long the user has to authorize anifunnel on Anilist.
+    const AUTHORIZATION_MAX_AGE: Duration = Duration::from_secs(10 * 60);
+
+    /// How long an admin login stays valid.
+    const ADMIN_SESSION_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+    #[derive(Debug)]
+    /// Global anifunnel application state.
+    pub struct Global {
+        pub multi_season: bool,
+        pub movies: bool,
+        pub inactive_lists: bool,
+        /// Whether the caches have been warmed up on startup, or true when warming
+        /// up is disabled. Readiness is only reported once they have.
+        pub cache_warmed: AtomicBool,
+        /// Stored Anilist tokens, one of which is used for all Anilist requests.
+        pub accounts: RwLock<Accounts>,
+        pub plex_user: Option<String>,
+        pub plex_servers: Vec<String>,
+        pub plex_libraries: Vec<String>,
+        pub account_filter: plex::AccountFilter,
+        /// Path that the routes are mounted under, empty for the root path.
+        pub base_path: String,
+        /// Reverse proxies whose X-Forwarded-For headers are trusted.
+        pub trusted_proxies: Vec<IpAddr>,
+        pub webhook_token: Option<String>,
+        pub admin_password: Option<String>,
+        pub admin_api_keys: Vec<String>,
+        pub read_only_api_keys: Vec<String>,
+        pub admin_sessions: RwLock<AdminSessions>,
+        /// Trakt account that scrobbles are also forwarded to, if configured.
+        pub trakt: Option<trakt::TraktClient>,
+        /// Plex server whose watch history is replayed by catch-up syncs.
+        pub plex: Option<plex::PlexServer>,
+        /// Discord webhook that events are posted to, if configured.
+        pub discord: Option<discord::DiscordWebhook>,
+        pub discord_events: RwLock<DiscordEvents>,
+        pub notifiers: RwLock<Notifiers>,
+        /// Anilist API client for authorizing through /auth/anilist, if configured.
+        pub oauth: Option<anilist::OAuthClient>,
+        /// Anilist GraphQL API that all Anilist requests are sent to.
+        pub anilist: anilist::AnilistApi,
+        pub match_settings: anilist::MatchSettings,
+        pub authorizations: RwLock<PendingAuthorizations>,
+        pub title_overrides: RwLock<TitleOverrides>,
+        pub account_title_overrides: RwLock<AccountTitleOverrides>,
+        pub guid_overrides: RwLock<GuidOverrides>,
+        pub title_patterns: RwLock<TitlePatterns>,
+        pub relations: RwLock<anilist::Relations>,
+        pub media_details: RwLock<MediaDetailsCache>,
+        pub readiness: RwLock<ReadinessCache>,
+        pub title_indexes: RwLock<TitleIndexes>,
+        pub mutations: anilist::MutationQueue,
+        pub episode_offsets: RwLock<EpisodeOverrides>,
+        pub mutes: RwLock<Mutes>,
+        pub minimum_confidences: RwLock<MinimumConfidences>,
+        pub log_only: RwLock<LogOnly>,
+        pub sync_ratings: bool,
+        /// Keep the title overrides used for resolving unmatched scrobbles.
+        pub remember_resolved: bool,
+        pub ignored_ratings: RwLock<IgnoredRatings>,
+        pub ignored: RwLock<IgnoredEntries>,
+        pub special_overrides: RwLock<SpecialOverrides>,
+        pub season_mappings: RwLock<SeasonMappings>,
+        pub override_versions: RwLock<OverrideVersions>,
+        pub minimum_watch_time: Option<u8>,
+        pub sessions: RwLock<WatchSessions>,
+        pub scrobble_debounce: Option<Duration>,
+        pub recent_scrobbles: RwLock<RecentScrobbles>,
+        pub rewatch_policy: RewatchPolicy,
+        pub rewatches: RwLock<Rewatches>,
+        pub conflict_policy: ConflictPolicy,
+        pub conflicts: RwLock<ProgressConflicts>,
+        pub maintenance: RwLock<Maintenance>,
+        pub sync_pause: RwLock<SyncPause>,
+        pub replication: RwLock<Replication>,
+        pub webhook_limit: WebhookLimit,
+        pub activity: RwLock<Activity>,
+        pub notifications: RwLock<Notifications>,
+        pub history: RwLock<History>,
+        pub failed_payloads: RwLock<FailedPayloads>,
+        pub unmatched: RwLock<Unmatched>,
+        /// Anilist IDs for AniDB IDs in Plex GUIDs. Empty unless a mapping file is used.
+        pub anidb_mapping: Arc<RwLock<anidb::AnidbMapping>>,
+    }
+
+    /// Write access to all overrides, for applying edits to them at once.
+    pub struct OverridesMut<'a> {
+        pub override_versions: RwLockWriteGuard<'a, OverrideVersions>,
+        pub title_overrides: RwLockWriteGuard<'a, TitleOverrides>,
+        pub account_title_overrides: RwLockWriteGuard<'a, AccountTitleOverrides>,
+        pub guid_overrides: RwLockWriteGuard<'a, GuidOverrides>,
+        pub title_patterns: RwLockWriteGuard<'a, TitlePatterns>,
+        pub episode_offsets: RwLockWriteGuard<'a, EpisodeOverrides>,
+        pub mutes: RwLockWriteGuard<'a, Mutes>,
+        pub minimum_confidences: RwLockWriteGuard<'a, MinimumConfidences>,
+        pub log_only: RwLockWriteGuard<'a, LogOnly>,
+        pub ignored_ratings: RwLockWriteGuard<'a, IgnoredRatings>,
+        pub ignored: RwLockWriteGuard<'a, IgnoredEntries>,
+        pub special_overrides: RwLockWriteGuard<'a, SpecialOverrides>,
+        pub season_mappings: RwLockWriteGuard<'a, SeasonMappings>,
+    }
+
+    impl OverridesMut<'_> {
+        /// Remove all overrides. Versions are kept so that they keep increasing.
+        pub fn clear(self: &mut Self) {
+            *self.title_overrides = TitleOverrides::new();
+            *self.account_title_overrides = AccountTitleOverrides::new();
+            *self.guid_overrides = GuidOverrides::new();
+            *self.title_patterns = TitlePatterns::new();
+            *self.episode_offsets = EpisodeOverrides::new();
+            *self.mutes = Mutes::new();
+            *self.minimum_confidences = MinimumConfidences::new();
+            *self.log_only = LogOnly::new();
+            *self.ignored_ratings = IgnoredRatings::new();
+            *self.ignored = IgnoredEntries::new();
+            *self.special_overrides = SpecialOverrides::new();
+            *self.season_mappings = SeasonMappings::new();
+        }
+    }
+
+    impl Global {
+        /// Copy of the active account, so that the token and user of a request stay
+        /// consistent even if another account is activated in the meantime.
+        pub async fn account(self: &Self) -> Account {
+            return self.accounts.read().await.active().clone();
+        }
+
+        /// Reuse the title index of an earlier refresh of a list if its titles have not
+        /// changed, or build and cache the index otherwise.
+        pub async fn index_titles(self: &Self, entries: &anilist::MediaListGroup) {
+            let fingerprint = entries.title_fingerprint();
+            if let Some(index) = self.title_indexes.read().await.get(fingerprint) {
+                entries.use_title_index(index);
+                return;
+            }
+            let index = entries.title_index();
+            self.title_indexes.write().await.insert(index);
+        }
+
+        /// Title override of a Plex title for an Anilist account, preferring the
+        /// overrides of the account over the shared ones.
+        pub async fn title_override(self: &Self, user_id: i32, title: &String) -> Option<i32> {
+            let account_override = self
+                .account_title_overrides
+                .read()
+                .await
+                .get(user_id, title);
+            if account_override.is_some() {
+                return account_override;
+            }
+            return self.title_overrides.read().await.get(title);
+        }
+
+        /// Anilist ID and episode that a Plex special (season 0) is mapped to, if any.
+        pub async fn special_override(self: &Self, webhook: &plex::Webhook) -> Option<(i32, i32)> {
+            if !webhook.is_special() {
+                return None;
+            }
+            return self
+                .special_overrides
+                .read()
+                .await
+                .find(&webhook.metadata.title, webhook.metadata.episode_number);
+        }
+
+        /// Anilist ID that the Plex season of an episode is mapped to, if any.
+        pub async fn season_mapping(self: &Self, webhook: &plex::Webhook) -> Option<i32> {
+            if !webhook.is_episode_scrobble() || webhook.metadata.season_number < 1 {
+                return None;
+            }
+            return self
+                .season_mappings
+                .read()
+                .await
+                .get(&webhook.metadata.title, webhook.metadata.season_number);
+        }
+
+        /// Anilist ID that the overrides pick for a webhook, in the order that scrobbles
+        /// use them: special and season mappings, GUID, title and pattern overrides.
+        pub async fn override_id(
+            self: &Self,
+            webhook: &plex::Webhook,
+            user_id: i32,
+        ) -> Option<i32> {
+            if let Some((id, _)) = self.special_override(webhook).await {
+                return Some(id);
+            }
+            if let Some(id) = self.season_mapping(webhook).await {
+                return Some(id);
+            }
+            let guid_override = {
+                let guid_overrides = self.guid_overrides.read().await;
+                webhook
+                    .metadata
+                    .override_guids()
+                    .into_iter()
+                    .find_map(|guid| guid_overrides.get(guid))
+            };
+            if guid_override.is_some() {
+                return guid_override;
+            }
+            let title = &webhook.metadata.title;
+            if let Some(id) = self.title_override(user_id, title).await {
+                return Some(id);
+            }
+            return self.title_patterns.read().await.get(title);
+        }
+
+        /// Whether a webhook is processed, either because of the Plex filters or because
+        /// the episode is mapped to an Anilist entry.
+        pub async fn is_actionable(self: &Self, webhook: &plex::Webhook) -> bool {
+            return webhook.is_actionable(self.multi_season, self.movies)
+                || self.special_override(webhook).await.is_some()
+                || self.season_mapping(webhook).await.is_some();
+        }
+
+        /// Lock all overrides for writing, versions first. Edits hold the versions for
+        /// their whole duration so that concurrent edits are applied one at a time.
+        pub async fn overrides_mut(self: &Self) -> OverridesMut<'_> {
+            return OverridesMut {
+                override_versions: self.override_versions.write().await,
+                title_overrides: self.title_overrides.write().await,
+                account_title_overrides: self.account_title_overrides.write().await,
+                guid_overrides: self.guid_overrides.write().await,
+                title_patterns: self.title_patterns.write().await,
+                episode_offsets: self.episode_offsets.write().await,
+                mutes: self.mutes.write().await,
+                minimum_confidences: self.minimum_confidences.write().await,
+                log_only: self.log_only.write().await,
+                ignored_ratings: self.ignored_ratings.write().await,
+                ignored: self.ignored.write().await,
+                special_overrides: self.special_overrides.write().await,
+                season_mappings: self.season_mappings.write().await,
+            };
+        }
+    }
+
+    /// Current date in UTC.
+    pub fn today() -> Date {
+        return OffsetDateTime::now_utc().date();
+    }
+
+    /// Current time in seconds since the Unix epoch.
+    pub fn unix_timestamp() -> u64 {
+        return SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or(0);
+    }
+
+    /// What to do with scrobbles for episodes that are already counted on Anilist.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, clap::ValueEnum)]
+    #[serde(rename_all = "lowercase")]
+    pub enum RewatchPolicy {
+        /// Skip the scrobble.
+        #[default]
+        Ignore,
+        /// Log the rewatch and record it in the history.
+        Notify,
+        /// Same as notify, but also keep a count of rewatches for each episode.
+        Count,
+    }
+
+    /// What to do with scrobbles for episodes that skip ahead of the Anilist progress.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, clap::ValueEnum)]
+    #[serde(rename_all = "lowercase")]
+    pub enum ConflictPolicy {
+        /// Skip the scrobble.
+        #[default]
+        Skip,
+        /// Set the Anilist progress to the scrobbled episode.
+        Force,
+        /// Skip the scrobble and list it in the management interface for review.
+        Review,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum HistoryOutcome {
+        /// Anilist progress was updated.
+        Updated,
+        /// Anilist progress update failed.
+        Failed,
+        /// No matching Anilist entry was found.
+        Unmatched,
+        /// Several Anilist entries matched equally well.
+        Ambiguous,
+        /// A match was found but the episode did not follow the Anilist progress.
+        Skipped,
+        /// The episode had already been counted on Anilist.
+        Rewatched,
+        /// Scrobbles for the matched entry were muted.
+        Muted,
+        /// The matched entry is ignored and never updated from Plex.
+        Ignored,
+        /// The matched entry is log-only, so the progress update was only logged.
+        Logged,
+        /// The episode skipped ahead of the Anilist progress and was not counted.
+        Conflict,
+        /// The Plex rating was saved as the Anilist score.
+        Rated,
+    }
+
+    /// Where a history entry came from.
+    #[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum ScrobbleSource {
+        /// Plex webhook, including webhooks queued during maintenance.
+        PlexWebhook,
+        /// Progress set through the management interface or API.
+        ManualApi,
+        /// Fabricated scrobble sent by the self-test.
+        SelfTest,
+        /// Scrobble replayed from the Plex watch history by a catch-up sync.
+        CatchUp,
+    }
+
+    impl ScrobbleSource {
+        pub fn as_str(self: &Self) -> &'static str {
+            return match self {
+                ScrobbleSource::PlexWebhook => "plex_webhook",
+                ScrobbleSource::ManualApi => "manual_api",
+                ScrobbleSource::SelfTest => "self_test",
+                ScrobbleSource::CatchUp => "catch_up",
+            };
+        }
+    }
+
+    /// Processed scrobble.
+    #[derive(Clone, Debug, Serialize)]
+    pub struct HistoryEntry {
+        pub timestamp: u64,
+        pub source: ScrobbleSource,
+        pub title: String,
+        pub guid: Option<String>,
+        pub season_number: i32,
+        pub episode_number: i32,
+        pub anilist_id: Option<i32>,
+        pub outcome: HistoryOutcome,
+    }
+
+    /// Most recent processed scrobbles, oldest first.
+    #[derive(Debug)]
+    pub struct History {
+        inner: VecDeque<HistoryEntry>,
+        events: broadcast::Sender<HistoryEntry>,
+        /// Number of episodes updated on Anilist on each day (UTC), for the last
+        /// DAILY_UPDATE_DAYS days. Unlike the entries, these are not limited to the
+        /// most recent scrobbles.
+        daily_updates: BTreeMap<Date, usize>,
+    }
+
+    /// Most recent webhook payloads that could not be processed, with potentially
+    /// identifying fields redacted.
+    #[derive(Debug)]
+    pub struct FailedPayloads {
+        inner: VecDeque<String>,
+    }
+
+    /// Anilist token and the user it belongs to.
+    #[derive(Clone, Debug)]
+    pub struct Account {
+        pub id: u32,
+        pub label: String,
+        pub token: String,
+        pub user: anilist::User,
+    }
+
+    /// Notifiers keyed by their IDs.
+    #[derive(Debug)]
+    pub struct Notifiers {
+        inner: BTreeMap<u32, notifiers::Notifier>,
+        next_id: u32,
+    }
+
+    /// Stored Anilist accounts and which of them is active.
+    #[derive(Debug)]
+    pub struct Accounts {
+        inner: Vec<Account>,
+        active: u32,
+    }
+
+    /// Replication of the overrides, Anilist tokens and runtime settings from a primary
+    /// instance to this standby instance.
+    #[derive(Clone, Debug, Serialize)]
+    pub struct Replication {
+        /// URL of the primary instance, if this instance is a standby.
+        pub primary: Option<String>,
+        pub promoted: bool,
+        pub last_sync: Option<u64>,
+        pub last_error: Option<String>,
+    }
+
+    /// Which events are posted to the Discord webhook.
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    pub struct DiscordEvents {
+        pub updated: bool,
+        pub unmatched: bool,
+        pub failed: bool,
+    }
+
+    /// OAuth state values of started Anilist authorizations, checked in the callback.
+    #[derive(Debug)]
+    pub struct PendingAuthorizations {
+        inner: HashMap<String, Instant>,
+    }
+
+    /// Logged in management interface sessions.
+    #[derive(Debug)]
+    pub struct AdminSessions {
+        inner: HashMap<String, SystemTime>,
+    }
+
+    /// Playback of a single item on a single Plex player.
+    #[derive(Debug)]
+    pub struct WatchSession {
+        pub account: String,
+        pub title: String,
+        pub season_number: i32,
+        pub episode_number: i32,
+        pub duration: Option<Duration>,
+        pub view_offset: Option<Duration>,
+        watched: Duration,
+        playing_since: Option<Instant>,
+        last_event: Instant,
+    }
+
+    /// Watch sessions keyed by the Plex player and item.
+    #[derive(Debug)]
+    pub struct WatchSessions {
+        inner: HashMap<String, WatchSession>,
+    }
+
+    #[derive(Debug)]
+    pub struct EpisodeOverrides {
+        inner: HashMap<i32, i32>,
+    }
+
+    /// Plex specials (season 0) of a show that are tracked as an Anilist entry, often
+    /// an OVA that has its own entry. The Plex episodes are the episodes of the entry
+    /// in order, so the first one is episode 1.
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    pub struct SpecialOverride {
+        /// Plex title of the show that the specials are in.
+        pub title: String,
+        pub episodes: Vec<i32>,
+    }
+
+    /// Special overrides keyed by Anilist IDs.
+    #[derive(Debug)]
+    pub struct SpecialOverrides {
+        inner: BTreeMap<i32, SpecialOverride>,
+    }
+
+    /// Plex season of a show that is tracked as a specific Anilist entry, e.g. the
+    /// second season as the sequel. Episodes keep their Plex numbers.
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    pub struct SeasonMapping {
+        /// Plex title of the show that the season is in.
+        pub title: String,
+        pub season: i32,
+        pub anilist_id: i32,
+    }
+
+    /// Season mappings keyed by Plex titles and season numbers.
+    #[derive(Debug)]
+    pub struct SeasonMappings {
+        inner: BTreeMap<(String, i32), i32>,
+    }
+
+    /// Title overrides using regular expressions, checked in the order they were set.
+    #[derive(Debug)]
+    pub struct TitlePatterns {
+        inner: Vec<(Regex, i32)>,
+    }
+
+    /// Entries whose scrobbles are ignored until the end of a given day (UTC).
+    #[derive(Debug)]
+    pub struct Mutes {
+        inner: HashMap<i32, Date>,
+    }
+
+    /// Version of the overrides of an entry, incremented on every change.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+    pub struct OverrideVersion {
+        pub version: u64,
+        /// Unix timestamp of the latest change.
+        pub updated_at: Option<u64>,
+    }
+
+    /// Entries whose scrobbles are matched and recorded, but never sent to Anilist.
+    #[derive(Debug)]
+    pub struct LogOnly {
+        inner: HashSet<i32>,
+    }
+
+    /// Entries whose Plex ratings are not synced to Anilist, which are kept the same
+    /// way as log-only entries.
+    pub type IgnoredRatings = LogOnly;
+
+    /// Entries that are never updated from Plex, neither progress nor ratings.
+    pub type IgnoredEntries = LogOnly;
+
+    /// Minimum fuzzy match confidences of entries that don't use the global minimum.
+    #[derive(Debug)]
+    pub struct MinimumConfidences {
+        inner: HashMap<i32, f64>,
+    }
+
+    /// Override versions for detecting conflicting edits.
+    #[derive(Debug)]
+    pub struct OverrideVersions {
+        inner: HashMap<i32, OverrideVersion>,
+    }
+
+    #[derive(Debug)]
+    pub struct TitleOverrides {
+        inner: HashMap<String, i32>,
+    }
+
+    /// Title overrides that only apply to a single Anilist account, keyed by the
+    /// Anilist user ID. They take precedence over the shared title overrides.
+    #[derive(Debug)]
+    pub struct AccountTitleOverrides {
+        inner: HashMap<i32, TitleOverrides>,
+    }
+
+    /// Overrides keyed on Plex GUIDs, which work the same way as title overrides.
+    pub type GuidOverrides = TitleOverrides;
+
+    #[derive(Clone, Debug, PartialEq, Serialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum NotificationKind {
+        TokenExpiring,
+        Unmatched,
+        UpdateFailed,
+        ProgressConflict,
+        /// Fields that anifunnel queries are missing from the Anilist schema.
+        SchemaChanged,
+    }
+
+    #[derive(Clone, Debug, Serialize)]
+    pub struct Notification {
+        pub id: u64,
+        pub timestamp: u64,
+        pub kind: NotificationKind,
+        pub message: String,
+        pub read: bool,
+    }
+
+    /// Notable events for showing in the management interface, newest last.
+    #[derive(Debug)]
+    pub struct Notifications {
+        inner: VecDeque<Notification>,
+        next_id: u64,
+    }
+
+    /// Scrobble that could not be matched to the watching list.
+    #[derive(Clone, Debug, Serialize)]
+    pub struct UnmatchedScrobble {
+        pub id: u64,
+        pub timestamp: u64,
+        pub title: String,
+        pub season_number: i32,
+        pub episode_number: i32,
+        /// Number of times the title has failed to match.
+        pub occurrences: u32,
+        /// Best fuzzy match candidates when the title last failed to match.
+        pub candidates: Vec<anilist::MatchCandidate>,
+        /// Raw webhook payload for processing the scrobble once it is resolved.
+        #[serde(skip)]
+        pub payload: String,
+    }
+
+    /// How long fetched Anilist media details are used before fetching them again, in
+    /// seconds. Details such as the airing status and episode count change over time.
+    pub const MEDIA_DETAILS_TTL: u64 = 24 * 60 * 60;
+
+    /// Anilist media details by media ID, with the time they were fetched.
+    #[derive(Debug, Default)]
+    pub struct MediaDetailsCache {
+        inner: HashMap<i32, (u64, anilist::MediaDetails)>,
+    }
+
+    /// Number of title indexes that are cached, enough for the watching list, the
+    /// planning and paused lists and the rated lists along with their movies.
+    const TITLE_INDEX_CAPACITY: usize = 6;
+
+    /// Title indexes of recently fetched lists, most recent last. Lists are fetched
+    /// again for every webhook, but their titles rarely change between refreshes.
+    #[derive(Debug, Default)]
+    pub struct TitleIndexes {
+        inner: VecDeque<Arc<anilist::TitleIndex>>,
+    }
+
+    /// How long a successful readiness check is reused, in seconds, so that frequent
+    /// probes do not each send a request to Anilist.
+    pub const READINESS_TTL: u64 = 60;
+
+    /// Last successful readiness check, with the time it was made, the token that it
+    /// checked and the name of the user. Failed checks are not cached so that probes
+    /// notice the recovery right away.
+    #[derive(Debug, Default)]
+    pub struct ReadinessCache {
+        inner: Option<(u64, String, String)>,
+    }
+
+    /// Unmatched scrobbles waiting for an override, oldest first.
+    #[derive(Debug)]
+    pub struct Unmatched {
+        inner: VecDeque<UnmatchedScrobble>,
+        next_id: u64,
+        backoff: HashMap<String, UnmatchedBackoff>,
+    }
+
+    /// Notification backoff for a title that keeps failing to match.
+    #[derive(Debug)]
+    struct UnmatchedBackoff {
+        occurrences: u32,
+        notifications: u32,
+        next_notification: u64,
+    }
+
+    /// Limit for the number of webhooks that are processed at the same time.
+    #[derive(Debug)]
+    pub struct WebhookLimit {
+        limit: Option<usize>,
+        pending: AtomicUsize,
+    }
+
+    /// Webhook being processed. Releases its place when dropped.
+    pub struct PendingWebhook<'a> {
+        pending: &'a AtomicUsize,
+    }
+
+    /// Maintenance mode, during which webhooks are queued instead of processed.
+    #[derive(Debug)]
+    pub struct Maintenance {
+        pub enabled: bool,
+        queued: VecDeque<String>,
+    }
+
+    /// Global pause of syncing, during which webhooks are accepted but nothing is
+    /// sent to Anilist or Trakt.
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct SyncPause {
+        /// When syncing was paused, if it is.
+        pub paused_since: Option<u64>,
+    }
+
+    /// Timestamps of notable events for the status endpoint.
+    #[derive(Debug)]
+    pub struct Activity {
+        pub started: Instant,
+        /// When the last webhook was received, in seconds since the Unix epoch.
+        pub last_webhook: Option<u64>,
+        /// When Anilist progress was last updated, in seconds since the Unix epoch.
+        pub last_update: Option<u64>,
+    }
+
+    /// Recently processed scrobbles keyed by title, season, episode and Plex user,
+    /// for coalescing repeated scrobbles of the same episode.
+    #[derive(Debug)]
+    pub struct RecentScrobbles {
+        inner: HashMap<(String, i32, i32, String), Instant>,
+    }
+
+    /// Number of rewatches for each Anilist media list ID and episode number.
+    #[derive(Debug)]
+    pub struct Rewatches {
+        inner: HashMap<(i32, i32), u32>,
+    }
+
+    /// Scrobbled episode that skipped ahead of the Anilist progress.
+    #[derive(Clone, Debug, PartialEq, Serialize)]
+    pub struct ProgressConflict {
+        pub anilist_id: i32,
+        pub title: String,
+        pub progress: i32,
+        pub episode: i32,
+        pub timestamp: u64,
+    }
+
+    /// Progress conflicts waiting for review, keyed by Anilist media list ID. Only the
+    /// latest conflict of each entry is kept.
+    #[derive(Debug)]
+    pub struct ProgressConflicts {
+        inner: BTreeMap<i32, ProgressConflict>,
+    }
+
+    impl History {
+        pub fn new() -> Self {
+            Self {
+                inner: VecDeque::new(),
+                events: broadcast::channel(HISTORY_EVENT_CAPACITY).0,
+                daily_updates: BTreeMap::new(),
+            }
+        }
+
+        pub fn push(self: &mut Self, entry: HistoryEntry) {
+            if self.inner.len() == HISTORY_CAPACITY {
+                self.inner.pop_front();
+            }
+            if entry.outcome == HistoryOutcome::Updated {
+                self.count_update(entry.timestamp);
+            }
+            // Sending only fails when nobody is listening.
+            let _ = self.events.send(entry.clone());
+            self.inner.push_back(entry);
+        }
+
+        /// Receive the entries pushed from now on.
+        pub fn subscribe(self: &Self) -> broadcast::Receiver<HistoryEntry> {
+            return self.events.subscribe();
+        }
+
+        pub fn record(
+            self: &mut Self,
+            webhook: &plex::Webhook,
+            anilist_id: Option<i32>,
+            outcome: HistoryOutcome,
+        ) {
+            let source = if webhook.is_self_test() {
+                ScrobbleSource::SelfTest
+            } else if webhook.is_catch_up() {
+                ScrobbleSource::CatchUp
+            } else {
+                ScrobbleSource::PlexWebhook
+            };
+            self.push(HistoryEntry {
+                timestamp: unix_timestamp(),
+                source,
+                title: webhook.metadata.title.clone(),
+                guid: webhook.metadata.guid.clone(),
+                season_number: webhook.metadata.season_number,
+                episode_number: webhook.metadata.episode_number,
+                anilist_id,
+                outcome,
+            });
+        }
+
+        pub fn iter(self: &Self) -> impl DoubleEndedIterator<Item = &HistoryEntry> {
+            return self.inner.iter();
+        }
+
+        pub fn source_counts(self: &Self) -> BTreeMap<String, usize> {
+            let mut result: BTreeMap<String, usize> = BTreeMap::new();
+            for entry in self.inner.iter() {
+                *result.entry(entry.source.as_str().to_string()).or_insert(0) += 1;
+            }
+            return result;
+        }
+
+        /// Count an Anilist update on the day of the timestamp, forgetting the days
+        /// that are older than DAILY_UPDATE_DAYS.
+        fn count_update(self: &mut Self, timestamp: u64) {
+            let date = match OffsetDateTime::from_unix_timestamp(timestamp as i64) {
+                Ok(timestamp) => timestamp.date(),
+                Err(_) => return,
+            };
+            *self.daily_updates.entry(date).or_insert(0) += 1;
+            let oldest = date - rocket::time::Duration::days(DAILY_UPDATE_DAYS - 1);
+            self.daily_updates = self.daily_updates.split_off(&oldest);
+        }
+
+        /// Number of episodes updated on Anilist on each day (UTC) since the given date.
+        pub fn daily_updates(self: &Self, since: Date) -> BTreeMap<Date, usize> {
+            return self
+                .daily_updates
+                .range(since..)
+                .map(|(date, count)| (*date, *count))
+                .collect();
+        }
+
+        /// Titles whose most recent scrobble could not be matched unambiguously.
+        pub fn ambiguous_titles(self: &Self) -> Vec<String> {
+            let mut latest: HashMap<&String, &HistoryOutcome> = HashMap::new();
+            for entry in self.inner.iter() {
+                latest.insert(&entry.title, &entry.outcome);
+            }
+            let mut result: Vec<String> = latest
+                .into_iter()
+                .filter(|(_, outcome)| **outcome == HistoryOutcome::Ambiguous)
+                .map(|(title, _)| title.clone())
+                .collect();
+            result.sort();
+            return result;
+        }
+    }
+
+    impl WebhookLimit {
+        pub fn new(limit: Option<usize>) -> Self {
+            Self {
+                limit,
+                pending: AtomicUsize::new(0),
+            }
+        }
+
+        pub fn limit(self: &Self) -> Option<usize> {
+            return self.limit;
+        }
+
+        /// Number of webhooks that are being processed.
+        pub fn pending(self: &Self) -> usize {
+            return self.pending.load(Ordering::SeqCst);
+        }
+
+        pub fn is_full(self: &Self, pending: usize) -> bool {
+            return self.limit.is_some_and(|x| pending >= x);
+        }
+
+        /// Reserve a place for processing a webhook, if the limit has not been reached.
+        pub fn acquire(self: &Self) -> Option<PendingWebhook<'_>> {
+            let previous = self.pending.fetch_add(1, Ordering::SeqCst);
+            let pending = PendingWebhook {
+                pending: &self.pending,
+            };
+            if self.is_full(previous) {
+                return None;
+            }
+            return Some(pending);
+        }
+    }
+
+    impl Drop for PendingWebhook<'_> {
+        fn drop(&mut self) {
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    impl Notifications {
+        pub fn new() -> Self {
+            Self {
+                inner: VecDeque::new(),
+                next_id: 1,
+            }
+        }
+
+        /// Add a notification, unless an identical one is still unread.
+        pub fn push(self: &mut Self, kind: NotificationKind, message: String) {
+            let is_duplicate = self
+                .inner
+                .iter()
+                .any(|x| !x.read && x.kind == kind && x.message == message);
+            if is_duplicate {
+                return;
+            }
+            if self.inner.len() == NOTIFICATION_CAPACITY {
+                self.inner.pop_front();
+            }
+            self.inner.push_back(Notification {
+                id: self.next_id,
+                timestamp: unix_timestamp(),
+                kind,
+                message,
+                read: false,
+            });
+            self.next_id += 1;
+        }
+
+        /// Add a notification if the Anilist token is about to expire.
+        /// Add a notification if the token is about to expire and there is no such
+        /// notification yet. Returns the message of the added notification.
+        pub fn check_token_expiry(self: &mut Self, token: &str) -> Option<String> {
+            let expiry = anilist::token_expiry(token)?;
+            if expiry > unix_timestamp() + TOKEN_EXPIRY_WARNING.as_secs() {
+                return None;
+            }
+            if self
+                .inner
+                .iter()
+                .any(|x| x.kind == NotificationKind::TokenExpiring)
+            {
+                return None;
+            }
+            let message = String::from(
+                "The Anilist token expires in less than 30 days. Authorize anifunnel again to get a new token.",
+            );
+            self.push(NotificationKind::TokenExpiring, message.clone());
+            return Some(message);
+        }
+
+        pub fn iter(self: &Self) -> impl DoubleEndedIterator<Item = &Notification> {
+            return self.inner.iter();
+        }
+
+        pub fn unread(self: &Self) -> usize {
+            return self.inner.iter().filter(|x| !x.read).count();
+        }
+
+        /// Mark a notification as read. Returns false if no such notification exists.
+        pub fn mark_read(self: &mut Self, id: u64) -> bool {
+            return match self.inner.iter_mut().find(|x| x.id == id) {
+                Some(notification) => {
+                    notification.read = true;
+                    true
+                }
+                None => false,
+            };
+        }
+
+        pub fn mark_all_read(self: &mut Self) {
+            self.inner.iter_mut().for_each(|x| x.read = true);
+        }
+    }
+
+    impl SyncPause {
+        pub fn is_paused(self: &Self) -> bool {
+            return self.paused_since.is_some();
+        }
+
+        /// Pause syncing. Returns false if syncing was already paused.
+        pub fn pause(self: &mut Self) -> bool {
+            if self.is_paused() {
+                return false;
+            }
+            self.paused_since = Some(unix_timestamp());
+            return true;
+        }
+
+        /// Resume syncing. Returns false if syncing was not paused.
+        pub fn resume(self: &mut Self) -> bool {
+            return self.paused_since.take().is_some();
+        }
+    }
+
+    impl Maintenance {
+        pub fn new() -> Self {
+            Self {
+                enabled: false,
+                queued: VecDeque::new(),
+            }
+        }
+
+        pub fn queue(self: &mut Self, payload: &str) {
+            if self.queued.len() == MAINTENANCE_QUEUE_CAPACITY {
+                warn!("Maintenance queue is full, dropping the oldest webhook");
+                self.queued.pop_front();
+            }
+            self.queued.push_back(payload.to_string());
+        }
+
+        pub fn queued(self: &Self) -> usize {
+            return self.queued.len();
+        }
+
+        /// Remove and return the queued webhook payloads, oldest first.
+        pub fn take_queued(self: &mut Self) -> VecDeque<String> {
+            return std::mem::take(&mut self.queued);
+        }
+
+        pub fn iter_queued(self: &Self) -> impl Iterator<Item = &String> {
+            return self.queued.iter();
+        }
+    }
+
+    impl Activity {
+        pub fn new() -> Self {
+            Self {
+                started: Instant::now(),
+                last_webhook: None,
+                last_update: None,
+            }
+        }
+    }
+
+    impl RecentScrobbles {
+        pub fn new() -> Self {
+            Self {
+                inner: HashMap::new(),
+            }
+        }
+
+        /// Record a scrobble and return whether the same episode was already scrobbled
+        /// by the same user within the window. Repeated scrobbles do not extend the
+        /// window.
+        pub fn is_duplicate(self: &mut Self, webhook: &plex::Webhook, window: Duration) -> bool {
+            self.inner
+                .retain(|_, scrobbled| scrobbled.elapsed() < window);
+            let key = (
+                webhook.metadata.title.clone(),
+                webhook.metadata.season_number,
+                webhook.metadata.episode_number,
+                webhook.account.name.clone(),
+            );
+            if self.inner.contains_key(&key) {
+                return true;
+            }
+            self.inner.insert(key, Instant::now());
+            return false;
+        }
+
+        /// Whether the same episode was already scrobbled by the same user within the
+        /// window, without recording the scrobble.
+        pub fn contains(self: &Self, webhook: &plex::Webhook, window: Duration) -> bool {
+            let key = (
+                webhook.metadata.title.clone(),
+                webhook.metadata.season_number,
+                webhook.metadata.episode_number,
+                webhook.account.name.clone(),
+            );
+            return self
+                .inner
+                .get(&key)
+                .is_some_and(|scrobbled| scrobbled.elapsed() < window);
+        }
+    }
+
+    impl Rewatches {
+        pub fn new() -> Self {
+            Self {
+                inner: HashMap::new(),
+            }
+        }
+
+        /// Count a rewatch of an episode and return the total number of rewatches.
+        pub fn record(self: &mut Self, id: i32, episode: i32) -> u32 {
+            let count = self.inner.entry((id, episode)).or_insert(0);
+            *count += 1;
+            return *count;
+        }
+
+        pub fn iter(self: &Self) -> impl Iterator<Item = (&(i32, i32), &u32)> {
+            return self.inner.iter();
+        }
+    }
+
+    impl ProgressConflicts {
+        pub fn new() -> Self {
+            Self {
+                inner: BTreeMap::new(),
+            }
+        }
+
+        pub fn record(self: &mut Self, conflict: ProgressConflict) {
+            self.inner.insert(conflict.anilist_id, conflict);
+        }
+
+        pub fn remove(self: &mut Self, id: i32) -> Option<ProgressConflict> {
+            return self.inner.remove(&id);
+        }
+
+        pub fn iter(self: &Self) -> impl Iterator<Item = &ProgressConflict> {
+            return self.inner.values();
+        }
+    }
+
+    impl FailedPayloads {
+        pub fn new() -> Self {
+            Self {
+                inner: VecDeque::new(),
+            }
+        }
+
+        pub fn record(self: &mut Self, payload: &str) {
+            if self.inner.len() == FAILED_PAYLOAD_CAPACITY {
+                self.inner.pop_front();
+            }
+            self.inner.push_back(sanitize_payload(payload));
+        }
+
+        pub fn iter(self: &Self) -> impl Iterator<Item = &String> {
+            return self.inner.iter();
+        }
+    }
+
+    impl Unmatched {
+        pub fn new() -> Self {
+            Self {
+                inner: VecDeque::new(),
+                next_id: 1,
+                backoff: HashMap::new(),
+            }
+        }
+
+        /// Store an unmatched scrobble. Repeated scrobbles for the same episode replace
+        /// the earlier one. Returns whether the user should be notified, which happens
+        /// at exponentially increasing intervals for a title that keeps failing to match.
+        pub fn record(
+            self: &mut Self,
+            webhook: &plex::Webhook,
+            payload: &str,
+            candidates: Vec<anilist::MatchCandidate>,
+        ) -> bool {
+            return self.record_at(webhook, payload, candidates, unix_timestamp());
+        }
+
+        fn record_at(
+            self: &mut Self,
+            webhook: &plex::Webhook,
+            payload: &str,
+            candidates: Vec<anilist::MatchCandidate>,
+            now: u64,
+        ) -> bool {
+            let metadata = &webhook.metadata;
+            let backoff = self
+                .backoff
+                .entry(metadata.title.clone())
+                .or_insert(UnmatchedBackoff {
+                    occurrences: 0,
+                    notifications: 0,
+                    next_notification: 0,
+                });
+            backoff.occurrences += 1;
+            let occurrences = backoff.occurrences;
+            let notify = now >= backoff.next_notification;
+            if notify {
+                let delay = UNMATCHED_NOTIFICATION_BACKOFF
+                    .saturating_mul(1 << backoff.notifications.min(16))
+                    .min(UNMATCHED_NOTIFICATION_MAX_BACKOFF);
+                backoff.notifications += 1;
+                backoff.next_notification = now + delay;
+            }
+            self.inner.retain(|x| {
+                x.title != metadata.title
+                    || x.season_number != metadata.season_number
+                    || x.episode_number != metadata.episode_number
+            });
+            if self.inner.len() == UNMATCHED_CAPACITY {
+                self.inner.pop_front();
+            }
+            self.inner.push_back(UnmatchedScrobble {
+                id: self.next_id,
+                timestamp: now,
+                title: metadata.title.clone(),
+                season_number: metadata.season_number,
+                episode_number: metadata.episode_number,
+                occurrences,
+                candidates,
+                payload: payload.to_string(),
+            });
+            self.next_id += 1;
+            for scrobble in self.inner.iter_mut() {
+                if scrobble.title == metadata.title {
+                    scrobble.occurrences = occurrences;
+                }
+            }
+            return notify;
+        }
+
+        pub fn iter(self: &Self) -> impl Iterator<Item = &UnmatchedScrobble> {
+            return self.inner.iter();
+        }
+
+        /// Remove the unmatched scrobble with the given ID along with all other
+        /// scrobbles for the same title, ordered by season and episode.
+        pub fn take(self: &mut Self, id: u64) -> Option<Vec<UnmatchedScrobble>> {
+            let title = self.inner.iter().find(|x| x.id == id)?.title.clone();
+            let (mut taken, kept): (Vec<UnmatchedScrobble>, Vec<UnmatchedScrobble>) =
+                self.inner.drain(..).partition(|x| x.title == title);
+            self.inner = kept.into();
+            self.backoff.remove(&title);
+            taken.sort_by_key(|x| (x.season_number, x.episode_number));
+            return Some(taken);
+        }
+
+        /// Remove the unmatched scrobbles with the given IDs, ordered by title, season
+        /// and episode.
+        pub fn take_ids(self: &mut Self, ids: &[u64]) -> Vec<UnmatchedScrobble> {
+            let (mut taken, kept): (Vec<UnmatchedScrobble>, Vec<UnmatchedScrobble>) =
+                self.inner.drain(..).partition(|x| ids.contains(&x.id));
+            self.inner = kept.into();
+            for scrobble in taken.iter() {
+                if !self.inner.iter().any(|x| x.title == scrobble.title) {
+                    self.backoff.remove(&scrobble.title);
+                }
+            }
+            taken.sort_by(|a, b| {
+                (&a.title, a.season_number, a.episode_number).cmp(&(
+                    &b.title,
+                    b.season_number,
+                    b.episode_number,
+                ))
+            });
+            return taken;
+        }
+    }
+
+    impl MediaDetailsCache {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn get(self: &Self, id: i32) -> Option<&anilist::MediaDetails> {
+            return self.get_at(id, unix_timestamp());
+        }
+
+        fn get_at(self: &Self, id: i32, now: u64) -> Option<&anilist::MediaDetails> {
+            return match self.inner.get(&id) {
+                Some((fetched, details)) if now < fetched + MEDIA_DETAILS_TTL => Some(details),
+                _ => None,
+            };
+        }
+
+        pub fn insert(self: &mut Self, details: anilist::MediaDetails) {
+            self.insert_at(details, unix_timestamp());
+        }
+
+        fn insert_at(self: &mut Self, details: anilist::MediaDetails, now: u64) {
+            self.inner.insert(details.id, (now, details));
+        }
+    }
+
+    impl TitleIndexes {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn get(self: &Self, fingerprint: u64) -> Option<Arc<anilist::TitleIndex>> {
+            return self
+                .inner
+                .iter()
+                .find(|x| x.fingerprint() == fingerprint)
+                .cloned();
+        }
+
+        pub fn insert(self: &mut Self, index: Arc<anilist::TitleIndex>) {
+            self.inner
+                .retain(|x| x.fingerprint() != index.fingerprint());
+            if self.inner.len() >= TITLE_INDEX_CAPACITY {
+                self.inner.pop_front();
+            }
+            self.inner.push_back(index);
+        }
+    }
+
+    impl ReadinessCache {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Name of the user if the token was successfully checked recently.
+        pub fn get(self: &Self, token: &str) -> Option<&str> {
+            return self.get_at(token, unix_timestamp());
+        }
+
+        fn get_at(self: &Self, token: &str, now: u64) -> Option<&str> {
+            return match &self.inner {
+                Some((checked, checked_token, user))
+                    if checked_token == token && now < checked + READINESS_TTL =>
+                {
+                    Some(user)
+                }
+                _ => None,
+            };
+        }
+
+        pub fn insert(self: &mut Self, token: &str, user: String) {
+            self.insert_at(token, user, unix_timestamp());
+        }
+
+        fn insert_at(self: &mut Self, token: &str, user: String, now: u64) {
+            self.inner = Some((now, token.to_string(), user));
+        }
+    }
+
+    /// Redact potentially identifying fields from a webhook payload. Payloads that are
+    /// not valid JSON are kept as they are.
+    fn sanitize_payload(payload: &str) -> String {
+        fn redact(value: &mut serde_json::Value) {
+            match value {
+                serde_json::Value::Object(object) => {
+                    for (key, value) in object.iter_mut() {
+                        if SENSITIVE_PAYLOAD_FIELDS.contains(&key.as_str()) {
+                            *value = serde_json::Value::from("<redacted>");
+                        } else {
+                            redact(value);
+                        }
+                    }
+                }
+                serde_json::Value::Array(values) => values.iter_mut().for_each(redact),
+                _ => {}
+            }
+        }
+        return match serde_json::from_str::<serde_json::Value>(payload) {
+            Ok(mut value) => {
+                redact(&mut value);
+                value.to_string()
+            }
+            Err(_) => payload.to_string(),
+        };
+    }
+
+    fn is_admin_session_valid(created: &SystemTime) -> bool {
+        return created
+            .elapsed()
+            .is_ok_and(|elapsed| elapsed < ADMIN_SESSION_MAX_AGE);
+    }
+
+    impl Notifiers {
+        pub fn new(notifiers: Vec<notifiers::Notifier>) -> Self {
+            let mut new = Self {
+                inner: BTreeMap::new(),
+                next_id: 1,
+            };
+            for notifier in notifiers {
+                new.add(notifier);
+            }
+            return new;
+        }
+
+        pub fn add(self: &mut Self, notifier: notifiers::Notifier) -> u32 {
+            let id = self.next_id;
+            self.next_id += 1;
+            self.inner.insert(id, notifier);
+            return id;
+        }
+
+        /// Replace a notifier. Returns false if there is no notifier with the ID.
+        pub fn replace(self: &mut Self, id: u32, notifier: notifiers::Notifier) -> bool {
+            return match self.inner.get_mut(&id) {
+                Some(existing) => {
+                    *existing = notifier;
+                    true
+                }
+                None => false,
+            };
+        }
+
+        pub fn remove(self: &mut Self, id: u32) -> bool {
+            return self.inner.remove(&id).is_some();
+        }
+
+        pub fn get(self: &Self, id: u32) -> Option<&notifiers::Notifier> {
+            return self.inner.get(&id);
+        }
+
+        pub fn iter(self: &Self) -> impl Iterator<Item = (&u32, &notifiers::Notifier)> {
+            return self.inner.iter();
+        }
+
+        /// Notifiers that fire on an event.
+        pub fn matching(self: &Self, event: notifiers::NotifierEvent) -> Vec<notifiers::Notifier> {
+            return self
+                .inner
+                .values()
+                .filter(|x| x.is_enabled(event))
+                .cloned()
+                .collect();
+        }
+    }
+
+    impl Accounts {
+        /// Accounts with the given token as the first and active account.
+        pub fn new(label: String, token: String, user: anilist::User) -> Self {
+            Self {
+                inner: vec![Account {
+                    id: 1,
+                    label,
+                    token,
+                    user,
+                }],
+                active: 1,
+            }
+        }
+
+        pub fn active(self: &Self) -> &Account {
+            return self
+                .inner
+                .iter()
+                .find(|x| x.id == self.active)
+                .expect("active account exists");
+        }
+
+        /// Store a token and return the ID of its account. Storing a token again
+        /// replaces its label and user instead, as does storing the first token when
+        /// anifunnel was started without one.
+        pub fn add(self: &mut Self, label: String, token: String, user: anilist::User) -> u32 {
+            if let Some(account) = self
+                .inner
+                .iter_mut()
+                .find(|x| x.token == token || x.token.is_empty())
+            {
+                account.label = label;
+                account.token = token;
+                account.user = user;
+                return account.id;
+            }
+            let id = self.inner.iter().map(|x| x.id).max().unwrap_or(0) + 1;
+            self.inner.push(Account {
+                id,
+                label,
+                token,
+                user,
+            });
+            return id;
+        }
+
+        /// Make an account active. Returns false if there is no such account.
+        pub fn activate(self: &mut Self, id: u32) -> bool {
+            if !self.inner.iter().any(|x| x.id == id) {
+                return false;
+            }
+            self.active = id;
+            return true;
+        }
+
+        pub fn iter(self: &Self) -> impl Iterator<Item = &Account> {
+            return self.inner.iter();
+        }
+    }
+
+    impl Replication {
+        pub fn new(primary: Option<String>) -> Self {
+            Self {
+                primary,
+                promoted: false,
+                last_sync: None,
+                last_error: None,
+            }
+        }
+
+        /// Whether changes are rejected because this is a standby instance.
+        pub fn is_read_only(self: &Self) -> bool {
+            return self.primary.is_some() && !self.promoted;
+        }
+    }
+
+    impl DiscordEvents {
+        pub fn new(events: &[discord::DiscordEvent]) -> Self {
+            Self {
+                updated: events.contains(&discord::DiscordEvent::Updated),
+                unmatched: events.contains(&discord::DiscordEvent::Unmatched),
+                failed: events.contains(&discord::DiscordEvent::Failed),
+            }
+        }
+
+        pub fn is_enabled(self: &Self, event: discord::DiscordEvent) -> bool {
+            return match event {
+                discord::DiscordEvent::Updated => self.updated,
+                discord::DiscordEvent::Unmatched => self.unmatched,
+                discord::DiscordEvent::Failed => self.failed,
+            };
+        }
+    }
+
+    impl PendingAuthorizations {
+        pub fn new() -> Self {
+            Self {
+                inner: HashMap::new(),
+            }
+        }
+
+        /// Start an authorization and return its state value.
+        pub fn create(self: &mut Self) -> String {
+            self.inner
+                .retain(|_, created| created.elapsed() < AUTHORIZATION_MAX_AGE);
+            let state = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+            self.inner.insert(state.clone(), Instant::now());
+            return state;
+        }
+
+        /// Finish an authorization. Returns false if the state value is unknown or
+        /// expired, e.g. when the callback is opened twice.
+        pub fn take(self: &mut Self, state: &str) -> bool {
+            return self
+                .inner
+                .remove(state)
+                .is_some_and(|created| created.elapsed() < AUTHORIZATION_MAX_AGE);
+        }
+    }
+
+    impl AdminSessions {
+        pub fn new() -> Self {
+            Self {
+                inner: HashMap::new(),
+            }
+        }
+
+        /// Create a new session and return its identifier.
+        pub fn create(self: &mut Self) -> String {
+            self.inner
+                .retain(|_, created| is_admin_session_valid(created));
+            let id = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+            self.inner.insert(id.clone(), SystemTime::now());
+            return id;
+        }
+
+        pub fn is_valid(self: &Self, id: &str) -> bool {
+            return self.inner.get(id).is_some_and(is_admin_session_valid);
+        }
+
+        pub fn remove(self: &mut Self, id: &str) {
+            self.inner.remove(id);
+        }
+    }
+
+    impl WatchSession {
+        fn new(webhook: &plex::Webhook) -> Self {
+            Self {
+                account: webhook.account.name.clone(),
+                title: webhook.metadata.title.clone(),
+                season_number: webhook.metadata.season_number,
+                episode_number: webhook.metadata.episode_number,
+                duration: webhook.metadata.duration.map(Duration::from_millis),
+                view_offset: None,
+                watched: Duration::ZERO,
+                playing_since: None,
+                last_event: Instant::now(),
+            }
+        }
+
+        pub fn is_playing(self: &Self) -> bool {
+            return self.playing_since.is_some();
+        }
+
+        fn play(self: &mut Self) {
+            if self.playing_since.is_none() {
+                self.playing_since = Some(Instant::now());
+            }
+        }
+
+        fn pause(self: &mut Self) {
+            if let Some(playing_since) = self.playing_since.take() {
+                self.watched += playing_since.elapsed();
+            }
+        }
+
+        /// Total time spent playing, including the currently ongoing playback.
+        pub fn watched(self: &Self) -> Duration {
+            let playing = self.playing_since.map(|x| x.elapsed()).unwrap_or_default();
+            return self.watched + playing;
+        }
+
+        /// Playback position as a fraction of the item duration, if both are known.
+        pub fn progress(self: &Self) -> Option<f64> {
+            let duration = self.duration.filter(|x| !x.is_zero())?;
+            let view_offset = self.view_offset?;
+            return Some(view_offset.as_secs_f64() / duration.as_secs_f64());
+        }
+
+        /// Fraction of the item that has been watched, if the duration is known.
+        pub fn watched_fraction(self: &Self) -> Option<f64> {
+            let duration = self.duration.filter(|x| !x.is_zero())?;
+            return Some(self.watched().as_secs_f64() / duration.as_secs_f64());
+        }
+    }
+
+    impl WatchSessions {
+        pub fn new() -> Self {
+            Self {
+                inner: HashMap::new(),
+            }
+        }
+
+        pub fn iter(self: &Self) -> impl Iterator<Item = (&String, &WatchSession)> {
+            return self.inner.iter();
+        }
+
+        /// Update the session with a playback event. Sessions that have received no
+        /// events in a long time are discarded at the same time.
+        pub fn record(
+            self: &mut Self,
+            key: String,
+            webhook: &plex::Webhook,
+            event: &plex::PlaybackEvent,
+        ) {
+            self.inner
+                .retain(|_, session| session.last_event.elapsed() < SESSION_MAX_AGE);
+            let session = self
+                .inner
+                .entry(key)
+                .or_insert_with(|| WatchSession::new(webhook));
+            match event {
+                plex::PlaybackEvent::Play => session.play(),
+                plex::PlaybackEvent::Pause | plex::PlaybackEvent::Stop => session.pause(),
+                plex::PlaybackEvent::Scrobble => {}
+            }
+            if let Some(view_offset) = webhook.metadata.view_offset {
+                session.view_offset = Some(Duration::from_millis(view_offset));
+            }
+            session.last_event = Instant::now();
+        }
+
+        pub fn remove(self: &mut Self, key: &String) -> Option<WatchSession> {
+            return self.inner.remove(key);
+        }
+    }
+
+    impl SpecialOverride {
+        pub fn is_valid(self: &Self) -> bool {
+            return !self.title.is_empty()
+                && !self.episodes.is_empty()
+                && self.episodes.iter().all(|x| *x >= 1);
+        }
+    }
+
+    impl SpecialOverrides {
+        pub fn new() -> Self {
+            Self {
+                inner: BTreeMap::new(),
+            }
+        }
+
+        pub fn get(self: &Self, key: &i32) -> Option<&SpecialOverride> {
+            return self.inner.get(key);
+        }
+
+        pub fn set(self: &mut Self, key: i32, value: SpecialOverride) {
+            self.inner.insert(key, value);
+        }
+
+        pub fn remove(self: &mut Self, key: &i32) {
+            self.inner.remove(key);
+        }
+
+        pub fn iter(self: &Self) -> impl Iterator<Item = (&i32, &SpecialOverride)> {
+            return self.inner.iter();
+        }
+
+        /// Anilist ID and episode of a special episode of a Plex show.
+        pub fn find(self: &Self, title: &str, special_episode: i32) -> Option<(i32, i32)> {
+            return self.inner.iter().find_map(|(id, special_override)| {
+                if special_override.title != title {
+                    return None;
+                }
+                let position = special_override
+                    .episodes
+                    .iter()
+                    .position(|x| *x == special_episode)?;
+                Some((*id, position as i32 + 1))
+            });
+        }
+    }
+
+    impl SeasonMapping {
+        pub fn is_valid(self: &Self) -> bool {
+            return !self.title.is_empty() && self.season >= 1 && self.anilist_id >= 1;
+        }
+    }
+
+    impl SeasonMappings {
+        pub fn new() -> Self {
+            Self {
+                inner: BTreeMap::new(),
+            }
+        }
+
+        pub fn get(self: &Self, title: &str, season: i32) -> Option<i32> {
+            return self.inner.get(&(title.to_string(), season)).copied();
+        }
+
+        /// Map a season to an Anilist ID. Returns the ID it was previously mapped to.
+        pub fn set(self: &mut Self, mapping: SeasonMapping) -> Option<i32> {
+            return self
+                .inner
+                .insert((mapping.title, mapping.season), mapping.anilist_id);
+        }
+
+        pub fn remove(self: &mut Self, title: &str, season: i32) -> Option<i32> {
+            return self.inner.remove(&(title.to_string(), season));
+        }
+
+        pub fn iter(self: &Self) -> impl Iterator<Item = SeasonMapping> + '_ {
+            return self
+                .inner
+                .iter()
+                .map(|((title, season), anilist_id)| SeasonMapping {
+                    title: title.clone(),
+                    season: *season,
+                    anilist_id: *anilist_id,
+                });
+        }
+    }
+
+    impl EpisodeOverrides {
+        pub fn new() -> Self {
+            Self {
+                inner: HashMap::new(),
+            }
+        }
+
+        pub fn get(self: &Self, key: &i32) -> Option<i32> {
+            return self.inner.get(key).copied();
+        }
+
+        pub fn set(self: &mut Self, key: i32, value: i32) {
+            self.inner.insert(key, value);
+        }
+
+        pub fn remove(self: &mut Self, key: &i32) {
+            self.inner.remove(key);
+        }
 
-pub mod forms {
-    #[derive(Debug, FromForm)]
-    pub struct Scrobble<'r> {
-        pub payload: &'r str,
+        pub fn iter(self: &Self) -> impl Iterator<Item = (&i32, &i32)> {
+            return self.inner.iter();
+        }
     }
 
-    #[derive(Debug, FromForm)]
-    pub struct AnimeOverride<'r> {
-        pub episode_offset: Option<i32>,
-        pub title: Option<&'r str>,
+    /// Title override map between titles (String) and Anilist IDs (i32).
+    impl TitlePatterns {
+        pub fn new() -> Self {
+            Self { inner: Vec::new() }
+        }
+
+        /// ID of the first pattern that matches the title.
+        pub fn get(self: &Self, title: &str) -> Option<i32> {
+            return self
+                .inner
+                .iter()
+                .find(|(pattern, _)| pattern.is_match(title))
+                .map(|(_, id)| *id);
+        }
+
+        pub fn get_key(self: &Self, value: &i32) -> Option<String> {
+            return self
+                .inner
+                .iter()
+                .find(|(_, id)| id == value)
+                .map(|(pattern, _)| pattern.to_string());
+        }
+
+        pub fn iter(self: &Self) -> impl Iterator<Item = (&Regex, &i32)> {
+            return self.inner.iter().map(|(pattern, id)| (pattern, id));
+        }
+
+        /// Set a title pattern for an ID. Replaces the existing pattern of the ID.
+        pub fn set(self: &mut Self, pattern: Regex, value: i32) {
+            self.remove_value(&value);
+            self.inner.push((pattern, value));
+        }
+
+        pub fn remove_value(self: &mut Self, value: &i32) {
+            self.inner.retain(|(_, id)| id != value);
+        }
     }
 
-    impl AnimeOverride<'_> {
-        /// Retrieve a usable episode offset value.
-        pub fn get_episode_offset(self: &Self) -> Option<i32> {
-            if let Some(episode_offset) = self.episode_offset {
-                if episode_offset != 0 {
-                    return Some(episode_offset);
-                }
+    impl Mutes {
+        pub fn new() -> Self {
+            Self {
+                inner: HashMap::new(),
             }
-            return None;
         }
 
-        /// Retrieve a usable title value.
-        pub fn get_title(self: &Self) -> Option<&str> {
-            if let Some(title) = self.title {
-                if title != "" {
-                    return Some(title);
-                }
-            }
-            return None;
+        /// Day until which the entry is muted. Mutes that have expired are ignored.
+        pub fn get(self: &Self, key: &i32) -> Option<Date> {
+            return self.get_on(key, today());
         }
-    }
 
-    #[cfg(test)]
-    mod tests {
-        use crate::data::forms::AnimeOverride;
+        fn get_on(self: &Self, key: &i32, date: Date) -> Option<Date> {
+            return self.inner.get(key).copied().filter(|x| *x >= date);
+        }
 
-        use test_case::test_case;
+        pub fn set(self: &mut Self, key: i32, value: Date) {
+            self.inner.retain(|_, x| *x >= today());
+            self.inner.insert(key, value);
+        }
 
-        #[test_case(Some(0), None ; "zero episode offset")]
-        #[test_case(Some(1), Some(1) ; "positive episode offset")]
-        #[test_case(Some(-12), Some(-12) ; "negative episode offset")]
-        #[test_case(None, None ; "no episode offset")]
-        fn episode_offset(value: Option<i32>, expected: Option<i32>) {
-            let anime_override = AnimeOverride {
-                episode_offset: value,
-                title: None,
-            };
-            assert_eq!(anime_override.get_episode_offset(), expected);
+        pub fn remove(self: &mut Self, key: &i32) {
+            self.inner.remove(key);
         }
 
-        #[test_case(Some(""), None ; "empty title")]
-        #[test_case(Some("title"), Some("title") ; "valid title")]
-        #[test_case(None, None ; "no title")]
-        fn title(value: Option<&str>, expected: Option<&str>) {
-            let anime_override = AnimeOverride {
-                episode_offset: None,
-                title: value,
-            };
-            assert_eq!(anime_override.get_title(), expected);
+        /// Mutes that have not expired.
+        pub fn iter(self: &Self) -> impl Iterator<Item = (&i32, &Date)> {
+            let date = today();
+            return self.inner.iter().filter(move |(_, x)| **x >= date);
         }
     }
-}
 
-pub mod state {
-    use crate::anilist;
-    use std::collections::HashMap;
-    use tokio::sync::RwLock;
+    impl LogOnly {
+        pub fn new() -> Self {
+            Self {
+                inner: HashSet::new(),
+            }
+        }
 
-    #[derive(Debug)]
-    /// Global anifunnel application state.
-    pub struct Global {
-        pub multi_season: bool,
-        pub token: String,
-        pub plex_user: Option<String>,
-        pub user: anilist::User,
-        pub title_overrides: RwLock<TitleOverrides>,
-        pub episode_offsets: RwLock<EpisodeOverrides>,
-    }
+        pub fn contains(self: &Self, key: &i32) -> bool {
+            return self.inner.contains(key);
+        }
 
-    #[derive(Debug)]
-    pub struct EpisodeOverrides {
-        inner: HashMap<i32, i32>,
-    }
+        pub fn set(self: &mut Self, key: i32, enabled: bool) {
+            if enabled {
+                self.inner.insert(key);
+            } else {
+                self.inner.remove(&key);
+            }
+        }
 
-    #[derive(Debug)]
-    pub struct TitleOverrides {
-        inner: HashMap<String, i32>,
+        pub fn iter(self: &Self) -> impl Iterator<Item = &i32> {
+            return self.inner.iter();
+        }
     }
 
-    impl EpisodeOverrides {
+    impl MinimumConfidences {
         pub fn new() -> Self {
             Self {
                 inner: HashMap::new(),
             }
         }
 
-        pub fn get(self: &Self, key: &i32) -> Option<i32> {
+        pub fn get(self: &Self, key: &i32) -> Option<f64> {
             return self.inner.get(key).copied();
         }
 
-        pub fn set(self: &mut Self, key: i32, value: i32) {
+        /// Minimum confidence of an entry, falling back to the global minimum.
+        pub fn effective(
+            self: &Self,
+            media_list: &anilist::MediaList,
+            settings: &anilist::MatchSettings,
+        ) -> f64 {
+            return self
+                .get(&media_list.id)
+                .unwrap_or(settings.minimum_confidence);
+        }
+
+        pub fn set(self: &mut Self, key: i32, value: f64) {
             self.inner.insert(key, value);
         }
 
         pub fn remove(self: &mut Self, key: &i32) {
             self.inner.remove(key);
         }
+
+        pub fn iter(self: &Self) -> impl Iterator<Item = (&i32, &f64)> {
+            return self.inner.iter();
+        }
+    }
+
+    impl OverrideVersions {
+        pub fn new() -> Self {
+            Self {
+                inner: HashMap::new(),
+            }
+        }
+
+        /// Version of the overrides of an entry. Entries that have never been edited
+        /// are at version 0.
+        pub fn get(self: &Self, key: &i32) -> OverrideVersion {
+            return self.inner.get(key).copied().unwrap_or_default();
+        }
+
+        /// Record a change to the overrides of an entry and return the new version.
+        pub fn bump(self: &mut Self, key: i32) -> OverrideVersion {
+            let version = self.inner.entry(key).or_default();
+            version.version += 1;
+            version.updated_at = Some(unix_timestamp());
+            return *version;
+        }
     }
 
-    /// Title override map between titles (String) and Anilist IDs (i32).
     impl TitleOverrides {
         pub fn new() -> Self {
             Self {
@@ -158,6 +4016,10 @@ pub mod state {
             return self.inner.get(key).copied();
         }
 
+        pub fn iter(self: &Self) -> impl Iterator<Item = (&String, &i32)> {
+            return self.inner.iter();
+        }
+
         pub fn get_key(self: &Self, value: &i32) -> Option<String> {
             for (key, inner_value) in self.inner.clone().iter() {
                 if inner_value == value {
@@ -173,6 +4035,11 @@ pub mod state {
             self.inner.insert(key, value);
         }
 
+        /// Remove override by the key.
+        pub fn remove_key(self: &mut Self, key: &String) {
+            self.inner.remove(key);
+        }
+
         /// Remove override by the ID.
         pub fn remove_value(self: &mut Self, value: &i32) {
             for (inner_key, inner_value) in self.inner.clone().iter() {
@@ -183,12 +4050,61 @@ pub mod state {
         }
     }
 
+    impl AccountTitleOverrides {
+        pub fn new() -> Self {
+            Self {
+                inner: HashMap::new(),
+            }
+        }
+
+        pub fn get(self: &Self, user_id: i32, key: &String) -> Option<i32> {
+            return self.inner.get(&user_id).and_then(|x| x.get(key));
+        }
+
+        pub fn iter(self: &Self) -> impl Iterator<Item = (&i32, &TitleOverrides)> {
+            return self.inner.iter();
+        }
+
+        /// Title overrides of an account, created if the account has none yet.
+        pub fn account_mut(self: &mut Self, user_id: i32) -> &mut TitleOverrides {
+            return self
+                .inner
+                .entry(user_id)
+                .or_insert_with(TitleOverrides::new);
+        }
+
+        /// Remove an override of an account by the key and return its ID.
+        pub fn remove_key(self: &mut Self, user_id: i32, key: &String) -> Option<i32> {
+            let overrides = self.inner.get_mut(&user_id)?;
+            let id = overrides.get(key)?;
+            overrides.remove_key(key);
+            if overrides.inner.is_empty() {
+                self.inner.remove(&user_id);
+            }
+            return Some(id);
+        }
+    }
+
     #[cfg(test)]
     mod tests {
         use std::collections::HashMap;
         use test_case::test_case;
 
-        use crate::data::state::{EpisodeOverrides, TitleOverrides};
+        use crate::data::state::{
+            sanitize_payload, today, AccountTitleOverrides, Accounts, AdminSessions, DiscordEvents,
+            EpisodeOverrides, FailedPayloads, History, HistoryEntry, HistoryOutcome,
+            MediaDetailsCache, Mutes, NotificationKind, Notifications, OverrideVersion,
+            OverrideVersions, PendingAuthorizations, ReadinessCache, Rewatches, ScrobbleSource,
+            SpecialOverride, SpecialOverrides, TitleIndexes, TitleOverrides, TitlePatterns,
+            Unmatched, WatchSession, WebhookLimit, ADMIN_SESSION_MAX_AGE, AUTHORIZATION_MAX_AGE,
+            DAILY_UPDATE_DAYS, FAILED_PAYLOAD_CAPACITY, HISTORY_CAPACITY, MEDIA_DETAILS_TTL,
+            READINESS_TTL, TITLE_INDEX_CAPACITY,
+        };
+        use crate::{anilist, discord, plex};
+        use regex::Regex;
+        use rocket::time::{Date, Month};
+        use std::collections::BTreeMap;
+        use std::time::{Duration, Instant, SystemTime};
 
         fn get_inner_contents<K: std::cmp::Ord, V: std::cmp::Ord>(
             inner: &HashMap<K, V>,
@@ -238,6 +4154,436 @@ pub mod state {
             );
         }
 
+        fn fake_watch_session(duration: Option<u64>, watched: u64) -> WatchSession {
+            WatchSession {
+                account: String::from("yukikaze"),
+                title: String::from("Onii-chan wa Oshimai!"),
+                season_number: 1,
+                episode_number: 4,
+                duration: duration.map(Duration::from_secs),
+                view_offset: None,
+                watched: Duration::from_secs(watched),
+                playing_since: None,
+                last_event: Instant::now(),
+            }
+        }
+
+        #[test]
+        fn accounts() {
+            let user = |name: &str| anilist::User {
+                name: String::from(name),
+                ..Default::default()
+            };
+            let mut accounts = Accounts::new(String::from("main"), String::from("A"), user("a"));
+            let id = accounts.add(String::from("shared"), String::from("B"), user("b"));
+            assert_eq!(id, 2);
+            assert_eq!(accounts.active().token, "A");
+            assert!(accounts.activate(id));
+            assert_eq!(accounts.active().user.name, "b");
+            assert!(!accounts.activate(3));
+            assert_eq!(accounts.active().id, 2);
+            assert_eq!(
+                accounts.add(String::from("renamed"), String::from("A"), user("a")),
+                1
+            );
+            assert_eq!(accounts.iter().count(), 2);
+            assert_eq!(accounts.iter().next().unwrap().label, "renamed");
+        }
+
+        #[test]
+        fn accounts_unauthorized() {
+            let mut accounts = Accounts::new(String::new(), String::new(), Default::default());
+            let user = anilist::User {
+                name: String::from("a"),
+                ..Default::default()
+            };
+            assert_eq!(accounts.add(String::from("a"), String::from("A"), user), 1);
+            assert_eq!(accounts.active().token, "A");
+            assert_eq!(accounts.iter().count(), 1);
+        }
+
+        #[test]
+        fn pending_authorizations() {
+            let mut authorizations = PendingAuthorizations::new();
+            let state = authorizations.create();
+            assert!(!authorizations.take("invalid"));
+            assert!(authorizations.take(&state));
+            assert!(!authorizations.take(&state));
+            let state = authorizations.create();
+            authorizations.inner.insert(
+                state.clone(),
+                Instant::now() - AUTHORIZATION_MAX_AGE - Duration::from_secs(1),
+            );
+            assert!(!authorizations.take(&state));
+        }
+
+        #[test]
+        fn admin_sessions() {
+            let mut admin_sessions = AdminSessions::new();
+            let id = admin_sessions.create();
+            assert_eq!(id.len(), 32);
+            assert!(admin_sessions.is_valid(&id));
+            assert!(!admin_sessions.is_valid("invalid"));
+            admin_sessions.remove(&id);
+            assert!(!admin_sessions.is_valid(&id));
+        }
+
+        #[test]
+        fn admin_sessions_expired() {
+            let mut admin_sessions = AdminSessions::new();
+            let id = admin_sessions.create();
+            admin_sessions.inner.insert(
+                id.clone(),
+                SystemTime::now() - ADMIN_SESSION_MAX_AGE - Duration::from_secs(1),
+            );
+            assert!(!admin_sessions.is_valid(&id));
+        }
+
+        #[test_case(
+            "{\"Account\":{\"title\":\"yukikaze\",\"thumb\":\"https://plex.tv/a\"},\"Player\":{\"publicAddress\":\"192.0.2.1\"}}",
+            "{\"Account\":{\"thumb\":\"<redacted>\",\"title\":\"yukikaze\"},\"Player\":{\"publicAddress\":\"<redacted>\"}}" ;
+            "sensitive fields"
+        )]
+        #[test_case("{\"event\":", "{\"event\":" ; "invalid json")]
+        fn payload_sanitization(payload: &str, expected: &str) {
+            assert_eq!(sanitize_payload(payload), expected);
+        }
+
+        #[test_case("Yuru Camp", 3, Some((98444, 1)) ; "first special")]
+        #[test_case("Yuru Camp", 5, Some((98444, 2)) ; "second special")]
+        #[test_case("Yuru Camp", 4, None ; "unmapped special")]
+        #[test_case("Frieren", 3, None ; "other show")]
+        fn special_overrides_find(title: &str, episode: i32, expected: Option<(i32, i32)>) {
+            let mut special_overrides = SpecialOverrides::new();
+            special_overrides.set(
+                98444,
+                SpecialOverride {
+                    title: String::from("Yuru Camp"),
+                    episodes: vec![3, 5],
+                },
+            );
+            assert_eq!(special_overrides.find(title, episode), expected);
+        }
+
+        #[test]
+        fn history_source_counts() {
+            let webhook: plex::Webhook = serde_json::from_str(
+                "{\"event\": \"media.scrobble\", \"Account\": {\"title\": \"yukikaze\"}, \
+                \"Metadata\": {\"type\": \"episode\", \"grandparentTitle\": \"Yuru Camp\"}}",
+            )
+            .unwrap();
+            let mut history = History::new();
+            history.record(&webhook, None, HistoryOutcome::Unmatched);
+            history.record(&webhook, None, HistoryOutcome::Unmatched);
+            history.push(HistoryEntry {
+                timestamp: 0,
+                source: ScrobbleSource::ManualApi,
+                title: String::from("Yuru Camp"),
+                guid: None,
+                season_number: 0,
+                episode_number: 3,
+                anilist_id: Some(1234),
+                outcome: HistoryOutcome::Updated,
+            });
+            assert_eq!(
+                history.source_counts(),
+                BTreeMap::from([
+                    (String::from("manual_api"), 1),
+                    (String::from("plex_webhook"), 2)
+                ])
+            );
+        }
+
+        #[test]
+        fn history_daily_updates() {
+            let mut history = History::new();
+            for (timestamp, outcome) in [
+                (86400, HistoryOutcome::Updated),
+                (172800, HistoryOutcome::Updated),
+                (172801, HistoryOutcome::Updated),
+                (172802, HistoryOutcome::Skipped),
+            ] {
+                history.push(HistoryEntry {
+                    timestamp,
+                    source: ScrobbleSource::PlexWebhook,
+                    title: String::from("Yuru Camp"),
+                    guid: None,
+                    season_number: 1,
+                    episode_number: 3,
+                    anilist_id: Some(1234),
+                    outcome,
+                });
+            }
+            let since = Date::from_calendar_date(1970, Month::January, 3).unwrap();
+            assert_eq!(history.daily_updates(since), BTreeMap::from([(since, 2)]));
+        }
+
+        #[test]
+        fn history_daily_updates_outlive_entries() {
+            let mut history = History::new();
+            let day = 86400;
+            for index in 0..(HISTORY_CAPACITY as u64 + 1) {
+                history.push(HistoryEntry {
+                    timestamp: day + index,
+                    source: ScrobbleSource::PlexWebhook,
+                    title: String::from("Yuru Camp"),
+                    guid: None,
+                    season_number: 1,
+                    episode_number: 3,
+                    anilist_id: Some(1234),
+                    outcome: HistoryOutcome::Updated,
+                });
+            }
+            let since = Date::from_calendar_date(1970, Month::January, 2).unwrap();
+            assert_eq!(
+                history.daily_updates(since),
+                BTreeMap::from([(since, HISTORY_CAPACITY + 1)])
+            );
+            history.count_update(day + DAILY_UPDATE_DAYS as u64 * 86400);
+            assert_eq!(history.daily_updates(since).get(&since), None);
+        }
+
+        #[test]
+        fn unmatched_take() {
+            let mut unmatched = Unmatched::new();
+            for (title, episode) in [("A", 3), ("B", 1), ("A", 2), ("A", 3)] {
+                let webhook: plex::Webhook = serde_json::from_str(&format!(
+                    "{{\"event\": \"media.scrobble\", \"Account\": {{\"title\": \"yukikaze\"}}, \
+                    \"Metadata\": {{\"type\": \"episode\", \"grandparentTitle\": \"{}\", \
+                    \"parentIndex\": 1, \"index\": {}}}}}",
+                    title, episode
+                ))
+                .unwrap();
+                unmatched.record(&webhook, "{}", Vec::new());
+            }
+            assert_eq!(unmatched.iter().count(), 3);
+            assert!(unmatched.take(1).is_none());
+            let taken = unmatched.take(3).unwrap();
+            assert_eq!(
+                taken.iter().map(|x| x.episode_number).collect::<Vec<i32>>(),
+                vec![2, 3]
+            );
+            assert_eq!(
+                unmatched
+                    .iter()
+                    .map(|x| x.title.as_str())
+                    .collect::<Vec<&str>>(),
+                vec!["B"]
+            );
+        }
+
+        #[test]
+        fn media_details_cache_expiry() {
+            let mut cache = MediaDetailsCache::new();
+            let details: anilist::MediaDetails = serde_json::from_str(
+                "{\"id\": 98444, \"status\": \"FINISHED\", \
+                \"title\": {\"userPreferred\": \"Yuru Camp\"}}",
+            )
+            .unwrap();
+            cache.insert_at(details, 1000);
+            assert!(cache.get_at(98444, 1000 + MEDIA_DETAILS_TTL - 1).is_some());
+            assert!(cache.get_at(98444, 1000 + MEDIA_DETAILS_TTL).is_none());
+            assert!(cache.get_at(104460, 1000).is_none());
+        }
+
+        #[test]
+        fn title_indexes_capacity() {
+            let mut title_indexes = TitleIndexes::new();
+            let groups: Vec<anilist::MediaListGroup> = (0..=TITLE_INDEX_CAPACITY)
+                .map(|x| {
+                    serde_json::from_str(&format!(
+                        "{{\"entries\": [{{\"id\": {0}, \"progress\": 0, \"media\": \
+                        {{\"id\": {0}, \"title\": {{\"romaji\": \"{0}\", \
+                        \"userPreferred\": \"{0}\"}}}}}}]}}",
+                        x
+                    ))
+                    .unwrap()
+                })
+                .collect();
+            for group in &groups {
+                title_indexes.insert(group.title_index());
+            }
+            assert!(title_indexes.get(groups[0].title_fingerprint()).is_none());
+            assert!(title_indexes
+                .get(groups[TITLE_INDEX_CAPACITY].title_fingerprint())
+                .is_some());
+        }
+
+        #[test]
+        fn readiness_cache_expiry() {
+            let mut cache = ReadinessCache::new();
+            cache.insert_at("token", String::from("yukikaze"), 1000);
+            assert_eq!(
+                cache.get_at("token", 1000 + READINESS_TTL - 1),
+                Some("yukikaze")
+            );
+            assert!(cache.get_at("token", 1000 + READINESS_TTL).is_none());
+            assert!(cache.get_at("other-token", 1000).is_none());
+        }
+
+        #[test]
+        fn unmatched_backoff() {
+            let mut unmatched = Unmatched::new();
+            let webhook: plex::Webhook = serde_json::from_str(
+                "{\"event\": \"media.scrobble\", \"Account\": {\"title\": \"yukikaze\"}, \
+                \"Metadata\": {\"type\": \"episode\", \"grandparentTitle\": \"A\", \
+                \"parentIndex\": 1, \"index\": 1}}",
+            )
+            .unwrap();
+            let notified: Vec<bool> = [0, 30, 60, 100, 180, 250, 420]
+                .iter()
+                .map(|&now| unmatched.record_at(&webhook, "{}", Vec::new(), now))
+                .collect();
+            assert_eq!(notified, vec![true, false, true, false, true, false, true]);
+            assert_eq!(unmatched.iter().count(), 1);
+            assert_eq!(unmatched.iter().next().unwrap().occurrences, 7);
+            let id = unmatched.iter().next().unwrap().id;
+            unmatched.take(id);
+            assert!(unmatched.record_at(&webhook, "{}", Vec::new(), 430));
+        }
+
+        #[test]
+        fn title_patterns() {
+            let mut title_patterns = TitlePatterns::new();
+            title_patterns.set(Regex::new("^Yuru Camp").unwrap(), 98444);
+            title_patterns.set(Regex::new("Season 2$").unwrap(), 104460);
+            assert_eq!(title_patterns.get("Yuru Camp (2018)"), Some(98444));
+            assert_eq!(
+                title_patterns.get("Bocchi the Rock! Season 2"),
+                Some(104460)
+            );
+            assert_eq!(title_patterns.get("Bocchi the Rock!"), None);
+            title_patterns.set(Regex::new("^Bocchi").unwrap(), 98444);
+            assert_eq!(title_patterns.get("Yuru Camp (2018)"), None);
+            assert_eq!(
+                title_patterns.get_key(&98444),
+                Some(String::from("^Bocchi"))
+            );
+        }
+
+        #[test]
+        fn mutes_expiry() {
+            let mut mutes = Mutes::new();
+            let date = today();
+            mutes.set(1, date);
+            mutes.set(2, date.previous_day().unwrap());
+            assert_eq!(mutes.get(&1), Some(date));
+            assert_eq!(mutes.get(&2), None);
+            assert_eq!(mutes.get_on(&1, date.next_day().unwrap()), None);
+            mutes.set(3, date);
+            assert_eq!(mutes.inner.len(), 2);
+        }
+
+        #[test]
+        fn override_versions() {
+            let mut override_versions = OverrideVersions::new();
+            assert_eq!(override_versions.get(&1), OverrideVersion::default());
+            override_versions.bump(1);
+            let version = override_versions.bump(1);
+            assert_eq!(version.version, 2);
+            assert!(version.updated_at.is_some());
+            assert_eq!(override_versions.get(&1), version);
+            assert_eq!(override_versions.get(&2).version, 0);
+        }
+
+        #[test]
+        fn webhook_limit() {
+            let webhook_limit = WebhookLimit::new(Some(2));
+            let first = webhook_limit.acquire();
+            let second = webhook_limit.acquire();
+            assert!(first.is_some());
+            assert!(second.is_some());
+            assert!(webhook_limit.acquire().is_none());
+            drop(first);
+            assert!(webhook_limit.acquire().is_some());
+            assert!(WebhookLimit::new(None).acquire().is_some());
+        }
+
+        #[test]
+        fn notifications_push() {
+            let mut notifications = Notifications::new();
+            notifications.push(NotificationKind::Unmatched, String::from("A"));
+            notifications.push(NotificationKind::Unmatched, String::from("A"));
+            notifications.push(NotificationKind::Unmatched, String::from("B"));
+            assert_eq!(notifications.unread(), 2);
+            assert!(notifications.mark_read(1));
+            assert!(!notifications.mark_read(3));
+            notifications.push(NotificationKind::Unmatched, String::from("A"));
+            assert_eq!(notifications.unread(), 2);
+            assert_eq!(
+                notifications.iter().map(|x| x.id).collect::<Vec<u64>>(),
+                vec![1, 2, 3]
+            );
+        }
+
+        #[test_case("header.eyJleHAiOjEwMDB9.signature", 1 ; "expiring token")]
+        #[test_case("header.eyJleHAiOjQxMDI0NDQ4MDB9.signature", 0 ; "valid token")]
+        #[test_case("invalid", 0 ; "unknown expiry")]
+        fn notifications_token_expiry(token: &str, expected: usize) {
+            let mut notifications = Notifications::new();
+            assert_eq!(
+                notifications.check_token_expiry(token).is_some(),
+                expected == 1
+            );
+            assert_eq!(notifications.check_token_expiry(token), None);
+            assert_eq!(notifications.unread(), expected);
+        }
+
+        #[test]
+        fn rewatches_record() {
+            let mut rewatches = Rewatches::new();
+            assert_eq!(rewatches.record(146065, 3), 1);
+            assert_eq!(rewatches.record(146065, 4), 1);
+            assert_eq!(rewatches.record(146065, 3), 2);
+        }
+
+        #[test]
+        fn failed_payloads_capacity() {
+            let mut failed_payloads = FailedPayloads::new();
+            for i in 0..FAILED_PAYLOAD_CAPACITY + 2 {
+                failed_payloads.record(&i.to_string());
+            }
+            assert_eq!(failed_payloads.inner.len(), FAILED_PAYLOAD_CAPACITY);
+            assert_eq!(failed_payloads.inner.front(), Some(&String::from("2")));
+        }
+
+        #[test_case(Some(1440), 720, Some(0.5) ; "half watched")]
+        #[test_case(Some(1440), 0, Some(0.0) ; "not watched")]
+        #[test_case(Some(0), 720, None ; "zero duration")]
+        #[test_case(None, 720, None ; "unknown duration")]
+        fn watch_session_watched_fraction(
+            duration: Option<u64>,
+            watched: u64,
+            expected: Option<f64>,
+        ) {
+            let session = fake_watch_session(duration, watched);
+            assert_eq!(session.watched_fraction(), expected);
+        }
+
+        #[test_case(Some(1440), Some(360), Some(0.25) ; "quarter played")]
+        #[test_case(Some(1440), None, None ; "unknown view offset")]
+        #[test_case(None, Some(360), None ; "unknown duration")]
+        fn watch_session_progress(
+            duration: Option<u64>,
+            view_offset: Option<u64>,
+            expected: Option<f64>,
+        ) {
+            let mut session = fake_watch_session(duration, 0);
+            session.view_offset = view_offset.map(Duration::from_secs);
+            assert_eq!(session.progress(), expected);
+        }
+
+        #[test]
+        fn watch_session_pause() {
+            let mut session = fake_watch_session(Some(1440), 600);
+            session.playing_since = Some(Instant::now() - Duration::from_secs(120));
+            assert!(session.is_playing());
+            session.pause();
+            assert!(!session.is_playing());
+            assert!(session.watched() >= Duration::from_secs(720));
+            assert!(session.watched() < Duration::from_secs(725));
+        }
+
         #[test_case("Mushoku Tensei II", Some(146065) ; "valid key")]
         #[test_case("Horimiya -piece-", Some(163132) ; "also valid key")]
         #[test_case("Mushoku Tensei S2", None ; "invalid key")]
@@ -267,6 +4613,20 @@ pub mod state {
             );
         }
 
+        #[test]
+        fn account_title_overrides() {
+            let mut overrides = AccountTitleOverrides::new();
+            let title = String::from("Yuru Camp");
+            overrides.account_mut(1).set(title.clone(), 98444);
+            overrides.account_mut(2).set(title.clone(), 104460);
+            assert_eq!(overrides.get(1, &title), Some(98444));
+            assert_eq!(overrides.get(2, &title), Some(104460));
+            assert_eq!(overrides.get(3, &title), None);
+            assert_eq!(overrides.remove_key(1, &title), Some(98444));
+            assert_eq!(overrides.remove_key(1, &title), None);
+            assert!(!overrides.inner.contains_key(&1));
+        }
+
         #[test]
         fn title_override_new() {
             let title_override = TitleOverrides::new();
@@ -341,5 +4701,16 @@ pub mod state {
                 ]
             );
         }
+
+        #[test]
+        fn discord_events_enabled() {
+            let events = DiscordEvents::new(&[
+                discord::DiscordEvent::Updated,
+                discord::DiscordEvent::Failed,
+            ]);
+            assert!(events.is_enabled(discord::DiscordEvent::Updated));
+            assert!(!events.is_enabled(discord::DiscordEvent::Unmatched));
+            assert!(events.is_enabled(discord::DiscordEvent::Failed));
+        }
     }
 }
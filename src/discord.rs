@@ -0,0 +1,109 @@
+use log::{debug, warn};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::trace;
+
+#[derive(Debug)]
+pub enum DiscordError {
+    Connection,
+}
+
+/// Events that can be posted to the Discord webhook.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DiscordEvent {
+    /// Anilist progress was updated from a scrobble.
+    Updated,
+    /// A scrobble did not match anything in the watching list.
+    Unmatched,
+    /// Anilist rejected or failed a progress update.
+    Failed,
+}
+
+impl DiscordEvent {
+    fn title(self: &Self) -> &'static str {
+        return match self {
+            DiscordEvent::Updated => "Progress updated",
+            DiscordEvent::Unmatched => "No match found",
+            DiscordEvent::Failed => "Update failed",
+        };
+    }
+
+    /// Colour of the embed border.
+    fn colour(self: &Self) -> u32 {
+        return match self {
+            DiscordEvent::Updated => 0x02a9ff,
+            DiscordEvent::Unmatched => 0xf5a623,
+            DiscordEvent::Failed => 0xe85d75,
+        };
+    }
+}
+
+/// Discord webhook URL that events are posted to.
+#[derive(Clone, Debug)]
+pub struct DiscordWebhook {
+    pub url: String,
+}
+
+/// Webhook body with a single embed describing the event.
+pub fn embed(event: DiscordEvent, description: &str) -> serde_json::Value {
+    return json!({
+        "username": "anifunnel",
+        "embeds": [{
+            "title": event.title(),
+            "description": description,
+            "color": event.colour(),
+        }],
+    });
+}
+
+impl DiscordWebhook {
+    pub async fn send(
+        self: &Self,
+        event: DiscordEvent,
+        description: &str,
+    ) -> Result<(), DiscordError> {
+        reqwest::Client::new()
+            .post(&self.url)
+            .json(&embed(event, description))
+            .send()
+            .await
+            .and_then(|x| x.error_for_status())
+            .map_err(|error| {
+                debug!("{}", error);
+                DiscordError::Connection
+            })?;
+        return Ok(());
+    }
+}
+
+/// Post an event to Discord in the background, so that Discord being slow or down does
+/// not hold back processing the scrobble.
+pub fn notify(webhook: &DiscordWebhook, event: DiscordEvent, description: String) {
+    let webhook = webhook.clone();
+    tokio::spawn(trace::scope(trace::current(), async move {
+        if let Err(error) = webhook.send(event, &description).await {
+            warn!("Could not post {:?} event to Discord: {:?}", event, error);
+        }
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embed_serialize() {
+        assert_eq!(
+            embed(
+                DiscordEvent::Failed,
+                "Failed to update progress for 'Frieren'"
+            )
+            .to_string(),
+            "{\"embeds\":[{\"color\":15228277,\"description\":\
+            \"Failed to update progress for 'Frieren'\",\"title\":\"Update failed\"}],\
+            \"username\":\"anifunnel\"}"
+        );
+    }
+}
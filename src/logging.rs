@@ -0,0 +1,84 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use log::{Log, Metadata, Record, SetLoggerError};
+use serde::Serialize;
+use simple_logger::SimpleLogger;
+
+use crate::data::state::unix_timestamp;
+use crate::trace;
+
+/// Number of log records kept in memory for debug bundles.
+const RECENT_LOG_CAPACITY: usize = 200;
+
+static RECENT_LOGS: Mutex<VecDeque<LogRecord>> = Mutex::new(VecDeque::new());
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LogRecord {
+    pub timestamp: u64,
+    pub level: String,
+    pub target: String,
+    pub message: String,
+}
+
+/// Logger that writes through SimpleLogger and keeps the most recent records.
+struct BufferedLogger {
+    inner: SimpleLogger,
+}
+
+impl Log for BufferedLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        // Records logged while processing a traced webhook are tagged with the trace so
+        // that they can be found from the distributed trace.
+        let message = match trace::current() {
+            Some(context) => format!(
+                "{} trace_id={} span_id={}",
+                record.args(),
+                context.trace_id,
+                context.span_id
+            ),
+            None => record.args().to_string(),
+        };
+        self.inner.log(
+            &Record::builder()
+                .args(format_args!("{}", message))
+                .metadata(record.metadata().clone())
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build(),
+        );
+        let mut recent_logs = RECENT_LOGS.lock().unwrap();
+        if recent_logs.len() == RECENT_LOG_CAPACITY {
+            recent_logs.pop_front();
+        }
+        recent_logs.push_back(LogRecord {
+            timestamp: unix_timestamp(),
+            level: record.level().to_string(),
+            target: record.target().to_string(),
+            message,
+        });
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// Install the global logger.
+pub fn init(logger: SimpleLogger) -> Result<(), SetLoggerError> {
+    log::set_max_level(logger.max_level());
+    return log::set_boxed_logger(Box::new(BufferedLogger { inner: logger }));
+}
+
+/// Most recent log records, oldest first.
+pub fn recent() -> Vec<LogRecord> {
+    return RECENT_LOGS.lock().unwrap().iter().cloned().collect();
+}
@@ -1,27 +1,75 @@
 #[macro_use]
 extern crate rocket;
 
+mod access;
+mod anidb;
 mod anilist;
+mod cli;
+mod config;
 mod data;
+mod discord;
+mod logging;
+mod metrics;
+mod notifiers;
 mod plex;
+mod replication;
+mod report;
+mod romaji;
+mod trace;
+mod trakt;
 
 use clap::Parser;
 use data::context::Anime;
 use log::{debug, error, info, warn, LevelFilter};
-use rocket::data::{Limits, ToByteUnit};
+use rocket::data::{ByteUnit, Limits};
+use rocket::fairing::{AdHoc, Fairing};
 use rocket::form::Form;
-use rocket::response::Redirect;
+use rocket::http::uri::Origin;
+use rocket::http::{Cookie, CookieJar, SameSite, Status};
+use rocket::response::stream::{Event, EventStream};
+use rocket::response::{status, Redirect};
+use rocket::serde::json::Json;
+use rocket::Request;
 use rocket_dyn_templates::{context, Template};
 use simple_logger::SimpleLogger;
-use std::{net::Ipv4Addr, vec};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{path::PathBuf, vec};
 use tempfile::tempdir;
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, oneshot, RwLock};
+
+/// Parse a match confidence between 0 and 1.
+fn parse_confidence(value: &str) -> Result<f64, String> {
+    let confidence: f64 = value.parse().map_err(|_| String::from("not a number"))?;
+    if !(0.0..=1.0).contains(&confidence) {
+        return Err(String::from("must be between 0 and 1"));
+    }
+    return Ok(confidence);
+}
 
 #[derive(Parser, Debug)]
 struct AnifunnelArgs {
-    /// Anilist API token.
+    #[command(subcommand)]
+    command: Option<cli::Command>,
+
+    /// TOML config file for the options. Command line arguments and environment
+    /// variables take precedence over the config file.
+    #[clap(long, env = "ANIFUNNEL_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Anilist API token. Can be left out if anifunnel is authorized through
+    /// /auth/anilist instead.
     #[clap(env = "ANILIST_TOKEN")]
-    anilist_token: String,
+    anilist_token: Option<String>,
+
+    /// Overrides to load at startup, as JSON in the format of an export. Useful on
+    /// platforms without persistent storage, where the overrides are lost on restart.
+    #[clap(long, env = "ANIFUNNEL_OVERRIDES_JSON")]
+    overrides_json: Option<String>,
 
     /// IP address to bind the server to.
     #[clap(long, default_value_t = Ipv4Addr::new(0, 0, 0, 0), env = "ANIFUNNEL_ADDRESS")]
@@ -31,291 +79,5780 @@ struct AnifunnelArgs {
     #[clap(long, default_value_t = 8000, env = "ANIFUNNEL_PORT")]
     port: u16,
 
+    /// Sub-path to serve anifunnel under behind a reverse proxy, e.g. /anifunnel.
+    #[clap(long, default_value = "/", env = "ANIFUNNEL_BASE_PATH", value_parser = parse_base_path)]
+    base_path: String,
+
+    /// Comma-separated addresses of reverse proxies whose X-Forwarded-For header is
+    /// used for the client address.
+    #[clap(long, env = "ANIFUNNEL_TRUSTED_PROXIES", value_delimiter = ',')]
+    trusted_proxies: Vec<IpAddr>,
+
+    /// Comma-separated origins that browsers may call the API from, or * for any origin.
+    /// No CORS headers are sent by default.
+    #[clap(long, env = "ANIFUNNEL_CORS_ORIGINS", value_delimiter = ',')]
+    cors_origins: Vec<String>,
+
+    /// Maximum number of API and webhook requests per minute from each client address.
+    /// Requests are not limited by default.
+    #[clap(long, env = "ANIFUNNEL_RATE_LIMIT", value_parser = clap::value_parser!(u32).range(1..))]
+    rate_limit: Option<u32>,
+
+    /// Seconds to wait on shutdown for webhooks being processed and queued Anilist
+    /// updates to finish.
+    #[clap(long, default_value_t = 10, env = "ANIFUNNEL_SHUTDOWN_TIMEOUT")]
+    shutdown_timeout: u32,
+
+    /// PEM certificate chain for serving over HTTPS. Requires --tls-key.
+    #[clap(long, env = "ANIFUNNEL_TLS_CERT")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key for the --tls-cert certificate.
+    #[clap(long, env = "ANIFUNNEL_TLS_KEY")]
+    tls_key: Option<PathBuf>,
+
+    /// Largest accepted form field, e.g. 24KiB.
+    #[clap(long, default_value = "24KiB", env = "ANIFUNNEL_STRING_LIMIT", value_parser = parse_limit)]
+    string_limit: ByteUnit,
+
+    /// Largest accepted URL-encoded form body.
+    #[clap(long, default_value = "32KiB", env = "ANIFUNNEL_FORM_LIMIT", value_parser = parse_limit)]
+    form_limit: ByteUnit,
+
+    /// Largest accepted multipart form body. Plex webhooks are multipart forms that can
+    /// include the thumbnail of the item.
+    #[clap(long, default_value = "10MiB", env = "ANIFUNNEL_DATA_FORM_LIMIT", value_parser = parse_limit)]
+    data_form_limit: ByteUnit,
+
+    /// Largest accepted JSON body, e.g. for imports and bulk edits.
+    #[clap(long, default_value = "1MiB", env = "ANIFUNNEL_JSON_LIMIT", value_parser = parse_limit)]
+    json_limit: ByteUnit,
+
     /// Match against all Plex library seasons. May not accurately find matches.
     #[arg(long, env = "ANIFUNNEL_MULTI_SEASON")]
     multi_season: bool,
 
+    /// Match movie scrobbles against movies in the Anilist watching list.
+    #[arg(long, env = "ANIFUNNEL_MOVIES")]
+    movies: bool,
+
+    /// Also match scrobbles against the planning and paused lists, and move matched
+    /// entries to the watching list.
+    #[arg(long, env = "ANIFUNNEL_INACTIVE_LISTS")]
+    inactive_lists: bool,
+
+    /// Fetch the Anilist lists, build their title indexes and load the AniDB ID
+    /// mapping on startup, and only report ready once done.
+    #[arg(long, env = "ANIFUNNEL_WARM_CACHE")]
+    warm_cache: bool,
+
+    /// Save ratings given in Plex as Anilist scores.
+    #[arg(long, env = "ANIFUNNEL_SYNC_RATINGS")]
+    sync_ratings: bool,
+
+    /// Keep the title override used for resolving unmatched scrobbles, so that later
+    /// scrobbles of the title match without resolving them again.
+    #[arg(long, env = "ANIFUNNEL_REMEMBER_RESOLVED")]
+    remember_resolved: bool,
+
+    /// Fuzzy match confidence (0-1) that a Plex title needs for matching a watching list
+    /// entry. Entries can have their own minimum in the management interface.
+    #[clap(long, default_value_t = anilist::DEFAULT_MINIMUM_CONFIDENCE, env = "ANIFUNNEL_MINIMUM_CONFIDENCE", value_parser = parse_confidence)]
+    minimum_confidence: f64,
+
+    /// Also match Plex titles written in kana by their romaji transliteration, e.g. for
+    /// libraries that use Japanese titles.
+    #[arg(long, env = "ANIFUNNEL_TRANSLITERATE_NATIVE")]
+    transliterate_native: bool,
+
+    /// Match shows by the AniDB IDs in HAMA GUIDs using a mapping file from this URL or
+    /// path, loaded again daily. Uses the Fribb/anime-lists mapping if no value is given.
+    #[clap(long, num_args = 0..=1, default_missing_value = anidb::DEFAULT_MAPPING_URL, env = "ANIFUNNEL_ANIDB_MAPPING")]
+    anidb_mapping: Option<String>,
+
+    /// Anilist GraphQL API URL, e.g. for testing with anifunnel-mock-anilist.
+    #[clap(long, default_value = anilist::DEFAULT_API_URL, env = "ANIFUNNEL_ANILIST_URL")]
+    anilist_url: String,
+
+    /// Client name that anifunnel identifies itself with in the Anilist User-Agent.
+    #[clap(long, default_value = anilist::DEFAULT_CLIENT_NAME, env = "ANIFUNNEL_ANILIST_CLIENT_NAME")]
+    anilist_client_name: String,
+
+    /// Contact details (e.g. email address) to include in the Anilist User-Agent.
+    #[clap(long, env = "ANIFUNNEL_ANILIST_CONTACT")]
+    anilist_contact: Option<String>,
+
+    /// Client ID of an Anilist API client for authorizing anifunnel at /auth/anilist.
+    #[clap(long, env = "ANIFUNNEL_ANILIST_CLIENT_ID")]
+    anilist_client_id: Option<String>,
+
+    /// Client secret of the Anilist API client.
+    #[clap(long, env = "ANIFUNNEL_ANILIST_CLIENT_SECRET")]
+    anilist_client_secret: Option<String>,
+
+    /// Redirect URL of the Anilist API client, ending in /auth/anilist/callback.
+    #[clap(long, env = "ANIFUNNEL_ANILIST_REDIRECT_URL")]
+    anilist_redirect_url: Option<String>,
+
     /// Only process updates from a specific Plex username.
     #[clap(long, env = "ANILIST_PLEX_USER")]
     plex_user: Option<String>,
-}
 
-#[get("/admin")]
-async fn management(state: &rocket::State<data::state::Global>) -> Template {
-    let title_overrides = state.title_overrides.read().await;
-    let episode_offsets = state.episode_offsets.read().await;
-    let watching_list = match anilist::get_watching_list(&state.token, &state.user).await {
-        Ok(media_list_group) => Anime::build(&media_list_group, &title_overrides, &episode_offsets),
-        Err(_) => vec![],
-    };
-    Template::render(
-        "management.html",
-        context! {
-            watching_list: watching_list,
-        },
-    )
+    /// Comma-separated Plex server names or UUIDs to only process updates from.
+    #[clap(
+        long = "plex-server",
+        env = "ANIFUNNEL_PLEX_SERVER",
+        value_delimiter = ','
+    )]
+    plex_servers: Vec<String>,
+
+    /// Comma-separated Plex library section titles or IDs to only process updates from.
+    #[clap(
+        long = "plex-library",
+        env = "ANIFUNNEL_PLEX_LIBRARY",
+        value_delimiter = ','
+    )]
+    plex_libraries: Vec<String>,
+
+    /// Only process updates from the Plex server owner or from shared users.
+    #[clap(long, value_enum, default_value_t, env = "ANIFUNNEL_ACCOUNT_FILTER")]
+    account_filter: plex::AccountFilter,
+
+    /// How to handle scrobbles for episodes that are already counted on Anilist.
+    #[clap(long, value_enum, default_value_t, env = "ANIFUNNEL_REWATCH")]
+    rewatch: data::state::RewatchPolicy,
+
+    /// How to handle scrobbles for episodes that skip ahead of the Anilist progress.
+    #[clap(long, value_enum, default_value_t, env = "ANIFUNNEL_PROGRESS_CONFLICT")]
+    progress_conflict: data::state::ConflictPolicy,
+
+    /// Only process the first of repeated scrobbles for the same episode and Plex user
+    /// within the given number of seconds.
+    #[clap(long, env = "ANIFUNNEL_SCROBBLE_DEBOUNCE", value_parser = clap::value_parser!(u64).range(1..))]
+    scrobble_debounce: Option<u64>,
+
+    /// Client ID of a Trakt API application for forwarding scrobbles to Trakt.
+    #[clap(long, env = "ANIFUNNEL_TRAKT_CLIENT_ID")]
+    trakt_client_id: Option<String>,
+
+    /// Trakt access token of the user that scrobbles are forwarded for.
+    #[clap(long, env = "ANIFUNNEL_TRAKT_TOKEN")]
+    trakt_token: Option<String>,
+
+    /// URL of the Plex Media Server whose watch history catch-up syncs replay, e.g.
+    /// http://127.0.0.1:32400.
+    #[clap(long, env = "ANIFUNNEL_PLEX_URL")]
+    plex_url: Option<String>,
+
+    /// Plex token for reading the watch history of the Plex Media Server.
+    #[clap(long, env = "ANIFUNNEL_PLEX_TOKEN")]
+    plex_token: Option<String>,
+
+    /// Discord webhook URL to post updates, unmatched titles and failures to.
+    #[clap(long, env = "ANIFUNNEL_DISCORD_WEBHOOK_URL")]
+    discord_webhook_url: Option<String>,
+
+    /// Comma-separated events to post to the Discord webhook.
+    #[clap(
+        long,
+        value_enum,
+        env = "ANIFUNNEL_DISCORD_EVENTS",
+        value_delimiter = ',',
+        default_value = "updated,unmatched,failed"
+    )]
+    discord_events: Vec<discord::DiscordEvent>,
+
+    /// URL of a primary anifunnel instance to replicate the overrides, Anilist tokens and
+    /// runtime settings from. Webhooks and changes are rejected until this standby
+    /// instance is promoted.
+    #[clap(long, env = "ANIFUNNEL_REPLICATE_FROM")]
+    replicate_from: Option<String>,
+
+    /// Admin API key of the primary instance.
+    #[clap(long, env = "ANIFUNNEL_REPLICATE_API_KEY")]
+    replicate_api_key: Option<String>,
+
+    /// Seconds between replications from the primary instance.
+    #[clap(long, default_value_t = replication::DEFAULT_INTERVAL, env = "ANIFUNNEL_REPLICATE_INTERVAL", value_parser = clap::value_parser!(u64).range(1..))]
+    replicate_interval: u64,
+
+    /// Respond with HTTP 503 to webhooks when this many are already being processed or
+    /// queued during maintenance.
+    #[clap(long, env = "ANIFUNNEL_MAX_PENDING_WEBHOOKS", value_parser = clap::value_parser!(u64).range(1..))]
+    max_pending_webhooks: Option<u64>,
+
+    /// Ignore scrobbles for watch sessions where less than the given percentage of
+    /// the episode was played.
+    #[clap(long, env = "ANIFUNNEL_MINIMUM_WATCH_TIME", value_parser = clap::value_parser!(u8).range(1..=100))]
+    minimum_watch_time: Option<u8>,
+
+    /// Require webhooks to supply this token in the "token" query parameter or the
+    /// X-Anifunnel-Token header.
+    #[clap(long, env = "ANIFUNNEL_WEBHOOK_TOKEN")]
+    webhook_token: Option<String>,
+
+    /// Password for the management interface and API. Access is unrestricted if unset.
+    #[clap(long, env = "ANIFUNNEL_ADMIN_PASSWORD")]
+    admin_password: Option<String>,
+
+    /// Comma-separated API keys with full access. Requires an admin password.
+    #[clap(long, env = "ANIFUNNEL_ADMIN_API_KEYS", value_delimiter = ',')]
+    admin_api_keys: Vec<String>,
+
+    /// Comma-separated API keys that can only read data. Requires an admin password.
+    #[clap(long, env = "ANIFUNNEL_READ_ONLY_API_KEYS", value_delimiter = ',')]
+    read_only_api_keys: Vec<String>,
 }
 
-#[post("/admin/edit/<id>", data = "<form>")]
-async fn management_edit(
-    id: i32,
-    form: Form<data::forms::AnimeOverride<'_>>,
-    state: &rocket::State<data::state::Global>,
-) -> Redirect {
-    let anifunnel_state: &data::state::Global = state.inner();
-    let mut title_overrides = anifunnel_state.title_overrides.write().await;
-    let mut episode_offsets = anifunnel_state.episode_offsets.write().await;
+#[get("/healthz")]
+async fn healthz() -> Json<data::api::Health> {
+    Json(data::api::Health::ok())
+}
 
-    if let Some(title) = form.get_title() {
-        debug!("Setting title override for ID {} to \"{}\"", id, title);
-        title_overrides.set(title.to_string(), id);
-    } else {
-        debug!("Removing possible title override for ID {}", id);
-        title_overrides.remove_value(&id);
+#[get("/readyz")]
+async fn readyz(
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> status::Custom<Json<data::api::Health>> {
+    if !state.cache_warmed.load(Ordering::SeqCst) {
+        return status::Custom(
+            Status::ServiceUnavailable,
+            Json(data::api::Health::error("warming up")),
+        );
     }
-
-    if let Some(episode_offset) = form.get_episode_offset() {
-        debug!("Setting episode offset for ID {} to {}", id, episode_offset);
-        episode_offsets.set(id, episode_offset);
-    } else {
-        debug!("Removing possible episode offset for ID {}", id);
-        episode_offsets.remove(&id);
+    let account = state.account().await;
+    if let Some(user) = state.readiness.read().await.get(&account.token) {
+        let mut health = data::api::Health::ok();
+        health.user = Some(user.to_string());
+        return status::Custom(Status::Ok, Json(health));
+    }
+    match anilist::get_user(&state.anilist, &account.token).await {
+        Ok(user) => {
+            state
+                .readiness
+                .write()
+                .await
+                .insert(&account.token, user.name.clone());
+            let mut health = data::api::Health::ok();
+            health.user = Some(user.name);
+            status::Custom(Status::Ok, Json(health))
+        }
+        Err(anilist::AnilistError::InvalidToken) => status::Custom(
+            Status::ServiceUnavailable,
+            Json(data::api::Health::error("invalid token")),
+        ),
+        Err(anilist::AnilistError::RateLimited) => status::Custom(
+            Status::ServiceUnavailable,
+            Json(data::api::Health::error("rate limited")),
+        ),
+        Err(_) => status::Custom(
+            Status::ServiceUnavailable,
+            Json(data::api::Health::error("anilist unavailable")),
+        ),
     }
-    Redirect::to(uri!(management))
 }
 
-#[get("/")]
-async fn management_redirect() -> Redirect {
-    Redirect::to(uri!(management))
+#[get("/api/user")]
+async fn user(
+    _authorized: data::guards::ApiReader,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<data::api::User> {
+    let account = state.account().await;
+    Json(data::api::User::build(&account.user))
 }
 
-#[post("/", data = "<form>")]
-async fn scrobble(
-    form: Form<data::forms::Scrobble<'_>>,
-    state: &rocket::State<data::state::Global>,
-) -> &'static str {
-    let webhook: plex::Webhook = match serde_json::from_str(form.payload) {
-        Ok(data) => data,
+#[get("/api/tokens")]
+async fn tokens(
+    _authorized: data::guards::ApiAdmin,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<Vec<data::api::Token>> {
+    let accounts = state.accounts.read().await;
+    let active = accounts.active().id;
+    Json(
+        accounts
+            .iter()
+            .map(|x| data::api::Token::build(x, x.id == active))
+            .collect(),
+    )
+}
+
+/// Store an Anilist token after checking it with Anilist. Storing a token does not
+/// activate it.
+#[post("/api/tokens", data = "<form>")]
+async fn token_add(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    form: Form<data::forms::TokenAdd<'_>>,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<Json<data::api::Token>, status::Custom<&'static str>> {
+    let user = match anilist::get_user(&state.anilist, &form.token.to_string()).await {
+        Ok(user) => user,
+        Err(anilist::AnilistError::InvalidToken) => {
+            return Err(status::Custom(Status::UnprocessableEntity, "Invalid token"));
+        }
         Err(error) => {
-            warn!("Unable to parse payload");
-            debug!("{}", error);
-            return "ERROR";
+            error!("Could not retrieve Anilist user: {:?}", error);
+            return Err(status::Custom(Status::BadGateway, "ERROR"));
         }
     };
+    let label = match form.label.is_empty() {
+        true => user.name.clone(),
+        false => form.label.to_string(),
+    };
+    let mut accounts = state.accounts.write().await;
+    let id = accounts.add(label, form.token.to_string(), user);
+    let active = accounts.active().id == id;
+    let account = accounts.iter().find(|x| x.id == id).unwrap();
+    info!("Stored Anilist token for {}", account.user.name);
+    Ok(Json(data::api::Token::build(account, active)))
+}
 
-    if !webhook.is_actionable(state.multi_season) {
-        info!("Webhook is not actionable");
-        return "NO OP";
-    }
-
-    // Check possible Plex username restriction.
-    if let Some(plex_user) = &state.plex_user {
-        if plex_user == &webhook.account.name {
-            debug!("Update matches Plex username restriction '{}'", plex_user);
-        } else {
-            info!("Ignoring update for Plex user '{}'", webhook.account.name);
-            return "NO OP";
-        }
+#[post("/api/tokens/<id>/activate")]
+async fn token_activate(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    id: u32,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Status {
+    let mut accounts = state.accounts.write().await;
+    if !activate_account(&mut accounts, id, state).await {
+        return Status::NotFound;
     }
+    return Status::NoContent;
+}
 
-    if let Ok(media_list_entries) = anilist::get_watching_list(&state.token, &state.user).await {
-        let title_overrides = state.title_overrides.read().await;
-        let matched_media_list = match title_overrides.get(&webhook.metadata.title) {
-            Some(id) => media_list_entries.find_id(&id),
-            None => media_list_entries.find_match(&webhook.metadata.title),
-        };
-        let matched_media_list = match matched_media_list {
-            Some(media_list) => media_list,
-            None => {
-                debug!("Could not find a match for '{}'", &webhook.metadata.title);
-                return "NO OP";
-            }
-        };
-        debug!("Processing {}", matched_media_list);
-        let episode_offsets = state.episode_offsets.read().await;
-        let episode_offset = episode_offsets.get(&matched_media_list.id).unwrap_or(0);
-        if webhook.metadata.episode_number + episode_offset == matched_media_list.progress + 1 {
-            match matched_media_list.update(&state.token).await {
-                Ok(true) => info!("Updated '{}' progress", matched_media_list.media.title),
-                Ok(false) => error!(
-                    "Failed to update progress for '{}'",
-                    matched_media_list.media.title
-                ),
-                Err(error) => error!("{:?}", error),
-            }
-        }
+/// Switch all Anilist requests to a stored account. The relations are cleared since
+/// they were looked up for the watching list of the previous account.
+async fn activate_account(
+    accounts: &mut data::state::Accounts,
+    id: u32,
+    state: &data::state::Global,
+) -> bool {
+    if !accounts.activate(id) {
+        return false;
     }
-    "OK"
+    *state.relations.write().await = anilist::Relations::new();
+    let account = accounts.active();
+    info!("Switched to Anilist user {}", account.user.name);
+    check_token_expiry(state, &account.token).await;
+    return true;
 }
 
-#[rocket::main]
-async fn main() {
-    let args = AnifunnelArgs::parse();
-
-    SimpleLogger::new()
-        .with_level(LevelFilter::Info)
-        .env()
-        .init()
-        .unwrap();
+/// Start authorizing anifunnel on Anilist with the authorization code grant.
+#[get("/auth/anilist")]
+async fn anilist_authorize(
+    _authorized: data::guards::AdminAuthorized,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<Redirect, Status> {
+    let oauth = match &state.oauth {
+        Some(oauth) => oauth,
+        None => return Err(Status::NotFound),
+    };
+    let authorization = state.authorizations.write().await.create();
+    Ok(Redirect::to(oauth.authorize_url(&authorization)))
+}
 
-    let user = match anilist::get_user(&args.anilist_token).await {
-        Ok(user) => user,
-        Err(anilist::AnilistError::InvalidToken) => {
+/// Store and activate the token of a finished Anilist authorization.
+#[get("/auth/anilist/callback?<callback..>")]
+async fn anilist_callback(
+    _authorized: data::guards::AdminAuthorized,
+    _writable: data::guards::Writable,
+    callback: data::forms::OAuthCallback<'_>,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<Redirect, status::Custom<&'static str>> {
+    let oauth = match &state.oauth {
+        Some(oauth) => oauth,
+        None => return Err(status::Custom(Status::NotFound, "ERROR")),
+    };
+    if !state.authorizations.write().await.take(callback.state) {
+        return Err(status::Custom(
+            Status::BadRequest,
+            "Authorization expired, try again",
+        ));
+    }
+    let token = match oauth.exchange_code(&state.anilist, callback.code).await {
+        Ok(token) => token,
+        Err(error) => {
             error!(
-                "Invalid token. Ensure that you have a valid token. \
-                Tokens are valid for up to one year from authorization."
+                "Could not exchange the Anilist authorization code: {:?}",
+                error
             );
-            return ();
+            return Err(status::Custom(Status::BadGateway, "ERROR"));
         }
-        Err(_) => {
-            error!("Could not retrieve Anilist user.");
-            return ();
+    };
+    let user = match anilist::get_user(&state.anilist, &token).await {
+        Ok(user) => user,
+        Err(error) => {
+            error!("Could not retrieve Anilist user: {:?}", error);
+            return Err(status::Custom(Status::BadGateway, "ERROR"));
         }
     };
+    info!("Authorized Anilist user {}", user.name);
+    let mut accounts = state.accounts.write().await;
+    let id = accounts.add(user.name.clone(), token, user);
+    activate_account(&mut accounts, id, state).await;
+    Ok(redirect(state, uri!(management)))
+}
 
-    let state = data::state::Global {
-        multi_season: args.multi_season,
-        plex_user: args.plex_user,
-        token: args.anilist_token,
-        user: user,
-        title_overrides: RwLock::new(data::state::TitleOverrides::new()),
-        episode_offsets: RwLock::new(data::state::EpisodeOverrides::new()),
-    };
+#[get("/api/status")]
+async fn system_status(
+    _authorized: data::guards::ApiReader,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<data::api::SystemStatus> {
+    let accounts = state.accounts.read().await;
+    let activity = state.activity.read().await;
+    let sessions = state.sessions.read().await;
+    let history = state.history.read().await;
+    let sync_pause = state.sync_pause.read().await;
+    Json(data::api::SystemStatus::build(
+        &accounts,
+        &activity,
+        &sessions,
+        &history,
+        &sync_pause,
+    ))
+}
 
-    // Because Rocket *requires* a template directory even though we are embedding our
-    // single template inside the binary, we need to make a dummy directory for anifunnel.
-    let dir = tempdir().unwrap();
+/// Stop sending scrobbles to Anilist and Trakt, e.g. while reorganizing the Plex
+/// library. Webhooks are still accepted, but they are dropped and not queued.
+#[post("/api/sync/pause")]
+async fn sync_pause(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<data::state::SyncPause> {
+    let mut sync_pause = state.sync_pause.write().await;
+    if sync_pause.pause() {
+        warn!("Syncing paused");
+    }
+    Json(sync_pause.clone())
+}
 
-    // Increase the string limit from default since Plex might send the thumbnail in some
-    // requests and we don't want those to cause unnecessary HTTP 413 Content Too Large
-    // errors (even though we don't use those requests).
-    let limits = Limits::default().limit("string", 24.kibibytes());
+#[post("/api/sync/resume")]
+async fn sync_resume(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<data::state::SyncPause> {
+    let mut sync_pause = state.sync_pause.write().await;
+    if sync_pause.resume() {
+        info!("Syncing resumed");
+    }
+    Json(sync_pause.clone())
+}
 
-    // Launch the web server.
-    let figment = rocket::Config::figment()
-        .merge(("limits", limits))
-        .merge(("port", args.port))
-        .merge(("address", args.bind_address))
-        .merge(("template_dir", dir.path()));
-    let rocket = rocket::custom(figment)
-        .manage(state)
-        .mount(
-            "/",
-            routes![scrobble, management, management_edit, management_redirect],
-        )
-        .attach(Template::custom(|engines| {
-            engines
-                .tera
-                .add_raw_template(
-                    "management.html",
-                    include_str!("../templates/management.html.tera"),
-                )
-                .expect("Could not load management template");
-        }));
-    let _ = rocket.launch().await;
+#[post("/api/system/maintenance", data = "<form>")]
+async fn maintenance(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    form: Form<data::forms::Maintenance>,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<data::api::Maintenance> {
+    let queued = {
+        let mut maintenance = state.maintenance.write().await;
+        maintenance.enabled = form.enabled;
+        if form.enabled {
+            info!("Maintenance mode enabled");
+            return Json(data::api::Maintenance::build(&maintenance));
+        }
+        maintenance.take_queued()
+    };
+    info!(
+        "Maintenance mode disabled, processing {} queued webhooks",
+        queued.len()
+    );
+    for payload in queued.iter() {
+        process_scrobble(payload, state, &mut report::Sink::live()).await;
+    }
+    let maintenance = state.maintenance.read().await;
+    Json(data::api::Maintenance::build(&maintenance))
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+#[get("/api/replication")]
+async fn replication_status(
+    _authorized: data::guards::ApiReader,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<data::state::Replication> {
+    Json(state.replication.read().await.clone())
+}
 
-    use rocket::http::{ContentType, Status};
-    use rocket::local::blocking::Client;
-    use test_case::test_case;
+/// Anilist tokens and runtime settings for standby instances. Since they include
+/// secrets, they are only shared when an admin password is set.
+#[get("/api/replication/snapshot")]
+async fn replication_snapshot(
+    _authorized: data::guards::ApiAdmin,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<Json<data::api::ReplicationSnapshot>, Status> {
+    if state.admin_password.is_none() {
+        return Err(Status::Forbidden);
+    }
+    let accounts = state.accounts.read().await;
+    let discord_events = state.discord_events.read().await;
+    let notifiers = state.notifiers.read().await;
+    let sync_pause = state.sync_pause.read().await;
+    Ok(Json(data::api::ReplicationSnapshot::build(
+        &accounts,
+        &discord_events,
+        &notifiers,
+        &sync_pause,
+    )))
+}
 
-    fn build_client() -> Client {
-        let state = data::state::Global {
-            multi_season: false,
-            plex_user: None,
-            token: String::from("A"),
-            user: anilist::User {
-                id: 1,
-                name: String::from("A"),
-            },
-            title_overrides: RwLock::new(data::state::TitleOverrides::new()),
-            episode_offsets: RwLock::new(data::state::EpisodeOverrides::new()),
-        };
-        let rocket = rocket::build()
-            .manage(state)
-            .mount("/", routes![scrobble, management_edit, management_redirect]);
-        return Client::tracked(rocket).expect("valid rocket instance");
+/// Promote a standby to accept webhooks and changes. Replication from the primary
+/// instance stops.
+#[post("/api/replication/promote")]
+async fn replication_promote(
+    _authorized: data::guards::ApiAdmin,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Status {
+    let mut replication = state.replication.write().await;
+    if replication.primary.is_none() {
+        return Status::Conflict;
     }
+    if !replication.promoted {
+        warn!("Standby promoted, accepting webhooks and changes");
+        replication.promoted = true;
+    }
+    return Status::NoContent;
+}
 
-    #[test_case("Mushoku Tensei S2", "1", Some(146065), Some(1) ; "title, episode offset")]
-    #[test_case("Mushoku Tensei S2", "", Some(146065), None ; "title, no episode offset")]
-    #[test_case("", "1", None, Some(1) ; "no title, episode_offset")]
-    #[test_case("", "", None, None ; "no title, no episode offset")]
-    fn management_edit_add(
-        title: &str,
-        episode_offset: &str,
-        expected_title_override: Option<i32>,
-        expected_episode_offset: Option<i32>,
-    ) {
-        let client = build_client();
-        let request = client
-            .post(uri!(management_edit(146065)))
-            .header(ContentType::Form)
-            .body(format!("title={}&episode_offset={}", title, episode_offset));
-        let state = request.rocket().state::<data::state::Global>().unwrap();
-        let response = request.dispatch();
-        assert_eq!(response.status(), Status::SeeOther);
-        assert_eq!(
-            state
-                .title_overrides
-                .blocking_read()
-                .get(&String::from("Mushoku Tensei S2")),
-            expected_title_override
-        );
-        assert_eq!(
-            state.episode_offsets.blocking_read().get(&146065),
-            expected_episode_offset
-        );
+#[get("/api/discord/events")]
+async fn discord_events(
+    _authorized: data::guards::ApiAdmin,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<Json<data::state::DiscordEvents>, Status> {
+    if state.discord.is_none() {
+        return Err(Status::NotFound);
     }
+    Ok(Json(state.discord_events.read().await.clone()))
+}
 
-    #[test_case("Mushoku Tensei S2", "", Some(146065), None ; "title, no episode offset")]
-    #[test_case("", "1", None, Some(1) ; "no title, episode_offset")]
-    #[test_case("", "", None, None ; "no title, no episode offset")]
-    fn management_edit_remove(
-        title: &str,
-        episode_offset: &str,
-        expected_title_override: Option<i32>,
-        expected_episode_offset: Option<i32>,
-    ) {
-        let client = build_client();
-        let request = client
-            .post(uri!(management_edit(146065)))
-            .header(ContentType::Form)
-            .body(format!("title={}&episode_offset={}", title, episode_offset));
-        let state = request.rocket().state::<data::state::Global>().unwrap();
-        state
-            .title_overrides
-            .blocking_write()
-            .set(String::from("Mushoku Tensei S2"), 146065);
-        state.episode_offsets.blocking_write().set(146065, 1);
-        let response = request.dispatch();
-        assert_eq!(response.status(), Status::SeeOther);
-        assert_eq!(
-            state
-                .title_overrides
-                .blocking_read()
-                .get(&String::from("Mushoku Tensei S2")),
-            expected_title_override
-        );
-        assert_eq!(
-            state.episode_offsets.blocking_read().get(&146065),
-            expected_episode_offset
-        );
+#[post("/api/discord/events", data = "<form>")]
+async fn discord_events_edit(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    form: Form<data::forms::DiscordEvents>,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<Json<data::state::DiscordEvents>, Status> {
+    if state.discord.is_none() {
+        return Err(Status::NotFound);
     }
+    let mut events = state.discord_events.write().await;
+    events.updated = form.updated.unwrap_or(events.updated);
+    events.unmatched = form.unmatched.unwrap_or(events.unmatched);
+    events.failed = form.failed.unwrap_or(events.failed);
+    info!("Discord events set to {:?}", *events);
+    Ok(Json(events.clone()))
+}
 
-    #[test]
-    fn management_redirect() {
-        let client = build_client();
-        let response = client.get(uri!(management_redirect)).dispatch();
-        assert_eq!(response.status(), Status::SeeOther);
-        assert_eq!(response.headers().get_one("Location"), Some("/admin"));
+#[post("/api/relations/refresh")]
+async fn relations_refresh(
+    _authorized: data::guards::ApiAdmin,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<Json<data::api::RelationsRefresh>, status::Custom<&'static str>> {
+    let account = state.account().await;
+    let media_list_group =
+        match anilist::get_watching_list(&state.anilist, &account.token, &account.user).await {
+            Ok(media_list_group) => media_list_group,
+            Err(error) => {
+                error!("Could not retrieve the watching list: {:?}", error);
+                return Err(status::Custom(Status::BadGateway, "ERROR"));
+            }
+        };
+    let mut relations = anilist::Relations::new();
+    for media_id in media_list_group.media_ids() {
+        match anilist::get_franchise_position(&state.anilist, &account.token, media_id).await {
+            Ok(Some(position)) => relations.set(media_id, position),
+            Ok(None) => debug!("Could not find the franchise position of {}", media_id),
+            Err(error) => {
+                error!("Could not retrieve relations for {}: {:?}", media_id, error);
+                return Err(status::Custom(Status::BadGateway, "ERROR"));
+            }
+        }
     }
+    info!("Refreshed relations for {} entries", relations.len());
+    let entries = relations.len();
+    *state.relations.write().await = relations;
+    Ok(Json(data::api::RelationsRefresh { entries }))
+}
 
-    #[test]
+#[get("/api/notifications")]
+async fn notifications(
+    _authorized: data::guards::ApiReader,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<Vec<data::state::Notification>> {
+    let account = state.account().await;
+    check_token_expiry(state, &account.token).await;
+    let notifications = state.notifications.read().await;
+    Json(notifications.iter().rev().cloned().collect())
+}
+
+#[post("/api/notifications/<id>/read")]
+async fn notification_read(
+    _authorized: data::guards::ApiAdmin,
+    id: u64,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Status {
+    if state.notifications.write().await.mark_read(id) {
+        return Status::NoContent;
+    }
+    return Status::NotFound;
+}
+
+#[post("/api/notifications/read")]
+async fn notifications_read(
+    _authorized: data::guards::ApiAdmin,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Status {
+    state.notifications.write().await.mark_all_read();
+    Status::NoContent
+}
+
+#[get("/api/notifications/notifiers")]
+async fn notifiers_list(
+    _authorized: data::guards::ApiAdmin,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<Vec<data::api::Notifier>> {
+    let notifiers = state.notifiers.read().await;
+    Json(
+        notifiers
+            .iter()
+            .map(|(id, notifier)| data::api::Notifier::build(*id, notifier))
+            .collect(),
+    )
+}
+
+#[post("/api/notifications/notifiers", data = "<form>")]
+async fn notifier_add(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    form: Form<data::forms::Notifier<'_>>,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<data::api::Notifier> {
+    let notifier = form.build();
+    let id = state.notifiers.write().await.add(notifier.clone());
+    info!("Added {:?} notifier {}", notifier.kind, id);
+    Json(data::api::Notifier::build(id, &notifier))
+}
+
+/// Replace a notifier. The token is kept if none is given.
+#[post("/api/notifications/notifiers/<id>/edit", data = "<form>")]
+async fn notifier_edit(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    id: u32,
+    form: Form<data::forms::Notifier<'_>>,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<Json<data::api::Notifier>, Status> {
+    let mut notifiers = state.notifiers.write().await;
+    let mut notifier = form.build();
+    if notifier.token.is_none() {
+        notifier.token = notifiers.get(id).and_then(|x| x.token.clone());
+    }
+    if !notifiers.replace(id, notifier.clone()) {
+        return Err(Status::NotFound);
+    }
+    info!("Changed notifier {}", id);
+    Ok(Json(data::api::Notifier::build(id, &notifier)))
+}
+
+#[post("/api/notifications/notifiers/<id>/delete")]
+async fn notifier_delete(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    id: u32,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Status {
+    if !state.notifiers.write().await.remove(id) {
+        return Status::NotFound;
+    }
+    info!("Removed notifier {}", id);
+    Status::NoContent
+}
+
+#[get("/api/history")]
+async fn history(
+    _authorized: data::guards::ApiReader,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<Vec<data::state::HistoryEntry>> {
+    let history = state.history.read().await;
+    Json(history.iter().rev().cloned().collect())
+}
+
+/// Stream processed scrobbles as Server-Sent Events while they happen. Each history
+/// entry is sent as a `scrobble` event.
+#[get("/api/events")]
+async fn live_events(
+    _authorized: data::guards::ApiReader,
+    state: &rocket::State<Arc<data::state::Global>>,
+    mut shutdown: rocket::Shutdown,
+) -> EventStream![] {
+    let mut receiver = state.history.read().await.subscribe();
+    EventStream! {
+        loop {
+            let entry = rocket::tokio::select! {
+                entry = receiver.recv() => entry,
+                _ = &mut shutdown => break,
+            };
+            match entry {
+                Ok(entry) => yield Event::json(&entry).event("scrobble"),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!("Live event stream skipped {} entries", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+}
+
+#[get("/api/progress")]
+async fn progress_overview(
+    _authorized: data::guards::ApiReader,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<Json<Vec<data::api::ProgressOverview>>, status::Custom<&'static str>> {
+    let account = state.account().await;
+    let media_list_group =
+        match anilist::get_watching_list(&state.anilist, &account.token, &account.user).await {
+            Ok(media_list_group) => media_list_group,
+            Err(error) => {
+                error!("Could not retrieve the watching list: {:?}", error);
+                return Err(status::Custom(
+                    Status::BadGateway,
+                    "Could not retrieve the watching list",
+                ));
+            }
+        };
+    let history = state.history.read().await;
+    let episode_offsets = state.episode_offsets.read().await;
+    Ok(Json(data::api::ProgressOverview::build(
+        &media_list_group,
+        &history,
+        &episode_offsets,
+    )))
+}
+
+#[get("/api/sync-status")]
+async fn sync_status(
+    _authorized: data::guards::ApiReader,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<Vec<data::api::SyncStatus>> {
+    let account = state.account().await;
+    let media_list_group =
+        match anilist::get_watching_list(&state.anilist, &account.token, &account.user).await {
+            Ok(media_list_group) => Some(media_list_group),
+            Err(error) => {
+                warn!("Building sync status without Anilist progress: {:?}", error);
+                None
+            }
+        };
+    let history = state.history.read().await;
+    let episode_offsets = state.episode_offsets.read().await;
+    let maintenance = state.maintenance.read().await;
+    Json(data::api::SyncStatus::build(
+        &history,
+        media_list_group.as_ref(),
+        &episode_offsets,
+        &maintenance,
+    ))
+}
+
+#[get("/api/debug/bundle")]
+async fn debug_bundle(
+    _authorized: data::guards::ApiAdmin,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<data::api::DebugBundle> {
+    let history = state.history.read().await;
+    let failed_payloads = state.failed_payloads.read().await;
+    Json(data::api::DebugBundle {
+        version: env!("CARGO_PKG_VERSION"),
+        settings: data::api::Settings::build(state),
+        logs: logging::recent(),
+        history: history.iter().cloned().collect(),
+        failed_payloads: failed_payloads.iter().cloned().collect(),
+    })
+}
+
+#[get("/api/system/config-summary")]
+async fn config_summary(
+    _authorized: data::guards::ApiReader,
+    config: &rocket::Config,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<data::api::ConfigSummary> {
+    let accounts = state.accounts.read().await;
+    Json(data::api::ConfigSummary::build(config, state, &accounts))
+}
+
+#[get("/api/rewatches")]
+async fn rewatches(
+    _authorized: data::guards::ApiReader,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<Vec<data::api::Rewatch>> {
+    let rewatches = state.rewatches.read().await;
+    Json(data::api::Rewatch::build(&rewatches))
+}
+
+#[get("/api/export")]
+async fn export(
+    _authorized: data::guards::ApiAdmin,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<data::api::Export> {
+    let title_overrides = state.title_overrides.read().await;
+    let account_title_overrides = state.account_title_overrides.read().await;
+    let guid_overrides = state.guid_overrides.read().await;
+    let title_patterns = state.title_patterns.read().await;
+    let episode_offsets = state.episode_offsets.read().await;
+    let mutes = state.mutes.read().await;
+    let minimum_confidences = state.minimum_confidences.read().await;
+    let log_only = state.log_only.read().await;
+    let ignored_ratings = state.ignored_ratings.read().await;
+    let ignored = state.ignored.read().await;
+    let special_overrides = state.special_overrides.read().await;
+    let season_mappings = state.season_mappings.read().await;
+    Json(data::api::Export::build(
+        state,
+        &title_overrides,
+        &account_title_overrides,
+        &guid_overrides,
+        &title_patterns,
+        &episode_offsets,
+        &mutes,
+        &minimum_confidences,
+        &log_only,
+        &ignored_ratings,
+        &ignored,
+        &special_overrides,
+        &season_mappings,
+    ))
+}
+
+#[post("/api/import?<conflict>", data = "<import>")]
+async fn import(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    conflict: Option<data::forms::ImportConflict>,
+    import: Json<data::api::Import>,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<data::api::ImportSummary> {
+    let mut overrides = state.overrides_mut().await;
+    let summary = import
+        .into_inner()
+        .apply(conflict.unwrap_or_default(), &mut overrides);
+    info!(
+        "Imported {} overrides ({} skipped, {} invalid)",
+        summary.imported, summary.skipped, summary.invalid
+    );
+    Json(summary)
+}
+
+/// Load the overrides given at startup, replacing any existing ones.
+async fn seed_overrides(
+    state: &data::state::Global,
+    overrides: data::api::Import,
+) -> data::api::ImportSummary {
+    let mut overrides_mut = state.overrides_mut().await;
+    return overrides.apply(data::forms::ImportConflict::Replace, &mut overrides_mut);
+}
+
+#[get("/api/overrides/search?<q>")]
+async fn overrides_search(
+    _authorized: data::guards::ApiReader,
+    q: &str,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<Vec<data::api::OverrideSearchResult>> {
+    let account = state.account().await;
+    let media_list_group =
+        match anilist::get_watching_list(&state.anilist, &account.token, &account.user).await {
+            Ok(media_list_group) => Some(media_list_group),
+            Err(error) => {
+                warn!("Searching overrides without Anilist titles: {:?}", error);
+                None
+            }
+        };
+    let title_overrides = state.title_overrides.read().await;
+    Json(data::api::OverrideSearchResult::search(
+        &title_overrides,
+        media_list_group.as_ref(),
+        q,
+    ))
+}
+
+/// Search Anilist for the anime to point an override at.
+#[get("/api/search?<q>")]
+async fn anilist_search(
+    _authorized: data::guards::ApiReader,
+    q: &str,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<Json<Vec<data::api::AnilistSearchResult>>, Status> {
+    let q = q.trim();
+    if q.is_empty() {
+        return Err(Status::BadRequest);
+    }
+    let account = state.account().await;
+    match anilist::search_media(&state.anilist, &account.token, q).await {
+        Ok(media) => Ok(Json(data::api::AnilistSearchResult::build(&media))),
+        Err(error) => {
+            error!("Could not search Anilist for '{}': {:?}", q, error);
+            Err(Status::BadGateway)
+        }
+    }
+}
+
+#[get("/api/sessions")]
+async fn sessions(
+    _authorized: data::guards::ApiReader,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<Vec<data::api::Session>> {
+    let sessions = state.sessions.read().await;
+    Json(data::api::Session::build(&sessions))
+}
+
+#[get("/api/now-watching")]
+async fn now_watching(
+    _authorized: data::guards::ApiReader,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<Vec<data::api::NowWatching>> {
+    let sessions = state.sessions.read().await;
+    Json(data::api::NowWatching::build(&sessions))
+}
+
+#[get("/metrics")]
+async fn prometheus_metrics(
+    _authorized: data::guards::ApiReader,
+    metrics: &rocket::State<metrics::Metrics>,
+) -> String {
+    metrics.render()
+}
+
+#[get("/api/metrics.json")]
+async fn json_metrics(
+    _authorized: data::guards::ApiReader,
+    metrics: &rocket::State<metrics::Metrics>,
+) -> Json<metrics::Snapshot> {
+    Json(metrics.snapshot())
+}
+
+#[get("/login")]
+async fn login_page(state: &rocket::State<Arc<data::state::Global>>) -> Result<Template, Redirect> {
+    if state.admin_password.is_none() {
+        return Err(redirect(state, uri!(management)));
+    }
+    Ok(Template::render(
+        "login.html",
+        context! { base_path: &state.base_path },
+    ))
+}
+
+#[post("/login", data = "<form>")]
+async fn login(
+    form: Form<data::forms::Login<'_>>,
+    client: data::guards::ClientIp,
+    cookies: &CookieJar<'_>,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<Redirect, status::Custom<Template>> {
+    let admin_password = match &state.admin_password {
+        Some(admin_password) => admin_password,
+        None => return Ok(redirect(state, uri!(management))),
+    };
+    if !data::guards::constant_time_eq(form.password, admin_password) {
+        warn!("Failed management interface login attempt from {}", client);
+        return Err(status::Custom(
+            Status::Unauthorized,
+            Template::render(
+                "login.html",
+                context! { base_path: &state.base_path, error: "Incorrect password." },
+            ),
+        ));
+    }
+    let session_id = state.admin_sessions.write().await.create();
+    cookies.add(
+        Cookie::build((data::guards::ADMIN_SESSION_COOKIE, session_id))
+            .http_only(true)
+            .same_site(SameSite::Lax),
+    );
+    Ok(redirect(state, uri!(management)))
+}
+
+#[post("/logout")]
+async fn logout(
+    cookies: &CookieJar<'_>,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Redirect {
+    if let Some(cookie) = cookies.get(data::guards::ADMIN_SESSION_COOKIE) {
+        state.admin_sessions.write().await.remove(cookie.value());
+    }
+    cookies.remove(data::guards::ADMIN_SESSION_COOKIE);
+    redirect(state, uri!(login_page))
+}
+
+#[get("/admin")]
+async fn management(
+    _authorized: data::guards::AdminAuthorized,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Template {
+    let account = state.account().await;
+    // Versions are locked first like in edits, which lock them for writing.
+    let override_versions = state.override_versions.read().await;
+    let title_overrides = state.title_overrides.read().await;
+    let guid_overrides = state.guid_overrides.read().await;
+    let title_patterns = state.title_patterns.read().await;
+    let episode_offsets = state.episode_offsets.read().await;
+    let mutes = state.mutes.read().await;
+    let minimum_confidences = state.minimum_confidences.read().await;
+    let log_only = state.log_only.read().await;
+    let ignored_ratings = state.ignored_ratings.read().await;
+    let ignored = state.ignored.read().await;
+    let watching_list =
+        match anilist::get_watching_list(&state.anilist, &account.token, &account.user).await {
+            Ok(media_list_group) => Anime::build(
+                &media_list_group,
+                &title_overrides,
+                &guid_overrides,
+                &title_patterns,
+                &episode_offsets,
+                &mutes,
+                &minimum_confidences,
+                &log_only,
+                &ignored_ratings,
+                &ignored,
+                &override_versions,
+            ),
+            Err(_) => vec![],
+        };
+    Template::render(
+        "management.html",
+        context! {
+            ambiguous_titles: state.history.read().await.ambiguous_titles(),
+            base_path: &state.base_path,
+            conflicts: state.conflicts.read().await.iter().cloned().collect::<Vec<_>>(),
+            logout: state.admin_password.is_some(),
+            maintenance: state.maintenance.read().await.enabled,
+            unread_notifications: state.notifications.read().await.unread(),
+            user: data::api::User::build(&account.user),
+            watching_list: watching_list,
+        },
+    )
+}
+
+#[get("/admin", rank = 2)]
+async fn management_login(state: &rocket::State<Arc<data::state::Global>>) -> Redirect {
+    redirect(state, uri!(login_page))
+}
+
+/// Anilist metadata of an anime, from the cache if it has been fetched recently.
+async fn media_details(
+    state: &data::state::Global,
+    id: i32,
+) -> Result<data::api::AnimeDetails, Status> {
+    if let Some(details) = state.media_details.read().await.get(id) {
+        return Ok(data::api::AnimeDetails::build(details));
+    }
+    let account = state.account().await;
+    let details = match anilist::get_media_details(&state.anilist, &account.token, id).await {
+        Ok(Some(details)) => details,
+        Ok(None) => return Err(Status::NotFound),
+        Err(error) => {
+            error!("Could not fetch details for ID {}: {:?}", id, error);
+            return Err(Status::BadGateway);
+        }
+    };
+    let response = data::api::AnimeDetails::build(&details);
+    state.media_details.write().await.insert(details);
+    return Ok(response);
+}
+
+/// Remove the title override of a Plex title, either the shared one or the one of the
+/// active account.
+#[post("/api/overrides/delete", data = "<form>")]
+async fn title_override_remove(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    form: Form<data::forms::TitleOverrideRemove<'_>>,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Status {
+    let title = form.title.to_string();
+    let user_id = state.account().await.user.id;
+    let mut overrides = state.overrides_mut().await;
+    let removed = if form.account {
+        overrides
+            .account_title_overrides
+            .remove_key(user_id, &title)
+    } else {
+        let id = overrides.title_overrides.get(&title);
+        overrides.title_overrides.remove_key(&title);
+        id
+    };
+    let id = match removed {
+        Some(id) => id,
+        None => return Status::NotFound,
+    };
+    info!("Removing title override \"{}\" for ID {}", title, id);
+    overrides.override_versions.bump(id);
+    return Status::NoContent;
+}
+
+/// Anilist metadata of an anime, such as the cover image and airing status.
+#[get("/api/anime/<id>")]
+async fn anime_details(
+    _authorized: data::guards::ApiReader,
+    id: i32,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<Json<data::api::AnimeDetails>, Status> {
+    Ok(Json(media_details(state, id).await?))
+}
+
+/// Point a Plex title at an Anilist entry given by its ID or URL. The entry is looked
+/// up on Anilist first so that typos in the target are not stored.
+#[post("/api/overrides", data = "<form>")]
+async fn title_override_add(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    form: Form<data::forms::TitleOverride<'_>>,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<Json<data::api::TitleOverride>, Status> {
+    let details = media_details(state, form.get_anilist_id()).await?;
+    let anilist_user_id = match form.account {
+        true => Some(state.account().await.user.id),
+        false => None,
+    };
+    info!(
+        "Setting title override for ID {} to \"{}\"",
+        details.anilist_id, form.title
+    );
+    let mut overrides = state.overrides_mut().await;
+    match anilist_user_id {
+        Some(user_id) => overrides
+            .account_title_overrides
+            .account_mut(user_id)
+            .set(form.title.to_string(), details.anilist_id),
+        None => overrides
+            .title_overrides
+            .set(form.title.to_string(), details.anilist_id),
+    }
+    overrides.override_versions.bump(details.anilist_id);
+    Ok(Json(data::api::TitleOverride {
+        title: form.title.to_string(),
+        anilist_id: details.anilist_id,
+        anilist_title: details.title,
+        anilist_user_id,
+    }))
+}
+
+#[get("/api/anime/<id>/overrides")]
+async fn anime_overrides(
+    _authorized: data::guards::ApiReader,
+    id: i32,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> data::api::Versioned<Json<data::api::Overrides>> {
+    let version = state.override_versions.read().await.get(&id);
+    let overrides = data::api::Overrides {
+        anilist_id: id,
+        title: state.title_overrides.read().await.get_key(&id),
+        guid: state.guid_overrides.read().await.get_key(&id),
+        pattern: state.title_patterns.read().await.get_key(&id),
+        episode_offset: state.episode_offsets.read().await.get(&id),
+        muted_until: state.mutes.read().await.get(&id).map(|x| x.to_string()),
+        minimum_confidence: state.minimum_confidences.read().await.get(&id),
+        log_only: state.log_only.read().await.contains(&id),
+        ignore_ratings: state.ignored_ratings.read().await.contains(&id),
+        ignored: state.ignored.read().await.contains(&id),
+        version,
+    };
+    data::api::Versioned::new(Json(overrides), &version)
+}
+
+#[post("/admin/edit/<id>", data = "<form>")]
+async fn management_edit(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    id: i32,
+    if_match: data::guards::IfMatch,
+    form: Form<data::forms::AnimeOverride<'_>>,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<data::api::Versioned<Redirect>, Status> {
+    let mut overrides = state.overrides_mut().await;
+    if check_version(id, if_match.0.or(form.version), &overrides).is_err() {
+        return Err(Status::PreconditionFailed);
+    }
+    let version = edit_overrides(id, &form, &mut overrides);
+    Ok(data::api::Versioned::new(
+        redirect(state, uri!(management)),
+        &version,
+    ))
+}
+
+#[post("/api/anime/bulk-edit", data = "<edits>")]
+async fn anime_bulk_edit(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    edits: Json<Vec<data::api::OverrideEdit>>,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> data::api::BulkEditResponse {
+    let mut overrides = state.overrides_mut().await;
+    // Validate every edit before applying any of them, so that either all of them
+    // are applied or none are.
+    let mut forms = Vec::new();
+    let mut errors = Vec::new();
+    for (index, edit) in edits.iter().enumerate() {
+        let error = if edits[..index]
+            .iter()
+            .any(|x| x.anilist_id == edit.anilist_id)
+        {
+            Some(String::from("duplicate edit for the same ID"))
+        } else {
+            match edit.to_form() {
+                Ok(form) => match check_version(edit.anilist_id, form.version, &overrides) {
+                    Ok(()) => {
+                        forms.push((edit.anilist_id, form));
+                        None
+                    }
+                    Err(current) => {
+                        Some(format!("outdated version, current version is {}", current))
+                    }
+                },
+                Err(error) => Some(error.to_string()),
+            }
+        };
+        if let Some(error) = error {
+            errors.push(data::api::BulkEditError {
+                index,
+                anilist_id: edit.anilist_id,
+                error,
+            });
+        }
+    }
+    if !errors.is_empty() {
+        warn!("Rejecting bulk edit with {} invalid edits", errors.len());
+        return data::api::BulkEditResponse::Invalid(Json(errors));
+    }
+    let results = forms
+        .iter()
+        .map(|(id, form)| data::api::BulkEditResult {
+            anilist_id: *id,
+            version: edit_overrides(*id, form, &mut overrides),
+        })
+        .collect();
+    info!("Applied bulk edit for {} entries", forms.len());
+    data::api::BulkEditResponse::Applied(Json(results))
+}
+
+/// Check the override version that an edit is based on, if it gives one. Returns the
+/// current version if it does not match.
+fn check_version(
+    id: i32,
+    expected: Option<u64>,
+    overrides: &data::state::OverridesMut<'_>,
+) -> Result<(), u64> {
+    let current = overrides.override_versions.get(&id).version;
+    match expected {
+        Some(expected) if expected != current => {
+            warn!(
+                "Rejecting edit for ID {} based on version {}, current version is {}",
+                id, expected, current
+            );
+            Err(current)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Replace the overrides of an entry and return their new version.
+fn edit_overrides(
+    id: i32,
+    form: &data::forms::AnimeOverride<'_>,
+    overrides: &mut data::state::OverridesMut<'_>,
+) -> data::state::OverrideVersion {
+    if let Some(title) = form.get_title() {
+        debug!("Setting title override for ID {} to \"{}\"", id, title);
+        overrides.title_overrides.set(title.to_string(), id);
+    } else {
+        debug!("Removing possible title override for ID {}", id);
+        overrides.title_overrides.remove_value(&id);
+    }
+
+    if let Some(guid) = form.get_guid() {
+        debug!("Setting GUID override for ID {} to \"{}\"", id, guid);
+        overrides.guid_overrides.set(guid.to_string(), id);
+    } else {
+        debug!("Removing possible GUID override for ID {}", id);
+        overrides.guid_overrides.remove_value(&id);
+    }
+
+    if let Some(pattern) = form.get_pattern() {
+        debug!("Setting title pattern for ID {} to \"{}\"", id, pattern);
+        overrides.title_patterns.set(pattern, id);
+    } else {
+        debug!("Removing possible title pattern for ID {}", id);
+        overrides.title_patterns.remove_value(&id);
+    }
+
+    if let Some(episode_offset) = form.get_episode_offset() {
+        debug!("Setting episode offset for ID {} to {}", id, episode_offset);
+        overrides.episode_offsets.set(id, episode_offset);
+    } else {
+        debug!("Removing possible episode offset for ID {}", id);
+        overrides.episode_offsets.remove(&id);
+    }
+
+    if let Some(muted_until) = form.muted_until {
+        debug!("Muting scrobbles for ID {} until {}", id, muted_until);
+        overrides.mutes.set(id, muted_until);
+    } else {
+        debug!("Removing possible mute for ID {}", id);
+        overrides.mutes.remove(&id);
+    }
+
+    if let Some(minimum_confidence) = form.minimum_confidence {
+        debug!(
+            "Setting minimum confidence for ID {} to {}",
+            id, minimum_confidence
+        );
+        overrides.minimum_confidences.set(id, minimum_confidence);
+    } else {
+        debug!("Removing possible minimum confidence for ID {}", id);
+        overrides.minimum_confidences.remove(&id);
+    }
+
+    debug!("Setting log-only for ID {} to {}", id, form.log_only);
+    overrides.log_only.set(id, form.log_only);
+    debug!(
+        "Setting ignore ratings for ID {} to {}",
+        id, form.ignore_ratings
+    );
+    overrides.ignored_ratings.set(id, form.ignore_ratings);
+    debug!("Setting ignored for ID {} to {}", id, form.ignored);
+    overrides.ignored.set(id, form.ignored);
+    return overrides.override_versions.bump(id);
+}
+
+#[post("/api/anime/<id>/progress", data = "<form>")]
+async fn anime_progress(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    id: i32,
+    form: Form<data::forms::Progress>,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<Redirect, status::Custom<&'static str>> {
+    let account = state.account().await;
+    debug!("Setting progress for ID {} to {}", id, form.progress);
+    return match anilist::set_progress(&state.mutations, &account.token, id, form.progress, true)
+        .await
+    {
+        Ok(anilist::Saved::Updated) => {
+            info!("Set progress for ID {} to {}", id, form.progress);
+            state.conflicts.write().await.remove(id);
+            let title =
+                match anilist::get_watching_list(&state.anilist, &account.token, &account.user)
+                    .await
+                {
+                    Ok(media_list_group) => media_list_group
+                        .find_id(&id)
+                        .map(|x| x.media.title.to_string()),
+                    Err(_) => None,
+                };
+            state.history.write().await.push(data::state::HistoryEntry {
+                timestamp: data::state::unix_timestamp(),
+                source: data::state::ScrobbleSource::ManualApi,
+                title: title.unwrap_or_default(),
+                guid: None,
+                season_number: 0,
+                episode_number: form.progress,
+                anilist_id: Some(id),
+                outcome: data::state::HistoryOutcome::Updated,
+            });
+            Ok(redirect(state, uri!(management)))
+        }
+        Ok(_) => {
+            error!("Failed to set progress for ID {}", id);
+            Err(status::Custom(Status::BadGateway, "ERROR"))
+        }
+        Err(error) => {
+            error!("{:?}", error);
+            Err(status::Custom(Status::BadGateway, "ERROR"))
+        }
+    };
+}
+
+#[get("/api/specials")]
+async fn specials(
+    _authorized: data::guards::ApiReader,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<BTreeMap<i32, data::state::SpecialOverride>> {
+    let special_overrides = state.special_overrides.read().await;
+    Json(
+        special_overrides
+            .iter()
+            .map(|(k, v)| (*k, v.clone()))
+            .collect(),
+    )
+}
+
+#[get("/api/season-mappings")]
+async fn season_mappings(
+    _authorized: data::guards::ApiReader,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<Vec<data::state::SeasonMapping>> {
+    Json(state.season_mappings.read().await.iter().collect())
+}
+
+/// Track a Plex season of a show as an Anilist entry instead of matching the title,
+/// e.g. the second season as the sequel entry.
+#[post("/api/season-mappings", data = "<form>")]
+async fn season_mapping_add(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    form: Form<data::forms::SeasonMapping<'_>>,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<Json<data::state::SeasonMapping>, Status> {
+    let details = media_details(state, form.get_anilist_id()).await?;
+    let season_mapping = data::state::SeasonMapping {
+        title: form.title.to_string(),
+        season: form.season,
+        anilist_id: details.anilist_id,
+    };
+    info!(
+        "Mapping season {} of \"{}\" to ID {}",
+        season_mapping.season, season_mapping.title, season_mapping.anilist_id
+    );
+    let mut overrides = state.overrides_mut().await;
+    if let Some(previous_id) = overrides.season_mappings.set(season_mapping.clone()) {
+        overrides.override_versions.bump(previous_id);
+    }
+    overrides.override_versions.bump(season_mapping.anilist_id);
+    Ok(Json(season_mapping))
+}
+
+#[post("/api/season-mappings/delete", data = "<form>")]
+async fn season_mapping_remove(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    form: Form<data::forms::SeasonMappingRemove<'_>>,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Status {
+    let mut overrides = state.overrides_mut().await;
+    let id = match overrides.season_mappings.remove(form.title, form.season) {
+        Some(id) => id,
+        None => return Status::NotFound,
+    };
+    info!(
+        "Removing season mapping of season {} of \"{}\" to ID {}",
+        form.season, form.title, id
+    );
+    overrides.override_versions.bump(id);
+    return Status::NoContent;
+}
+
+/// Track Plex specials (season 0) of a show as an Anilist entry. Plex does not number
+/// specials like Anilist does, so each episode of the entry is mapped explicitly.
+#[post("/api/anime/<id>/specials", data = "<form>")]
+async fn anime_specials(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    id: i32,
+    form: Form<data::forms::SpecialOverride<'_>>,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<Json<Option<data::state::SpecialOverride>>, status::Custom<&'static str>> {
+    let episodes = form.episodes();
+    let mut overrides = state.overrides_mut().await;
+    if episodes.is_empty() {
+        debug!("Removing special override for ID {}", id);
+        overrides.override_versions.bump(id);
+        overrides.special_overrides.remove(&id);
+        return Ok(Json(None));
+    }
+    let special_override = data::state::SpecialOverride {
+        title: form.title.trim().to_string(),
+        episodes,
+    };
+    if !special_override.is_valid() {
+        return Err(status::Custom(
+            Status::UnprocessableEntity,
+            "The Plex title of the specials is required",
+        ));
+    }
+    info!(
+        "Mapping specials {:?} of '{}' to ID {}",
+        special_override.episodes, special_override.title, id
+    );
+    overrides.override_versions.bump(id);
+    overrides
+        .special_overrides
+        .set(id, special_override.clone());
+    Ok(Json(Some(special_override)))
+}
+
+#[get("/api/conflicts")]
+async fn conflicts(
+    _authorized: data::guards::ApiReader,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<Vec<data::state::ProgressConflict>> {
+    let conflicts = state.conflicts.read().await;
+    Json(conflicts.iter().cloned().collect())
+}
+
+#[post("/api/conflicts/<id>/dismiss")]
+async fn conflict_dismiss(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    id: i32,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Status {
+    if state.conflicts.write().await.remove(id).is_some() {
+        return Status::NoContent;
+    }
+    return Status::NotFound;
+}
+
+/// Send a fabricated scrobble for the next episode of an entry through matching and
+/// the Anilist update. Plex filters are skipped since the scrobble does not come from
+/// Plex.
+#[post("/api/selftest/scrobble", data = "<form>")]
+async fn selftest_scrobble(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    form: Form<data::forms::SelfTest>,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<Json<data::api::SelfTest>, status::Custom<&'static str>> {
+    let account = state.account().await;
+    let media_list_group =
+        match anilist::get_watching_list(&state.anilist, &account.token, &account.user).await {
+            Ok(media_list_group) => media_list_group,
+            Err(error) => {
+                error!(
+                    "Self-test could not retrieve the watching list: {:?}",
+                    error
+                );
+                return Err(status::Custom(
+                    Status::BadGateway,
+                    "Could not retrieve the watching list",
+                ));
+            }
+        };
+    let media_list = match media_list_group.find_id(&form.anilist_id) {
+        Some(media_list) => media_list,
+        None => {
+            return Err(status::Custom(
+                Status::NotFound,
+                "Entry is not in the watching list",
+            ))
+        }
+    };
+    let title = media_list.media.title.to_string();
+    let episode_offset = state.episode_offsets.read().await.get(&media_list.id);
+    let episode = media_list.progress + 1 - episode_offset.unwrap_or(0);
+    let movie = media_list.media.format.as_deref() == Some("MOVIE");
+    let plex_account = state.plex_user.as_deref().unwrap_or("anifunnel");
+    let payload = plex::self_test_payload(&title, episode, movie, plex_account);
+    let webhook: plex::Webhook = serde_json::from_str(&payload).unwrap();
+    info!(
+        "Sending self-test scrobble for '{}' episode {}",
+        title, episode
+    );
+    apply_scrobble(
+        &webhook,
+        &payload,
+        state,
+        &mut report::Sink::live(),
+        &mut None,
+        None,
+    )
+    .await;
+    let result = state
+        .history
+        .read()
+        .await
+        .iter()
+        .rev()
+        .find(|x| x.source == data::state::ScrobbleSource::SelfTest)
+        .map(|x| (x.anilist_id, Some(x.outcome.clone())));
+    let (matched_id, outcome) = result.unwrap_or((None, None));
+    let passed = matched_id == Some(form.anilist_id)
+        && outcome == Some(data::state::HistoryOutcome::Updated);
+    if passed {
+        info!("Self-test passed");
+    } else {
+        warn!("Self-test failed: {:?} ({:?})", outcome, matched_id);
+    }
+    Ok(Json(data::api::SelfTest {
+        anilist_id: form.anilist_id,
+        title,
+        episode,
+        matched_id,
+        outcome,
+        passed,
+    }))
+}
+
+/// Replay the Plex watch history since the given Unix timestamp (or all of it) through
+/// matching, to bring Anilist up to date after downtime or a fresh installation.
+#[post("/api/sync/catchup?<since>")]
+async fn sync_catchup(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    since: Option<u64>,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<Json<data::api::CatchUp>, status::Custom<&'static str>> {
+    let server = match &state.plex {
+        Some(server) => server,
+        None => {
+            return Err(status::Custom(
+                Status::NotFound,
+                "No Plex server is configured",
+            ))
+        }
+    };
+    if state.sync_pause.read().await.is_paused() {
+        return Err(status::Custom(Status::Conflict, "Syncing is paused"));
+    }
+    let payloads = match server.catch_up_payloads(since).await {
+        Ok(payloads) => payloads,
+        Err(error) => {
+            error!("Could not retrieve the Plex watch history: {:?}", error);
+            return Err(status::Custom(
+                Status::BadGateway,
+                "Could not retrieve the Plex watch history",
+            ));
+        }
+    };
+    info!("Catching up on {} watched Plex items", payloads.len());
+    let mut summary = data::api::CatchUp::default();
+    let mut watching_list = None;
+    for payload in payloads {
+        let action = catch_up_scrobble(&payload, state, &mut watching_list).await;
+        match action {
+            "OK" => summary.processed += 1,
+            "ERROR" => summary.failed += 1,
+            _ => summary.ignored += 1,
+        }
+    }
+    info!(
+        "Caught up on the Plex watch history ({} processed, {} ignored, {} failed)",
+        summary.processed, summary.ignored, summary.failed
+    );
+    Ok(Json(summary))
+}
+
+/// Apply the Plex filters to a replayed watch history item and scrobble it. Unlike
+/// webhooks, replayed items are not forwarded to Trakt or counted as webhook activity.
+/// The watching list is shared by the items of the same catch-up.
+async fn catch_up_scrobble(
+    payload: &str,
+    state: &data::state::Global,
+    watching_list: &mut Option<anilist::MediaListGroup>,
+) -> &'static str {
+    let webhook: plex::Webhook = match serde_json::from_str(payload) {
+        Ok(webhook) => webhook,
+        Err(_) => return "ERROR",
+    };
+    let plex_user_matches = state
+        .plex_user
+        .as_ref()
+        .is_none_or(|x| x == &webhook.account.name);
+    if !plex_user_matches
+        || !webhook.matches_library_filter(&state.plex_libraries)
+        || !webhook.matches_account_filter(state.account_filter)
+        || !state.is_actionable(&webhook).await
+    {
+        debug!("Ignoring watched item '{}'", webhook.metadata.title);
+        return "NO OP";
+    }
+    return apply_scrobble(
+        &webhook,
+        payload,
+        state,
+        &mut report::Sink::live(),
+        watching_list,
+        None,
+    )
+    .await;
+}
+
+#[get("/api/stats/activity")]
+async fn stats_activity(
+    _authorized: data::guards::ApiReader,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<Vec<data::api::ActivityDay>> {
+    let history = state.history.read().await;
+    Json(data::api::ActivityDay::build_year(&history))
+}
+
+#[get("/api/unmatched")]
+async fn unmatched(
+    _authorized: data::guards::ApiReader,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<Vec<data::state::UnmatchedScrobble>> {
+    let unmatched = state.unmatched.read().await;
+    Json(unmatched.iter().cloned().collect())
+}
+
+#[post("/api/unmatched/<id>/resolve", data = "<form>")]
+async fn unmatched_resolve(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    id: u64,
+    form: Form<data::forms::UnmatchedResolve>,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<&'static str, Status> {
+    if state.sync_pause.read().await.is_paused() {
+        return Err(Status::Conflict);
+    }
+    // Without an ID, approve the best fuzzy match candidate of the scrobble.
+    let best_candidate = match state.unmatched.read().await.iter().find(|x| x.id == id) {
+        Some(scrobble) => scrobble.candidates.first().map(|x| x.anilist_id),
+        None => return Err(Status::NotFound),
+    };
+    let anilist_id = match form.anilist_id.or(best_candidate) {
+        Some(anilist_id) => anilist_id,
+        None => return Err(Status::UnprocessableEntity),
+    };
+    let scrobbles = match state.unmatched.write().await.take(id) {
+        Some(scrobbles) => scrobbles,
+        None => return Err(Status::NotFound),
+    };
+    let title = scrobbles[0].title.clone();
+    let remember = form.remember.unwrap_or(state.remember_resolved);
+    info!("Resolving '{}' to ID {}", title, anilist_id);
+    if remember {
+        let mut overrides = state.overrides_mut().await;
+        overrides.title_overrides.set(title, anilist_id);
+        overrides.override_versions.bump(anilist_id);
+    }
+    // Process the stored scrobbles in episode order so that each of them can advance
+    // the progress by one.
+    let mut result = "OK";
+    let mut watching_list = None;
+    for scrobble in scrobbles {
+        let webhook: plex::Webhook = match serde_json::from_str(&scrobble.payload) {
+            Ok(webhook) => webhook,
+            Err(_) => continue,
+        };
+        result = apply_scrobble(
+            &webhook,
+            &scrobble.payload,
+            state,
+            &mut report::Sink::live(),
+            &mut watching_list,
+            Some(anilist_id),
+        )
+        .await;
+    }
+    Ok(result)
+}
+
+/// Replay the stored unmatched scrobbles that the overrides now match to an entry, so
+/// that the episodes that were missed before adding an override are applied.
+#[post("/api/anime/<id>/apply-unmatched")]
+async fn anime_apply_unmatched(
+    _authorized: data::guards::ApiAdmin,
+    _writable: data::guards::Writable,
+    id: i32,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<Json<data::api::AppliedUnmatched>, status::Custom<&'static str>> {
+    if state.sync_pause.read().await.is_paused() {
+        return Err(status::Custom(Status::Conflict, "Syncing is paused"));
+    }
+    let user_id = state.account().await.user.id;
+    let unmatched: Vec<(u64, String)> = state
+        .unmatched
+        .read()
+        .await
+        .iter()
+        .map(|x| (x.id, x.payload.clone()))
+        .collect();
+    let mut ids = Vec::new();
+    for (unmatched_id, payload) in unmatched {
+        let webhook: plex::Webhook = match serde_json::from_str(&payload) {
+            Ok(webhook) => webhook,
+            Err(_) => continue,
+        };
+        if state.override_id(&webhook, user_id).await == Some(id) {
+            ids.push(unmatched_id);
+        }
+    }
+    let scrobbles = state.unmatched.write().await.take_ids(&ids);
+    info!(
+        "Applying {} unmatched scrobbles to ID {}",
+        scrobbles.len(),
+        id
+    );
+    let mut summary = data::api::AppliedUnmatched::default();
+    let mut watching_list = None;
+    for scrobble in scrobbles {
+        let webhook: plex::Webhook = match serde_json::from_str(&scrobble.payload) {
+            Ok(webhook) => webhook,
+            Err(_) => continue,
+        };
+        match apply_scrobble(
+            &webhook,
+            &scrobble.payload,
+            state,
+            &mut report::Sink::live(),
+            &mut watching_list,
+            None,
+        )
+        .await
+        {
+            "OK" => summary.processed += 1,
+            "ERROR" => summary.failed += 1,
+            _ => summary.ignored += 1,
+        }
+    }
+    Ok(Json(summary))
+}
+
+/// Error response with hints about likely misconfigurations, which are also logged
+/// since the client (e.g. Plex) rarely shows the response body.
+fn diagnosed_error(request: &Request, status: Status) -> Json<data::api::Error> {
+    let hints = data::guards::request_hints(request, status);
+    for hint in hints.iter() {
+        warn!(
+            "{} {} from {} failed: {}",
+            request.method(),
+            request.uri().path(),
+            data::guards::client_ip(request),
+            hint
+        );
+    }
+    Json(data::api::Error::new(status).with_hints(hints))
+}
+
+#[catch(401)]
+fn unauthorized(request: &Request) -> Json<data::api::Error> {
+    diagnosed_error(request, Status::Unauthorized)
+}
+
+#[catch(404)]
+fn not_found(request: &Request) -> Json<data::api::Error> {
+    diagnosed_error(request, Status::NotFound)
+}
+
+#[catch(422)]
+fn unprocessable_entity() -> Json<data::api::Error> {
+    Json(data::api::Error::new(Status::UnprocessableEntity))
+}
+
+#[catch(500)]
+fn internal_server_error(request: &Request) -> Json<data::api::Error> {
+    error!(
+        "Internal server error for {} {}",
+        request.method(),
+        request.uri()
+    );
+    Json(data::api::Error::new(Status::InternalServerError))
+}
+
+#[get("/")]
+async fn management_redirect(state: &rocket::State<Arc<data::state::Global>>) -> Redirect {
+    redirect(state, uri!(management))
+}
+
+#[post("/", data = "<payload>")]
+async fn scrobble(
+    _authorized: data::guards::WebhookAuthorized,
+    _writable: data::guards::Writable,
+    trace: data::guards::Trace,
+    format: data::guards::ReportFormat,
+    payload: data::forms::ScrobblePayload,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> data::api::ScrobbleResponse {
+    let _pending = match state.webhook_limit.acquire() {
+        Some(pending) => pending,
+        None => {
+            warn!("Too many webhooks are being processed, rejecting webhook");
+            return data::api::ScrobbleResponse::busy();
+        }
+    };
+    let mut sink = report::Sink::live();
+    let action = trace::scope(trace.0, process_scrobble(&payload.0, state, &mut sink)).await;
+    if action == "BUSY" {
+        return data::api::ScrobbleResponse::busy();
+    }
+    if format.0 {
+        return data::api::ScrobbleResponse::Report(Json(sink.into_report(action)));
+    }
+    return data::api::ScrobbleResponse::Processed(action);
+}
+
+/// Process a webhook through the Plex filters, matching and the Anilist update. The
+/// steps are recorded to the sink, and dry runs stop before anything is updated or
+/// recorded, so that replays go through exactly the same steps as live webhooks.
+async fn process_scrobble(
+    payload: &str,
+    state: &data::state::Global,
+    sink: &mut report::Sink,
+) -> &'static str {
+    {
+        let mut maintenance = state.maintenance.write().await;
+        if maintenance.enabled {
+            sink.step("maintenance", || String::from("enabled"));
+            sink.reason("maintenance");
+            sink.action(|| String::from("queue until maintenance mode is disabled"));
+            if sink.is_dry_run() {
+                return "QUEUED";
+            }
+            if state.webhook_limit.is_full(maintenance.queued()) {
+                warn!("Maintenance queue is full, rejecting webhook");
+                return "BUSY";
+            }
+            debug!("Queueing webhook during maintenance");
+            maintenance.queue(payload);
+            return "QUEUED";
+        }
+    }
+
+    let webhook: plex::Webhook = match serde_json::from_str(payload) {
+        Ok(data) => data,
+        Err(error) => {
+            warn!("Unable to parse payload");
+            debug!("{}", error);
+            sink.step("parse", || error.to_string());
+            sink.reason("invalid_payload");
+            sink.action(|| String::from("reject unparseable payload"));
+            if !sink.is_dry_run() {
+                state.failed_payloads.write().await.record(payload);
+            }
+            return "ERROR";
+        }
+    };
+    sink.step("parse", || format!("{:?}", webhook.metadata));
+
+    if !sink.is_dry_run() {
+        state.activity.write().await.last_webhook = Some(data::state::unix_timestamp());
+    }
+
+    // Check possible Plex username restriction.
+    if let Some(plex_user) = &state.plex_user {
+        if plex_user == &webhook.account.name {
+            debug!("Update matches Plex username restriction '{}'", plex_user);
+        } else {
+            info!("Ignoring update for Plex user '{}'", webhook.account.name);
+            return sink.reject("plex_user", "plex_user");
+        }
+    }
+    sink.pass("plex_user");
+
+    if !webhook.matches_server_filter(&state.plex_servers) {
+        info!(
+            "Ignoring update from Plex server '{}'",
+            webhook
+                .server
+                .as_ref()
+                .map_or("unknown", |x| x.name.as_str())
+        );
+        return sink.reject("plex_server", "plex_server");
+    }
+    sink.pass("plex_server");
+
+    if !webhook.matches_library_filter(&state.plex_libraries) {
+        info!(
+            "Ignoring update from Plex library '{}'",
+            webhook
+                .metadata
+                .library_section_title
+                .as_deref()
+                .unwrap_or("unknown")
+        );
+        return sink.reject("plex_library", "plex_library");
+    }
+    sink.pass("plex_library");
+
+    if !webhook.matches_account_filter(state.account_filter) {
+        info!(
+            "Ignoring update for Plex user '{}' (owner: {}, webhook user: {})",
+            webhook.account.name, webhook.owner, webhook.user
+        );
+        return sink.reject("account_filter", "account_filter");
+    }
+    sink.pass("account_filter");
+
+    if !sink.is_dry_run() {
+        if let (Some(key), Some(event)) = (webhook.session_key(), webhook.playback_event()) {
+            state.sessions.write().await.record(key, &webhook, &event);
+        }
+    }
+
+    if state.sync_pause.read().await.is_paused() {
+        info!(
+            "Syncing is paused, ignoring update for '{}'",
+            webhook.metadata.title
+        );
+        return sink.reject("sync_pause", "sync_paused");
+    }
+
+    if let Some(rating) = webhook.rating().filter(|_| state.sync_ratings) {
+        sink.step("rating", || format!("{}", rating));
+        return apply_rating(&webhook, rating, state, sink).await;
+    }
+
+    if !state.is_actionable(&webhook).await {
+        info!("Webhook is not actionable");
+        return sink.reject("actionable", "not_actionable");
+    }
+    sink.pass("actionable");
+
+    if let Some(scrobble_debounce) = state.scrobble_debounce {
+        let duplicate = match sink.is_dry_run() {
+            true => state
+                .recent_scrobbles
+                .read()
+                .await
+                .contains(&webhook, scrobble_debounce),
+            false => state
+                .recent_scrobbles
+                .write()
+                .await
+                .is_duplicate(&webhook, scrobble_debounce),
+        };
+        if duplicate {
+            info!(
+                "Ignoring repeated scrobble for '{}' episode {}",
+                webhook.metadata.title, webhook.metadata.episode_number
+            );
+            return sink.reject("duplicate", "duplicate");
+        }
+        sink.pass("duplicate");
+    }
+
+    // Scrobbles end the watch session, which can be used to reject scrobbles that
+    // happened without the episode actually being watched (e.g. seeking to the end).
+    let watched_fraction = match webhook.session_key() {
+        Some(key) if sink.is_dry_run() => state
+            .sessions
+            .read()
+            .await
+            .iter()
+            .find(|(session_key, _)| **session_key == key)
+            .and_then(|(_, session)| session.watched_fraction()),
+        Some(key) => state
+            .sessions
+            .write()
+            .await
+            .remove(&key)
+            .and_then(|session| session.watched_fraction()),
+        None => None,
+    };
+    if let (Some(minimum_watch_time), Some(watched_fraction)) =
+        (state.minimum_watch_time, watched_fraction)
+    {
+        let watched = watched_fraction * 100.0;
+        sink.step("minimum_watch_time", || format!("{:.0}% watched", watched));
+        if watched < minimum_watch_time as f64 {
+            info!(
+                "Ignoring scrobble for '{}' with {:.0}% watched",
+                webhook.metadata.title, watched
+            );
+            return sink.reject("minimum_watch_time", "minimum_watch_time");
+        }
+    }
+
+    return apply_scrobble(&webhook, payload, state, sink, &mut None, None).await;
+}
+
+/// How a Plex title was matched to a list entry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MatchSource {
+    Override,
+    AnidbMapping,
+    /// Fuzzy title match, with the confidence of the best matching entry.
+    Title(Option<f64>),
+}
+
+impl MatchSource {
+    /// Add how the title was matched to the report of the webhook.
+    fn report(self: Self, sink: &mut report::Sink) {
+        match self {
+            MatchSource::Override => sink.reason("override"),
+            MatchSource::AnidbMapping => sink.reason("anidb_mapping"),
+            MatchSource::Title(confidence) => {
+                sink.reason("title_match");
+                if let Some(confidence) = confidence {
+                    sink.confidence(confidence);
+                }
+            }
+        }
+    }
+}
+
+/// Match a Plex title to the list entries. Overrides are used first, followed by the
+/// AniDB mapping and fuzzy matching.
+fn match_entries<'a>(
+    entries: &'a anilist::MediaListGroup,
+    title: &String,
+    override_id: Option<i32>,
+    anidb_media_id: Option<i32>,
+    minimum_confidences: &data::state::MinimumConfidences,
+    settings: &anilist::MatchSettings,
+) -> (anilist::TitleMatch<'a>, MatchSource) {
+    if let Some(id) = override_id {
+        let title_match = match entries.find_id(&id) {
+            Some(media_list) => {
+                debug!("Using override for '{}' ({})", title, id);
+                anilist::TitleMatch::Found(media_list)
+            }
+            None => anilist::TitleMatch::NotFound,
+        };
+        return (title_match, MatchSource::Override);
+    }
+    if let Some(media_list) = anidb_media_id.and_then(|id| entries.find_media_id(&id)) {
+        debug!(
+            "Using AniDB mapping for '{}' ({})",
+            title, media_list.media.id
+        );
+        return (
+            anilist::TitleMatch::Found(media_list),
+            MatchSource::AnidbMapping,
+        );
+    }
+    let (title_match, confidence) =
+        entries.find_scored_match(title, settings.transliterate_native, |x| {
+            minimum_confidences.effective(x, settings)
+        });
+    return (title_match, MatchSource::Title(confidence));
+}
+
+/// Retry a failed match with the metadata of the item on the Plex server: the GUIDs of
+/// the show for GUID overrides and the AniDB mapping, the original title, which is
+/// often the romaji title when the Plex title is localized, and the year to pick
+/// between equally good matches.
+fn refine_match<'a>(
+    entries: &'a anilist::MediaListGroup,
+    title_match: (anilist::TitleMatch<'a>, MatchSource),
+    metadata: &plex::ItemMetadata,
+    guid_overrides: &data::state::GuidOverrides,
+    anidb_mapping: &anidb::AnidbMapping,
+    minimum_confidences: &data::state::MinimumConfidences,
+    settings: &anilist::MatchSettings,
+) -> (anilist::TitleMatch<'a>, MatchSource) {
+    let override_id = metadata
+        .guids
+        .iter()
+        .find_map(|guid| guid_overrides.get(guid));
+    let anidb_media_id = anidb_mapping.get(&metadata.guids);
+    let original_title = metadata.original_title.as_ref();
+    if override_id.is_some() || anidb_media_id.is_some() || original_title.is_some() {
+        let title = original_title.cloned().unwrap_or_default();
+        let refined = match_entries(
+            entries,
+            &title,
+            override_id,
+            anidb_media_id,
+            minimum_confidences,
+            settings,
+        );
+        if let anilist::TitleMatch::Found(_) = refined.0 {
+            return refined;
+        }
+    }
+    let (title_match, source) = title_match;
+    return match metadata.year {
+        Some(year) => (title_match.narrow_by_year(year), source),
+        None => (title_match, source),
+    };
+}
+
+/// Short description of a match for the replay steps.
+fn describe_match(title_match: &anilist::TitleMatch) -> String {
+    return match title_match {
+        anilist::TitleMatch::Found(x) => format!("{} ({})", x.media.title, x.id),
+        anilist::TitleMatch::Ambiguous(_) => String::from("ambiguous"),
+        anilist::TitleMatch::NotFound => String::from("not found"),
+    };
+}
+
+/// Parse a size such as 10MiB for a request body limit.
+fn parse_limit(value: &str) -> Result<ByteUnit, String> {
+    return value
+        .parse()
+        .map_err(|_| format!("invalid size '{}', expected e.g. 24KiB or 10MiB", value));
+}
+
+/// Normalize the base path to start with a slash and have no trailing slash. The root
+/// path becomes empty so that it can be prepended to route paths.
+fn parse_base_path(value: &str) -> Result<String, String> {
+    let path = value.trim().trim_matches('/');
+    if path.is_empty() {
+        return Ok(String::new());
+    }
+    let path = format!("/{}", path);
+    return match Origin::parse(&path) {
+        Ok(origin) if origin.query().is_none() => Ok(path),
+        _ => Err(format!("invalid base path '{}'", value)),
+    };
+}
+
+/// Redirect to a route, under the base path that the routes are mounted at.
+fn redirect(state: &data::state::Global, uri: Origin<'static>) -> Redirect {
+    Redirect::to(format!("{}{}", state.base_path, uri))
+}
+
+/// Request body limits. The string limit is higher than the Rocket default since Plex
+/// might send the thumbnail in some requests and we don't want those to cause
+/// unnecessary HTTP 413 Content Too Large errors (even though we don't use those
+/// requests). The thumbnail is sent as a file part of the multipart form, so files
+/// share the limit of the whole form.
+fn build_limits(string: ByteUnit, form: ByteUnit, data_form: ByteUnit, json: ByteUnit) -> Limits {
+    return Limits::default()
+        .limit("string", string)
+        .limit("form", form)
+        .limit("data-form", data_form)
+        .limit("file", data_form)
+        .limit("json", json);
+}
+
+/// Send an event to the Discord webhook, if one is configured and the event is
+/// enabled, and to the notifiers that fire on the event.
+async fn notify(state: &data::state::Global, event: notifiers::NotifierEvent, message: String) {
+    if let (Some(webhook), Some(discord_event)) = (&state.discord, event.discord_event()) {
+        if state.discord_events.read().await.is_enabled(discord_event) {
+            discord::notify(webhook, discord_event, message.clone());
+        }
+    }
+    for notifier in state.notifiers.read().await.matching(event) {
+        notifiers::notify(&notifier, event, message.clone());
+    }
+}
+
+/// Notify if the Anilist token is about to expire, once per token.
+async fn check_token_expiry(state: &data::state::Global, token: &str) {
+    let message = state.notifications.write().await.check_token_expiry(token);
+    if let Some(message) = message {
+        notify(state, notifiers::NotifierEvent::TokenExpiring, message).await;
+    }
+}
+
+/// Check the Anilist schema daily and notify when fields that anifunnel queries
+/// disappear. The same missing fields are only notified about once.
+/// Fetch the lists that scrobbles are matched against and build their title indexes,
+/// so that the first scrobble after a restart doesn't risk a Plex webhook timeout.
+/// Waits for the first load of the AniDB ID mapping if it is used. Readiness is
+/// reported afterwards even if Anilist could not be reached, since /readyz checks
+/// Anilist on its own.
+async fn warm_cache(state: Arc<data::state::Global>, anidb_loaded: Option<oneshot::Receiver<()>>) {
+    if let Some(anidb_loaded) = anidb_loaded {
+        let _ = anidb_loaded.await;
+    }
+    let account = state.account().await;
+    if !account.token.is_empty() {
+        match anilist::get_watching_list(&state.anilist, &account.token, &account.user).await {
+            Ok(watching_list) => {
+                state.index_titles(&watching_list).await;
+                if state.movies {
+                    state.index_titles(&watching_list.movies()).await;
+                }
+            }
+            Err(error) => warn!("Could not warm up the watching list: {:?}", error),
+        }
+        if state.inactive_lists {
+            match anilist::get_inactive_list(&state.anilist, &account.token, &account.user).await {
+                Ok(inactive_list) => state.index_titles(&inactive_list).await,
+                Err(error) => warn!("Could not warm up the inactive lists: {:?}", error),
+            }
+        }
+    }
+    info!("Caches warmed up");
+    state.cache_warmed.store(true, Ordering::SeqCst);
+}
+
+async fn probe_schema(state: Arc<data::state::Global>) {
+    let mut reported: Vec<String> = Vec::new();
+    loop {
+        let account = state.account().await;
+        if !account.token.is_empty() {
+            match anilist::probe_schema(&state.anilist, &account.token).await {
+                Ok(missing) if missing.is_empty() => {
+                    debug!("Anilist schema has all the queried fields");
+                    reported.clear();
+                }
+                Ok(missing) => {
+                    if missing != reported {
+                        let message = format!(
+                            "The Anilist API no longer has the fields {}. Updates may fail until anifunnel is updated.",
+                            missing.join(", ")
+                        );
+                        error!("{}", message);
+                        state.notifications.write().await.push(
+                            data::state::NotificationKind::SchemaChanged,
+                            message.clone(),
+                        );
+                        notify(&state, notifiers::NotifierEvent::Error, message).await;
+                        reported = missing;
+                    }
+                }
+                Err(error) => warn!("Could not probe the Anilist schema: {:?}", error),
+            }
+        }
+        tokio::time::sleep(anilist::SCHEMA_PROBE_INTERVAL).await;
+    }
+}
+
+/// How applying a scrobble changed the watching list that it was matched against.
+enum ListChange {
+    Unchanged,
+    /// The progress of a watching list entry was updated.
+    Progress(i32, i32),
+    /// An entry was completed or moved to the watching list, so the list has to be
+    /// retrieved again.
+    Stale,
+}
+
+/// What a matched scrobble does to the Anilist entry.
+enum ProgressPlan {
+    /// Record the next episode without updating Anilist.
+    Log,
+    Update,
+    Rewatch(data::state::RewatchPolicy),
+    /// The episode skips ahead of the progress.
+    Conflict,
+    Skip,
+}
+
+/// Record the outcome of a scrobble in the history and in the report of the webhook.
+/// Dry runs only report it.
+async fn record_outcome(
+    state: &data::state::Global,
+    sink: &mut report::Sink,
+    webhook: &plex::Webhook,
+    anilist_id: Option<i32>,
+    outcome: data::state::HistoryOutcome,
+) {
+    sink.outcome(&outcome);
+    if !sink.is_dry_run() {
+        state
+            .history
+            .write()
+            .await
+            .record(webhook, anilist_id, outcome);
+    }
+}
+
+/// Match an accepted scrobble to the watching list and update the Anilist progress.
+/// The watching list is retrieved on first use and kept up to date with the progress
+/// updates, so that a batch of scrobbles can share it.
+async fn apply_scrobble(
+    webhook: &plex::Webhook,
+    payload: &str,
+    state: &data::state::Global,
+    sink: &mut report::Sink,
+    watching_list: &mut Option<anilist::MediaListGroup>,
+    resolved_id: Option<i32>,
+) -> &'static str {
+    let mut media_list_entries = match watching_list.take() {
+        Some(media_list_entries) => media_list_entries,
+        None => {
+            let account = state.account().await;
+            match anilist::get_watching_list(&state.anilist, &account.token, &account.user).await {
+                Ok(media_list_entries) => media_list_entries,
+                Err(error) => {
+                    error!("Could not retrieve the watching list: {:?}", error);
+                    sink.step("watching_list", || format!("{:?}", error));
+                    sink.reason("watching_list_unavailable");
+                    sink.action(|| String::from("fail to retrieve the watching list"));
+                    return "OK";
+                }
+            }
+        }
+    };
+    let (result, change) = match_scrobble(
+        webhook,
+        payload,
+        state,
+        sink,
+        &media_list_entries,
+        resolved_id,
+    )
+    .await;
+    match change {
+        ListChange::Unchanged => *watching_list = Some(media_list_entries),
+        ListChange::Progress(id, progress) => {
+            if media_list_entries.set_progress(id, progress) {
+                *watching_list = Some(media_list_entries);
+            }
+        }
+        ListChange::Stale => {}
+    }
+    return result;
+}
+
+async fn match_scrobble(
+    webhook: &plex::Webhook,
+    payload: &str,
+    state: &data::state::Global,
+    sink: &mut report::Sink,
+    watching_list: &anilist::MediaListGroup,
+    resolved_id: Option<i32>,
+) -> (&'static str, ListChange) {
+    let account = state.account().await;
+    let movies;
+    let media_list_entries = match webhook.metadata.is_movie() {
+        true => {
+            movies = watching_list.movies();
+            &movies
+        }
+        false => watching_list,
+    };
+    state.index_titles(media_list_entries).await;
+    let title = &webhook.metadata.title;
+    // Resolving an unmatched scrobble matches it as if the title had an override.
+    let title_override = match resolved_id {
+        Some(id) => Some(id),
+        None => state.title_override(account.user.id, title).await,
+    };
+    let title_pattern = state.title_patterns.read().await.get(title);
+    let guid_overrides = state.guid_overrides.read().await;
+    let minimum_confidences = state.minimum_confidences.read().await;
+    let guid_override = webhook
+        .metadata
+        .override_guids()
+        .into_iter()
+        .find_map(|guid| guid_overrides.get(guid));
+    let anidb_media_id = state
+        .anidb_mapping
+        .read()
+        .await
+        .get(webhook.metadata.override_guids());
+    let special_override = state.special_override(webhook).await;
+    let season_mapping = state.season_mapping(webhook).await;
+    sink.step("overrides", || {
+        format!(
+            "special: {:?}, season: {:?}, GUID: {:?}, title: {:?}, pattern: {:?}, \
+            AniDB mapping: {:?}",
+            special_override,
+            season_mapping,
+            guid_override,
+            title_override,
+            title_pattern,
+            anidb_media_id
+        )
+    });
+    let override_id = special_override
+        .map(|(id, _)| id)
+        .or(season_mapping)
+        .or(guid_override)
+        .or(title_override)
+        .or(title_pattern);
+    if override_id.is_none()
+        && anidb_media_id
+            .and_then(|id| media_list_entries.find_media_id(&id))
+            .is_none()
+    {
+        sink.candidates(|| {
+            media_list_entries.candidates(
+                title,
+                REPLAY_CANDIDATES,
+                state.match_settings.transliterate_native,
+                |x| minimum_confidences.effective(x, &state.match_settings),
+            )
+        });
+    }
+    // Matching works on arbitrary titles, so make sure that a bug in it only fails
+    // this one scrobble.
+    let mut matched_media_list = panic::catch_unwind(AssertUnwindSafe(|| {
+        match_entries(
+            media_list_entries,
+            title,
+            override_id,
+            anidb_media_id,
+            &minimum_confidences,
+            &state.match_settings,
+        )
+    }));
+    let inactive_entries = match matched_media_list {
+        Ok((anilist::TitleMatch::NotFound, _)) if state.inactive_lists => {
+            match anilist::get_inactive_list(&state.anilist, &account.token, &account.user).await {
+                Ok(entries) if webhook.metadata.is_movie() => Some(entries.movies()),
+                Ok(entries) => Some(entries),
+                Err(error) => {
+                    error!(
+                        "Could not retrieve the planning and paused lists: {:?}",
+                        error
+                    );
+                    sink.step("inactive_lists", || format!("{:?}", error));
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+    if let Some(inactive_entries) = &inactive_entries {
+        debug!("Matching '{}' against the planning and paused lists", title);
+        state.index_titles(inactive_entries).await;
+        matched_media_list = panic::catch_unwind(AssertUnwindSafe(|| {
+            match_entries(
+                inactive_entries,
+                title,
+                override_id,
+                anidb_media_id,
+                &minimum_confidences,
+                &state.match_settings,
+            )
+        }));
+        if let Ok((title_match, _)) = &matched_media_list {
+            sink.step("inactive_lists", || describe_match(title_match));
+        }
+    }
+    let plex_metadata = match (
+        &matched_media_list,
+        &state.plex,
+        &webhook.metadata.metadata_key,
+    ) {
+        (Ok((anilist::TitleMatch::Found(_), _)) | Err(_), _, _) => None,
+        (_, Some(server), Some(key)) => match server.item_metadata(key).await {
+            Ok(metadata) => Some(metadata),
+            Err(error) => {
+                warn!(
+                    "Could not retrieve the Plex metadata of '{}': {:?}",
+                    title, error
+                );
+                sink.step("plex_metadata", || format!("{:?}", error));
+                None
+            }
+        },
+        _ => None,
+    };
+    let matched_media_list = match (plex_metadata, matched_media_list) {
+        (Some(metadata), Ok(title_match)) => {
+            debug!("Retrying '{}' with the Plex metadata {:?}", title, metadata);
+            sink.step("plex_metadata", || format!("{:?}", metadata));
+            let anidb_mapping = state.anidb_mapping.read().await;
+            let refined = panic::catch_unwind(AssertUnwindSafe(|| {
+                refine_match(
+                    inactive_entries.as_ref().unwrap_or(media_list_entries),
+                    title_match,
+                    &metadata,
+                    &guid_overrides,
+                    &anidb_mapping,
+                    &minimum_confidences,
+                    &state.match_settings,
+                )
+            }));
+            if let Ok((title_match, _)) = &refined {
+                sink.step("plex_metadata", || describe_match(title_match));
+            }
+            refined
+        }
+        (_, matched_media_list) => matched_media_list,
+    };
+    let (matched_media_list, source) = match matched_media_list {
+        Ok(matched_media_list) => matched_media_list,
+        Err(_) => {
+            error!("Matching '{}' failed unexpectedly", webhook.metadata.title);
+            sink.step("match", || String::from("failed unexpectedly"));
+            sink.reason("match_failed");
+            sink.action(|| String::from("fail matching"));
+            if !sink.is_dry_run() {
+                state.failed_payloads.write().await.record(payload);
+            }
+            return ("ERROR", ListChange::Unchanged);
+        }
+    };
+    let matched_media_list = match matched_media_list {
+        anilist::TitleMatch::Found(media_list) => {
+            source.report(sink);
+            media_list
+        }
+        anilist::TitleMatch::Ambiguous(candidates) => {
+            let candidates: Vec<String> = candidates
+                .iter()
+                .map(|x| format!("{} ({})", x.media.title, x.id))
+                .collect();
+            warn!(
+                "'{}' matches several entries equally well, set a title override to pick one: {}",
+                &webhook.metadata.title,
+                candidates.join(", ")
+            );
+            sink.step("match", || candidates.join(", "));
+            sink.reason("ambiguous");
+            sink.action(|| String::from("ignore ambiguous match"));
+            record_outcome(
+                state,
+                sink,
+                webhook,
+                None,
+                data::state::HistoryOutcome::Ambiguous,
+            )
+            .await;
+            return ("NO OP", ListChange::Unchanged);
+        }
+        anilist::TitleMatch::NotFound => {
+            sink.step("match", || String::from("not found"));
+            sink.reason("not_found");
+            sink.action(|| String::from("record as unmatched"));
+            record_outcome(
+                state,
+                sink,
+                webhook,
+                None,
+                data::state::HistoryOutcome::Unmatched,
+            )
+            .await;
+            if sink.is_dry_run() {
+                return ("NO OP", ListChange::Unchanged);
+            }
+            let candidates = media_list_entries.candidates(
+                title,
+                UNMATCHED_CANDIDATES,
+                state.match_settings.transliterate_native,
+                |x| minimum_confidences.effective(x, &state.match_settings),
+            );
+            if state
+                .unmatched
+                .write()
+                .await
+                .record(webhook, payload, candidates)
+            {
+                info!("Could not find a match for '{}'", &webhook.metadata.title);
+                let message = format!(
+                    "Could not find a match for '{}' in the watching list",
+                    webhook.metadata.title
+                );
+                state
+                    .notifications
+                    .write()
+                    .await
+                    .push(data::state::NotificationKind::Unmatched, message.clone());
+                notify(state, notifiers::NotifierEvent::Unmatched, message).await;
+            } else {
+                debug!(
+                    "Could not find a match for '{}' again, not notifying yet",
+                    &webhook.metadata.title
+                );
+            }
+            return ("NO OP", ListChange::Unchanged);
+        }
+    };
+    sink.step("match", || {
+        format!(
+            "{} ({}), progress {}",
+            matched_media_list.media.title, matched_media_list.id, matched_media_list.progress
+        )
+    });
+    let mut matched_media_list = matched_media_list;
+    let mut episode = webhook.metadata.episode_number;
+    let episode_offsets = state.episode_offsets.read().await;
+    if let Some((_, special_episode)) = special_override {
+        info!(
+            "Mapped special {} of '{}' to episode {} of {}",
+            episode, webhook.metadata.title, special_episode, matched_media_list.media.title
+        );
+        sink.step("episode", || {
+            format!("special {} mapped to {}", episode, special_episode)
+        });
+        episode = special_episode;
+    } else if let Some(episode_offset) = episode_offsets.get(&matched_media_list.id) {
+        episode += episode_offset;
+        sink.step("episode", || {
+            format!("offset {} to {}", episode_offset, episode)
+        });
+    } else if season_mapping.is_none()
+        && matched_media_list
+            .media
+            .episodes
+            .is_some_and(|x| episode > x)
+    {
+        // Episode numbers past the end of the matched entry are likely absolute
+        // episode numbers of the whole franchise.
+        let relations = state.relations.read().await;
+        if let Some((media_list, relative_episode)) =
+            relations.resolve(media_list_entries, matched_media_list, episode)
+        {
+            info!(
+                "Mapped absolute episode {} of '{}' to episode {} of {}",
+                episode, webhook.metadata.title, relative_episode, media_list.media.title
+            );
+            sink.step("episode", || {
+                format!(
+                    "absolute episode {} mapped to episode {} of {} ({})",
+                    episode, relative_episode, media_list.media.title, media_list.id
+                )
+            });
+            matched_media_list = media_list;
+            episode = relative_episode;
+        }
+    }
+    sink.media(
+        matched_media_list.id,
+        &matched_media_list.media.title,
+        Some(episode),
+    );
+    if let Some(muted_until) = state.mutes.read().await.get(&matched_media_list.id) {
+        info!(
+            "Ignoring scrobble for '{}', muted until {}",
+            matched_media_list.media.title, muted_until
+        );
+        sink.step("mute", || format!("muted until {}", muted_until));
+        sink.reason("muted");
+        sink.action(|| String::from("ignore muted entry"));
+        record_outcome(
+            state,
+            sink,
+            webhook,
+            Some(matched_media_list.id),
+            data::state::HistoryOutcome::Muted,
+        )
+        .await;
+        return ("NO OP", ListChange::Unchanged);
+    }
+    if state.ignored.read().await.contains(&matched_media_list.id) {
+        info!(
+            "Ignoring scrobble for '{}', the entry is ignored",
+            matched_media_list.media.title
+        );
+        sink.step("ignore", || String::from("the entry is ignored"));
+        sink.reason("ignored");
+        sink.action(|| String::from("ignore ignored entry"));
+        record_outcome(
+            state,
+            sink,
+            webhook,
+            Some(matched_media_list.id),
+            data::state::HistoryOutcome::Ignored,
+        )
+        .await;
+        return ("NO OP", ListChange::Unchanged);
+    }
+    let log_only = state.log_only.read().await.contains(&matched_media_list.id);
+    if log_only {
+        sink.reason("log_only");
+    }
+    let progress = matched_media_list.progress;
+    let plan = if episode == progress + 1 && log_only {
+        ProgressPlan::Log
+    } else if episode == progress + 1 {
+        ProgressPlan::Update
+    } else if episode >= 1 && episode <= progress {
+        // Catch-up syncs replay the whole watch history, which includes every episode
+        // that has already been counted.
+        ProgressPlan::Rewatch(match webhook.is_catch_up() {
+            true => data::state::RewatchPolicy::Ignore,
+            false => state.rewatch_policy,
+        })
+    } else if episode > progress + 1 {
+        ProgressPlan::Conflict
+    } else {
+        ProgressPlan::Skip
+    };
+    sink.action(|| match &plan {
+        ProgressPlan::Log => format!("log progress {} without updating Anilist", episode),
+        ProgressPlan::Update if webhook.metadata.is_movie() => String::from("complete the entry"),
+        ProgressPlan::Update if matched_media_list.is_inactive() => {
+            format!("move to watching and update progress to {}", episode)
+        }
+        ProgressPlan::Update => format!("update progress to {}", episode),
+        ProgressPlan::Rewatch(rewatch_policy) => format!("handle rewatch ({:?})", rewatch_policy),
+        ProgressPlan::Conflict => format!(
+            "handle episode {}, which is ahead of progress {} ({:?})",
+            episode, progress, state.conflict_policy
+        ),
+        ProgressPlan::Skip => format!(
+            "skip episode {}, which does not follow progress {}",
+            episode, progress
+        ),
+    });
+    if sink.is_dry_run() {
+        return ("OK", ListChange::Unchanged);
+    }
+
+    // Log-only entries are never sent to any tracker. Catch-up items and self-tests are
+    // not actual playback, so they are not forwarded either.
+    if let Some(trakt) = state.trakt.as_ref().filter(|_| !log_only) {
+        if !webhook.is_catch_up() && !webhook.is_self_test() {
+            trakt::forward(trakt, webhook);
+        }
+    }
+
+    debug!("Processing {}", matched_media_list);
+    let (outcome, change) = match plan {
+        ProgressPlan::Log => {
+            info!(
+                "Not updating '{}' progress to {}, the entry is log-only",
+                matched_media_list.media.title, episode
+            );
+            (data::state::HistoryOutcome::Logged, ListChange::Unchanged)
+        }
+        ProgressPlan::Update => {
+            if matched_media_list.is_inactive() {
+                info!(
+                    "Moving '{}' from the {} list to watching",
+                    matched_media_list.media.title,
+                    matched_media_list
+                        .status
+                        .as_deref()
+                        .unwrap_or_default()
+                        .to_lowercase()
+                );
+            }
+            let completes =
+                webhook.metadata.is_movie() || matched_media_list.media.episodes == Some(episode);
+            let result = if webhook.metadata.is_movie() {
+                matched_media_list
+                    .complete(&state.mutations, &account.token)
+                    .await
+            } else {
+                matched_media_list
+                    .update(&state.mutations, &account.token)
+                    .await
+            };
+            let (outcome, change) = match result {
+                Ok(anilist::Saved::Updated) => {
+                    info!("Updated '{}' progress", matched_media_list.media.title);
+                    state.activity.write().await.last_update = Some(data::state::unix_timestamp());
+                    notify(
+                        state,
+                        notifiers::NotifierEvent::Update,
+                        format!(
+                            "Updated '{}' progress to episode {}",
+                            matched_media_list.media.title, episode
+                        ),
+                    )
+                    .await;
+                    let change = match completes {
+                        true => ListChange::Stale,
+                        false => ListChange::Progress(matched_media_list.id, episode),
+                    };
+                    (data::state::HistoryOutcome::Updated, change)
+                }
+                Ok(anilist::Saved::AlreadyCounted) => {
+                    info!(
+                        "Episode {} of '{}' has already been counted on Anilist",
+                        episode, matched_media_list.media.title
+                    );
+                    (data::state::HistoryOutcome::Skipped, ListChange::Stale)
+                }
+                Ok(anilist::Saved::Failed) => {
+                    error!(
+                        "Failed to update progress for '{}'",
+                        matched_media_list.media.title
+                    );
+                    (data::state::HistoryOutcome::Failed, ListChange::Unchanged)
+                }
+                Err(error) => {
+                    error!("{:?}", error);
+                    (data::state::HistoryOutcome::Failed, ListChange::Unchanged)
+                }
+            };
+            if outcome == data::state::HistoryOutcome::Failed {
+                state.failed_payloads.write().await.record(payload);
+                let message = format!(
+                    "Failed to update progress for '{}'",
+                    matched_media_list.media.title
+                );
+                state
+                    .notifications
+                    .write()
+                    .await
+                    .push(data::state::NotificationKind::UpdateFailed, message.clone());
+                notify(state, notifiers::NotifierEvent::Error, message).await;
+            }
+            (outcome, change)
+        }
+        ProgressPlan::Rewatch(rewatch_policy) => {
+            let outcome = match rewatch_policy {
+                data::state::RewatchPolicy::Ignore => {
+                    debug!(
+                        "Episode {} of '{}' has already been counted",
+                        episode, matched_media_list.media.title
+                    );
+                    data::state::HistoryOutcome::Skipped
+                }
+                data::state::RewatchPolicy::Notify => {
+                    info!(
+                        "Rewatched episode {} of '{}'",
+                        episode, matched_media_list.media.title
+                    );
+                    data::state::HistoryOutcome::Rewatched
+                }
+                data::state::RewatchPolicy::Count => {
+                    let count = state
+                        .rewatches
+                        .write()
+                        .await
+                        .record(matched_media_list.id, episode);
+                    info!(
+                        "Rewatched episode {} of '{}' ({} times)",
+                        episode, matched_media_list.media.title, count
+                    );
+                    data::state::HistoryOutcome::Rewatched
+                }
+            };
+            (outcome, ListChange::Unchanged)
+        }
+        ProgressPlan::Conflict => {
+            let outcome = resolve_conflict(matched_media_list, episode, state).await;
+            let change = match outcome {
+                data::state::HistoryOutcome::Updated => {
+                    ListChange::Progress(matched_media_list.id, episode)
+                }
+                _ => ListChange::Unchanged,
+            };
+            (outcome, change)
+        }
+        ProgressPlan::Skip => (data::state::HistoryOutcome::Skipped, ListChange::Unchanged),
+    };
+    record_outcome(state, sink, webhook, Some(matched_media_list.id), outcome).await;
+    return ("OK", change);
+}
+
+/// Save a Plex rating of a show or movie as the Anilist score of the matched entry.
+/// Ratings of single episodes count for the whole show.
+async fn apply_rating(
+    webhook: &plex::Webhook,
+    rating: f64,
+    state: &data::state::Global,
+    sink: &mut report::Sink,
+) -> &'static str {
+    let account = state.account().await;
+    let mut media_list_entries =
+        match anilist::get_rated_list(&state.anilist, &account.token, &account.user).await {
+            Ok(media_list_entries) => media_list_entries,
+            Err(error) => {
+                error!("Could not retrieve the Anilist lists: {:?}", error);
+                sink.step("anilist_lists", || format!("{:?}", error));
+                sink.reason("anilist_lists_unavailable");
+                sink.action(|| String::from("fail to retrieve the Anilist lists"));
+                return "OK";
+            }
+        };
+    if webhook.metadata.is_movie() {
+        media_list_entries = media_list_entries.movies();
+    }
+    state.index_titles(&media_list_entries).await;
+    let title = &webhook.metadata.title;
+    let guid_overrides = state.guid_overrides.read().await;
+    let override_id = webhook
+        .metadata
+        .override_guids()
+        .into_iter()
+        .find_map(|guid| guid_overrides.get(guid))
+        .or(state.title_override(account.user.id, title).await)
+        .or(state.title_patterns.read().await.get(title));
+    let anidb_media_id = state
+        .anidb_mapping
+        .read()
+        .await
+        .get(webhook.metadata.override_guids());
+    let minimum_confidences = state.minimum_confidences.read().await;
+    let media_list = match match_entries(
+        &media_list_entries,
+        title,
+        override_id,
+        anidb_media_id,
+        &minimum_confidences,
+        &state.match_settings,
+    ) {
+        (anilist::TitleMatch::Found(media_list), source) => {
+            source.report(sink);
+            media_list
+        }
+        (title_match, _) => {
+            info!("Could not find a match for the rating of '{}'", title);
+            sink.step("match", || describe_match(&title_match));
+            sink.reason("rating_unmatched");
+            sink.action(|| String::from("ignore unmatched rating"));
+            return "NO OP";
+        }
+    };
+    sink.step("match", || {
+        format!("{} ({})", media_list.media.title, media_list.id)
+    });
+    sink.media(media_list.id, &media_list.media.title, None);
+    if state.ignored_ratings.read().await.contains(&media_list.id)
+        || state.ignored.read().await.contains(&media_list.id)
+    {
+        info!("Not syncing the rating of '{}', ratings are ignored", title);
+        sink.reason("ratings_ignored");
+        sink.action(|| String::from("ignore rating of ignored entry"));
+        return "NO OP";
+    }
+    let score = (rating * 10.0).round().clamp(0.0, 100.0) as i32;
+    sink.action(|| format!("sync the rating as the Anilist score {}/100", score));
+    if sink.is_dry_run() {
+        return "OK";
+    }
+    let outcome =
+        match anilist::set_score(&state.mutations, &account.token, media_list.id, score).await {
+            Ok(true) => {
+                info!("Set '{}' score to {}/100", media_list.media.title, score);
+                data::state::HistoryOutcome::Rated
+            }
+            result => {
+                error!(
+                    "Failed to set score for '{}': {:?}",
+                    media_list.media.title, result
+                );
+                data::state::HistoryOutcome::Failed
+            }
+        };
+    record_outcome(state, sink, webhook, Some(media_list.id), outcome).await;
+    return "OK";
+}
+
+/// Handle a scrobbled episode that skips ahead of the Anilist progress according to
+/// the conflict policy. Progress is never decreased, since episodes that are behind
+/// the Anilist progress are handled as rewatches.
+async fn resolve_conflict(
+    media_list: &anilist::MediaList,
+    episode: i32,
+    state: &data::state::Global,
+) -> data::state::HistoryOutcome {
+    let account = state.account().await;
+    match state.conflict_policy {
+        data::state::ConflictPolicy::Skip => {
+            info!(
+                "Skipping episode {} of '{}', which is ahead of progress {}",
+                episode, media_list.media.title, media_list.progress
+            );
+        }
+        data::state::ConflictPolicy::Force => {
+            if state.log_only.read().await.contains(&media_list.id) {
+                info!(
+                    "Not forcing '{}' progress to {}, the entry is log-only",
+                    media_list.media.title, episode
+                );
+                return data::state::HistoryOutcome::Logged;
+            }
+            info!(
+                "Forcing '{}' progress from {} to {}",
+                media_list.media.title, media_list.progress, episode
+            );
+            return match anilist::set_progress(
+                &state.mutations,
+                &account.token,
+                media_list.id,
+                episode,
+                false,
+            )
+            .await
+            {
+                Ok(anilist::Saved::AlreadyCounted) => {
+                    info!(
+                        "Not forcing '{}' progress to {}, Anilist is already past it",
+                        media_list.media.title, episode
+                    );
+                    data::state::HistoryOutcome::Skipped
+                }
+                Ok(anilist::Saved::Updated) => {
+                    state.activity.write().await.last_update = Some(data::state::unix_timestamp());
+                    notify(
+                        state,
+                        notifiers::NotifierEvent::Update,
+                        format!(
+                            "Updated '{}' progress to episode {}",
+                            media_list.media.title, episode
+                        ),
+                    )
+                    .await;
+                    data::state::HistoryOutcome::Updated
+                }
+                result => {
+                    error!(
+                        "Failed to force progress for '{}': {:?}",
+                        media_list.media.title, result
+                    );
+                    let message =
+                        format!("Failed to update progress for '{}'", media_list.media.title);
+                    state
+                        .notifications
+                        .write()
+                        .await
+                        .push(data::state::NotificationKind::UpdateFailed, message.clone());
+                    notify(state, notifiers::NotifierEvent::Error, message).await;
+                    data::state::HistoryOutcome::Failed
+                }
+            };
+        }
+        data::state::ConflictPolicy::Review => {
+            info!(
+                "Episode {} of '{}' is ahead of progress {}, flagging for review",
+                episode, media_list.media.title, media_list.progress
+            );
+            state
+                .conflicts
+                .write()
+                .await
+                .record(data::state::ProgressConflict {
+                    anilist_id: media_list.id,
+                    title: media_list.media.title.to_string(),
+                    progress: media_list.progress,
+                    episode,
+                    timestamp: data::state::unix_timestamp(),
+                });
+            state.notifications.write().await.push(
+                data::state::NotificationKind::ProgressConflict,
+                format!(
+                    "Episode {} of '{}' is ahead of the Anilist progress {}",
+                    episode, media_list.media.title, media_list.progress
+                ),
+            );
+        }
+    }
+    return data::state::HistoryOutcome::Conflict;
+}
+
+#[post("/api/replay", data = "<payload>")]
+async fn replay(
+    _authorized: data::guards::ApiAdmin,
+    payload: &str,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Json<data::api::Replay> {
+    let mut sink = report::Sink::dry_run();
+    process_scrobble(payload, state, &mut sink).await;
+    Json(sink.into_replay())
+}
+
+/// Match a Plex title against the watching list without updating anything, with the
+/// scores of the best fuzzy match candidates.
+#[get("/api/match?<title>")]
+async fn match_test(
+    _authorized: data::guards::ApiReader,
+    title: &str,
+    state: &rocket::State<Arc<data::state::Global>>,
+) -> Result<Json<data::api::MatchReport>, status::Custom<&'static str>> {
+    let account = state.account().await;
+    let media_list_entries =
+        match anilist::get_watching_list(&state.anilist, &account.token, &account.user).await {
+            Ok(media_list_entries) => media_list_entries,
+            Err(error) => {
+                error!("Could not retrieve the watching list: {:?}", error);
+                return Err(status::Custom(
+                    Status::BadGateway,
+                    "Could not retrieve the watching list",
+                ));
+            }
+        };
+    state.index_titles(&media_list_entries).await;
+    let title = title.to_string();
+    let override_id = match state.title_override(account.user.id, &title).await {
+        Some(id) => Some(id),
+        None => state.title_patterns.read().await.get(&title),
+    };
+    let minimum_confidences = state.minimum_confidences.read().await;
+    let (title_match, _) = match_entries(
+        &media_list_entries,
+        &title,
+        override_id,
+        None,
+        &minimum_confidences,
+        &state.match_settings,
+    );
+    Ok(Json(data::api::MatchReport::build(
+        &title,
+        override_id,
+        &title_match,
+        media_list_entries.candidates(
+            &title,
+            REPLAY_CANDIDATES,
+            state.match_settings.transliterate_native,
+            |x| minimum_confidences.effective(x, &state.match_settings),
+        ),
+    )))
+}
+
+/// Number of fuzzy match candidates included in replays.
+const REPLAY_CANDIDATES: usize = 5;
+/// Number of fuzzy match candidates kept with unmatched scrobbles.
+const UNMATCHED_CANDIDATES: usize = 3;
+
+/// How often to check whether the pending work has finished when shutting down.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Wait for webhooks that are being processed and queued Anilist updates to finish,
+/// for at most the given time. Returns whether everything finished.
+async fn drain(state: &data::state::Global, timeout: Duration) -> bool {
+    let started = Instant::now();
+    loop {
+        let webhooks = state.webhook_limit.pending();
+        let mutations = state.mutations.pending();
+        if webhooks == 0 && mutations == 0 {
+            return true;
+        }
+        if started.elapsed() >= timeout {
+            warn!(
+                "Shutting down with {} webhooks and {} Anilist updates unfinished",
+                webhooks, mutations
+            );
+            return false;
+        }
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+}
+
+/// Fairing for finishing pending work before the server shuts down. Webhooks queued
+/// in maintenance mode cannot be kept, so they are only reported.
+fn shutdown_drain(timeout: Duration) -> impl Fairing {
+    AdHoc::on_shutdown("Shutdown drain", move |rocket| {
+        Box::pin(async move {
+            if let Some(state) = rocket.state::<Arc<data::state::Global>>() {
+                info!("Shutting down, waiting for pending work to finish");
+                if drain(state, timeout).await {
+                    info!("Pending work finished");
+                }
+                let queued = state.maintenance.read().await.queued();
+                if queued > 0 {
+                    warn!(
+                        "Discarding {} webhooks queued during maintenance mode",
+                        queued
+                    );
+                }
+            }
+        })
+    })
+}
+
+/// Fairing for logging the configuration once the server has started.
+fn config_summary_log() -> impl Fairing {
+    AdHoc::on_liftoff("Configuration summary", |rocket| {
+        Box::pin(async move {
+            if let Some(state) = rocket.state::<Arc<data::state::Global>>() {
+                let accounts = state.accounts.read().await;
+                let summary = data::api::ConfigSummary::build(rocket.config(), state, &accounts);
+                match serde_json::to_string(&summary) {
+                    Ok(summary) => info!("Configuration: {}", summary),
+                    Err(error) => warn!("Could not serialize configuration: {}", error),
+                }
+            }
+        })
+    })
+}
+
+/// Fairing for loading the templates embedded in the binary.
+fn templates() -> impl Fairing {
+    Template::custom(|engines| {
+        engines
+            .tera
+            .add_raw_templates(vec![
+                ("login.html", include_str!("../templates/login.html.tera")),
+                (
+                    "management.html",
+                    include_str!("../templates/management.html.tera"),
+                ),
+            ])
+            .expect("Could not load templates");
+    })
+}
+
+#[rocket::main]
+async fn main() {
+    let args: AnifunnelArgs = config::parse();
+    match args.command {
+        None | Some(cli::Command::Serve) => {}
+        Some(command) => std::process::exit(cli::run(command).await),
+    }
+
+    logging::init(SimpleLogger::new().with_level(LevelFilter::Info).env()).unwrap();
+    let anilist_api = anilist::AnilistApi::new(
+        &args.anilist_url,
+        &args.anilist_client_name,
+        args.anilist_contact.as_deref(),
+    );
+    let match_settings = anilist::MatchSettings {
+        minimum_confidence: args.minimum_confidence,
+        transliterate_native: args.transliterate_native,
+    };
+
+    let oauth = match (
+        args.anilist_client_id,
+        args.anilist_client_secret,
+        args.anilist_redirect_url,
+    ) {
+        (Some(id), Some(secret), Some(redirect_url)) => Some(anilist::OAuthClient {
+            id,
+            secret,
+            redirect_url,
+        }),
+        (None, None, None) => None,
+        _ => {
+            error!("The Anilist client ID, client secret and redirect URL must all be set.");
+            return;
+        }
+    };
+
+    let trakt = match (args.trakt_client_id, args.trakt_token) {
+        (Some(client_id), Some(token)) => Some(trakt::TraktClient { client_id, token }),
+        (None, None) => None,
+        _ => {
+            error!("Both the Trakt client ID and token must be set.");
+            return;
+        }
+    };
+
+    let plex = match (args.plex_url, args.plex_token) {
+        (Some(url), Some(token)) => Some(plex::PlexServer { url, token }),
+        (None, None) => None,
+        _ => {
+            error!("Both the Plex URL and token must be set.");
+            return;
+        }
+    };
+
+    let tls = match (args.tls_cert, args.tls_key) {
+        (Some(cert), Some(key)) => Some(rocket::config::TlsConfig::from_paths(cert, key)),
+        (None, None) => None,
+        _ => {
+            error!("Both the TLS certificate and key must be set.");
+            return;
+        }
+    };
+
+    let notifiers = match &args.config {
+        Some(path) => match config::notifiers(path) {
+            Ok(notifiers) => notifiers,
+            Err(message) => {
+                error!("{}", message);
+                return;
+            }
+        },
+        None => Vec::new(),
+    };
+
+    let overrides: Option<data::api::Import> = match &args.overrides_json {
+        Some(json) => match serde_json::from_str(json) {
+            Ok(overrides) => Some(overrides),
+            Err(error) => {
+                error!("Could not parse the overrides JSON: {}", error);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let discord = args
+        .discord_webhook_url
+        .map(|url| discord::DiscordWebhook { url });
+
+    let accounts = match args.anilist_token {
+        Some(token) => {
+            let user = match anilist::get_user(&anilist_api, &token).await {
+                Ok(user) => user,
+                Err(anilist::AnilistError::InvalidToken) => {
+                    error!(
+                        "Invalid token. Ensure that you have a valid token. \
+                        Tokens are valid for up to one year from authorization."
+                    );
+                    return;
+                }
+                Err(_) => {
+                    error!("Could not retrieve Anilist user.");
+                    return;
+                }
+            };
+            data::state::Accounts::new(user.name.clone(), token, user)
+        }
+        None if oauth.is_some() => {
+            warn!("No Anilist token given, authorize anifunnel at /auth/anilist");
+            data::state::Accounts::new(String::new(), String::new(), anilist::User::default())
+        }
+        None if args.replicate_from.is_some() => {
+            warn!("No Anilist token given, using the tokens of the primary instance");
+            data::state::Accounts::new(String::new(), String::new(), anilist::User::default())
+        }
+        None => {
+            error!("An Anilist token, an Anilist API client or a primary instance is required.");
+            return;
+        }
+    };
+
+    // Ignore empty keys, which would otherwise be created by empty environment variables.
+    let admin_api_keys: Vec<String> = args
+        .admin_api_keys
+        .into_iter()
+        .filter(|x| !x.is_empty())
+        .collect();
+    let read_only_api_keys: Vec<String> = args
+        .read_only_api_keys
+        .into_iter()
+        .filter(|x| !x.is_empty())
+        .collect();
+    if args.admin_password.is_none()
+        && !(admin_api_keys.is_empty() && read_only_api_keys.is_empty())
+    {
+        warn!("API keys have no effect without an admin password");
+    }
+
+    let state = data::state::Global {
+        multi_season: args.multi_season,
+        movies: args.movies,
+        inactive_lists: args.inactive_lists,
+        cache_warmed: AtomicBool::new(!args.warm_cache),
+        sync_ratings: args.sync_ratings,
+        remember_resolved: args.remember_resolved,
+        plex_user: args.plex_user,
+        plex_servers: args.plex_servers,
+        plex_libraries: args.plex_libraries,
+        account_filter: args.account_filter,
+        base_path: args.base_path.clone(),
+        trusted_proxies: args.trusted_proxies,
+        scrobble_debounce: args.scrobble_debounce.map(Duration::from_secs),
+        recent_scrobbles: RwLock::new(data::state::RecentScrobbles::new()),
+        rewatch_policy: args.rewatch,
+        conflict_policy: args.progress_conflict,
+        rewatches: RwLock::new(data::state::Rewatches::new()),
+        conflicts: RwLock::new(data::state::ProgressConflicts::new()),
+        accounts: RwLock::new(accounts),
+        webhook_token: args.webhook_token,
+        admin_password: args.admin_password,
+        admin_api_keys,
+        read_only_api_keys,
+        admin_sessions: RwLock::new(data::state::AdminSessions::new()),
+        oauth,
+        anilist: anilist_api.clone(),
+        match_settings,
+        trakt,
+        plex,
+        discord,
+        discord_events: RwLock::new(data::state::DiscordEvents::new(&args.discord_events)),
+        notifiers: RwLock::new(data::state::Notifiers::new(notifiers)),
+        authorizations: RwLock::new(data::state::PendingAuthorizations::new()),
+        title_overrides: RwLock::new(data::state::TitleOverrides::new()),
+        account_title_overrides: RwLock::new(data::state::AccountTitleOverrides::new()),
+        title_patterns: RwLock::new(data::state::TitlePatterns::new()),
+        guid_overrides: RwLock::new(data::state::GuidOverrides::new()),
+        relations: RwLock::new(anilist::Relations::new()),
+        media_details: RwLock::new(data::state::MediaDetailsCache::new()),
+        readiness: RwLock::new(data::state::ReadinessCache::new()),
+        title_indexes: RwLock::new(data::state::TitleIndexes::new()),
+        mutations: anilist::MutationQueue::new(anilist_api.clone()),
+        episode_offsets: RwLock::new(data::state::EpisodeOverrides::new()),
+        mutes: RwLock::new(data::state::Mutes::new()),
+        minimum_confidences: RwLock::new(data::state::MinimumConfidences::new()),
+        log_only: RwLock::new(data::state::LogOnly::new()),
+        ignored_ratings: RwLock::new(data::state::IgnoredRatings::new()),
+        ignored: RwLock::new(data::state::IgnoredEntries::new()),
+        special_overrides: RwLock::new(data::state::SpecialOverrides::new()),
+        season_mappings: RwLock::new(data::state::SeasonMappings::new()),
+        override_versions: RwLock::new(data::state::OverrideVersions::new()),
+        minimum_watch_time: args.minimum_watch_time,
+        sessions: RwLock::new(data::state::WatchSessions::new()),
+        activity: RwLock::new(data::state::Activity::new()),
+        notifications: RwLock::new(data::state::Notifications::new()),
+        maintenance: RwLock::new(data::state::Maintenance::new()),
+        sync_pause: RwLock::new(data::state::SyncPause::default()),
+        replication: RwLock::new(data::state::Replication::new(args.replicate_from.clone())),
+        webhook_limit: data::state::WebhookLimit::new(
+            args.max_pending_webhooks.map(|x| x as usize),
+        ),
+        history: RwLock::new(data::state::History::new()),
+        failed_payloads: RwLock::new(data::state::FailedPayloads::new()),
+        unmatched: RwLock::new(data::state::Unmatched::new()),
+        anidb_mapping: Arc::new(RwLock::new(anidb::AnidbMapping::new())),
+    };
+    let state = Arc::new(state);
+    if let Some(overrides) = overrides {
+        let summary = seed_overrides(&state, overrides).await;
+        info!(
+            "Loaded {} overrides from the overrides JSON ({} invalid)",
+            summary.imported, summary.invalid
+        );
+    }
+    let mut anidb_loaded = None;
+    if let Some(source) = args.anidb_mapping {
+        let (first_load, loaded) = oneshot::channel();
+        anidb_loaded = Some(loaded);
+        tokio::spawn(anidb::refresh(
+            source,
+            state.anidb_mapping.clone(),
+            Some(first_load),
+        ));
+    }
+    if args.warm_cache {
+        tokio::spawn(warm_cache(state.clone(), anidb_loaded));
+    }
+    tokio::spawn(probe_schema(state.clone()));
+    if let Some(primary) = args.replicate_from {
+        info!("Running as a standby of {}", primary);
+        tokio::spawn(replication::run(
+            primary,
+            args.replicate_api_key,
+            Duration::from_secs(args.replicate_interval),
+            state.clone(),
+        ));
+    }
+    let account = state.account().await;
+    check_token_expiry(&state, &account.token).await;
+
+    // Because Rocket *requires* a template directory even though we are embedding our
+    // single template inside the binary, we need to make a dummy directory for anifunnel.
+    let dir = tempdir().unwrap();
+
+    let limits = build_limits(
+        args.string_limit,
+        args.form_limit,
+        args.data_form_limit,
+        args.json_limit,
+    );
+
+    // Launch the web server.
+    let figment = rocket::Config::figment()
+        .merge(("limits", limits))
+        .merge(("port", args.port))
+        .merge(("address", args.bind_address))
+        .merge(("template_dir", dir.path()))
+        .merge(("shutdown.grace", args.shutdown_timeout));
+    let figment = match tls {
+        Some(tls) => figment.merge(("tls", tls)),
+        None => figment,
+    };
+    let rocket = match args.rate_limit {
+        Some(rate_limit) => rocket::custom(figment).attach(access::RateLimit::new(rate_limit)),
+        None => rocket::custom(figment),
+    };
+    let rocket = rocket
+        .manage(state)
+        .mount(
+            match args.base_path.as_str() {
+                "" => "/",
+                base_path => base_path,
+            },
+            routes![
+                healthz,
+                readyz,
+                prometheus_metrics,
+                json_metrics,
+                user,
+                tokens,
+                token_add,
+                token_activate,
+                anilist_authorize,
+                anilist_callback,
+                system_status,
+                config_summary,
+                notifications,
+                notification_read,
+                notifications_read,
+                notifiers_list,
+                notifier_add,
+                notifier_edit,
+                notifier_delete,
+                history,
+                live_events,
+                sync_status,
+                progress_overview,
+                conflicts,
+                conflict_dismiss,
+                selftest_scrobble,
+                sync_catchup,
+                stats_activity,
+                unmatched,
+                unmatched_resolve,
+                anime_apply_unmatched,
+                maintenance,
+                replication_status,
+                replication_snapshot,
+                discord_events,
+                discord_events_edit,
+                replication_promote,
+                sync_pause,
+                sync_resume,
+                relations_refresh,
+                debug_bundle,
+                replay,
+                match_test,
+                rewatches,
+                overrides_search,
+                anilist_search,
+                title_override_add,
+                title_override_remove,
+                export,
+                import,
+                sessions,
+                now_watching,
+                scrobble,
+                login_page,
+                login,
+                logout,
+                management,
+                management_edit,
+                anime_bulk_edit,
+                anime_details,
+                anime_overrides,
+                management_login,
+                management_redirect,
+                anime_progress,
+                anime_specials,
+                specials,
+                season_mappings,
+                season_mapping_add,
+                season_mapping_remove
+            ],
+        )
+        .register(
+            "/",
+            catchers![
+                unauthorized,
+                not_found,
+                unprocessable_entity,
+                internal_server_error
+            ],
+        )
+        .attach(metrics::RequestMetrics)
+        .attach(access::Cors::new(args.cors_origins))
+        .attach(config_summary_log())
+        .attach(shutdown_drain(Duration::from_secs(
+            args.shutdown_timeout.into(),
+        )))
+        .attach(templates());
+    let _ = rocket.launch().await;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rocket::http::{Accept, ContentType, Header};
+    use rocket::local::blocking::Client;
+    use test_case::test_case;
+
+    fn build_state() -> data::state::Global {
+        return data::state::Global {
+            multi_season: false,
+            movies: false,
+            inactive_lists: false,
+            cache_warmed: AtomicBool::new(true),
+            sync_ratings: false,
+            remember_resolved: false,
+            plex_user: None,
+            plex_servers: vec![],
+            plex_libraries: vec![],
+            account_filter: plex::AccountFilter::All,
+            base_path: String::new(),
+            trusted_proxies: vec![],
+            scrobble_debounce: None,
+            recent_scrobbles: RwLock::new(data::state::RecentScrobbles::new()),
+            rewatch_policy: data::state::RewatchPolicy::Ignore,
+            conflict_policy: data::state::ConflictPolicy::Skip,
+            rewatches: RwLock::new(data::state::Rewatches::new()),
+            conflicts: RwLock::new(data::state::ProgressConflicts::new()),
+            accounts: RwLock::new(data::state::Accounts::new(
+                String::from("A"),
+                String::from("A"),
+                anilist::User {
+                    id: 1,
+                    name: String::from("A"),
+                    ..Default::default()
+                },
+            )),
+            webhook_token: None,
+            admin_password: None,
+            admin_api_keys: vec![],
+            read_only_api_keys: vec![],
+            admin_sessions: RwLock::new(data::state::AdminSessions::new()),
+            oauth: None,
+            anilist: anilist::AnilistApi::default(),
+            match_settings: anilist::MatchSettings::default(),
+            trakt: None,
+            plex: None,
+            discord: None,
+            discord_events: RwLock::new(data::state::DiscordEvents::new(&[])),
+            notifiers: RwLock::new(data::state::Notifiers::new(Vec::new())),
+            authorizations: RwLock::new(data::state::PendingAuthorizations::new()),
+            title_overrides: RwLock::new(data::state::TitleOverrides::new()),
+            account_title_overrides: RwLock::new(data::state::AccountTitleOverrides::new()),
+            title_patterns: RwLock::new(data::state::TitlePatterns::new()),
+            guid_overrides: RwLock::new(data::state::GuidOverrides::new()),
+            relations: RwLock::new(anilist::Relations::new()),
+            media_details: RwLock::new(data::state::MediaDetailsCache::new()),
+            readiness: RwLock::new(data::state::ReadinessCache::new()),
+            title_indexes: RwLock::new(data::state::TitleIndexes::new()),
+            mutations: anilist::MutationQueue::default(),
+            episode_offsets: RwLock::new(data::state::EpisodeOverrides::new()),
+            mutes: RwLock::new(data::state::Mutes::new()),
+            minimum_confidences: RwLock::new(data::state::MinimumConfidences::new()),
+            log_only: RwLock::new(data::state::LogOnly::new()),
+            ignored_ratings: RwLock::new(data::state::IgnoredRatings::new()),
+            ignored: RwLock::new(data::state::IgnoredEntries::new()),
+            special_overrides: RwLock::new(data::state::SpecialOverrides::new()),
+            season_mappings: RwLock::new(data::state::SeasonMappings::new()),
+            override_versions: RwLock::new(data::state::OverrideVersions::new()),
+            minimum_watch_time: None,
+            sessions: RwLock::new(data::state::WatchSessions::new()),
+            activity: RwLock::new(data::state::Activity::new()),
+            notifications: RwLock::new(data::state::Notifications::new()),
+            maintenance: RwLock::new(data::state::Maintenance::new()),
+            sync_pause: RwLock::new(data::state::SyncPause::default()),
+            replication: RwLock::new(data::state::Replication::new(None)),
+            webhook_limit: data::state::WebhookLimit::new(None),
+            history: RwLock::new(data::state::History::new()),
+            failed_payloads: RwLock::new(data::state::FailedPayloads::new()),
+            unmatched: RwLock::new(data::state::Unmatched::new()),
+            anidb_mapping: Arc::new(RwLock::new(anidb::AnidbMapping::new())),
+        };
+    }
+
+    fn build_client() -> Client {
+        let rocket = rocket::build()
+            .manage(Arc::new(build_state()))
+            .mount(
+                "/",
+                routes![
+                    healthz,
+                    tokens,
+                    token_add,
+                    token_activate,
+                    anilist_authorize,
+                    anilist_callback,
+                    system_status,
+                    config_summary,
+                    notifications,
+                    notification_read,
+                    notifications_read,
+                    notifiers_list,
+                    notifier_add,
+                    notifier_edit,
+                    notifier_delete,
+                    history,
+                    live_events,
+                    sync_status,
+                    progress_overview,
+                    conflicts,
+                    conflict_dismiss,
+                    selftest_scrobble,
+                    sync_catchup,
+                    stats_activity,
+                    unmatched,
+                    unmatched_resolve,
+                    anime_apply_unmatched,
+                    maintenance,
+                    replication_status,
+                    replication_snapshot,
+                    discord_events,
+                    discord_events_edit,
+                    replication_promote,
+                    sync_pause,
+                    sync_resume,
+                    debug_bundle,
+                    replay,
+                    match_test,
+                    overrides_search,
+                    anilist_search,
+                    title_override_add,
+                    title_override_remove,
+                    export,
+                    import,
+                    sessions,
+                    now_watching,
+                    scrobble,
+                    management_edit,
+                    anime_bulk_edit,
+                    anime_details,
+                    anime_overrides,
+                    management_redirect,
+                    anime_progress,
+                    anime_specials,
+                    specials,
+                    season_mappings,
+                    season_mapping_add,
+                    season_mapping_remove
+                ],
+            )
+            .register(
+                "/",
+                catchers![
+                    unauthorized,
+                    not_found,
+                    unprocessable_entity,
+                    internal_server_error
+                ],
+            );
+        return Client::tracked(rocket).expect("valid rocket instance");
+    }
+
+    #[test]
+    fn healthz() {
+        let client = build_client();
+        let response = client.get(uri!(healthz)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().unwrap(), "{\"status\":\"ok\"}");
+    }
+
+    #[test]
+    fn readyz_cached() {
+        let state = build_state();
+        state
+            .readiness
+            .blocking_write()
+            .insert("A", String::from("A"));
+        let rocket = rocket::build()
+            .manage(Arc::new(state))
+            .mount("/", routes![readyz]);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let response = client.get(uri!(readyz)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_string().unwrap(),
+            "{\"status\":\"ok\",\"user\":\"A\"}"
+        );
+    }
+
+    #[rocket::async_test]
+    async fn readyz_warm_cache() {
+        let list = "{\"data\": {\"MediaListCollection\": {\"lists\": [{\"entries\": [\
+            {\"id\": 98444, \"progress\": 1, \"media\": {\"id\": 98444, \
+            \"title\": {\"romaji\": \"Yuru Camp\", \"userPreferred\": \"Yuru Camp\"}}}]}]}}}";
+        let (api, _) = anilist::fake::serve(move |_| String::from(list));
+        let state = Arc::new(data::state::Global {
+            anilist: api,
+            cache_warmed: AtomicBool::new(false),
+            ..build_state()
+        });
+        state.readiness.write().await.insert("A", String::from("A"));
+        let rocket = rocket::build()
+            .manage(state.clone())
+            .mount("/", routes![readyz]);
+        let client = rocket::local::asynchronous::Client::tracked(rocket)
+            .await
+            .expect("valid rocket instance");
+        let response = client.get(uri!(readyz)).dispatch().await;
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        assert_eq!(
+            response.into_string().await.unwrap(),
+            "{\"status\":\"error\",\"reason\":\"warming up\"}"
+        );
+        let (first_load, anidb_loaded) = oneshot::channel();
+        first_load.send(()).unwrap();
+        warm_cache(state.clone(), Some(anidb_loaded)).await;
+        let watching_list: anilist::MediaListGroup =
+            serde_json::from_str::<serde_json::Value>(list)
+                .map(|x| {
+                    serde_json::from_value(x["data"]["MediaListCollection"]["lists"][0].clone())
+                })
+                .unwrap()
+                .unwrap();
+        assert!(state
+            .title_indexes
+            .read()
+            .await
+            .get(watching_list.title_fingerprint())
+            .is_some());
+        let response = client.get(uri!(readyz)).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[test_case("Mushoku Tensei S2", "1", Some(146065), Some(1) ; "title, episode offset")]
+    #[test_case("Mushoku Tensei S2", "", Some(146065), None ; "title, no episode offset")]
+    #[test_case("", "1", None, Some(1) ; "no title, episode_offset")]
+    #[test_case("", "", None, None ; "no title, no episode offset")]
+    fn management_edit_add(
+        title: &str,
+        episode_offset: &str,
+        expected_title_override: Option<i32>,
+        expected_episode_offset: Option<i32>,
+    ) {
+        let client = build_client();
+        let request = client
+            .post(uri!(management_edit(146065)))
+            .header(ContentType::Form)
+            .body(format!("title={}&episode_offset={}", title, episode_offset));
+        let state = request
+            .rocket()
+            .state::<Arc<data::state::Global>>()
+            .unwrap();
+        let response = request.dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        assert_eq!(
+            state
+                .title_overrides
+                .blocking_read()
+                .get(&String::from("Mushoku Tensei S2")),
+            expected_title_override
+        );
+        assert_eq!(
+            state.episode_offsets.blocking_read().get(&146065),
+            expected_episode_offset
+        );
+    }
+
+    #[test_case("Mushoku Tensei S2", "", Some(146065), None ; "title, no episode offset")]
+    #[test_case("", "1", None, Some(1) ; "no title, episode_offset")]
+    #[test_case("", "", None, None ; "no title, no episode offset")]
+    fn management_edit_remove(
+        title: &str,
+        episode_offset: &str,
+        expected_title_override: Option<i32>,
+        expected_episode_offset: Option<i32>,
+    ) {
+        let client = build_client();
+        let request = client
+            .post(uri!(management_edit(146065)))
+            .header(ContentType::Form)
+            .body(format!("title={}&episode_offset={}", title, episode_offset));
+        let state = request
+            .rocket()
+            .state::<Arc<data::state::Global>>()
+            .unwrap();
+        state
+            .title_overrides
+            .blocking_write()
+            .set(String::from("Mushoku Tensei S2"), 146065);
+        state.episode_offsets.blocking_write().set(146065, 1);
+        let response = request.dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        assert_eq!(
+            state
+                .title_overrides
+                .blocking_read()
+                .get(&String::from("Mushoku Tensei S2")),
+            expected_title_override
+        );
+        assert_eq!(
+            state.episode_offsets.blocking_read().get(&146065),
+            expected_episode_offset
+        );
+    }
+
+    #[test]
+    fn sessions() {
+        let client = build_client();
+        let response = client
+            .post(uri!(scrobble))
+            .header(ContentType::Form)
+            .body(
+                "payload={\"event\": \"media.play\", \"Metadata\": {\
+                \"type\": \"episode\", \"grandparentTitle\": \"Onii-chan wa Oshimai!\", \
+                \"parentIndex\": 1, \"index\": 2, \"ratingKey\": \"1234\", \"duration\": 1440000, \
+                \"viewOffset\": 360000}, \"Account\": {\"title\": \"yukikaze\"}, \
+                \"Player\": {\"uuid\": \"abcdef\"}}",
+            )
+            .dispatch();
+        assert_eq!(response.into_string().unwrap(), "NO OP");
+        let response = client.get(uri!(now_watching)).dispatch();
+        assert_eq!(
+            response.into_string().unwrap(),
+            "[{\"account\":\"yukikaze\",\"title\":\"Onii-chan wa Oshimai!\",\"season_number\":1,\
+            \"episode_number\":2,\"progress\":0.25}]"
+        );
+        let response = client.get(uri!(sessions)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_string().unwrap(),
+            "[{\"key\":\"abcdef:1234\",\"title\":\"Onii-chan wa Oshimai!\",\"season_number\":1,\
+            \"episode_number\":2,\"playing\":true,\"watched_seconds\":0,\"progress\":0.25,\
+            \"duration_seconds\":1440}]"
+        );
+    }
+
+    #[test]
+    fn admin_password() {
+        let state = data::state::Global {
+            admin_password: Some(String::from("hunter2")),
+            ..build_state()
+        };
+        let rocket = rocket::build()
+            .manage(Arc::new(state))
+            .mount(
+                "/",
+                routes![login, logout, management_edit, management_login, sessions],
+            )
+            .attach(templates());
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+
+        let response = client.get(uri!(sessions)).dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = client.get("/admin").dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        assert_eq!(response.headers().get_one("Location"), Some("/login"));
+        let response = client
+            .post(uri!(management_edit(146065)))
+            .header(ContentType::Form)
+            .body("title=Mushoku Tensei S2&episode_offset=1")
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client
+            .post(uri!(login))
+            .header(ContentType::Form)
+            .body("password=hunter3")
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert!(response
+            .cookies()
+            .get(data::guards::ADMIN_SESSION_COOKIE)
+            .is_none());
+
+        let response = client
+            .post(uri!(login))
+            .header(ContentType::Form)
+            .body("password=hunter2")
+            .dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        assert!(response
+            .cookies()
+            .get(data::guards::ADMIN_SESSION_COOKIE)
+            .is_some());
+        let response = client.get(uri!(sessions)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client.post(uri!(logout)).dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        let response = client.get(uri!(sessions)).dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[test_case("Bearer admin", Status::Ok, Status::SeeOther ; "admin key")]
+    #[test_case("Bearer dashboard", Status::Ok, Status::Forbidden ; "read-only key")]
+    #[test_case("Bearer invalid", Status::Unauthorized, Status::Unauthorized ; "invalid key")]
+    #[test_case("admin", Status::Unauthorized, Status::Unauthorized ; "not bearer")]
+    fn api_keys(authorization: &str, expected_read: Status, expected_write: Status) {
+        let state = data::state::Global {
+            admin_password: Some(String::from("hunter2")),
+            admin_api_keys: vec![String::from("admin")],
+            read_only_api_keys: vec![String::from("dashboard")],
+            ..build_state()
+        };
+        let rocket = rocket::build()
+            .manage(Arc::new(state))
+            .mount("/", routes![management_edit, sessions]);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let authorization = Header::new("Authorization", authorization.to_string());
+        let response = client
+            .get(uri!(sessions))
+            .header(authorization.clone())
+            .dispatch();
+        assert_eq!(response.status(), expected_read);
+        let response = client
+            .post(uri!(management_edit(146065)))
+            .header(ContentType::Form)
+            .header(authorization)
+            .body("title=Mushoku Tensei S2&episode_offset=1")
+            .dispatch();
+        assert_eq!(response.status(), expected_write);
+    }
+
+    #[test]
+    fn prometheus_metrics() {
+        let rocket = rocket::build()
+            .manage(Arc::new(build_state()))
+            .mount("/", routes![healthz, prometheus_metrics])
+            .attach(metrics::RequestMetrics);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        client.get(uri!(healthz)).dispatch();
+        let response = client.get(uri!(prometheus_metrics)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_string().unwrap().contains(
+            "anifunnel_http_request_duration_seconds_count{method=\"GET\",route=\"/healthz\"} 1\n"
+        ));
+    }
+
+    #[test]
+    fn rate_limit_api() {
+        let rocket = rocket::build()
+            .manage(Arc::new(build_state()))
+            .mount("/", routes![healthz, system_status])
+            .attach(access::RateLimit::new(1));
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let remote: std::net::SocketAddr = "192.0.2.1:32400".parse().unwrap();
+        let request = |uri: Origin<'static>| client.get(uri).remote(remote).dispatch();
+        assert_eq!(request(uri!(system_status)).status(), Status::Ok);
+        let response = request(uri!(system_status));
+        assert_eq!(response.status(), Status::TooManyRequests);
+        assert!(response.headers().get_one("Retry-After").is_some());
+        assert_eq!(request(uri!(healthz)).status(), Status::Ok);
+    }
+
+    #[test]
+    fn cors_preflight() {
+        let rocket = rocket::build()
+            .manage(Arc::new(build_state()))
+            .mount("/", routes![system_status])
+            .attach(access::Cors::new(vec![String::from("https://example.com")]));
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let response = client
+            .options(uri!(system_status))
+            .header(Header::new("Origin", "https://example.com"))
+            .header(Header::new("Access-Control-Request-Method", "GET"))
+            .dispatch();
+        assert_eq!(response.status(), Status::NoContent);
+        assert_eq!(
+            response.headers().get_one("Access-Control-Allow-Origin"),
+            Some("https://example.com")
+        );
+        let response = client
+            .get(uri!(system_status))
+            .header(Header::new("Origin", "https://example.org"))
+            .dispatch();
+        assert_eq!(
+            response.headers().get_one("Access-Control-Allow-Origin"),
+            None
+        );
+    }
+
+    #[test]
+    fn json_metrics() {
+        let rocket = rocket::build()
+            .manage(Arc::new(build_state()))
+            .mount("/", routes![healthz, json_metrics])
+            .attach(metrics::RequestMetrics);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        client.get(uri!(healthz)).dispatch();
+        let response = client.get(uri!(json_metrics)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let snapshot: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(snapshot["routes"][0]["route"], "/healthz");
+        assert_eq!(snapshot["routes"][0]["requests"], 1);
+    }
+
+    #[test]
+    fn user() {
+        let state = data::state::Global {
+            accounts: RwLock::new(data::state::Accounts::new(
+                String::from("A"),
+                String::from("A"),
+                anilist::User {
+                    id: 1,
+                    name: String::from("A"),
+                    avatar: Some(anilist::UserAvatar {
+                        large: Some(String::from("https://example.com/avatar.png")),
+                    }),
+                    options: Some(anilist::UserOptions {
+                        profileColor: Some(String::from("blue")),
+                    }),
+                    mediaListOptions: Some(anilist::MediaListOptions {
+                        scoreFormat: Some(String::from("POINT_10")),
+                    }),
+                },
+            )),
+            ..build_state()
+        };
+        let rocket = rocket::build()
+            .manage(Arc::new(state))
+            .mount("/", routes![user]);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let response = client.get(uri!(user)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_string().unwrap(),
+            "{\"id\":1,\"name\":\"A\",\"avatar\":\"https://example.com/avatar.png\",\
+            \"profile_color\":\"blue\",\"score_format\":\"POINT_10\"}"
+        );
+    }
+
+    #[test]
+    fn management_edit_guid() {
+        let client = build_client();
+        let request = client
+            .post(uri!(management_edit(146065)))
+            .header(ContentType::Form)
+            .body("title=&episode_offset=&guid=plex://show/1");
+        let state = request
+            .rocket()
+            .state::<Arc<data::state::Global>>()
+            .unwrap();
+        let response = request.dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        assert_eq!(
+            state
+                .guid_overrides
+                .blocking_read()
+                .get(&String::from("plex://show/1")),
+            Some(146065)
+        );
+    }
+
+    #[test_case("%5EYuru%20Camp", Status::SeeOther, Some(146065) ; "valid pattern")]
+    #[test_case("Yuru%20Camp%20(", Status::UnprocessableEntity, None ; "invalid pattern")]
+    fn management_edit_pattern(pattern: &str, expected_status: Status, expected_id: Option<i32>) {
+        let client = build_client();
+        let request = client
+            .post(uri!(management_edit(146065)))
+            .header(ContentType::Form)
+            .body(format!("title=&episode_offset=&pattern={}", pattern));
+        let state = request
+            .rocket()
+            .state::<Arc<data::state::Global>>()
+            .unwrap();
+        let response = request.dispatch();
+        assert_eq!(response.status(), expected_status);
+        assert_eq!(
+            state.title_patterns.blocking_read().get("Yuru Camp (2018)"),
+            expected_id
+        );
+    }
+
+    #[test_case("2999-12-31", true ; "future date")]
+    #[test_case("", false ; "no date")]
+    fn management_edit_mute(muted_until: &str, expected_muted: bool) {
+        let client = build_client();
+        let request = client
+            .post(uri!(management_edit(146065)))
+            .header(ContentType::Form)
+            .body(format!(
+                "title=&episode_offset=&muted_until={}",
+                muted_until
+            ));
+        let state = request
+            .rocket()
+            .state::<Arc<data::state::Global>>()
+            .unwrap();
+        let response = request.dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        assert_eq!(
+            state.mutes.blocking_read().get(&146065).is_some(),
+            expected_muted
+        );
+    }
+
+    #[test_case(None, "", Status::SeeOther ; "no precondition")]
+    #[test_case(Some("\"1\""), "", Status::SeeOther ; "current version")]
+    #[test_case(Some("*"), "", Status::SeeOther ; "any version")]
+    #[test_case(Some("\"0\""), "", Status::PreconditionFailed ; "outdated version")]
+    #[test_case(None, "&version=0", Status::PreconditionFailed ; "outdated form version")]
+    #[test_case(Some("abc"), "", Status::BadRequest ; "invalid version")]
+    fn management_edit_version(if_match: Option<&str>, version: &str, expected_status: Status) {
+        let client = build_client();
+        let edit = |if_match: Option<&str>, version: &str| {
+            let mut request = client
+                .post(uri!(management_edit(146065)))
+                .header(ContentType::Form)
+                .body(format!("title=Mushoku Tensei S2{}", version));
+            if let Some(if_match) = if_match {
+                request = request.header(Header::new("If-Match", if_match.to_string()));
+            }
+            request.dispatch()
+        };
+        let response = edit(None, "");
+        assert_eq!(response.headers().get_one("ETag"), Some("\"1\""));
+        let response = edit(if_match, version);
+        assert_eq!(response.status(), expected_status);
+        let response = client.get(uri!(anime_overrides(146065))).dispatch();
+        let expected_version = if expected_status == Status::SeeOther {
+            2
+        } else {
+            1
+        };
+        assert_eq!(
+            response.headers().get_one("ETag"),
+            Some(format!("\"{}\"", expected_version).as_str())
+        );
+        let overrides: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(overrides["title"], "Mushoku Tensei S2");
+        assert_eq!(overrides["version"], expected_version);
+    }
+
+    #[test_case("0.6", Status::SeeOther, Some(0.6) ; "valid")]
+    #[test_case("", Status::SeeOther, None ; "empty")]
+    #[test_case("1.5", Status::UnprocessableEntity, None ; "over one")]
+    fn management_edit_minimum_confidence(
+        minimum_confidence: &str,
+        expected_status: Status,
+        expected_minimum_confidence: Option<f64>,
+    ) {
+        let client = build_client();
+        let request = client
+            .post(uri!(management_edit(146065)))
+            .header(ContentType::Form)
+            .body(format!("minimum_confidence={}", minimum_confidence));
+        let state = request
+            .rocket()
+            .state::<Arc<data::state::Global>>()
+            .unwrap();
+        let response = request.dispatch();
+        assert_eq!(response.status(), expected_status);
+        assert_eq!(
+            state.minimum_confidences.blocking_read().get(&146065),
+            expected_minimum_confidence
+        );
+    }
+
+    #[test_case("0.75", Ok(0.75) ; "valid")]
+    #[test_case("-0.1", Err(String::from("must be between 0 and 1")) ; "negative")]
+    #[test_case("high", Err(String::from("not a number")) ; "not a number")]
+    fn minimum_confidence_argument(value: &str, expected: Result<f64, String>) {
+        assert_eq!(parse_confidence(value), expected);
+    }
+
+    #[test_case("log_only=on", true ; "checked")]
+    #[test_case("log_only=true", true ; "true value")]
+    #[test_case("title=", false ; "unchecked")]
+    fn management_edit_log_only(body: &str, expected_log_only: bool) {
+        let client = build_client();
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        state.log_only.blocking_write().set(146065, true);
+        let response = client
+            .post(uri!(management_edit(146065)))
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        assert_eq!(
+            state.log_only.blocking_read().contains(&146065),
+            expected_log_only
+        );
+    }
+
+    #[test]
+    fn management_edit_ignore_ratings() {
+        let client = build_client();
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        let response = client
+            .post(uri!(management_edit(146065)))
+            .header(ContentType::Form)
+            .body("ignore_ratings=on")
+            .dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        assert!(state.ignored_ratings.blocking_read().contains(&146065));
+        assert!(!state.log_only.blocking_read().contains(&146065));
+    }
+
+    #[test]
+    fn management_edit_ignored() {
+        let client = build_client();
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        let response = client
+            .post(uri!(management_edit(146065)))
+            .header(ContentType::Form)
+            .body("ignored=on")
+            .dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        assert!(state.ignored.blocking_read().contains(&146065));
+        assert!(!state.ignored_ratings.blocking_read().contains(&146065));
+        let response = client.get(uri!(anime_overrides(146065))).dispatch();
+        let overrides: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(overrides["ignored"], true);
+    }
+
+    #[test]
+    fn anime_bulk_edit() {
+        let client = build_client();
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        let response = client
+            .post(uri!(anime_bulk_edit))
+            .header(ContentType::JSON)
+            .body(
+                "[{\"anilist_id\": 146065, \"title\": \"Mushoku Tensei S2\"}, \
+                {\"anilist_id\": 98444, \"episode_offset\": -12, \"muted_until\": \"2999-12-31\"}]",
+            )
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let results: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(results[1]["anilist_id"], 98444);
+        assert_eq!(results[1]["version"], 1);
+        assert_eq!(state.episode_offsets.blocking_read().get(&98444), Some(-12));
+
+        // One invalid edit rejects the whole batch.
+        let response = client
+            .post(uri!(anime_bulk_edit))
+            .header(ContentType::JSON)
+            .body(
+                "[{\"anilist_id\": 146065}, {\"anilist_id\": 98444, \"version\": 0}, \
+                {\"anilist_id\": 1, \"pattern\": \"(\"}, {\"anilist_id\": 1}]",
+            )
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let errors: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(
+            errors
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|x| x["index"].as_u64().unwrap())
+                .collect::<Vec<u64>>(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(
+            state
+                .title_overrides
+                .blocking_read()
+                .get(&String::from("Mushoku Tensei S2")),
+            Some(146065)
+        );
+    }
+
+    #[test]
+    fn management_redirect() {
+        let client = build_client();
+        let response = client.get(uri!(management_redirect)).dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        assert_eq!(response.headers().get_one("Location"), Some("/admin"));
+    }
+
+    #[test]
+    fn anime_progress_negative() {
+        let client = build_client();
+        let response = client
+            .post(uri!(anime_progress(id = 146065)))
+            .header(ContentType::Form)
+            .body("progress=-1")
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        assert_eq!(
+            response.into_string().unwrap(),
+            "{\"status\":422,\"error\":\"Unprocessable Entity\"}"
+        );
+    }
+
+    #[test]
+    fn anime_progress_decrease() {
+        // Anilist is past the progress that is set, which only scrobbles avoid.
+        let (api, requests) = anilist::fake::serve(|body| {
+            if body.contains("SaveMediaListEntry") {
+                return String::from("{\"data\": {\"SaveMediaListEntry\": {\"progress\": 2}}}");
+            }
+            if body.contains("MediaListCollection") {
+                return String::from("{\"data\": {\"MediaListCollection\": {\"lists\": []}}}");
+            }
+            return String::from("{\"data\": {\"MediaList\": {\"progress\": 8}}}");
+        });
+        let state = data::state::Global {
+            mutations: anilist::MutationQueue::new(api),
+            ..build_state()
+        };
+        let rocket = rocket::build()
+            .manage(Arc::new(state))
+            .mount("/", routes![anime_progress]);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let response = client
+            .post(uri!(anime_progress(id = 146065)))
+            .header(ContentType::Form)
+            .body("progress=2")
+            .dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].contains("SaveMediaListEntry"));
+        assert!(!requests.iter().any(|x| x.contains("MediaList(")));
+    }
+
+    #[test]
+    fn catcher_not_found() {
+        let client = build_client();
+        let response = client.get("/nonexistent").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(
+            response.into_string().unwrap(),
+            "{\"status\":404,\"error\":\"Not Found\"}"
+        );
+    }
+
+    #[test_case("/admin", &[("User-Agent", "PlexMediaServer/1.40.0")], Status::NotFound, 1 ; "admin path")]
+    #[test_case("/anifunnel", &[("User-Agent", "PlexMediaServer/1.40.0")], Status::NotFound, 1 ; "sub-path")]
+    #[test_case("/", &[("X-Forwarded-For", "10.0.0.1")], Status::Unauthorized, 2 ; "query string stripped")]
+    #[test_case("/", &[("X-Forwarded-For", "10.0.0.1"), ("X-Forwarded-Proto", "https")], Status::Unauthorized, 1 ; "forwarded proto")]
+    #[test_case("/?token=wrong", &[], Status::Unauthorized, 0 ; "wrong token")]
+    #[test_case("/api/nonexistent", &[("Host", "anifunnel"), ("X-Forwarded-Host", "example.com")], Status::NotFound, 1 ; "forwarded host")]
+    #[test_case("/api/nonexistent", &[], Status::NotFound, 0 ; "no hints")]
+    fn catcher_hints(
+        uri: &str,
+        headers: &[(&'static str, &'static str)],
+        expected_status: Status,
+        expected_hints: usize,
+    ) {
+        let state = data::state::Global {
+            webhook_token: Some(String::from("secret")),
+            ..build_state()
+        };
+        let rocket = rocket::build()
+            .manage(Arc::new(state))
+            .mount("/", routes![scrobble])
+            .register("/", catchers![unauthorized, not_found]);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let mut request = client
+            .post(uri.to_string())
+            .header(ContentType::Form)
+            .body("payload={}");
+        for (name, value) in headers {
+            request = request.header(Header::new(*name, *value));
+        }
+        let response = request.dispatch();
+        assert_eq!(response.status(), expected_status);
+        let error: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(
+            error["hints"].as_array().map_or(0, |x| x.len()),
+            expected_hints
+        );
+    }
+
+    #[test]
+    fn system_status() {
+        let client = build_client();
+        let response = client.get(uri!(system_status)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let status: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(status["last_webhook"], serde_json::Value::Null);
+        client
+            .post(uri!(scrobble))
+            .header(ContentType::Form)
+            .body(
+                "payload={\"event\": \"media.play\", \"Metadata\": {\
+                \"type\": \"episode\", \"grandparentTitle\": \"Onii-chan wa Oshimai!\", \
+                \"parentIndex\": 1, \"index\": 2}, \"Account\": {\"title\": \"yukikaze\"}}",
+            )
+            .dispatch();
+        let response = client.get(uri!(system_status)).dispatch();
+        let status: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert!(status["last_webhook"].is_u64());
+        assert_eq!(status["last_update"], serde_json::Value::Null);
+        assert_eq!(status["token_expires_in"], serde_json::Value::Null);
+        assert_eq!(status["history_sources"], serde_json::json!({}));
+        assert_eq!(status["sync_paused"], false);
+    }
+
+    #[test_case(1 ; "small thumbnail")]
+    #[test_case(3072 ; "large thumbnail")]
+    fn scrobble_multipart_thumbnail(thumbnail_kibibytes: usize) {
+        let limits = build_limits(
+            parse_limit("24KiB").unwrap(),
+            parse_limit("32KiB").unwrap(),
+            parse_limit("10MiB").unwrap(),
+            parse_limit("1MiB").unwrap(),
+        );
+        let figment = rocket::Config::figment().merge(("limits", limits));
+        let rocket = rocket::custom(figment)
+            .manage(Arc::new(build_state()))
+            .mount("/", routes![scrobble]);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let mut body = b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"payload\"\r\n\
+            Content-Type: application/json\r\n\r\n\
+            {\"event\": \"library.new\", \"Metadata\": {\"type\": \"episode\", \
+            \"grandparentTitle\": \"Yuru Camp\"}, \"Account\": {\"title\": \"yukikaze\"}}\r\n\
+            --boundary\r\n\
+            Content-Disposition: form-data; name=\"thumb\"; filename=\"image.jpg\"\r\n\
+            Content-Type: image/jpeg\r\n\r\n"
+            .to_vec();
+        body.extend((0..thumbnail_kibibytes * 1024).map(|x| (x % 256) as u8));
+        body.extend(b"\r\n--boundary--\r\n");
+        let response = client
+            .post(uri!(scrobble))
+            .header(
+                ContentType::new("multipart", "form-data").with_params(("boundary", "boundary")),
+            )
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().unwrap(), "NO OP");
+    }
+
+    #[test_case(ContentType::JSON, "{\"event\": \"library.new\", \"Metadata\": {\"type\": \"episode\"}, \"Account\": {\"title\": \"yukikaze\"}}", "NO OP" ; "json")]
+    #[test_case(ContentType::JSON, "payload=", "ERROR" ; "invalid json")]
+    #[test_case(ContentType::Form, "payload={\"event\": \"library.new\", \"Metadata\": {\"type\": \"episode\"}, \"Account\": {\"title\": \"yukikaze\"}}", "NO OP" ; "form")]
+    fn scrobble_content_type(content_type: ContentType, body: &str, expected: &str) {
+        let client = build_client();
+        let response = client
+            .post(uri!(scrobble))
+            .header(content_type)
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().unwrap(), expected);
+    }
+
+    #[test_case("/", Some(Accept::JSON), "{\"event\": \"library.new\", \"Metadata\": {\"type\": \"episode\"}, \"Account\": {\"title\": \"yukikaze\"}}", "NO OP", "not_actionable" ; "accept header")]
+    #[test_case("/?format=json", None, "{\"event\": \"media.scrobble\", \"Metadata\": {\"type\": \"episode\"}, \"Account\": {\"title\": \"shiranui\"}}", "NO OP", "plex_user" ; "query parameter")]
+    #[test_case("/?format=json", None, "payload=", "ERROR", "invalid_payload" ; "invalid json")]
+    fn scrobble_report(
+        uri: &str,
+        accept: Option<Accept>,
+        body: &str,
+        decision: &str,
+        reason: &str,
+    ) {
+        let state = data::state::Global {
+            plex_user: Some(String::from("yukikaze")),
+            ..build_state()
+        };
+        let rocket = rocket::build()
+            .manage(Arc::new(state))
+            .mount("/", routes![scrobble]);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let mut request = client
+            .post(uri.to_string())
+            .header(ContentType::JSON)
+            .body(body);
+        if let Some(accept) = accept {
+            request = request.header(accept);
+        }
+        let response = request.dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let report: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(report["decision"], decision);
+        assert_eq!(report["reasons"], serde_json::json!([reason]));
+        assert_eq!(report["media"], serde_json::Value::Null);
+    }
+
+    #[test_case("/", "" ; "root")]
+    #[test_case("anifunnel/", "/anifunnel" ; "missing leading slash")]
+    #[test_case("/apps/anifunnel", "/apps/anifunnel" ; "nested")]
+    fn parse_base_path_normalize(value: &str, expected: &str) {
+        assert_eq!(parse_base_path(value).unwrap(), expected);
+    }
+
+    #[test]
+    fn base_path() {
+        let state = data::state::Global {
+            admin_password: Some(String::from("hunter2")),
+            base_path: String::from("/anifunnel"),
+            ..build_state()
+        };
+        let rocket = rocket::build()
+            .manage(Arc::new(state))
+            .mount(
+                "/anifunnel",
+                routes![login, login_page, management_login, scrobble],
+            )
+            .register("/", catchers![not_found])
+            .attach(templates());
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+
+        let response = client.get("/anifunnel/admin").dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        assert_eq!(
+            response.headers().get_one("Location"),
+            Some("/anifunnel/login")
+        );
+        let response = client.get("/anifunnel/login").dispatch();
+        assert!(response
+            .into_string()
+            .unwrap()
+            .contains("action=\"/anifunnel/login\""));
+        let response = client
+            .post("/anifunnel")
+            .header(ContentType::Form)
+            .body("payload={}")
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        // Webhooks sent to the management interface under the base path get the same
+        // hint as without one.
+        let response = client
+            .post("/anifunnel/admin")
+            .header(Header::new("User-Agent", "PlexMediaServer/1.40.0"))
+            .dispatch();
+        let error: serde_json::Value = response.into_json().unwrap();
+        assert!(error["hints"][0].as_str().unwrap().contains("(/admin)"));
+    }
+
+    #[test]
+    fn anilist_search_empty_query() {
+        let client = build_client();
+        let response = client.get(uri!(anilist_search(q = " "))).dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn anime_details_cached() {
+        let client = build_client();
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        let details: anilist::MediaDetails = serde_json::from_str(
+            "{\"id\": 98444, \"format\": \"TV\", \"episodes\": 12, \"season\": \"WINTER\", \
+            \"seasonYear\": 2018, \"status\": \"FINISHED\", \"coverImage\": {\"large\": \
+            \"https://s4.anilist.co/file/anilistcdn/media/anime/cover/large/98444.jpg\", \
+            \"color\": \"#e4a15d\"}, \"title\": {\"romaji\": \"Yuru Camp\", \
+            \"userPreferred\": \"Yuru Camp\"}}",
+        )
+        .unwrap();
+        state.media_details.blocking_write().insert(details);
+        let response = client.get(uri!(anime_details(98444))).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_string().unwrap(),
+            "{\"anilist_id\":98444,\"title\":\"Yuru Camp\",\"format\":\"TV\",\"episodes\":12,\
+            \"season\":\"WINTER\",\"season_year\":2018,\"status\":\"FINISHED\",\"cover_image\":\
+            \"https://s4.anilist.co/file/anilistcdn/media/anime/cover/large/98444.jpg\",\
+            \"cover_color\":\"#e4a15d\"}"
+        );
+    }
+
+    #[test_case("target=https://anilist.co/anime/98444/Yuru-Camp", Status::Ok ; "url")]
+    #[test_case("target=98444", Status::Ok ; "id")]
+    #[test_case("target=Yuru Camp", Status::UnprocessableEntity ; "title")]
+    fn title_override_add_target(body: &str, expected_status: Status) {
+        let client = build_client();
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        let details: anilist::MediaDetails =
+            serde_json::from_str("{\"id\": 98444, \"title\": {\"userPreferred\": \"Yuru Camp\"}}")
+                .unwrap();
+        state.media_details.blocking_write().insert(details);
+        let response = client
+            .post(uri!(title_override_add))
+            .header(ContentType::Form)
+            .body(format!("title=Laid-Back Camp&{}", body))
+            .dispatch();
+        assert_eq!(response.status(), expected_status);
+        let expected_override = (expected_status == Status::Ok).then_some(98444);
+        assert_eq!(
+            state
+                .title_overrides
+                .blocking_read()
+                .get(&String::from("Laid-Back Camp")),
+            expected_override
+        );
+    }
+
+    #[rocket::async_test]
+    async fn live_events_stream() {
+        use rocket::tokio::io::AsyncReadExt;
+
+        let state = Arc::new(build_state());
+        let rocket = rocket::build()
+            .manage(state.clone())
+            .mount("/", routes![live_events]);
+        let client = rocket::local::asynchronous::Client::tracked(rocket)
+            .await
+            .expect("valid rocket instance");
+        let mut response = client.get(uri!(live_events)).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        state.history.write().await.push(data::state::HistoryEntry {
+            timestamp: 0,
+            source: data::state::ScrobbleSource::PlexWebhook,
+            title: String::from("Yuru Camp"),
+            guid: None,
+            season_number: 1,
+            episode_number: 3,
+            anilist_id: Some(98444),
+            outcome: data::state::HistoryOutcome::Updated,
+        });
+        // Events end with an empty line but can arrive in several chunks.
+        let mut buffer = Vec::new();
+        while !buffer.ends_with(b"\n\n") {
+            let mut chunk = [0; 512];
+            let read = response.read(&mut chunk).await.unwrap();
+            assert!(read > 0, "stream ended before the event");
+            buffer.extend_from_slice(&chunk[..read]);
+        }
+        let event = String::from_utf8_lossy(&buffer);
+        assert!(event.starts_with("event:scrobble\n"), "{}", event);
+        assert!(event.contains("\"outcome\":\"updated\""), "{}", event);
+    }
+
+    #[test]
+    fn anime_specials() {
+        let client = build_client();
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        let set_specials = |body: &'static str| {
+            client
+                .post(uri!(anime_specials(98444)))
+                .header(ContentType::Form)
+                .body(body)
+                .dispatch()
+                .status()
+        };
+        assert_eq!(set_specials("title=Yuru Camp&episodes=3, 5"), Status::Ok);
+        assert_eq!(
+            state.special_overrides.blocking_read().find("Yuru Camp", 5),
+            Some((98444, 2))
+        );
+        assert_eq!(
+            set_specials("title=Yuru Camp&episodes=0"),
+            Status::UnprocessableEntity
+        );
+        assert_eq!(
+            set_specials("title=&episodes=3"),
+            Status::UnprocessableEntity
+        );
+        assert_eq!(set_specials("title=&episodes="), Status::Ok);
+        assert!(state
+            .special_overrides
+            .blocking_read()
+            .get(&98444)
+            .is_none());
+    }
+
+    #[test]
+    fn season_mappings() {
+        let client = build_client();
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        let details: anilist::MediaDetails = serde_json::from_str(
+            "{\"id\": 104460, \"title\": {\"userPreferred\": \"Yuru Camp 2\"}}",
+        )
+        .unwrap();
+        state.media_details.blocking_write().insert(details);
+        let response = client
+            .post(uri!(season_mapping_add))
+            .header(ContentType::Form)
+            .body("title=Yuru Camp&season=2&target=104460")
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .post(uri!(season_mapping_add))
+            .header(ContentType::Form)
+            .body("title=Yuru Camp&season=0&target=104460")
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let response = client.get(uri!(season_mappings)).dispatch();
+        assert_eq!(
+            response.into_string().unwrap(),
+            "[{\"title\":\"Yuru Camp\",\"season\":2,\"anilist_id\":104460}]"
+        );
+
+        let remove = |body: &'static str| {
+            client
+                .post(uri!(season_mapping_remove))
+                .header(ContentType::Form)
+                .body(body)
+                .dispatch()
+                .status()
+        };
+        assert_eq!(remove("title=Yuru Camp&season=2"), Status::NoContent);
+        assert_eq!(remove("title=Yuru Camp&season=2"), Status::NotFound);
+        assert!(state
+            .season_mappings
+            .blocking_read()
+            .iter()
+            .next()
+            .is_none());
+    }
+
+    #[test_case(2, true ; "mapped season")]
+    #[test_case(3, false ; "unmapped season")]
+    #[rocket::async_test]
+    async fn season_mapping_actionable(season: i32, expected: bool) {
+        let state = build_state();
+        state
+            .season_mappings
+            .write()
+            .await
+            .set(data::state::SeasonMapping {
+                title: String::from("Yuru Camp"),
+                season: 2,
+                anilist_id: 104460,
+            });
+        let payload = format!(
+            "{{\"event\": \"media.scrobble\", \"Metadata\": {{\"type\": \"episode\", \
+            \"grandparentTitle\": \"Yuru Camp\", \"parentIndex\": {}, \"index\": 3}}, \
+            \"Account\": {{\"title\": \"yukikaze\"}}}}",
+            season
+        );
+        let webhook: plex::Webhook = serde_json::from_str(&payload).unwrap();
+        assert_eq!(
+            state.season_mapping(&webhook).await,
+            expected.then_some(104460)
+        );
+        assert_eq!(state.is_actionable(&webhook).await, expected);
+    }
+
+    #[rocket::async_test]
+    async fn title_override_account() {
+        let state = build_state();
+        let title = String::from("Laid-Back Camp");
+        state
+            .title_overrides
+            .write()
+            .await
+            .set(title.clone(), 104460);
+        state
+            .account_title_overrides
+            .write()
+            .await
+            .account_mut(1)
+            .set(title.clone(), 98444);
+        assert_eq!(state.title_override(1, &title).await, Some(98444));
+        assert_eq!(state.title_override(2, &title).await, Some(104460));
+    }
+
+    #[test]
+    fn title_override_remove_title() {
+        let client = build_client();
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        state
+            .title_overrides
+            .blocking_write()
+            .set(String::from("Laid-Back Camp"), 98444);
+        for expected_status in [Status::NoContent, Status::NotFound] {
+            let response = client
+                .post(uri!(title_override_remove))
+                .header(ContentType::Form)
+                .body("title=Laid-Back Camp")
+                .dispatch();
+            assert_eq!(response.status(), expected_status);
+        }
+        assert_eq!(
+            state
+                .title_overrides
+                .blocking_read()
+                .get(&String::from("Laid-Back Camp")),
+            None
+        );
+    }
+
+    #[test]
+    fn args_subcommands() {
+        use clap::CommandFactory;
+
+        AnifunnelArgs::command().debug_assert();
+        let args = AnifunnelArgs::try_parse_from(["anifunnel", "token-value"]).unwrap();
+        assert!(args.command.is_none());
+        assert_eq!(args.anilist_token.as_deref(), Some("token-value"));
+        let args = AnifunnelArgs::try_parse_from([
+            "anifunnel",
+            "override",
+            "set",
+            "Laid-Back Camp",
+            "98444",
+            "--url",
+            "http://anifunnel:8000",
+        ])
+        .unwrap();
+        assert!(matches!(
+            args.command,
+            Some(cli::Command::Override {
+                command: cli::OverrideCommand::Set { .. },
+                ..
+            })
+        ));
+        let args =
+            AnifunnelArgs::try_parse_from(["anifunnel", "override", "delete", "Laid-Back Camp"])
+                .unwrap();
+        assert!(matches!(
+            args.command,
+            Some(cli::Command::Override {
+                command: cli::OverrideCommand::Remove { account: false, .. },
+                ..
+            })
+        ));
+        let args = AnifunnelArgs::try_parse_from([
+            "anifunnel",
+            "sync-from",
+            "http://staging:8000",
+            "--source-api-key",
+            "key1",
+            "--api-key",
+            "key2",
+        ])
+        .unwrap();
+        let Some(cli::Command::SyncFrom {
+            source,
+            source_api_key,
+            ..
+        }) = args.command
+        else {
+            panic!("sync-from was not parsed");
+        };
+        assert_eq!(source, "http://staging:8000");
+        assert_eq!(source_api_key.as_deref(), Some("key1"));
+    }
+
+    #[test]
+    fn parse_limit_invalid() {
+        assert!(parse_limit("lots").is_err());
+    }
+
+    #[test]
+    fn sync_pause_resume() {
+        let client = build_client();
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        let response = client.post(uri!(sync_pause)).dispatch();
+        let sync_pause: serde_json::Value = response.into_json().unwrap();
+        assert!(sync_pause["paused_since"].is_u64());
+        let response = client.get(uri!(system_status)).dispatch();
+        let status: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(status["sync_paused"], true);
+        let response = client
+            .post(uri!(scrobble))
+            .header(ContentType::Form)
+            .body(
+                "payload={\"event\": \"media.scrobble\", \"Metadata\": {\
+                \"type\": \"episode\", \"grandparentTitle\": \"Onii-chan wa Oshimai!\", \
+                \"parentIndex\": 1, \"index\": 2}, \"Account\": {\"title\": \"yukikaze\"}}",
+            )
+            .dispatch();
+        assert_eq!(response.into_string().unwrap(), "NO OP");
+        assert_eq!(state.history.blocking_read().iter().count(), 0);
+        let response = client.post(uri!(sync_resume)).dispatch();
+        assert_eq!(response.into_string().unwrap(), "{\"paused_since\":null}");
+        assert!(!state.sync_pause.blocking_read().is_paused());
+    }
+
+    #[test]
+    fn config_summary() {
+        let client = build_client();
+        let response = client.get(uri!(config_summary)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let summary: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(summary["storage"], "memory");
+        assert_eq!(summary["anilist_user"], "A");
+        assert!(summary["port"].is_u64());
+        assert_eq!(summary["settings"]["webhook_token_set"], false);
+    }
+
+    #[test]
+    fn sync_status() {
+        let client = build_client();
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        for (episode_number, anilist_id) in [(3, Some(42)), (4, Some(42)), (1, None)] {
+            state
+                .history
+                .blocking_write()
+                .push(data::state::HistoryEntry {
+                    timestamp: 1700000000 + episode_number as u64,
+                    source: data::state::ScrobbleSource::PlexWebhook,
+                    title: String::from("Onii-chan wa Oshimai!"),
+                    guid: None,
+                    season_number: 1,
+                    episode_number,
+                    anilist_id,
+                    outcome: data::state::HistoryOutcome::Updated,
+                });
+        }
+        state.episode_offsets.blocking_write().set(42, -2);
+        state.maintenance.blocking_write().queue(
+            "{\"event\": \"media.scrobble\", \"Metadata\": {\"type\": \"episode\", \
+            \"grandparentTitle\": \"Onii-chan wa Oshimai!\", \"parentIndex\": 1, \"index\": 5}, \
+            \"Account\": {\"title\": \"yukikaze\"}}",
+        );
+        let response = client.get(uri!(sync_status)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let statuses: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(statuses.as_array().unwrap().len(), 1);
+        assert_eq!(statuses[0]["anilist_id"], 42);
+        assert_eq!(statuses[0]["last_scrobbled_episode"], 4);
+        assert_eq!(statuses[0]["episode_offset"], -2);
+        assert_eq!(statuses[0]["trackers"]["anilist"], serde_json::Value::Null);
+        assert_eq!(statuses[0]["queued"], 1);
+    }
+
+    #[test]
+    fn progress_overview_alignment() {
+        let entries: anilist::MediaListGroup = serde_json::from_str(
+            "{\"entries\": [\
+            {\"id\": 1, \"progress\": 4, \"media\": {\"id\": 1, \
+            \"title\": {\"romaji\": \"Yuru Camp\", \"userPreferred\": \"Yuru Camp\"}}}, \
+            {\"id\": 2, \"progress\": 2, \"media\": {\"id\": 2, \
+            \"title\": {\"romaji\": \"Frieren\", \"userPreferred\": \"Frieren\"}}}, \
+            {\"id\": 3, \"progress\": 1, \"media\": {\"id\": 3, \
+            \"title\": {\"romaji\": \"Dungeon Meshi\", \"userPreferred\": \"Dungeon Meshi\"}}}, \
+            {\"id\": 4, \"progress\": 7, \"media\": {\"id\": 4, \
+            \"title\": {\"romaji\": \"Oshi no Ko\", \"userPreferred\": \"Oshi no Ko\"}}}]}",
+        )
+        .unwrap();
+        let mut history = data::state::History::new();
+        for (anilist_id, episode_number) in [(1, 3), (1, 4), (2, 1), (3, 14)] {
+            history.push(data::state::HistoryEntry {
+                timestamp: 1700000000,
+                source: data::state::ScrobbleSource::PlexWebhook,
+                title: String::from("Title"),
+                guid: None,
+                season_number: 1,
+                episode_number,
+                anilist_id: Some(anilist_id),
+                outcome: data::state::HistoryOutcome::Updated,
+            });
+        }
+        let mut episode_offsets = data::state::EpisodeOverrides::new();
+        episode_offsets.set(3, -12);
+        let overview = data::api::ProgressOverview::build(&entries, &history, &episode_offsets);
+        let alignments: Vec<_> = overview
+            .iter()
+            .map(|x| (x.anilist_id, x.delta, x.alignment))
+            .collect();
+        assert_eq!(
+            alignments,
+            vec![
+                (1, Some(0), data::api::ProgressAlignment::InSync),
+                (2, Some(1), data::api::ProgressAlignment::AnilistAhead),
+                (3, Some(-1), data::api::ProgressAlignment::AnilistBehind),
+                (4, None, data::api::ProgressAlignment::NotSeen),
+            ]
+        );
+    }
+
+    #[test]
+    fn maintenance() {
+        let client = build_client();
+        let set_maintenance = |enabled: bool| {
+            client
+                .post(uri!(maintenance))
+                .header(ContentType::Form)
+                .body(format!("enabled={}", enabled))
+                .dispatch()
+                .into_string()
+                .unwrap()
+        };
+        assert_eq!(set_maintenance(true), "{\"enabled\":true,\"queued\":0}");
+        let response = client
+            .post(uri!(scrobble))
+            .header(ContentType::Form)
+            .body(
+                "payload={\"event\": \"media.play\", \"Metadata\": {\
+                \"type\": \"episode\", \"grandparentTitle\": \"Onii-chan wa Oshimai!\", \
+                \"parentIndex\": 1, \"index\": 2, \"ratingKey\": \"1234\"}, \
+                \"Account\": {\"title\": \"yukikaze\"}, \"Player\": {\"uuid\": \"abcdef\"}}",
+            )
+            .dispatch();
+        assert_eq!(response.into_string().unwrap(), "QUEUED");
+        let response = client.get(uri!(sessions)).dispatch();
+        assert_eq!(response.into_string().unwrap(), "[]");
+        assert_eq!(set_maintenance(false), "{\"enabled\":false,\"queued\":0}");
+        let response = client.get(uri!(sessions)).dispatch();
+        assert!(response.into_string().unwrap().contains("abcdef:1234"));
+    }
+
+    #[test]
+    fn notifications() {
+        let client = build_client();
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        state.notifications.blocking_write().push(
+            data::state::NotificationKind::Unmatched,
+            String::from("Could not find a match for 'A' in the watching list"),
+        );
+        let response = client.post(uri!(notification_read(id = 1))).dispatch();
+        assert_eq!(response.status(), Status::NoContent);
+        let response = client.post(uri!(notification_read(id = 2))).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let response = client.get(uri!(notifications)).dispatch();
+        let notifications: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(notifications[0]["kind"], "unmatched");
+        assert_eq!(notifications[0]["read"], true);
+    }
+
+    #[test]
+    fn conflicts() {
+        let client = build_client();
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        for episode in [5, 6] {
+            state
+                .conflicts
+                .blocking_write()
+                .record(data::state::ProgressConflict {
+                    anilist_id: 146065,
+                    title: String::from("Mushoku Tensei II"),
+                    progress: 3,
+                    episode,
+                    timestamp: 0,
+                });
+        }
+        let response = client.get(uri!(conflicts)).dispatch();
+        let conflicts: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(conflicts.as_array().unwrap().len(), 1);
+        assert_eq!(conflicts[0]["episode"], 6);
+        let response = client.post(uri!(conflict_dismiss(id = 146065))).dispatch();
+        assert_eq!(response.status(), Status::NoContent);
+        let response = client.post(uri!(conflict_dismiss(id = 146065))).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn anilist_authorize_not_configured() {
+        let client = build_client();
+        let response = client.get(uri!(anilist_authorize)).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn anilist_authorize() {
+        let state = data::state::Global {
+            oauth: Some(anilist::OAuthClient {
+                id: String::from("1234"),
+                secret: String::from("secret"),
+                redirect_url: String::from("http://localhost:8000/auth/anilist/callback"),
+            }),
+            ..build_state()
+        };
+        let rocket = rocket::build()
+            .manage(Arc::new(state))
+            .mount("/", routes![anilist_authorize, anilist_callback]);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let response = client.get(uri!(anilist_authorize)).dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        let location = response.headers().get_one("Location").unwrap();
+        assert!(location.starts_with("https://anilist.co/api/v2/oauth/authorize?client_id=1234"));
+        let response = client
+            .get("/auth/anilist/callback?code=abc&state=invalid")
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn stats_activity() {
+        let client = build_client();
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        state
+            .history
+            .blocking_write()
+            .push(data::state::HistoryEntry {
+                timestamp: data::state::unix_timestamp(),
+                source: data::state::ScrobbleSource::PlexWebhook,
+                title: String::from("Yuru Camp"),
+                guid: None,
+                season_number: 1,
+                episode_number: 3,
+                anilist_id: Some(98444),
+                outcome: data::state::HistoryOutcome::Updated,
+            });
+        let response = client.get(uri!(stats_activity)).dispatch();
+        let activity: serde_json::Value = response.into_json().unwrap();
+        let days = activity.as_array().unwrap();
+        assert_eq!(days.len(), 365);
+        assert_eq!(days[364]["date"], data::state::today().to_string());
+        assert_eq!(days[364]["episodes"], 1);
+        assert_eq!(days[0]["episodes"], 0);
+    }
+
+    #[test]
+    fn tokens() {
+        let client = build_client();
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        state.accounts.blocking_write().add(
+            String::from("shared"),
+            String::from("B"),
+            anilist::User {
+                id: 2,
+                name: String::from("B"),
+                ..Default::default()
+            },
+        );
+        let response = client.post(uri!(token_activate(id = 3))).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let response = client.post(uri!(token_activate(id = 2))).dispatch();
+        assert_eq!(response.status(), Status::NoContent);
+        let response = client.get(uri!(tokens)).dispatch();
+        let tokens: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(tokens.as_array().unwrap().len(), 2);
+        assert_eq!(tokens[0]["active"], false);
+        assert_eq!(tokens[1]["label"], "shared");
+        assert_eq!(tokens[1]["active"], true);
+        assert_eq!(tokens[1]["user"], "B");
+        assert_eq!(tokens[1].get("token"), None);
+        assert_eq!(state.accounts.blocking_read().active().token, "B");
+    }
+
+    #[test]
+    fn selftest_scrobble_unreachable() {
+        let client = build_client();
+        let response = client
+            .post(uri!(selftest_scrobble))
+            .header(ContentType::Form)
+            .body("anilist_id=146065")
+            .dispatch();
+        assert_eq!(response.status(), Status::BadGateway);
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        assert_eq!(state.history.blocking_read().iter().count(), 0);
+    }
+
+    #[test_case(Some("Yuru Camp"), &[], None, Some(98444) ; "original title")]
+    #[test_case(None, &["tvdb://339506"], None, Some(104460) ; "guid override")]
+    #[test_case(None, &[], Some(2021), Some(104460) ; "year")]
+    #[test_case(None, &[], Some(2022), None ; "no match")]
+    fn refine_match_plex_metadata(
+        original_title: Option<&str>,
+        guids: &[&str],
+        year: Option<i32>,
+        expected: Option<i32>,
+    ) {
+        let entries: anilist::MediaListGroup = serde_json::from_str(
+            "{\"entries\": [\
+            {\"id\": 98444, \"media\": {\"id\": 98444, \"seasonYear\": 2018, \
+            \"title\": {\"romaji\": \"Yuru Camp\", \"userPreferred\": \"Yuru Camp\"}}}, \
+            {\"id\": 104460, \"media\": {\"id\": 104460, \"seasonYear\": 2021, \
+            \"title\": {\"romaji\": \"Yuru Camp Season 2\", \"userPreferred\": \"Yuru Camp Season 2\"}}}]}",
+        )
+        .unwrap();
+        let metadata = plex::ItemMetadata {
+            original_title: original_title.map(String::from),
+            year,
+            guids: guids.iter().map(|x| x.to_string()).collect(),
+        };
+        let mut guid_overrides = data::state::GuidOverrides::new();
+        guid_overrides.set(String::from("tvdb://339506"), 104460);
+        let title_match = match year {
+            Some(_) => anilist::TitleMatch::Ambiguous(vec![
+                entries.find_id(&98444).unwrap(),
+                entries.find_id(&104460).unwrap(),
+            ]),
+            None => anilist::TitleMatch::NotFound,
+        };
+        let (refined, _) = refine_match(
+            &entries,
+            (title_match, MatchSource::Title(None)),
+            &metadata,
+            &guid_overrides,
+            &anidb::AnidbMapping::new(),
+            &data::state::MinimumConfidences::new(),
+            &anilist::MatchSettings::default(),
+        );
+        let refined_id = match refined {
+            anilist::TitleMatch::Found(media_list) => Some(media_list.id),
+            _ => None,
+        };
+        assert_eq!(refined_id, expected);
+    }
+
+    #[rocket::async_test]
+    async fn dry_run_plex_metadata_retry() {
+        let state = data::state::Global {
+            plex: Some(plex::PlexServer {
+                url: String::from("http://127.0.0.1:9"),
+                token: String::from("token"),
+            }),
+            ..build_state()
+        };
+        let webhook: plex::Webhook = serde_json::from_str(
+            "{\"event\": \"media.scrobble\", \"Metadata\": {\"type\": \"episode\", \
+            \"grandparentTitle\": \"Laid-Back Camp\", \"grandparentKey\": \"/library/metadata/1\", \
+            \"parentIndex\": 1, \"index\": 2}, \"Account\": {\"title\": \"yukikaze\"}}",
+        )
+        .unwrap();
+        let entries: anilist::MediaListGroup = serde_json::from_str(
+            "{\"entries\": [{\"id\": 98444, \"media\": {\"id\": 98444, \
+            \"title\": {\"romaji\": \"Yuru Camp\", \"userPreferred\": \"Yuru Camp\"}}}]}",
+        )
+        .unwrap();
+        let mut sink = report::Sink::dry_run();
+        let result =
+            apply_scrobble(&webhook, "", &state, &mut sink, &mut Some(entries), None).await;
+        assert_eq!(result, "NO OP");
+        let replay = sink.into_replay();
+        assert!(replay.steps.iter().any(|x| x.step == "plex_metadata"));
+        assert_eq!(replay.action, "record as unmatched");
+        assert_eq!(state.unmatched.read().await.iter().count(), 0);
+    }
+
+    #[test_case("muted", data::state::HistoryOutcome::Muted ; "muted")]
+    #[test_case("ignored", data::state::HistoryOutcome::Ignored ; "ignored")]
+    #[test_case("log_only", data::state::HistoryOutcome::Logged ; "log only")]
+    #[rocket::async_test]
+    async fn scrobble_report_reasons(reason: &str, expected_outcome: data::state::HistoryOutcome) {
+        let state = build_state();
+        match reason {
+            "muted" => state
+                .mutes
+                .write()
+                .await
+                .set(98444, rocket::time::Date::MAX),
+            "ignored" => state.ignored.write().await.set(98444, true),
+            _ => state.log_only.write().await.set(98444, true),
+        }
+        let webhook: plex::Webhook = serde_json::from_str(
+            "{\"event\": \"media.scrobble\", \"Metadata\": {\"type\": \"episode\", \
+            \"grandparentTitle\": \"Yuru Camp\", \"parentIndex\": 1, \"index\": 2}, \
+            \"Account\": {\"title\": \"yukikaze\"}}",
+        )
+        .unwrap();
+        let entries: anilist::MediaListGroup = serde_json::from_str(
+            "{\"entries\": [{\"id\": 98444, \"progress\": 1, \"media\": {\"id\": 98444, \
+            \"title\": {\"romaji\": \"Yuru Camp\", \"userPreferred\": \"Yuru Camp\"}}}]}",
+        )
+        .unwrap();
+        let mut sink = report::Sink::live();
+        apply_scrobble(&webhook, "", &state, &mut sink, &mut Some(entries), None).await;
+        let report = sink.into_report("OK");
+        assert_eq!(report.reasons, vec!["title_match", reason]);
+        assert_eq!(report.confidence, Some(1.0));
+        assert_eq!(report.outcome, Some(expected_outcome));
+        assert_eq!(report.media.unwrap().episode, Some(2));
+    }
+
+    #[rocket::async_test]
+    async fn scrobble_already_counted() {
+        // Anilist is already past the progress that the scrobble saw.
+        let (api, requests) = anilist::fake::serve(|body| match body.contains("MediaList(") {
+            true => String::from("{\"data\": {\"MediaList\": {\"progress\": 5}}}"),
+            false => String::from("{\"data\": {\"SaveMediaListEntry\": {\"progress\": 2}}}"),
+        });
+        let state = data::state::Global {
+            mutations: anilist::MutationQueue::new(api),
+            ..build_state()
+        };
+        let webhook: plex::Webhook = serde_json::from_str(
+            "{\"event\": \"media.scrobble\", \"Metadata\": {\"type\": \"episode\", \
+            \"grandparentTitle\": \"Yuru Camp\", \"parentIndex\": 1, \"index\": 2}, \
+            \"Account\": {\"title\": \"yukikaze\"}}",
+        )
+        .unwrap();
+        let entries: anilist::MediaListGroup = serde_json::from_str(
+            "{\"entries\": [{\"id\": 98444, \"progress\": 1, \"media\": {\"id\": 98444, \
+            \"title\": {\"romaji\": \"Yuru Camp\", \"userPreferred\": \"Yuru Camp\"}}}]}",
+        )
+        .unwrap();
+        let mut sink = report::Sink::live();
+        let mut watching_list = Some(entries);
+        apply_scrobble(&webhook, "", &state, &mut sink, &mut watching_list, None).await;
+        let report = sink.into_report("OK");
+        assert_eq!(report.outcome, Some(data::state::HistoryOutcome::Skipped));
+        assert!(watching_list.is_none());
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        assert!(state.activity.read().await.last_update.is_none());
+        assert_eq!(state.notifications.read().await.iter().count(), 0);
+    }
+
+    #[test]
+    fn match_test_unreachable() {
+        let client = build_client();
+        let response = client.get(uri!(match_test(title = "Yuru Camp"))).dispatch();
+        assert_eq!(response.status(), Status::BadGateway);
+    }
+
+    #[test]
+    fn sync_catchup_unconfigured() {
+        let client = build_client();
+        let response = client.post(uri!(sync_catchup(since = _))).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test_case("{\"event\": \"anifunnel.catchup\", \"Account\": {\"title\": \"yukikaze\"}, \
+        \"Metadata\": {\"type\": \"episode\", \"grandparentTitle\": \"Yuru Camp\", \"parentIndex\": 1, \"index\": 2}}" ; "other plex user")]
+    #[test_case("{\"event\": \"anifunnel.catchup\", \"Account\": {\"title\": \"kaga\"}, \
+        \"Metadata\": {\"type\": \"episode\", \"grandparentTitle\": \"Yuru Camp\", \"parentIndex\": 2, \"index\": 2}}" ; "later season")]
+    #[rocket::async_test]
+    async fn catch_up_scrobble_filtered(payload: &str) {
+        let state = data::state::Global {
+            plex_user: Some(String::from("kaga")),
+            ..build_state()
+        };
+        assert_eq!(catch_up_scrobble(payload, &state, &mut None).await, "NO OP");
+        assert_eq!(state.history.read().await.iter().count(), 0);
+    }
+
+    #[test]
+    fn unmatched_resolve() {
+        let client = build_client();
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        let payload = "{\"event\": \"media.scrobble\", \"Metadata\": {\
+            \"type\": \"episode\", \"grandparentTitle\": \"Onii-chan wa Oshimai!\", \
+            \"parentIndex\": 1, \"index\": 2}, \"Account\": {\"title\": \"yukikaze\"}}";
+        let webhook: plex::Webhook = serde_json::from_str(payload).unwrap();
+        state
+            .unmatched
+            .blocking_write()
+            .record(&webhook, payload, Vec::new());
+        let response = client.get(uri!(unmatched)).dispatch();
+        let unmatched: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(unmatched[0]["title"], "Onii-chan wa Oshimai!");
+        assert_eq!(unmatched[0]["payload"], serde_json::Value::Null);
+        let resolve = |id: u64| {
+            client
+                .post(uri!(unmatched_resolve(id = id)))
+                .header(ContentType::Form)
+                .body("anilist_id=146065&remember=true")
+                .dispatch()
+                .status()
+        };
+        assert_eq!(resolve(2), Status::NotFound);
+        state.sync_pause.blocking_write().pause();
+        assert_eq!(resolve(1), Status::Conflict);
+        assert_eq!(state.unmatched.blocking_read().iter().count(), 1);
+        state.sync_pause.blocking_write().resume();
+        assert_eq!(resolve(1), Status::Ok);
+        assert_eq!(
+            state
+                .title_overrides
+                .blocking_read()
+                .get(&String::from("Onii-chan wa Oshimai!")),
+            Some(146065)
+        );
+        assert_eq!(state.unmatched.blocking_read().iter().count(), 0);
+        assert_eq!(resolve(1), Status::NotFound);
+    }
+
+    #[test]
+    fn unmatched_resolve_best_candidate() {
+        // The watching list has the entry under a title that does not match.
+        let (api, requests) = anilist::fake::serve(|body| {
+            if body.contains("SaveMediaListEntry") {
+                return String::from("{\"data\": {\"SaveMediaListEntry\": {\"progress\": 2}}}");
+            }
+            if body.contains("MediaListCollection") {
+                return String::from(
+                    "{\"data\": {\"MediaListCollection\": {\"lists\": [{\"entries\": [\
+                    {\"id\": 146065, \"progress\": 1, \"status\": \"CURRENT\", \
+                    \"media\": {\"id\": 146065, \"episodes\": 12, \
+                    \"title\": {\"romaji\": \"Oniimai\", \"userPreferred\": \"Oniimai\"}}}]}]}}}",
+                );
+            }
+            return String::from("{\"data\": {\"MediaList\": {\"progress\": 1}}}");
+        });
+        let state = data::state::Global {
+            anilist: api.clone(),
+            mutations: anilist::MutationQueue::new(api),
+            ..build_state()
+        };
+        let rocket = rocket::build()
+            .manage(Arc::new(state))
+            .mount("/", routes![unmatched_resolve]);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        let payload = "{\"event\": \"media.scrobble\", \"Metadata\": {\
+            \"type\": \"episode\", \"grandparentTitle\": \"Onii-chan wa Oshimai!\", \
+            \"parentIndex\": 1, \"index\": 2}, \"Account\": {\"title\": \"yukikaze\"}}";
+        let webhook: plex::Webhook = serde_json::from_str(payload).unwrap();
+        let title = String::from("Onii-chan wa Oshimai!");
+        let resolve = |id: u64| {
+            client
+                .post(uri!(unmatched_resolve(id = id)))
+                .header(ContentType::Form)
+                .body("")
+                .dispatch()
+                .status()
+        };
+        state
+            .unmatched
+            .blocking_write()
+            .record(&webhook, payload, Vec::new());
+        assert_eq!(resolve(1), Status::UnprocessableEntity);
+        assert_eq!(state.unmatched.blocking_read().iter().count(), 1);
+        state.unmatched.blocking_write().take(1);
+        let candidate = anilist::MatchCandidate {
+            anilist_id: 146065,
+            title: title.clone(),
+            confidence: 0.6,
+            variant: "romaji",
+            massaged: false,
+            transliterated: false,
+        };
+        state
+            .unmatched
+            .blocking_write()
+            .record(&webhook, payload, vec![candidate]);
+        assert_eq!(resolve(2), Status::Ok);
+        assert_eq!(state.unmatched.blocking_read().iter().count(), 0);
+        // The scrobble is applied to the candidate without adding a title override.
+        assert!(requests
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|x| x.contains("SaveMediaListEntry") && x.contains("146065")));
+        assert_eq!(state.title_overrides.blocking_read().get(&title), None);
+        let history = state.history.blocking_read();
+        assert_eq!(
+            history.iter().next().map(|x| x.outcome.clone()),
+            Some(data::state::HistoryOutcome::Updated)
+        );
+    }
+
+    #[test]
+    fn anime_apply_unmatched() {
+        let client = build_client();
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        for (title, episode) in [("Onii-chan wa Oshimai!", 2), ("Yuru Camp", 1)] {
+            let payload = format!(
+                "{{\"event\": \"media.scrobble\", \"Metadata\": {{\
+                \"type\": \"episode\", \"grandparentTitle\": \"{}\", \
+                \"parentIndex\": 1, \"index\": {}}}, \"Account\": {{\"title\": \"yukikaze\"}}}}",
+                title, episode
+            );
+            let webhook: plex::Webhook = serde_json::from_str(&payload).unwrap();
+            state
+                .unmatched
+                .blocking_write()
+                .record(&webhook, &payload, Vec::new());
+        }
+        state
+            .title_overrides
+            .blocking_write()
+            .set(String::from("Onii-chan wa Oshimai!"), 146065);
+        let response = client.post(uri!(anime_apply_unmatched(146065))).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let unmatched: Vec<String> = state
+            .unmatched
+            .blocking_read()
+            .iter()
+            .map(|x| x.title.clone())
+            .collect();
+        assert_eq!(unmatched, vec![String::from("Yuru Camp")]);
+    }
+
+    #[test]
+    fn overrides_search() {
+        let client = build_client();
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        {
+            let mut title_overrides = state.title_overrides.blocking_write();
+            title_overrides.set(String::from("Onii-chan wa Oshimai!"), 146065);
+            title_overrides.set(String::from("Yuru Camp"), 98444);
+            title_overrides.set(String::from("Yuru Camp Season 2"), 104460);
+        }
+        let search = |query: &str| {
+            let response = client
+                .get(format!("/api/overrides/search?q={}", query))
+                .dispatch();
+            let results: serde_json::Value =
+                serde_json::from_str(&response.into_string().unwrap()).unwrap();
+            results
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|x| x["anilist_id"].as_i64().unwrap())
+                .collect::<Vec<i64>>()
+        };
+        assert_eq!(search("yuru"), vec![98444, 104460]);
+        assert_eq!(search("Oniichan%20wa%20Oshimai"), vec![146065]);
+        assert_eq!(search("Bocchi"), Vec::<i64>::new());
+    }
+
+    #[test_case("", 146065, 1 ; "skip conflicts")]
+    #[test_case("?conflict=replace", 98444, 2 ; "replace conflicts")]
+    fn export_import(query: &str, expected_id: i32, expected_imported: usize) {
+        let client = build_client();
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        state
+            .title_overrides
+            .blocking_write()
+            .set(String::from("Yuru Camp"), 146065);
+        state.episode_offsets.blocking_write().set(146065, -12);
+        let response = client.get(uri!(export)).dispatch();
+        let export: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(
+            export["title_overrides"],
+            serde_json::json!({"Yuru Camp": 146065})
+        );
+        assert_eq!(
+            export["episode_offsets"],
+            serde_json::json!({"146065": -12})
+        );
+        assert!(export["settings"].is_object());
+        let response = client
+            .post(format!("/api/import{}", query))
+            .header(ContentType::JSON)
+            .body(
+                "{\"title_overrides\": {\"Yuru Camp\": 98444}, \"episode_offsets\": {\"1\": 0}, \
+                \"mutes\": {\"98444\": \"2999-12-31\"}}",
+            )
+            .dispatch();
+        let summary: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(summary["imported"], expected_imported);
+        assert_eq!(summary["invalid"], 1);
+        assert_eq!(
+            state
+                .title_overrides
+                .blocking_read()
+                .get(&String::from("Yuru Camp")),
+            Some(expected_id)
+        );
+        assert!(state.mutes.blocking_read().get(&98444).is_some());
+    }
+
+    #[rocket::async_test]
+    async fn seed_overrides_replace() {
+        let state = build_state();
+        state
+            .title_overrides
+            .write()
+            .await
+            .set(String::from("Yuru Camp"), 146065);
+        let overrides: data::api::Import = serde_json::from_str(
+            "{\"version\": \"1.4.0\", \"title_overrides\": {\"Yuru Camp\": 98444}, \
+            \"log_only\": [98444], \"title_patterns\": {\"(\": 1}}",
+        )
+        .unwrap();
+        let summary = seed_overrides(&state, overrides).await;
+        assert_eq!(summary.imported, 2);
+        assert_eq!(summary.invalid, 1);
+        assert_eq!(
+            state
+                .title_overrides
+                .read()
+                .await
+                .get(&String::from("Yuru Camp")),
+            Some(98444)
+        );
+        assert!(state.log_only.read().await.contains(&98444));
+    }
+
+    #[test_case("{\"event\": \"media.scrobble\"", "reject unparseable payload" ; "invalid payload")]
+    #[test_case("{\"event\": \"media.play\", \"Metadata\": {\"type\": \"episode\", \
+        \"grandparentTitle\": \"Yuru Camp\", \"parentIndex\": 1, \"index\": 2}, \
+        \"Account\": {\"title\": \"yukikaze\"}}", "ignore" ; "not actionable")]
+    #[test_case("{\"event\": \"media.scrobble\", \"Metadata\": {\"type\": \"episode\", \
+        \"grandparentTitle\": \"Yuru Camp\", \"parentIndex\": 1, \"index\": 2}, \
+        \"Account\": {\"title\": \"yukikaze\"}}", "fail to retrieve the watching list" ; "actionable")]
+    fn replay_trace(payload: &str, expected_action: &str) {
+        let client = build_client();
+        let response = client.post(uri!(replay)).body(payload).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let replay: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(replay["action"], expected_action);
+        assert_eq!(replay["steps"][0]["step"], "parse");
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        assert_eq!(state.history.blocking_read().iter().count(), 0);
+        assert!(state.activity.blocking_read().last_webhook.is_none());
+    }
+
+    #[test]
+    fn replay_sync_paused() {
+        let client = build_client();
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        state.sync_pause.blocking_write().pause();
+        let response = client
+            .post(uri!(replay))
+            .body(
+                "{\"event\": \"media.scrobble\", \"Metadata\": {\"type\": \"episode\", \
+                \"grandparentTitle\": \"Yuru Camp\", \"parentIndex\": 1, \"index\": 2}, \
+                \"Account\": {\"title\": \"yukikaze\"}}",
+            )
+            .dispatch();
+        let replay: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(replay["action"], "ignore");
+        let steps = replay["steps"].as_array().unwrap();
+        assert_eq!(steps.last().unwrap()["step"], "sync_pause");
+        assert_eq!(steps.last().unwrap()["detail"], "rejected");
+    }
+
+    #[test]
+    fn replay_maintenance() {
+        let client = build_client();
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        state.maintenance.blocking_write().enabled = true;
+        let response = client
+            .post(uri!(replay))
+            .body("{\"event\": \"media.scrobble\"}")
+            .dispatch();
+        let replay: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(replay["action"], "queue until maintenance mode is disabled");
+        assert_eq!(state.maintenance.blocking_read().queued(), 0);
+    }
+
+    #[test_case(true, "fail to retrieve the Anilist lists" ; "sync ratings")]
+    #[test_case(false, "ignore" ; "ratings not synced")]
+    fn replay_rating(sync_ratings: bool, expected_action: &str) {
+        let state = data::state::Global {
+            sync_ratings,
+            ..build_state()
+        };
+        let rocket = rocket::build()
+            .manage(Arc::new(state))
+            .mount("/", routes![replay]);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let response = client
+            .post(uri!(replay))
+            .body(
+                "{\"event\": \"media.rate\", \"rating\": 8.0, \"Metadata\": {\
+                \"type\": \"show\", \"title\": \"Onii-chan wa Oshimai!\"}, \
+                \"Account\": {\"title\": \"yukikaze\"}}",
+            )
+            .dispatch();
+        let replay: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(replay["action"], expected_action);
+        let rating_step = replay["steps"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|x| x["step"] == "rating");
+        assert_eq!(rating_step, sync_ratings);
+    }
+
+    #[test]
+    fn debug_bundle() {
+        let client = build_client();
+        let response = client
+            .post(uri!(scrobble))
+            .header(ContentType::Form)
+            .body("payload={\"event\": \"media.scrobble\", \"Player\": {\"publicAddress\": \"192.0.2.1\"}}")
+            .dispatch();
+        assert_eq!(response.into_string().unwrap(), "ERROR");
+        let response = client.get(uri!(history)).dispatch();
+        assert_eq!(response.into_string().unwrap(), "[]");
+        let response = client.get(uri!(debug_bundle)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let bundle: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(bundle["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(bundle["settings"]["admin_password_set"], false);
+        assert_eq!(
+            bundle["failed_payloads"],
+            serde_json::json!([
+                "{\"Player\":{\"publicAddress\":\"<redacted>\"},\"event\":\"media.scrobble\"}"
+            ])
+        );
+    }
+
+    #[test]
     fn scrobble() {
         let client = build_client();
         let response = client
@@ -335,17 +5872,12 @@ mod test {
     #[test_case("shiranui", "NO OP" ; "incorrect username")]
     fn scrobble_username_filter(plex_user: &str, expected_response: &str) {
         let state = data::state::Global {
-            multi_season: false,
             plex_user: Some(String::from(plex_user)),
-            token: String::from("A"),
-            user: anilist::User {
-                id: 1,
-                name: String::from("A"),
-            },
-            title_overrides: RwLock::new(data::state::TitleOverrides::new()),
-            episode_offsets: RwLock::new(data::state::EpisodeOverrides::new()),
+            ..build_state()
         };
-        let rocket = rocket::build().manage(state).mount("/", routes![scrobble]);
+        let rocket = rocket::build()
+            .manage(Arc::new(state))
+            .mount("/", routes![scrobble]);
         let client = Client::tracked(rocket).expect("valid rocket instance");
         let response = client
             .post(uri!(scrobble))
@@ -360,6 +5892,400 @@ mod test {
         assert_eq!(response.into_string().unwrap(), expected_response)
     }
 
+    #[test_case("Burakku", "OK" ; "correct server")]
+    #[test_case("Shiranui", "NO OP" ; "incorrect server")]
+    fn scrobble_server_filter(plex_server: &str, expected_response: &str) {
+        let state = data::state::Global {
+            plex_servers: vec![String::from(plex_server)],
+            ..build_state()
+        };
+        let rocket = rocket::build()
+            .manage(Arc::new(state))
+            .mount("/", routes![scrobble]);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let response = client
+            .post(uri!(scrobble))
+            .header(ContentType::Form)
+            .body(
+                "payload={\"event\": \"media.scrobble\", \"Server\": {\"title\": \"Burakku\", \
+                \"uuid\": \"0123456789abcdef\"}, \"Metadata\": {\"type\": \"episode\", \
+                \"grandparentTitle\": \"Onii-chan wa Oshimai!\", \"parentIndex\": 1, \"index\": 2}, \
+                \"Account\": {\"title\": \"yukikaze\"}}",
+            )
+            .dispatch();
+        assert_eq!(response.into_string().unwrap(), expected_response)
+    }
+
+    #[test_case("Anime", "OK" ; "correct library")]
+    #[test_case("TV Shows", "NO OP" ; "incorrect library")]
+    fn scrobble_library_filter(plex_library: &str, expected_response: &str) {
+        let state = data::state::Global {
+            plex_libraries: vec![String::from(plex_library)],
+            ..build_state()
+        };
+        let rocket = rocket::build()
+            .manage(Arc::new(state))
+            .mount("/", routes![scrobble]);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let response = client
+            .post(uri!(scrobble))
+            .header(ContentType::Form)
+            .body(
+                "payload={\"event\": \"media.scrobble\", \"Metadata\": {\"type\": \"episode\", \
+                \"grandparentTitle\": \"Onii-chan wa Oshimai!\", \"parentIndex\": 1, \"index\": 2, \
+                \"librarySectionTitle\": \"Anime\", \"librarySectionID\": 3}, \
+                \"Account\": {\"title\": \"yukikaze\"}}",
+            )
+            .dispatch();
+        assert_eq!(response.into_string().unwrap(), expected_response)
+    }
+
+    #[test_case(plex::AccountFilter::Owner, "NO OP" ; "owner only")]
+    #[test_case(plex::AccountFilter::Shared, "OK" ; "shared only")]
+    fn scrobble_account_filter(account_filter: plex::AccountFilter, expected_response: &str) {
+        let state = data::state::Global {
+            account_filter,
+            ..build_state()
+        };
+        let rocket = rocket::build()
+            .manage(Arc::new(state))
+            .mount("/", routes![scrobble]);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let response = client
+            .post(uri!(scrobble))
+            .header(ContentType::Form)
+            .body(
+                "payload={\"event\": \"media.scrobble\", \"owner\": false, \"user\": false, \
+                \"Metadata\": {\"type\": \"episode\", \"grandparentTitle\": \"Onii-chan wa Oshimai!\", \
+                \"parentIndex\": 1, \"index\": 2}, \"Account\": {\"title\": \"yukikaze\"}}",
+            )
+            .dispatch();
+        assert_eq!(response.into_string().unwrap(), expected_response)
+    }
+
+    #[test]
+    fn scrobble_debounce() {
+        let state = data::state::Global {
+            scrobble_debounce: Some(Duration::from_secs(60)),
+            ..build_state()
+        };
+        let rocket = rocket::build()
+            .manage(Arc::new(state))
+            .mount("/", routes![scrobble]);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let payload = |user: &str| {
+            format!(
+                "payload={{\"event\": \"media.scrobble\", \"Metadata\": {{\
+                \"type\": \"episode\", \"grandparentTitle\": \"Onii-chan wa Oshimai!\", \
+                \"parentIndex\": 1, \"index\": 2}}, \"Account\": {{\"title\": \"{}\"}}}}",
+                user
+            )
+        };
+        let responses: Vec<String> = ["yukikaze", "yukikaze", "shiranui"]
+            .iter()
+            .map(|user| {
+                client
+                    .post(uri!(scrobble))
+                    .header(ContentType::Form)
+                    .body(payload(user))
+                    .dispatch()
+                    .into_string()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(responses, vec!["OK", "NO OP", "OK"]);
+    }
+
+    #[test]
+    fn scrobble_minimum_watch_time() {
+        let state = data::state::Global {
+            minimum_watch_time: Some(50),
+            ..build_state()
+        };
+        let rocket = rocket::build()
+            .manage(Arc::new(state))
+            .mount("/", routes![scrobble]);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let payload = |event: &str| {
+            format!(
+                "payload={{\"event\": \"{}\", \"Metadata\": {{\
+                \"type\": \"episode\", \"grandparentTitle\": \"Onii-chan wa Oshimai!\", \
+                \"parentIndex\": 1, \"index\": 2, \"ratingKey\": \"1234\", \"duration\": 1440000}}, \
+                \"Account\": {{\"title\": \"yukikaze\"}}, \"Player\": {{\"uuid\": \"abcdef\"}}}}",
+                event
+            )
+        };
+        for (event, expected_response) in [("media.play", "NO OP"), ("media.scrobble", "NO OP")] {
+            let response = client
+                .post(uri!(scrobble))
+                .header(ContentType::Form)
+                .body(payload(event))
+                .dispatch();
+            assert_eq!(response.into_string().unwrap(), expected_response);
+        }
+    }
+
+    #[test_case("/", None, Status::Unauthorized ; "no token")]
+    #[test_case("/?token=wrong", None, Status::Unauthorized ; "wrong query token")]
+    #[test_case("/?token=secret", None, Status::Ok ; "query token")]
+    #[test_case("/", Some("secret"), Status::Ok ; "header token")]
+    fn scrobble_webhook_token(uri: &str, header: Option<&str>, expected_status: Status) {
+        let state = data::state::Global {
+            webhook_token: Some(String::from("secret")),
+            ..build_state()
+        };
+        let rocket = rocket::build()
+            .manage(Arc::new(state))
+            .mount("/", routes![scrobble]);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let mut request = client.post(uri.to_string()).header(ContentType::Form).body(
+            "payload={\"event\": \"library.new\", \"Metadata\": {\
+                \"type\": \"episode\", \"grandparentTitle\": \"Onii-chan wa Oshimai!\", \
+                \"parentIndex\": 1, \"index\": 2}, \"Account\": {\"title\": \"yukikaze\"}}",
+        );
+        if let Some(header) = header {
+            request = request.header(Header::new("X-Anifunnel-Token", header.to_string()));
+        }
+        let response = request.dispatch();
+        assert_eq!(response.status(), expected_status);
+    }
+
+    #[test]
+    fn standby_replicated_settings() {
+        let state = data::state::Global {
+            replication: RwLock::new(data::state::Replication::new(Some(String::from(
+                "http://primary:8000",
+            )))),
+            ..build_state()
+        };
+        let rocket = rocket::build().manage(Arc::new(state)).mount(
+            "/",
+            routes![
+                sync_pause,
+                sync_resume,
+                discord_events_edit,
+                notifier_add,
+                notifier_edit,
+                notifier_delete
+            ],
+        );
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let state = client.rocket().state::<Arc<data::state::Global>>().unwrap();
+        let notifier = "kind=ntfy&url=https://ntfy.sh/anifunnel";
+        for (uri, body) in [
+            (uri!(sync_pause).to_string(), ""),
+            (uri!(sync_resume).to_string(), ""),
+            (uri!(discord_events_edit).to_string(), "updated=false"),
+            (uri!(notifier_add).to_string(), notifier),
+            (uri!(notifier_edit(id = 1)).to_string(), notifier),
+            (uri!(notifier_delete(id = 1)).to_string(), ""),
+        ] {
+            let response = client
+                .post(uri.clone())
+                .header(ContentType::Form)
+                .body(body)
+                .dispatch();
+            assert_eq!(response.status(), Status::ServiceUnavailable, "{}", uri);
+        }
+        assert!(!state.sync_pause.blocking_read().is_paused());
+        assert_eq!(state.notifiers.blocking_read().iter().count(), 0);
+    }
+
+    #[test]
+    fn standby_promote() {
+        let state = data::state::Global {
+            replication: RwLock::new(data::state::Replication::new(Some(String::from(
+                "http://primary:8000",
+            )))),
+            ..build_state()
+        };
+        let rocket = rocket::build().manage(Arc::new(state)).mount(
+            "/",
+            routes![scrobble, replication_status, replication_promote],
+        );
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let scrobble = || {
+            client
+                .post(uri!(scrobble))
+                .header(ContentType::Form)
+                .body(
+                    "payload={\"event\": \"library.new\", \"Metadata\": {\
+                    \"type\": \"episode\", \"grandparentTitle\": \"Yuru Camp\"}, \
+                    \"Account\": {\"title\": \"yukikaze\"}}",
+                )
+                .dispatch()
+                .status()
+        };
+        assert_eq!(scrobble(), Status::ServiceUnavailable);
+        let response = client.get(uri!(replication_status)).dispatch();
+        let replication: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(replication["primary"], "http://primary:8000");
+        assert_eq!(replication["promoted"], false);
+        let response = client.post(uri!(replication_promote)).dispatch();
+        assert_eq!(response.status(), Status::NoContent);
+        assert_eq!(scrobble(), Status::Ok);
+    }
+
+    #[test]
+    fn notifiers() {
+        let client = build_client();
+        let response = client
+            .post(uri!(notifier_add))
+            .header(ContentType::Form)
+            .body("kind=gotify&url=https://gotify.invalid&token=abc&events=error&events=token_expiring")
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .post(uri!(notifier_add))
+            .header(ContentType::Form)
+            .body("kind=ntfy&url=ntfy.sh/anifunnel")
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let response = client
+            .post(uri!(notifier_edit(1)))
+            .header(ContentType::Form)
+            .body("kind=gotify&url=https://gotify.invalid/&events=update")
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let response = client.get(uri!(notifiers_list)).dispatch();
+        let notifiers: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(notifiers[0]["url"], "https://gotify.invalid/");
+        assert_eq!(notifiers[0]["token_set"], true);
+        assert_eq!(notifiers[0]["events"], serde_json::json!(["update"]));
+        let response = client.post(uri!(notifier_delete(1))).dispatch();
+        assert_eq!(response.status(), Status::NoContent);
+        let response = client.post(uri!(notifier_delete(1))).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn discord_events_not_configured() {
+        let client = build_client();
+        let response = client.get(uri!(discord_events)).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn discord_events_edit() {
+        let state = data::state::Global {
+            discord: Some(discord::DiscordWebhook {
+                url: String::from("http://discord.invalid/api/webhooks/1/abc"),
+            }),
+            discord_events: RwLock::new(data::state::DiscordEvents::new(&[
+                discord::DiscordEvent::Updated,
+                discord::DiscordEvent::Unmatched,
+            ])),
+            ..build_state()
+        };
+        let rocket = rocket::build()
+            .manage(Arc::new(state))
+            .mount("/", routes![discord_events, discord_events_edit]);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let response = client
+            .post(uri!(discord_events_edit))
+            .header(ContentType::Form)
+            .body("unmatched=false&failed=true")
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let response = client.get(uri!(discord_events)).dispatch();
+        assert_eq!(
+            response.into_string().unwrap(),
+            "{\"updated\":true,\"unmatched\":false,\"failed\":true}"
+        );
+    }
+
+    #[test]
+    fn replication_promote_primary() {
+        let client = build_client();
+        let response = client.post(uri!(replication_promote)).dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+    }
+
+    #[rocket::async_test]
+    async fn shutdown_drain_pending() {
+        let state = build_state();
+        assert!(drain(&state, Duration::ZERO).await);
+        let pending = state.webhook_limit.acquire();
+        assert!(!drain(&state, Duration::from_millis(200)).await);
+        drop(pending);
+        assert!(drain(&state, Duration::ZERO).await);
+    }
+
+    #[rocket::async_test]
+    async fn replication_apply() {
+        let state = data::state::Global {
+            replication: RwLock::new(data::state::Replication::new(Some(String::from(
+                "http://primary:8000",
+            )))),
+            ..build_state()
+        };
+        state
+            .title_overrides
+            .write()
+            .await
+            .set(String::from("Yuru Camp"), 98444);
+        let export = "{\"version\": \"1.4.0\", \"settings\": {}, \
+            \"title_overrides\": {\"Mushoku Tensei S2\": 146065}, \"guid_overrides\": {}, \
+            \"title_patterns\": {}, \"episode_offsets\": {\"146065\": -12}, \"mutes\": {}, \
+            \"minimum_confidences\": {}, \"log_only\": [], \"ignored_ratings\": [], \"ignored\": []}";
+        let summary = replication::apply(export, &state).await.unwrap().unwrap();
+        assert_eq!(summary.imported, 2);
+        let title_overrides = state.title_overrides.read().await;
+        assert_eq!(title_overrides.get(&String::from("Yuru Camp")), None);
+        assert_eq!(
+            title_overrides.get(&String::from("Mushoku Tensei S2")),
+            Some(146065)
+        );
+        drop(title_overrides);
+        state.replication.write().await.promoted = true;
+        assert!(replication::apply(export, &state).await.unwrap().is_none());
+    }
+
+    #[rocket::async_test]
+    async fn replication_apply_snapshot() {
+        let state = data::state::Global {
+            replication: RwLock::new(data::state::Replication::new(Some(String::from(
+                "http://primary:8000",
+            )))),
+            ..build_state()
+        };
+        let snapshot = "{\"accounts\": [{\"label\": \"A\", \"token\": \"A\", \"active\": true}], \
+            \"discord_events\": {\"updated\": false, \"unmatched\": true, \"failed\": true}, \
+            \"notifiers\": [{\"kind\": \"ntfy\", \"url\": \"https://ntfy.sh/anifunnel\"}], \
+            \"sync_pause\": {\"paused_since\": 1700000000}}";
+        assert!(replication::apply_snapshot(snapshot, &state).await.unwrap());
+        assert_eq!(state.notifiers.read().await.iter().count(), 1);
+        assert!(!state.discord_events.read().await.updated);
+        assert!(state.sync_pause.read().await.is_paused());
+        assert_eq!(state.account().await.token, "A");
+        state.replication.write().await.promoted = true;
+        assert!(!replication::apply_snapshot(snapshot, &state).await.unwrap());
+    }
+
+    #[test_case(None, Status::Forbidden ; "no admin password")]
+    #[test_case(Some("hunter2"), Status::Ok ; "admin password")]
+    fn replication_snapshot_secrets(admin_password: Option<&str>, expected_status: Status) {
+        let state = data::state::Global {
+            admin_password: admin_password.map(String::from),
+            admin_api_keys: vec![String::from("admin")],
+            ..build_state()
+        };
+        let rocket = rocket::build()
+            .manage(Arc::new(state))
+            .mount("/", routes![replication_snapshot]);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+        let response = client
+            .get(uri!(replication_snapshot))
+            .header(Header::new("Authorization", "Bearer admin"))
+            .dispatch();
+        assert_eq!(response.status(), expected_status);
+        if expected_status == Status::Ok {
+            let snapshot: serde_json::Value = response.into_json().unwrap();
+            assert_eq!(snapshot["accounts"][0]["token"], "A");
+            assert_eq!(snapshot["accounts"][0]["active"], true);
+        }
+    }
+
     #[test]
     fn scrobble_non_actionable() {
         let client = build_client();
@@ -0,0 +1,310 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Build, Data, Request, Response, Rocket};
+use serde::Serialize;
+
+use crate::anilist;
+
+/// Upper bounds (in seconds) of the request duration histogram buckets.
+const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Label used for requests that did not match any route.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+#[derive(Debug, Default)]
+struct RouteMetrics {
+    /// Non-cumulative counts for each bucket in DURATION_BUCKETS.
+    duration_buckets: [u64; DURATION_BUCKETS.len()],
+    duration_count: u64,
+    duration_sum: f64,
+    request_bytes: u64,
+    response_bytes: u64,
+}
+
+impl RouteMetrics {
+    fn record(self: &mut Self, duration: Duration, request_bytes: u64, response_bytes: u64) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = DURATION_BUCKETS.iter().position(|x| seconds <= *x) {
+            self.duration_buckets[bucket] += 1;
+        }
+        self.duration_count += 1;
+        self.duration_sum += seconds;
+        self.request_bytes += request_bytes;
+        self.response_bytes += response_bytes;
+    }
+}
+
+/// Render a per-route counter in the Prometheus text exposition format.
+fn render_counter(
+    output: &mut String,
+    routes: &BTreeMap<(String, String), RouteMetrics>,
+    name: &str,
+    description: &str,
+    value: fn(&RouteMetrics) -> u64,
+) {
+    let _ = writeln!(output, "# HELP {} {}", name, description);
+    let _ = writeln!(output, "# TYPE {} counter", name);
+    for ((method, route), metrics) in routes.iter() {
+        let _ = writeln!(
+            output,
+            "{}{{method=\"{}\",route=\"{}\"}} {}",
+            name,
+            method,
+            route,
+            value(metrics)
+        );
+    }
+}
+
+/// Current metric values of a single route for the JSON snapshot.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct RouteSnapshot {
+    pub method: String,
+    pub route: String,
+    pub requests: u64,
+    pub duration_seconds_sum: f64,
+    pub request_bytes: u64,
+    pub response_bytes: u64,
+}
+
+/// Current metric values in a plain JSON-friendly form.
+#[derive(Debug, Serialize)]
+pub struct Snapshot {
+    pub anilist_rate_limited_total: u64,
+    pub anilist_rate_limit_remaining: Option<i64>,
+    pub routes: Vec<RouteSnapshot>,
+}
+
+/// Request metrics collected per route, keyed by the request method and route URI.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    routes: Mutex<BTreeMap<(String, String), RouteMetrics>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn record(
+        self: &Self,
+        method: &str,
+        route: &str,
+        duration: Duration,
+        request_bytes: u64,
+        response_bytes: u64,
+    ) {
+        let mut routes = self.routes.lock().unwrap();
+        routes
+            .entry((method.to_string(), route.to_string()))
+            .or_default()
+            .record(duration, request_bytes, response_bytes);
+    }
+
+    /// Take a snapshot of the current metric values.
+    pub fn snapshot(self: &Self) -> Snapshot {
+        let routes = self.routes.lock().unwrap();
+        let rate_limit_stats = anilist::rate_limit_stats();
+        return Snapshot {
+            anilist_rate_limited_total: rate_limit_stats.rate_limited_requests,
+            anilist_rate_limit_remaining: rate_limit_stats.remaining,
+            routes: routes
+                .iter()
+                .map(|((method, route), metrics)| RouteSnapshot {
+                    method: method.clone(),
+                    route: route.clone(),
+                    requests: metrics.duration_count,
+                    duration_seconds_sum: metrics.duration_sum,
+                    request_bytes: metrics.request_bytes,
+                    response_bytes: metrics.response_bytes,
+                })
+                .collect(),
+        };
+    }
+
+    /// Render the metrics in the Prometheus text exposition format.
+    pub fn render(self: &Self) -> String {
+        let routes = self.routes.lock().unwrap();
+        let mut output = String::new();
+        let rate_limit_stats = anilist::rate_limit_stats();
+        output.push_str(
+            "# HELP anifunnel_anilist_rate_limited_total Anilist requests rejected due to rate limiting.\n",
+        );
+        output.push_str("# TYPE anifunnel_anilist_rate_limited_total counter\n");
+        let _ = writeln!(
+            output,
+            "anifunnel_anilist_rate_limited_total {}",
+            rate_limit_stats.rate_limited_requests
+        );
+        if let Some(remaining) = rate_limit_stats.remaining {
+            output.push_str(
+                "# HELP anifunnel_anilist_rate_limit_remaining Remaining Anilist requests in the current window.\n",
+            );
+            output.push_str("# TYPE anifunnel_anilist_rate_limit_remaining gauge\n");
+            let _ = writeln!(
+                output,
+                "anifunnel_anilist_rate_limit_remaining {}",
+                remaining
+            );
+        }
+        output.push_str("# HELP anifunnel_http_request_duration_seconds HTTP request latency.\n");
+        output.push_str("# TYPE anifunnel_http_request_duration_seconds histogram\n");
+        for ((method, route), metrics) in routes.iter() {
+            let labels = format!("method=\"{}\",route=\"{}\"", method, route);
+            let mut cumulative = 0;
+            for (bound, count) in DURATION_BUCKETS.iter().zip(metrics.duration_buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    output,
+                    "anifunnel_http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                output,
+                "anifunnel_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, metrics.duration_count
+            );
+            let _ = writeln!(
+                output,
+                "anifunnel_http_request_duration_seconds_sum{{{}}} {}",
+                labels, metrics.duration_sum
+            );
+            let _ = writeln!(
+                output,
+                "anifunnel_http_request_duration_seconds_count{{{}}} {}",
+                labels, metrics.duration_count
+            );
+        }
+        render_counter(
+            &mut output,
+            &routes,
+            "anifunnel_http_request_bytes_total",
+            "Total size of HTTP request bodies.",
+            |x| x.request_bytes,
+        );
+        render_counter(
+            &mut output,
+            &routes,
+            "anifunnel_http_response_bytes_total",
+            "Total size of HTTP response bodies.",
+            |x| x.response_bytes,
+        );
+        return output;
+    }
+}
+
+/// Fairing that records the latency and payload sizes of every request into the
+/// managed Metrics state.
+pub struct RequestMetrics;
+
+/// Request start time stored in the request-local cache.
+struct RequestStart(Option<Instant>);
+
+#[rocket::async_trait]
+impl Fairing for RequestMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request metrics",
+            kind: Kind::Ignite | Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        Ok(rocket.manage(Metrics::new()))
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Some(Instant::now())));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let duration = match request.local_cache(|| RequestStart(None)).0 {
+            Some(start) => start.elapsed(),
+            None => return,
+        };
+        let metrics = match request.rocket().state::<Metrics>() {
+            Some(metrics) => metrics,
+            None => return,
+        };
+        let route = match request.route() {
+            Some(route) => route.uri.as_str(),
+            None => UNMATCHED_ROUTE,
+        };
+        let request_bytes = request
+            .headers()
+            .get_one("Content-Length")
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(0);
+        let response_bytes = response.body().preset_size().unwrap_or(0) as u64;
+        metrics.record(
+            request.method().as_str(),
+            route,
+            duration,
+            request_bytes,
+            response_bytes,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_metrics_record() {
+        let mut metrics = RouteMetrics::default();
+        metrics.record(Duration::from_millis(20), 100, 2);
+        metrics.record(Duration::from_secs(30), 50, 2);
+        assert_eq!(metrics.duration_buckets, [0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(metrics.duration_count, 2);
+        assert_eq!(metrics.request_bytes, 150);
+        assert_eq!(metrics.response_bytes, 4);
+    }
+
+    #[test]
+    fn metrics_render() {
+        let metrics = Metrics::new();
+        metrics.record("POST", "/", Duration::from_millis(20), 100, 2);
+        let output = metrics.render();
+        assert!(output.contains(
+            "anifunnel_http_request_duration_seconds_bucket{method=\"POST\",route=\"/\",le=\"0.01\"} 0\n"
+        ));
+        assert!(output.contains(
+            "anifunnel_http_request_duration_seconds_bucket{method=\"POST\",route=\"/\",le=\"0.025\"} 1\n"
+        ));
+        assert!(output.contains(
+            "anifunnel_http_request_duration_seconds_count{method=\"POST\",route=\"/\"} 1\n"
+        ));
+        assert!(output
+            .contains("anifunnel_http_request_bytes_total{method=\"POST\",route=\"/\"} 100\n"));
+        assert!(
+            output.contains("anifunnel_http_response_bytes_total{method=\"POST\",route=\"/\"} 2\n")
+        );
+    }
+
+    #[test]
+    fn metrics_snapshot() {
+        let metrics = Metrics::new();
+        metrics.record("POST", "/", Duration::from_millis(250), 100, 2);
+        metrics.record("POST", "/", Duration::from_millis(500), 50, 2);
+        let snapshot = metrics.snapshot();
+        assert_eq!(
+            snapshot.routes,
+            vec![RouteSnapshot {
+                method: String::from("POST"),
+                route: String::from("/"),
+                requests: 2,
+                duration_seconds_sum: 0.75,
+                request_bytes: 150,
+                response_bytes: 4,
+            }]
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use log::{debug, warn};
+use rocket::FromFormField;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{discord, trace};
+
+#[derive(Debug)]
+pub enum NotifierError {
+    Connection,
+}
+
+/// Service that a notifier posts to.
+#[derive(Clone, Copy, Debug, Deserialize, FromFormField, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifierKind {
+    /// ntfy topic URL, e.g. https://ntfy.sh/anifunnel.
+    Ntfy,
+    /// Gotify server URL, with the application token as the token.
+    Gotify,
+    /// Any URL that accepts a JSON body with the event, title and message.
+    Webhook,
+}
+
+/// Events that notifiers can be fired on.
+#[derive(Clone, Copy, Debug, Deserialize, FromFormField, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifierEvent {
+    /// Anilist progress was updated from a scrobble.
+    Update,
+    /// Anilist failed an update or the Anilist API has changed.
+    Error,
+    /// The Anilist token expires soon.
+    #[field(value = "token_expiring")]
+    TokenExpiring,
+    /// A scrobble did not match anything in the watching list.
+    Unmatched,
+}
+
+pub const ALL_EVENTS: [NotifierEvent; 4] = [
+    NotifierEvent::Update,
+    NotifierEvent::Error,
+    NotifierEvent::TokenExpiring,
+    NotifierEvent::Unmatched,
+];
+
+impl NotifierEvent {
+    fn title(self: &Self) -> &'static str {
+        return match self {
+            NotifierEvent::Update => "Progress updated",
+            NotifierEvent::Error => "Anilist error",
+            NotifierEvent::TokenExpiring => "Anilist token expiring",
+            NotifierEvent::Unmatched => "No match found",
+        };
+    }
+
+    /// Matching Discord webhook event, if Discord supports the event.
+    pub fn discord_event(self: &Self) -> Option<discord::DiscordEvent> {
+        return match self {
+            NotifierEvent::Update => Some(discord::DiscordEvent::Updated),
+            NotifierEvent::Error => Some(discord::DiscordEvent::Failed),
+            NotifierEvent::TokenExpiring => None,
+            NotifierEvent::Unmatched => Some(discord::DiscordEvent::Unmatched),
+        };
+    }
+}
+
+fn all_events() -> Vec<NotifierEvent> {
+    return ALL_EVENTS.to_vec();
+}
+
+/// Notification target, configured in the `notifications` table of the config file
+/// or through the API.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Notifier {
+    pub kind: NotifierKind,
+    pub url: String,
+    /// ntfy access token, Gotify application token or webhook bearer token.
+    #[serde(default)]
+    pub token: Option<String>,
+    #[serde(default = "all_events")]
+    pub events: Vec<NotifierEvent>,
+}
+
+impl Notifier {
+    pub fn is_enabled(self: &Self, event: NotifierEvent) -> bool {
+        return self.events.contains(&event);
+    }
+
+    fn request(
+        self: &Self,
+        client: &reqwest::Client,
+        event: NotifierEvent,
+        message: &str,
+    ) -> reqwest::RequestBuilder {
+        let request = match self.kind {
+            NotifierKind::Ntfy => client
+                .post(&self.url)
+                .header("Title", event.title())
+                .header("Tags", "anifunnel")
+                .body(message.to_string()),
+            NotifierKind::Gotify => client
+                .post(format!("{}/message", self.url.trim_end_matches('/')))
+                .json(&json!({"title": event.title(), "message": message})),
+            NotifierKind::Webhook => client.post(&self.url).json(&json!({
+                "event": event,
+                "title": event.title(),
+                "message": message,
+            })),
+        };
+        return match (self.kind, &self.token) {
+            (NotifierKind::Gotify, Some(token)) => request.header("X-Gotify-Key", token),
+            (_, Some(token)) => request.bearer_auth(token),
+            (_, None) => request,
+        };
+    }
+
+    pub async fn send(
+        self: &Self,
+        event: NotifierEvent,
+        message: &str,
+    ) -> Result<(), NotifierError> {
+        self.request(&reqwest::Client::new(), event, message)
+            .send()
+            .await
+            .and_then(|x| x.error_for_status())
+            .map_err(|error| {
+                debug!("{}", error);
+                NotifierError::Connection
+            })?;
+        return Ok(());
+    }
+}
+
+/// Send an event through a notifier in the background.
+pub fn notify(notifier: &Notifier, event: NotifierEvent, message: String) {
+    let notifier = notifier.clone();
+    tokio::spawn(trace::scope(trace::current(), async move {
+        if let Err(error) = notifier.send(event, &message).await {
+            warn!(
+                "Could not send {:?} notification to {}: {:?}",
+                event, notifier.url, error
+            );
+        }
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_case::test_case;
+
+    fn build_notifier(kind: NotifierKind, token: Option<&str>) -> Notifier {
+        return Notifier {
+            kind,
+            url: String::from("https://notify.invalid/anifunnel/"),
+            token: token.map(String::from),
+            events: all_events(),
+        };
+    }
+
+    #[test_case(NotifierKind::Ntfy, None, "https://notify.invalid/anifunnel/", None ; "ntfy")]
+    #[test_case(NotifierKind::Ntfy, Some("abc"), "https://notify.invalid/anifunnel/", Some(("authorization", "Bearer abc")) ; "ntfy token")]
+    #[test_case(NotifierKind::Gotify, Some("abc"), "https://notify.invalid/anifunnel/message", Some(("x-gotify-key", "abc")) ; "gotify")]
+    #[test_case(NotifierKind::Webhook, Some("abc"), "https://notify.invalid/anifunnel/", Some(("authorization", "Bearer abc")) ; "webhook")]
+    fn notifier_request(
+        kind: NotifierKind,
+        token: Option<&str>,
+        url: &str,
+        header: Option<(&str, &str)>,
+    ) {
+        let request = build_notifier(kind, token)
+            .request(&reqwest::Client::new(), NotifierEvent::Update, "Updated")
+            .build()
+            .unwrap();
+        assert_eq!(request.url().as_str(), url);
+        if let Some((name, value)) = header {
+            assert_eq!(request.headers()[name], value);
+        }
+    }
+
+    #[test]
+    fn notifier_deserialize_defaults() {
+        let notifier: Notifier =
+            serde_json::from_str("{\"kind\": \"ntfy\", \"url\": \"https://ntfy.sh/anifunnel\"}")
+                .unwrap();
+        assert_eq!(notifier.token, None);
+        assert!(notifier.is_enabled(NotifierEvent::TokenExpiring));
+    }
+}
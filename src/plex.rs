@@ -1,25 +1,199 @@
-use serde::Deserialize;
+use std::collections::HashMap;
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+/// Event of the fabricated scrobbles sent by the self-test.
+const SELF_TEST_EVENT: &str = "anifunnel.selftest";
+
+/// Event of the scrobbles replayed from the Plex watch history by a catch-up sync.
+const CATCH_UP_EVENT: &str = "anifunnel.catchup";
+
+/// Number of watch history items fetched from Plex per request.
+const HISTORY_PAGE_SIZE: usize = 200;
+
+#[derive(Debug)]
+pub enum PlexError {
+    Connection,
+    Parsing,
+    InvalidToken,
+}
+
+/// Plex Media Server API that the watch history is read from.
+#[derive(Clone, Debug)]
+pub struct PlexServer {
+    pub url: String,
+    pub token: String,
+}
 
 #[derive(Debug, Deserialize)]
 pub struct Webhook {
     event: String,
 
+    /// Whether the event is for the Plex server owner's account.
+    #[serde(default)]
+    pub owner: bool,
+
+    /// Whether the event is for the account that the webhook belongs to.
+    #[serde(default)]
+    pub user: bool,
+
     #[serde(rename = "Account")]
     pub account: WebhookAccount,
 
     #[serde(rename = "Metadata")]
     pub metadata: WebhookMetadata,
+
+    #[serde(rename = "Player")]
+    pub player: Option<WebhookPlayer>,
+
+    #[serde(rename = "Server")]
+    pub server: Option<WebhookServer>,
+
+    /// Rating (0-10) given in Plex, sent with media.rate events.
+    rating: Option<f64>,
+}
+
+/// Plex accounts whose plays are processed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountFilter {
+    /// Only the Plex server owner.
+    Owner,
+    /// Only users that the server is shared with.
+    Shared,
+    /// Every account.
+    #[default]
+    All,
+}
+
+/// Playback state changes that Plex reports through webhooks.
+#[derive(Debug, PartialEq)]
+pub enum PlaybackEvent {
+    Play,
+    Pause,
+    Stop,
+    Scrobble,
 }
 
 impl Webhook {
-    pub fn is_actionable(self: &Self, multi_season: bool) -> bool {
-        return self.event == "media.scrobble"
-            && self.metadata.media_type == "episode"
-            && (self.metadata.season_number == 1
-                || (multi_season && self.metadata.season_number >= 1));
+    pub fn is_actionable(self: &Self, multi_season: bool, movies: bool) -> bool {
+        if self.event != "media.scrobble" && self.event != CATCH_UP_EVENT {
+            return false;
+        }
+        return match self.metadata.media_type.as_str() {
+            "episode" => {
+                self.metadata.season_number == 1
+                    || (multi_season && self.metadata.season_number >= 1)
+            }
+            "movie" => movies,
+            _ => false,
+        };
+    }
+
+    pub fn matches_account_filter(self: &Self, account_filter: AccountFilter) -> bool {
+        return match account_filter {
+            AccountFilter::Owner => self.owner,
+            AccountFilter::Shared => !self.owner,
+            AccountFilter::All => true,
+        };
+    }
+
+    /// Check whether the webhook came from one of the given Plex servers, matched by
+    /// either the server name or UUID. Every server is accepted if none are given.
+    pub fn matches_server_filter(self: &Self, servers: &[String]) -> bool {
+        if servers.is_empty() {
+            return true;
+        }
+        return match &self.server {
+            Some(server) => servers
+                .iter()
+                .any(|x| x == &server.name || x == &server.uuid),
+            None => false,
+        };
+    }
+
+    /// Check whether the item is in one of the given Plex library sections, matched by
+    /// either the section title or ID. Every section is accepted if none are given.
+    pub fn matches_library_filter(self: &Self, libraries: &[String]) -> bool {
+        if libraries.is_empty() {
+            return true;
+        }
+        let metadata = &self.metadata;
+        let section_id = metadata.library_section_id.map(|x| x.to_string());
+        return libraries.iter().any(|x| {
+            metadata.library_section_title.as_ref() == Some(x) || section_id.as_ref() == Some(x)
+        });
+    }
+
+    pub fn playback_event(self: &Self) -> Option<PlaybackEvent> {
+        return match self.event.as_str() {
+            "media.play" | "media.resume" => Some(PlaybackEvent::Play),
+            "media.pause" => Some(PlaybackEvent::Pause),
+            "media.stop" => Some(PlaybackEvent::Stop),
+            "media.scrobble" => Some(PlaybackEvent::Scrobble),
+            _ => None,
+        };
+    }
+
+    /// Whether the webhook is a fabricated self-test scrobble instead of coming from Plex.
+    pub fn is_self_test(self: &Self) -> bool {
+        return self.event == SELF_TEST_EVENT;
+    }
+
+    /// Whether the webhook is a scrobble of a special (season 0) episode, which is only
+    /// processed if it is mapped to an Anilist entry.
+    pub fn is_special(self: &Self) -> bool {
+        return self.is_episode_scrobble() && self.metadata.season_number == 0;
+    }
+
+    /// Whether the webhook is a scrobble of a show episode in any season.
+    pub fn is_episode_scrobble(self: &Self) -> bool {
+        return (self.event == "media.scrobble" || self.event == CATCH_UP_EVENT)
+            && self.metadata.media_type == "episode";
+    }
+
+    /// Whether the webhook was replayed from the Plex watch history by a catch-up sync.
+    pub fn is_catch_up(self: &Self) -> bool {
+        return self.event == CATCH_UP_EVENT;
+    }
+
+    /// Plex rating (0-10) of a media.rate event. None for other events.
+    pub fn rating(self: &Self) -> Option<f64> {
+        if self.event != "media.rate" {
+            return None;
+        }
+        return self.rating;
+    }
+
+    /// Key for correlating playback events of one item on one player.
+    pub fn session_key(self: &Self) -> Option<String> {
+        let player = self.player.as_ref()?;
+        let rating_key = self.metadata.rating_key.as_ref()?;
+        return Some(format!("{}:{}", player.uuid, rating_key));
     }
 }
 
+/// Build a scrobble payload for the self-test, looking like a Plex webhook for the
+/// given show episode or movie.
+pub fn self_test_payload(title: &str, episode: i32, movie: bool, account: &str) -> String {
+    let metadata = if movie {
+        serde_json::json!({"type": "movie", "title": title})
+    } else {
+        serde_json::json!({
+            "type": "episode", "grandparentTitle": title, "parentIndex": 1, "index": episode
+        })
+    };
+    return serde_json::json!({
+        "event": SELF_TEST_EVENT,
+        "owner": true,
+        "user": true,
+        "Account": {"title": account},
+        "Metadata": metadata,
+    })
+    .to_string();
+}
+
 #[derive(Debug, Deserialize)]
 pub struct WebhookAccount {
     #[serde(rename = "title")]
@@ -27,28 +201,334 @@ pub struct WebhookAccount {
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(from = "RawWebhookMetadata")]
 pub struct WebhookMetadata {
-    #[serde(rename = "type")]
     pub media_type: String,
 
-    #[serde(rename = "grandparentTitle")]
+    /// Show title for episodes and the movie title for movies.
     pub title: String,
 
-    #[serde(rename = "parentIndex")]
     pub season_number: i32,
 
-    #[serde(rename = "index")]
     pub episode_number: i32,
+
+    pub rating_key: Option<String>,
+
+    /// Item duration in milliseconds.
+    pub duration: Option<u64>,
+
+    /// Playback position in milliseconds.
+    pub view_offset: Option<u64>,
+
+    /// Plex GUID of the show for episodes and of the movie for movies.
+    pub guid: Option<String>,
+
+    /// External IDs (e.g. `tmdb://1234`) of the item reported by the Plex agent.
+    pub external_guids: Vec<String>,
+
+    pub library_section_title: Option<String>,
+
+    pub library_section_id: Option<u64>,
+
+    /// Plex API path of the show for episodes and of the movie for movies, e.g.
+    /// `/library/metadata/1234`.
+    pub metadata_key: Option<String>,
+}
+
+impl WebhookMetadata {
+    pub fn is_movie(self: &Self) -> bool {
+        return self.media_type == "movie";
+    }
+
+    /// GUIDs that identify the show or movie, for matching GUID overrides. External
+    /// IDs of episodes identify the episode rather than the show, so they are only
+    /// used for movies.
+    pub fn override_guids(self: &Self) -> Vec<&String> {
+        let mut guids: Vec<&String> = self.guid.iter().collect();
+        if self.is_movie() {
+            guids.extend(self.external_guids.iter());
+        }
+        return guids;
+    }
+}
+
+/// Metadata as sent by Plex. Movies have no show title, season or episode number.
+#[derive(Deserialize)]
+struct RawWebhookMetadata {
+    #[serde(rename = "type")]
+    media_type: String,
+    #[serde(rename = "grandparentTitle")]
+    grandparent_title: Option<String>,
+    title: Option<String>,
+    #[serde(rename = "parentIndex")]
+    parent_index: Option<i32>,
+    index: Option<i32>,
+    #[serde(rename = "ratingKey")]
+    rating_key: Option<String>,
+    duration: Option<u64>,
+    #[serde(rename = "viewOffset")]
+    view_offset: Option<u64>,
+    guid: Option<String>,
+    #[serde(rename = "grandparentGuid")]
+    grandparent_guid: Option<String>,
+    #[serde(rename = "Guid", default)]
+    external_guids: Vec<RawWebhookGuid>,
+    #[serde(rename = "librarySectionTitle")]
+    library_section_title: Option<String>,
+    #[serde(rename = "librarySectionID")]
+    library_section_id: Option<u64>,
+    key: Option<String>,
+    #[serde(rename = "grandparentKey")]
+    grandparent_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RawWebhookGuid {
+    id: String,
+}
+
+impl From<RawWebhookMetadata> for WebhookMetadata {
+    fn from(raw: RawWebhookMetadata) -> Self {
+        // Movies are treated as the first and only episode of their first season.
+        let default_number = if raw.media_type == "movie" { 1 } else { 0 };
+        Self {
+            title: raw.grandparent_title.or(raw.title).unwrap_or_default(),
+            season_number: raw.parent_index.unwrap_or(default_number),
+            episode_number: raw.index.unwrap_or(default_number),
+            media_type: raw.media_type,
+            rating_key: raw.rating_key,
+            duration: raw.duration,
+            view_offset: raw.view_offset,
+            guid: raw.grandparent_guid.or(raw.guid),
+            external_guids: raw.external_guids.into_iter().map(|x| x.id).collect(),
+            library_section_title: raw.library_section_title,
+            library_section_id: raw.library_section_id,
+            metadata_key: raw.grandparent_key.or(raw.key),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MediaContainer<T> {
+    #[serde(rename = "MediaContainer")]
+    inner: T,
+}
+
+#[derive(Debug, Deserialize)]
+struct AccountsContainer {
+    #[serde(rename = "Account", default)]
+    accounts: Vec<ServerAccount>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ServerAccount {
+    id: u64,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SectionsContainer {
+    #[serde(rename = "Directory", default)]
+    sections: Vec<LibrarySection>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LibrarySection {
+    key: String,
+    title: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct MetadataContainer {
+    #[serde(rename = "Metadata", default)]
+    items: Vec<ItemMetadata>,
+}
+
+/// Metadata of a show or movie on the Plex server, used to retry failed matches.
+#[derive(Debug, Default, Deserialize)]
+pub struct ItemMetadata {
+    /// Title in the original language, often the romaji title for anime.
+    #[serde(rename = "originalTitle")]
+    pub original_title: Option<String>,
+    pub year: Option<i32>,
+    /// External IDs of the show or movie, e.g. `tvdb://1234`.
+    #[serde(rename = "Guid", default, deserialize_with = "guid_ids")]
+    pub guids: Vec<String>,
+}
+
+fn guid_ids<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    let guids: Vec<RawWebhookGuid> = Deserialize::deserialize(deserializer)?;
+    return Ok(guids.into_iter().map(|x| x.id).collect());
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryContainer {
+    #[serde(rename = "Metadata", default)]
+    items: Vec<HistoryItem>,
+}
+
+/// Watched item in the Plex watch history.
+#[derive(Debug, Deserialize)]
+struct HistoryItem {
+    #[serde(rename = "type")]
+    media_type: String,
+    title: Option<String>,
+    #[serde(rename = "grandparentTitle")]
+    grandparent_title: Option<String>,
+    #[serde(rename = "parentIndex")]
+    parent_index: Option<i32>,
+    index: Option<i32>,
+    #[serde(rename = "ratingKey")]
+    rating_key: Option<String>,
+    #[serde(rename = "accountID")]
+    account_id: Option<u64>,
+    /// Plex sends the section ID as a string in the history.
+    #[serde(rename = "librarySectionID")]
+    library_section_id: Option<serde_json::Value>,
+}
+
+impl HistoryItem {
+    fn library_section_id(self: &Self) -> Option<u64> {
+        return match &self.library_section_id {
+            Some(serde_json::Value::String(id)) => id.parse().ok(),
+            Some(id) => id.as_u64(),
+            None => None,
+        };
+    }
+}
+
+/// Build a scrobble payload from a watch history item, looking like the Plex webhook
+/// that would have been sent when the item was watched.
+fn catch_up_payload(
+    item: &HistoryItem,
+    accounts: &HashMap<u64, String>,
+    sections: &HashMap<u64, String>,
+) -> String {
+    let account = item.account_id.and_then(|x| accounts.get(&x));
+    let section_id = item.library_section_id();
+    return serde_json::json!({
+        "event": CATCH_UP_EVENT,
+        // The server owner always has the account ID 1.
+        "owner": item.account_id == Some(1),
+        "user": true,
+        "Account": {"title": account.map_or("", |x| x.as_str())},
+        "Metadata": {
+            "type": item.media_type,
+            "title": item.title,
+            "grandparentTitle": item.grandparent_title,
+            "parentIndex": item.parent_index,
+            "index": item.index,
+            "ratingKey": item.rating_key,
+            "librarySectionID": section_id,
+            "librarySectionTitle": section_id.and_then(|x| sections.get(&x)),
+        },
+    })
+    .to_string();
+}
+
+impl PlexServer {
+    async fn get<T: for<'a> Deserialize<'a>>(
+        self: &Self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<T, PlexError> {
+        let response = reqwest::Client::new()
+            .get(format!("{}{}", self.url.trim_end_matches('/'), path))
+            .query(query)
+            .header("Accept", "application/json")
+            .header("X-Plex-Token", &self.token)
+            .send()
+            .await
+            .map_err(|error| {
+                debug!("{}", error);
+                PlexError::Connection
+            })?;
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            return Err(PlexError::InvalidToken);
+        }
+        let container: MediaContainer<T> = response
+            .error_for_status()
+            .map_err(|_| PlexError::Connection)?
+            .json()
+            .await
+            .map_err(|_| PlexError::Parsing)?;
+        return Ok(container.inner);
+    }
+
+    /// Metadata of the show or movie at the given API path.
+    pub async fn item_metadata(self: &Self, key: &str) -> Result<ItemMetadata, PlexError> {
+        let container: MetadataContainer = self.get(key, &[]).await?;
+        return container.items.into_iter().next().ok_or(PlexError::Parsing);
+    }
+
+    /// Scrobble payloads for the items watched since the given Unix timestamp, or the
+    /// whole watch history, oldest first.
+    pub async fn catch_up_payloads(
+        self: &Self,
+        since: Option<u64>,
+    ) -> Result<Vec<String>, PlexError> {
+        let accounts: AccountsContainer = self.get("/accounts", &[]).await?;
+        let accounts: HashMap<u64, String> = accounts
+            .accounts
+            .into_iter()
+            .map(|x| (x.id, x.name))
+            .collect();
+        let sections: SectionsContainer = self.get("/library/sections", &[]).await?;
+        let sections: HashMap<u64, String> = sections
+            .sections
+            .into_iter()
+            .filter_map(|x| Some((x.key.parse().ok()?, x.title)))
+            .collect();
+        let mut payloads = Vec::new();
+        loop {
+            let mut query = vec![
+                ("sort", String::from("viewedAt:asc")),
+                ("X-Plex-Container-Start", payloads.len().to_string()),
+                ("X-Plex-Container-Size", HISTORY_PAGE_SIZE.to_string()),
+            ];
+            if let Some(since) = since {
+                query.push(("viewedAt>", since.to_string()));
+            }
+            let history: HistoryContainer =
+                self.get("/status/sessions/history/all", &query).await?;
+            let count = history.items.len();
+            payloads.extend(
+                history
+                    .items
+                    .iter()
+                    .map(|x| catch_up_payload(x, &accounts, &sections)),
+            );
+            if count < HISTORY_PAGE_SIZE {
+                return Ok(payloads);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WebhookPlayer {
+    pub uuid: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WebhookServer {
+    #[serde(rename = "title")]
+    pub name: String,
+    pub uuid: String,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use test_case::test_case;
+
     #[test]
     fn webhook_actionable() {
         let webhook = Webhook {
             event: String::from("media.scrobble"),
+            owner: true,
+            user: true,
             account: WebhookAccount {
                 name: String::from("yukikaze"),
             },
@@ -57,9 +537,20 @@ mod tests {
                 title: String::from("Onii-chan wa Oshimai!"),
                 season_number: 1,
                 episode_number: 4,
+                rating_key: None,
+                duration: None,
+                view_offset: None,
+                guid: None,
+                external_guids: vec![],
+                library_section_title: None,
+                library_section_id: None,
+                metadata_key: None,
             },
+            player: None,
+            server: None,
+            rating: None,
         };
-        assert_eq!(webhook.is_actionable(false), true);
+        assert!(webhook.is_actionable(false, false));
     }
 
     #[test]
@@ -67,6 +558,8 @@ mod tests {
     fn webhook_actionable_first_episode() {
         let webhook = Webhook {
             event: String::from("media.scrobble"),
+            owner: true,
+            user: true,
             account: WebhookAccount {
                 name: String::from("yukikaze"),
             },
@@ -75,9 +568,20 @@ mod tests {
                 title: String::from("Onii-chan wa Oshimai!"),
                 season_number: 1,
                 episode_number: 1,
+                rating_key: None,
+                duration: None,
+                view_offset: None,
+                guid: None,
+                external_guids: vec![],
+                library_section_title: None,
+                library_section_id: None,
+                metadata_key: None,
             },
+            player: None,
+            server: None,
+            rating: None,
         };
-        assert_eq!(webhook.is_actionable(false), true);
+        assert!(webhook.is_actionable(false, false));
     }
 
     #[test]
@@ -85,6 +589,8 @@ mod tests {
     fn webhook_actionable_music() {
         let webhook = Webhook {
             event: String::from("media.scrobble"),
+            owner: true,
+            user: true,
             account: WebhookAccount {
                 name: String::from("yukikaze"),
             },
@@ -93,9 +599,20 @@ mod tests {
                 title: String::from("Onii-chan wa Oshimai!"),
                 season_number: 1,
                 episode_number: 4,
+                rating_key: None,
+                duration: None,
+                view_offset: None,
+                guid: None,
+                external_guids: vec![],
+                library_section_title: None,
+                library_section_id: None,
+                metadata_key: None,
             },
+            player: None,
+            server: None,
+            rating: None,
         };
-        assert_eq!(webhook.is_actionable(false), false);
+        assert!(!webhook.is_actionable(false, false));
     }
 
     #[test]
@@ -103,6 +620,8 @@ mod tests {
     fn webhook_actionable_playback() {
         let webhook = Webhook {
             event: String::from("media.play"),
+            owner: true,
+            user: true,
             account: WebhookAccount {
                 name: String::from("yukikaze"),
             },
@@ -111,9 +630,20 @@ mod tests {
                 title: String::from("Onii-chan wa Oshimai!"),
                 season_number: 1,
                 episode_number: 4,
+                rating_key: None,
+                duration: None,
+                view_offset: None,
+                guid: None,
+                external_guids: vec![],
+                library_section_title: None,
+                library_section_id: None,
+                metadata_key: None,
             },
+            player: None,
+            server: None,
+            rating: None,
         };
-        assert_eq!(webhook.is_actionable(false), false);
+        assert!(!webhook.is_actionable(false, false));
     }
 
     #[test]
@@ -121,6 +651,8 @@ mod tests {
     fn webhook_actionable_second_season() {
         let webhook = Webhook {
             event: String::from("media.scrobble"),
+            owner: true,
+            user: true,
             account: WebhookAccount {
                 name: String::from("yukikaze"),
             },
@@ -129,9 +661,20 @@ mod tests {
                 title: String::from("Kidou Senshi Gundam: Suisei no Majo"),
                 season_number: 2,
                 episode_number: 4,
+                rating_key: None,
+                duration: None,
+                view_offset: None,
+                guid: None,
+                external_guids: vec![],
+                library_section_title: None,
+                library_section_id: None,
+                metadata_key: None,
             },
+            player: None,
+            server: None,
+            rating: None,
         };
-        assert_eq!(webhook.is_actionable(false), false);
+        assert!(!webhook.is_actionable(false, false));
     }
 
     #[test]
@@ -139,6 +682,8 @@ mod tests {
     fn webhook_actionable_second_season_multi_season() {
         let webhook = Webhook {
             event: String::from("media.scrobble"),
+            owner: true,
+            user: true,
             account: WebhookAccount {
                 name: String::from("yukikaze"),
             },
@@ -147,9 +692,20 @@ mod tests {
                 title: String::from("Kidou Senshi Gundam: Suisei no Majo"),
                 season_number: 2,
                 episode_number: 4,
+                rating_key: None,
+                duration: None,
+                view_offset: None,
+                guid: None,
+                external_guids: vec![],
+                library_section_title: None,
+                library_section_id: None,
+                metadata_key: None,
             },
+            player: None,
+            server: None,
+            rating: None,
         };
-        assert_eq!(webhook.is_actionable(true), true);
+        assert!(webhook.is_actionable(true, false));
     }
 
     #[test]
@@ -157,6 +713,8 @@ mod tests {
     fn webhook_actionable_special() {
         let webhook = Webhook {
             event: String::from("media.scrobble"),
+            owner: true,
+            user: true,
             account: WebhookAccount {
                 name: String::from("yukikaze"),
             },
@@ -165,9 +723,21 @@ mod tests {
                 title: String::from("Bakemonogatari"),
                 season_number: 0,
                 episode_number: 3,
+                rating_key: None,
+                duration: None,
+                view_offset: None,
+                guid: None,
+                external_guids: vec![],
+                library_section_title: None,
+                library_section_id: None,
+                metadata_key: None,
             },
+            player: None,
+            server: None,
+            rating: None,
         };
-        assert_eq!(webhook.is_actionable(false), false);
+        assert!(!webhook.is_actionable(false, false));
+        assert!(webhook.is_special());
     }
 
     #[test]
@@ -175,6 +745,8 @@ mod tests {
     fn webhook_actionable_special_multi_season() {
         let webhook = Webhook {
             event: String::from("media.scrobble"),
+            owner: true,
+            user: true,
             account: WebhookAccount {
                 name: String::from("yukikaze"),
             },
@@ -183,8 +755,247 @@ mod tests {
                 title: String::from("Bakemonogatari"),
                 season_number: 0,
                 episode_number: 3,
+                rating_key: None,
+                duration: None,
+                view_offset: None,
+                guid: None,
+                external_guids: vec![],
+                library_section_title: None,
+                library_section_id: None,
+                metadata_key: None,
+            },
+            player: None,
+            server: None,
+            rating: None,
+        };
+        assert!(!webhook.is_actionable(true, false));
+    }
+
+    #[test]
+    // Movies are only actionable when movie support is enabled.
+    fn webhook_actionable_movie() {
+        let webhook = Webhook {
+            event: String::from("media.scrobble"),
+            owner: true,
+            user: true,
+            account: WebhookAccount {
+                name: String::from("yukikaze"),
+            },
+            metadata: WebhookMetadata {
+                media_type: String::from("movie"),
+                title: String::from("Kimi no Na wa."),
+                season_number: 1,
+                episode_number: 1,
+                rating_key: None,
+                duration: None,
+                view_offset: None,
+                guid: None,
+                external_guids: vec![],
+                library_section_title: None,
+                library_section_id: None,
+                metadata_key: None,
+            },
+            player: None,
+            server: None,
+            rating: None,
+        };
+        assert!(!webhook.is_actionable(false, false));
+        assert!(webhook.is_actionable(false, true));
+    }
+
+    #[test_case(true, AccountFilter::Owner, true ; "owner with owner filter")]
+    #[test_case(false, AccountFilter::Owner, false ; "shared user with owner filter")]
+    #[test_case(true, AccountFilter::Shared, false ; "owner with shared filter")]
+    #[test_case(false, AccountFilter::Shared, true ; "shared user with shared filter")]
+    #[test_case(false, AccountFilter::All, true ; "shared user with no filter")]
+    fn webhook_account_filter(owner: bool, account_filter: AccountFilter, expected: bool) {
+        let webhook: Webhook = serde_json::from_str(&format!(
+            "{{\"event\": \"media.scrobble\", \"owner\": {}, \"user\": true, \
+            \"Account\": {{\"title\": \"yukikaze\"}}, \"Metadata\": {{\"type\": \"episode\"}}}}",
+            owner
+        ))
+        .unwrap();
+        assert_eq!(webhook.matches_account_filter(account_filter), expected);
+    }
+
+    #[test]
+    fn webhook_metadata_movie() {
+        let metadata: WebhookMetadata =
+            serde_json::from_str("{\"type\": \"movie\", \"title\": \"Kimi no Na wa.\"}").unwrap();
+        assert!(metadata.is_movie());
+        assert_eq!(metadata.title, "Kimi no Na wa.");
+        assert_eq!(metadata.season_number, 1);
+        assert_eq!(metadata.episode_number, 1);
+    }
+
+    #[test]
+    fn webhook_metadata_episode() {
+        let metadata: WebhookMetadata = serde_json::from_str(
+            "{\"type\": \"episode\", \"title\": \"Onii-chan wa Onii-chan no Mama?\", \
+            \"grandparentTitle\": \"Onii-chan wa Oshimai!\", \"parentIndex\": 1, \"index\": 4}",
+        )
+        .unwrap();
+        assert!(!metadata.is_movie());
+        assert_eq!(metadata.title, "Onii-chan wa Oshimai!");
+        assert_eq!(metadata.season_number, 1);
+        assert_eq!(metadata.episode_number, 4);
+    }
+
+    #[test]
+    fn webhook_metadata_guids() {
+        let metadata: WebhookMetadata = serde_json::from_str(
+            "{\"type\": \"episode\", \"guid\": \"plex://episode/1\", \
+            \"grandparentGuid\": \"plex://show/1\", \"Guid\": [{\"id\": \"tvdb://2\"}]}",
+        )
+        .unwrap();
+        assert_eq!(metadata.override_guids(), vec!["plex://show/1"]);
+        let metadata: WebhookMetadata = serde_json::from_str(
+            "{\"type\": \"movie\", \"guid\": \"plex://movie/1\", \
+            \"Guid\": [{\"id\": \"tmdb://2\"}, {\"id\": \"imdb://tt3\"}]}",
+        )
+        .unwrap();
+        assert_eq!(
+            metadata.override_guids(),
+            vec!["plex://movie/1", "tmdb://2", "imdb://tt3"]
+        );
+    }
+
+    #[test_case("media.rate", ", \"rating\": 8.0", Some(8.0) ; "rated")]
+    #[test_case("media.rate", "", None ; "rating removed")]
+    #[test_case("media.scrobble", ", \"rating\": 8.0", None ; "other event")]
+    fn webhook_rating(event: &str, rating: &str, expected: Option<f64>) {
+        let webhook: Webhook = serde_json::from_str(&format!(
+            "{{\"event\": \"{}\", \"Account\": {{\"title\": \"yukikaze\"}}, \
+            \"Metadata\": {{\"type\": \"show\", \"title\": \"Yuru Camp\"}}{}}}",
+            event, rating
+        ))
+        .unwrap();
+        assert_eq!(webhook.rating(), expected);
+        assert_eq!(webhook.metadata.title, "Yuru Camp");
+    }
+
+    #[test_case(false, "episode", 5 ; "episode")]
+    #[test_case(true, "movie", 1 ; "movie")]
+    fn self_test_webhook(movie: bool, expected_type: &str, expected_episode: i32) {
+        let payload = self_test_payload("Yuru Camp", 5, movie, "yukikaze");
+        let webhook: Webhook = serde_json::from_str(&payload).unwrap();
+        assert!(webhook.is_self_test());
+        assert_eq!(webhook.metadata.media_type, expected_type);
+        assert_eq!(webhook.metadata.title, "Yuru Camp");
+        assert_eq!(webhook.metadata.episode_number, expected_episode);
+        assert_eq!(webhook.account.name, "yukikaze");
+    }
+
+    #[test_case("{\"type\": \"episode\", \"grandparentTitle\": \"Yuru Camp\", \"title\": \"Mount Fuji and Curry Noodles\", \
+        \"parentIndex\": 1, \"index\": 1, \"accountID\": 1, \"librarySectionID\": \"2\"}", "episode", "yukikaze", true, Some("Anime") ; "owner episode")]
+    #[test_case("{\"type\": \"movie\", \"title\": \"Yuru Camp Movie\", \"accountID\": 5}", "movie", "", false, None ; "shared movie")]
+    fn catch_up_webhook(
+        item: &str,
+        expected_type: &str,
+        expected_account: &str,
+        expected_owner: bool,
+        expected_section: Option<&str>,
+    ) {
+        let item: HistoryItem = serde_json::from_str(item).unwrap();
+        let accounts = HashMap::from([(1, String::from("yukikaze"))]);
+        let sections = HashMap::from([(2, String::from("Anime"))]);
+        let payload = catch_up_payload(&item, &accounts, &sections);
+        let webhook: Webhook = serde_json::from_str(&payload).unwrap();
+        assert!(webhook.is_catch_up());
+        assert!(webhook.is_actionable(false, true));
+        assert_eq!(webhook.metadata.media_type, expected_type);
+        assert!(webhook.metadata.title.starts_with("Yuru Camp"));
+        assert_eq!(webhook.metadata.episode_number, 1);
+        assert_eq!(webhook.account.name, expected_account);
+        assert_eq!(webhook.owner, expected_owner);
+        assert_eq!(
+            webhook.metadata.library_section_title.as_deref(),
+            expected_section
+        );
+    }
+
+    #[test_case("{\"type\": \"episode\", \"key\": \"/library/metadata/12\", \"grandparentKey\": \"/library/metadata/10\"}", Some("/library/metadata/10") ; "episode")]
+    #[test_case("{\"type\": \"movie\", \"key\": \"/library/metadata/12\"}", Some("/library/metadata/12") ; "movie")]
+    #[test_case("{\"type\": \"movie\"}", None ; "missing")]
+    fn webhook_metadata_key(metadata: &str, expected: Option<&str>) {
+        let metadata: WebhookMetadata = serde_json::from_str(metadata).unwrap();
+        assert_eq!(metadata.metadata_key.as_deref(), expected);
+    }
+
+    #[test]
+    fn item_metadata_deserialize() {
+        let container: MediaContainer<MetadataContainer> = serde_json::from_str(
+            "{\"MediaContainer\": {\"Metadata\": [{\"title\": \"Laid-Back Camp\", \
+            \"originalTitle\": \"Yuru Camp\", \"year\": 2018, \
+            \"Guid\": [{\"id\": \"tvdb://339506\"}, {\"id\": \"tmdb://76075\"}]}]}}",
+        )
+        .unwrap();
+        let metadata = &container.inner.items[0];
+        assert_eq!(metadata.original_title.as_deref(), Some("Yuru Camp"));
+        assert_eq!(metadata.year, Some(2018));
+        assert_eq!(metadata.guids, vec!["tvdb://339506", "tmdb://76075"]);
+    }
+
+    #[test]
+    fn webhook_session_key() {
+        let webhook = Webhook {
+            event: String::from("media.play"),
+            owner: true,
+            user: true,
+            account: WebhookAccount {
+                name: String::from("yukikaze"),
             },
+            metadata: WebhookMetadata {
+                media_type: String::from("episode"),
+                title: String::from("Onii-chan wa Oshimai!"),
+                season_number: 1,
+                episode_number: 4,
+                rating_key: Some(String::from("1234")),
+                duration: Some(1440000),
+                view_offset: None,
+                guid: None,
+                external_guids: vec![],
+                library_section_title: None,
+                library_section_id: None,
+                metadata_key: None,
+            },
+            player: Some(WebhookPlayer {
+                uuid: String::from("abcdef"),
+            }),
+            server: None,
+            rating: None,
         };
-        assert_eq!(webhook.is_actionable(true), false);
+        assert_eq!(webhook.playback_event(), Some(PlaybackEvent::Play));
+        assert_eq!(webhook.session_key(), Some(String::from("abcdef:1234")));
+    }
+
+    #[test_case(&[], true ; "no filter")]
+    #[test_case(&["Burakku"], true ; "server name")]
+    #[test_case(&["Shiranui", "0123456789abcdef"], true ; "server uuid")]
+    #[test_case(&["Shiranui"], false ; "other server")]
+    fn webhook_server_filter(servers: &[&str], expected: bool) {
+        let webhook: Webhook = serde_json::from_str(
+            "{\"event\": \"media.scrobble\", \"Account\": {\"title\": \"yukikaze\"}, \
+            \"Server\": {\"title\": \"Burakku\", \"uuid\": \"0123456789abcdef\"}, \
+            \"Metadata\": {\"type\": \"episode\", \"grandparentTitle\": \"Yuru Camp\"}}",
+        )
+        .unwrap();
+        let servers: Vec<String> = servers.iter().map(|x| x.to_string()).collect();
+        assert_eq!(webhook.matches_server_filter(&servers), expected);
+    }
+
+    #[test_case(&[], true ; "no filter")]
+    #[test_case(&["Anime"], true ; "section title")]
+    #[test_case(&["TV Shows", "3"], true ; "section id")]
+    #[test_case(&["TV Shows"], false ; "other section")]
+    fn webhook_library_filter(libraries: &[&str], expected: bool) {
+        let webhook: Webhook = serde_json::from_str(
+            "{\"event\": \"media.scrobble\", \"Account\": {\"title\": \"yukikaze\"}, \
+            \"Metadata\": {\"type\": \"episode\", \"grandparentTitle\": \"Yuru Camp\", \
+            \"librarySectionTitle\": \"Anime\", \"librarySectionID\": 3}}",
+        )
+        .unwrap();
+        let libraries: Vec<String> = libraries.iter().map(|x| x.to_string()).collect();
+        assert_eq!(webhook.matches_library_filter(&libraries), expected);
     }
 }
@@ -0,0 +1,214 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+
+use crate::anilist;
+use crate::data::{api, forms, state};
+
+/// Seconds between replications unless configured otherwise.
+pub const DEFAULT_INTERVAL: u64 = 300;
+
+#[derive(Debug)]
+pub enum ReplicationError {
+    Connection,
+    Unauthorized,
+    Parsing,
+}
+
+/// Fetch the overrides export of the primary instance.
+pub async fn fetch(primary: &str, api_key: Option<&str>) -> Result<String, ReplicationError> {
+    return get(primary, "/api/export", api_key).await;
+}
+
+/// Fetch the Anilist tokens and runtime settings of the primary instance.
+pub async fn fetch_snapshot(
+    primary: &str,
+    api_key: Option<&str>,
+) -> Result<String, ReplicationError> {
+    return get(primary, "/api/replication/snapshot", api_key).await;
+}
+
+async fn get(primary: &str, path: &str, api_key: Option<&str>) -> Result<String, ReplicationError> {
+    let url = format!("{}{}", primary.trim_end_matches('/'), path);
+    let mut request = reqwest::Client::new().get(url);
+    if let Some(api_key) = api_key {
+        request = request.bearer_auth(api_key);
+    }
+    let response = request
+        .send()
+        .await
+        .map_err(|_| ReplicationError::Connection)?;
+    let status = response.status();
+    if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+        return Err(ReplicationError::Unauthorized);
+    }
+    return response
+        .error_for_status()
+        .map_err(|_| ReplicationError::Connection)?
+        .text()
+        .await
+        .map_err(|_| ReplicationError::Connection);
+}
+
+/// Replace all overrides with the ones in an export of the primary instance. Nothing
+/// is changed once this instance has been promoted.
+pub async fn apply(
+    export: &str,
+    state: &state::Global,
+) -> Result<Option<api::ImportSummary>, ReplicationError> {
+    let import: api::Import = serde_json::from_str(export).map_err(|error| {
+        debug!("{}", error);
+        ReplicationError::Parsing
+    })?;
+    let mut overrides = state.overrides_mut().await;
+    if !state.replication.read().await.is_read_only() {
+        return Ok(None);
+    }
+    overrides.clear();
+    return Ok(Some(
+        import.apply(forms::ImportConflict::Replace, &mut overrides),
+    ));
+}
+
+/// Replace the Anilist tokens and runtime settings with the ones in a snapshot of the
+/// primary instance. Tokens that this instance does not have yet are checked with
+/// Anilist first. Returns false if nothing was changed because this instance has been
+/// promoted.
+pub async fn apply_snapshot(
+    snapshot: &str,
+    state: &state::Global,
+) -> Result<bool, ReplicationError> {
+    let snapshot: api::ReplicationSnapshot = serde_json::from_str(snapshot).map_err(|error| {
+        debug!("{}", error);
+        ReplicationError::Parsing
+    })?;
+    let known: Vec<String> = state
+        .accounts
+        .read()
+        .await
+        .iter()
+        .map(|x| x.token.clone())
+        .collect();
+    let mut users = Vec::new();
+    for account in snapshot
+        .accounts
+        .iter()
+        .filter(|x| !known.contains(&x.token))
+    {
+        match anilist::get_user(&state.anilist, &account.token).await {
+            Ok(user) => users.push((account, user)),
+            Err(anilist::AnilistError::InvalidToken) => {
+                warn!("Not replicating the invalid token of {}", account.label);
+            }
+            Err(_) => return Err(ReplicationError::Connection),
+        }
+    }
+    if !state.replication.read().await.is_read_only() {
+        return Ok(false);
+    }
+    let mut accounts = state.accounts.write().await;
+    for (account, user) in users {
+        accounts.add(account.label.clone(), account.token.clone(), user);
+    }
+    let active = accounts
+        .iter()
+        .find(|x| {
+            snapshot
+                .accounts
+                .iter()
+                .any(|y| y.active && y.token == x.token)
+        })
+        .map(|x| x.id);
+    if let Some(active) = active {
+        accounts.activate(active);
+    }
+    drop(accounts);
+    *state.discord_events.write().await = snapshot.discord_events;
+    *state.notifiers.write().await = state::Notifiers::new(snapshot.notifiers);
+    *state.sync_pause.write().await = snapshot.sync_pause;
+    return Ok(true);
+}
+
+/// What the previous replication received from the primary instance.
+#[derive(Default)]
+struct Previous {
+    export: Option<String>,
+    snapshot: Option<String>,
+    /// Whether the primary refused to share the snapshot, which is only logged once.
+    snapshot_refused: bool,
+}
+
+/// Replicate the overrides, Anilist tokens and runtime settings from the primary
+/// instance. Exports and snapshots that have not changed since the previous
+/// replication are not applied again. If the primary does not share its snapshot, only
+/// the overrides are replicated.
+async fn replicate(
+    primary: &str,
+    api_key: Option<&str>,
+    state: &state::Global,
+    previous: &mut Previous,
+) -> Result<(), ReplicationError> {
+    let export = fetch(primary, api_key).await?;
+    if previous.export.as_ref() != Some(&export) {
+        if let Some(summary) = apply(&export, state).await? {
+            info!(
+                "Replicated {} overrides from {} ({} invalid)",
+                summary.imported, primary, summary.invalid
+            );
+        }
+        previous.export = Some(export);
+    }
+    let snapshot = match fetch_snapshot(primary, api_key).await {
+        Ok(snapshot) => snapshot,
+        Err(ReplicationError::Unauthorized) => {
+            if !previous.snapshot_refused {
+                warn!(
+                    "{} does not share its tokens and settings, replicating only the overrides",
+                    primary
+                );
+                previous.snapshot_refused = true;
+            }
+            return Ok(());
+        }
+        Err(error) => return Err(error),
+    };
+    previous.snapshot_refused = false;
+    if previous.snapshot.as_ref() != Some(&snapshot) {
+        if apply_snapshot(&snapshot, state).await? {
+            info!("Replicated the tokens and settings from {}", primary);
+        }
+        previous.snapshot = Some(snapshot);
+    }
+    return Ok(());
+}
+
+/// Replicate from the primary instance periodically until this instance is promoted.
+pub async fn run(
+    primary: String,
+    api_key: Option<String>,
+    interval: Duration,
+    state: Arc<state::Global>,
+) {
+    let mut previous = Previous::default();
+    loop {
+        if !state.replication.read().await.is_read_only() {
+            info!("Standby promoted, stopping replication from {}", primary);
+            return;
+        }
+        let result = replicate(&primary, api_key.as_deref(), &state, &mut previous).await;
+        let mut replication = state.replication.write().await;
+        match result {
+            Ok(()) => {
+                replication.last_sync = Some(state::unix_timestamp());
+                replication.last_error = None;
+            }
+            Err(error) => {
+                warn!("Could not replicate from {}: {:?}", primary, error);
+                replication.last_error = Some(format!("{:?}", error));
+            }
+        }
+        drop(replication);
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt;
+
+use crate::anilist;
+use crate::data;
+
+/// Where processing a webhook records its steps, so that live webhooks and replays go
+/// through the same pipeline. Live webhooks collect the report of the decision, while
+/// dry runs also trace every step and skip anything that updates Anilist or the state.
+#[derive(Default)]
+pub struct Sink {
+    dry_run: bool,
+    report: data::api::ScrobbleReport,
+    replay: data::api::Replay,
+}
+
+impl Sink {
+    pub fn live() -> Self {
+        return Self::default();
+    }
+
+    pub fn dry_run() -> Self {
+        return Self {
+            dry_run: true,
+            ..Default::default()
+        };
+    }
+
+    pub fn is_dry_run(self: &Self) -> bool {
+        return self.dry_run;
+    }
+
+    /// Trace a step of a dry run. The detail is only built for dry runs.
+    pub fn step(self: &mut Self, step: &'static str, detail: impl FnOnce() -> String) {
+        if self.dry_run {
+            self.replay.step(step, detail());
+        }
+    }
+
+    /// Trace a filter that the webhook passed.
+    pub fn pass(self: &mut Self, step: &'static str) {
+        self.step(step, || String::from("passed"));
+    }
+
+    /// Reject the webhook at a filter with the code of the reason.
+    pub fn reject(self: &mut Self, step: &'static str, code: &'static str) -> &'static str {
+        self.step(step, || String::from("rejected"));
+        self.reason(code);
+        self.action(|| String::from("ignore"));
+        return "NO OP";
+    }
+
+    /// Add a reason code explaining the decision, e.g. plex_user or muted.
+    pub fn reason(self: &mut Self, code: &'static str) {
+        self.report.reasons.push(code);
+    }
+
+    pub fn outcome(self: &mut Self, outcome: &data::state::HistoryOutcome) {
+        self.report.outcome = Some(outcome.clone());
+    }
+
+    /// Record the Anilist entry that the webhook was matched to.
+    pub fn media(
+        self: &mut Self,
+        anilist_id: i32,
+        title: &impl fmt::Display,
+        episode: Option<i32>,
+    ) {
+        self.report.media = Some(data::api::ScrobbleMedia {
+            anilist_id,
+            title: title.to_string(),
+            episode,
+        });
+    }
+
+    pub fn confidence(self: &mut Self, confidence: f64) {
+        self.report.confidence = Some(confidence);
+    }
+
+    /// Trace the best fuzzy match candidates of a dry run.
+    pub fn candidates(self: &mut Self, candidates: impl FnOnce() -> Vec<anilist::MatchCandidate>) {
+        if !self.dry_run {
+            return;
+        }
+        self.replay.candidates = candidates();
+        let candidates: Vec<String> = self
+            .replay
+            .candidates
+            .iter()
+            .map(|x| format!("{} ({}): {:.3}", x.title, x.anilist_id, x.confidence))
+            .collect();
+        self.replay.step("candidates", candidates.join(", "));
+    }
+
+    /// Describe what a dry run does with the webhook.
+    pub fn action(self: &mut Self, action: impl FnOnce() -> String) {
+        if self.dry_run {
+            self.replay.action = action();
+        }
+    }
+
+    pub fn into_report(self: Self, decision: &'static str) -> data::api::ScrobbleReport {
+        return data::api::ScrobbleReport {
+            decision,
+            ..self.report
+        };
+    }
+
+    pub fn into_replay(self: Self) -> data::api::Replay {
+        return self.replay;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn live_sink_skips_trace() {
+        let mut sink = Sink::live();
+        sink.step("parse", || panic!("built the detail of a live webhook"));
+        assert_eq!(sink.reject("plex_user", "plex_user"), "NO OP");
+        sink.media(98444, &"Yuru Camp", Some(2));
+        let report = sink.into_report("NO OP");
+        assert_eq!(report.decision, "NO OP");
+        assert_eq!(report.reasons, vec!["plex_user"]);
+        assert_eq!(report.media.map(|x| x.anilist_id), Some(98444));
+    }
+
+    #[test]
+    fn dry_run_sink_traces_steps() {
+        let mut sink = Sink::dry_run();
+        sink.pass("plex_user");
+        sink.reject("plex_server", "plex_server");
+        let replay = sink.into_replay();
+        let steps: Vec<&str> = replay.steps.iter().map(|x| x.step).collect();
+        assert_eq!(steps, vec!["plex_user", "plex_server"]);
+        assert_eq!(replay.action, "ignore");
+    }
+}
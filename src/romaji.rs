@@ -0,0 +1,200 @@
+/// Hepburn romaji of a hiragana character. Katakana is converted to hiragana first.
+fn kana_romaji(chr: char) -> Option<&'static str> {
+    let romaji = match chr {
+        'あ' => "a",
+        'い' => "i",
+        'う' => "u",
+        'え' => "e",
+        'お' => "o",
+        'か' | 'ゕ' => "ka",
+        'き' => "ki",
+        'く' => "ku",
+        'け' | 'ゖ' => "ke",
+        'こ' => "ko",
+        'が' => "ga",
+        'ぎ' => "gi",
+        'ぐ' => "gu",
+        'げ' => "ge",
+        'ご' => "go",
+        'さ' => "sa",
+        'し' => "shi",
+        'す' => "su",
+        'せ' => "se",
+        'そ' => "so",
+        'ざ' => "za",
+        'じ' | 'ぢ' => "ji",
+        'ず' | 'づ' => "zu",
+        'ぜ' => "ze",
+        'ぞ' => "zo",
+        'た' => "ta",
+        'ち' => "chi",
+        'つ' => "tsu",
+        'て' => "te",
+        'と' => "to",
+        'だ' => "da",
+        'で' => "de",
+        'ど' => "do",
+        'な' => "na",
+        'に' => "ni",
+        'ぬ' => "nu",
+        'ね' => "ne",
+        'の' => "no",
+        'は' => "ha",
+        'ひ' => "hi",
+        'ふ' => "fu",
+        'へ' => "he",
+        'ほ' => "ho",
+        'ば' => "ba",
+        'び' => "bi",
+        'ぶ' => "bu",
+        'べ' => "be",
+        'ぼ' => "bo",
+        'ぱ' => "pa",
+        'ぴ' => "pi",
+        'ぷ' => "pu",
+        'ぺ' => "pe",
+        'ぽ' => "po",
+        'ま' => "ma",
+        'み' => "mi",
+        'む' => "mu",
+        'め' => "me",
+        'も' => "mo",
+        'や' => "ya",
+        'ゆ' => "yu",
+        'よ' => "yo",
+        'ら' => "ra",
+        'り' => "ri",
+        'る' => "ru",
+        'れ' => "re",
+        'ろ' => "ro",
+        'わ' => "wa",
+        'ゐ' => "i",
+        'ゑ' => "e",
+        'を' => "wo",
+        'ん' => "n",
+        'ゔ' => "vu",
+        _ => return None,
+    };
+    return Some(romaji);
+}
+
+/// Vowel of a small kana that combines with the previous kana, e.g. the ゃ in きゃ.
+fn small_kana_vowel(chr: char) -> Option<(bool, char)> {
+    return match chr {
+        'ゃ' => Some((true, 'a')),
+        'ゅ' => Some((true, 'u')),
+        'ょ' => Some((true, 'o')),
+        'ぁ' => Some((false, 'a')),
+        'ぃ' => Some((false, 'i')),
+        'ぅ' => Some((false, 'u')),
+        'ぇ' => Some((false, 'e')),
+        'ぉ' => Some((false, 'o')),
+        'ゎ' => Some((false, 'a')),
+        _ => None,
+    };
+}
+
+fn to_hiragana(chr: char) -> char {
+    if ('ァ'..='ヶ').contains(&chr) {
+        return char::from_u32(chr as u32 - 0x60).unwrap_or(chr);
+    }
+    return chr;
+}
+
+fn is_ideograph(chr: char) -> bool {
+    return ('\u{4E00}'..='\u{9FFF}').contains(&chr)
+        || ('\u{3400}'..='\u{4DBF}').contains(&chr)
+        || chr == '々';
+}
+
+fn is_vowel(chr: char) -> bool {
+    return matches!(chr, 'a' | 'e' | 'i' | 'o' | 'u');
+}
+
+/// Transliterate the kana of a title to Hepburn romaji, keeping other characters as
+/// they are. Returns None if the title has no kana, or has kanji, which cannot be
+/// read without a dictionary.
+pub fn transliterate(title: &str) -> Option<String> {
+    let mut output = String::new();
+    let mut has_kana = false;
+    let mut geminate = false;
+    for chr in title.chars().map(to_hiragana) {
+        if is_ideograph(chr) {
+            return None;
+        }
+        if chr == 'っ' {
+            // Doubles the consonant of the next kana.
+            geminate = true;
+            continue;
+        }
+        if let Some((palatal, vowel)) = small_kana_vowel(chr) {
+            let previous = output.chars().last();
+            if palatal && ["shi", "chi", "ji"].iter().any(|x| output.ends_with(x)) {
+                // Sha, cha and ja instead of shya, chya and jya.
+                output.pop();
+            } else if palatal && output.ends_with('i') {
+                output.pop();
+                output.push('y');
+            } else if !palatal && previous.is_some_and(is_vowel) {
+                // Replaces the vowel of the previous kana, e.g. fa from ファ.
+                let consonant = output.chars().rev().nth(1);
+                if consonant.is_some_and(|x| x.is_ascii_alphabetic() && !is_vowel(x)) {
+                    output.pop();
+                }
+            } else if palatal {
+                output.push('y');
+            }
+            output.push(vowel);
+            has_kana = true;
+            geminate = false;
+            continue;
+        }
+        if chr == 'ー' {
+            // Long vowel mark repeats the previous vowel.
+            if let Some(vowel) = output.chars().last().filter(|x| is_vowel(*x)) {
+                output.push(vowel);
+            }
+            continue;
+        }
+        if chr == '・' {
+            output.push(' ');
+            continue;
+        }
+        match kana_romaji(chr) {
+            Some(romaji) => {
+                if geminate && !is_vowel(romaji.chars().next().unwrap()) {
+                    output.push(if romaji.starts_with("ch") {
+                        't'
+                    } else {
+                        romaji.chars().next().unwrap()
+                    });
+                }
+                output.push_str(romaji);
+                has_kana = true;
+            }
+            None => output.push(chr),
+        }
+        geminate = false;
+    }
+    if !has_kana {
+        return None;
+    }
+    return Some(output);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_case::test_case;
+
+    #[test_case("ゆるキャン△", Some("yurukyan△") ; "hiragana and katakana")]
+    #[test_case("ぼっち・ざ・ろっく！", Some("botchi za rokku！") ; "geminate")]
+    #[test_case("ティーカップ", Some("tiikappu") ; "small vowel and long vowel")]
+    #[test_case("ショー", Some("shoo") ; "palatal")]
+    #[test_case("無職転生", None ; "kanji")]
+    #[test_case("Yuru Camp", None ; "no kana")]
+    fn transliterate_title(title: &str, expected: Option<&str>) {
+        assert_eq!(transliterate(title).as_deref(), expected);
+    }
+}
@@ -0,0 +1,110 @@
+use std::future::Future;
+
+use rand::Rng;
+
+tokio::task_local! {
+    /// Trace of the webhook or request that is currently being processed.
+    static CURRENT: TraceContext;
+}
+
+/// W3C trace context, with anifunnel as the parent of outbound requests.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceContext {
+    pub trace_id: String,
+    /// Span ID of anifunnel, which replaces the parent ID of the incoming header.
+    pub span_id: String,
+    flags: u8,
+}
+
+impl TraceContext {
+    /// Parse a traceparent header (`00-<trace ID>-<parent ID>-<flags>`) and start a
+    /// span for anifunnel in the trace. Invalid headers are ignored like the
+    /// specification requires.
+    pub fn parse(header: &str) -> Option<Self> {
+        let mut parts = header.trim().split('-');
+        let (version, trace_id, parent_id, flags) =
+            (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        if version != "00" || parts.next().is_some() {
+            return None;
+        }
+        if !is_id(trace_id, 32) || !is_id(parent_id, 16) || flags.len() != 2 {
+            return None;
+        }
+        return Some(Self {
+            trace_id: trace_id.to_string(),
+            span_id: new_span_id(),
+            flags: u8::from_str_radix(flags, 16).ok()?,
+        });
+    }
+
+    /// traceparent header value for outbound requests.
+    pub fn header(self: &Self) -> String {
+        return format!("00-{}-{}-{:02x}", self.trace_id, self.span_id, self.flags);
+    }
+}
+
+/// Check that a trace or parent ID is lowercase hex of the given length and not all
+/// zeroes.
+fn is_id(id: &str, length: usize) -> bool {
+    return id.len() == length
+        && id.bytes().all(|x| matches!(x, b'0'..=b'9' | b'a'..=b'f'))
+        && id.bytes().any(|x| x != b'0');
+}
+
+fn new_span_id() -> String {
+    return format!("{:016x}", rand::thread_rng().gen_range(1..=u64::MAX));
+}
+
+/// Run a future with the given trace as the current one, if there is a trace.
+pub async fn scope<F: Future>(context: Option<TraceContext>, future: F) -> F::Output {
+    return match context {
+        Some(context) => CURRENT.scope(context, future).await,
+        None => future.await,
+    };
+}
+
+/// Trace of the webhook or request that is currently being processed.
+pub fn current() -> Option<TraceContext> {
+    return CURRENT.try_with(|x| x.clone()).ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_case::test_case;
+
+    #[test_case("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01", true ; "valid")]
+    #[test_case("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01", false ; "unknown version")]
+    #[test_case("00-00000000000000000000000000000000-00f067aa0ba902b7-01", false ; "zero trace id")]
+    #[test_case("00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01", false ; "zero parent id")]
+    #[test_case("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01", false ; "uppercase")]
+    #[test_case("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7", false ; "missing flags")]
+    fn trace_context_parse(header: &str, expected: bool) {
+        assert_eq!(TraceContext::parse(header).is_some(), expected);
+    }
+
+    #[test]
+    fn trace_context_header() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let context = TraceContext::parse(header).unwrap();
+        let propagated = context.header();
+        assert!(propagated.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        assert!(propagated.ends_with("-01"));
+        assert_ne!(propagated, header);
+        assert_eq!(
+            TraceContext::parse(&propagated).unwrap().trace_id,
+            context.trace_id
+        );
+    }
+
+    #[rocket::async_test]
+    async fn trace_scope() {
+        let context =
+            TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01");
+        assert_eq!(current(), None);
+        let inner = scope(context.clone(), async { current() }).await;
+        assert_eq!(inner, context);
+        assert_eq!(scope(None, async { current() }).await, None);
+    }
+}
@@ -0,0 +1,175 @@
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{plex, trace};
+
+/// Trakt API that watched episodes and movies are added to.
+const API_URL: &str = "https://api.trakt.tv/";
+
+#[derive(Debug)]
+pub enum TraktError {
+    Connection,
+    Parsing,
+    InvalidToken,
+    /// Trakt did not recognise any of the external IDs.
+    NotFound,
+}
+
+/// Trakt API application and the access token of the user.
+#[derive(Clone, Debug)]
+pub struct TraktClient {
+    pub client_id: String,
+    pub token: String,
+}
+
+/// IDs that Trakt looks up episodes and movies with.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct TraktIds {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tvdb: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tmdb: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub imdb: Option<String>,
+}
+
+#[derive(Serialize)]
+struct HistoryItem {
+    ids: TraktIds,
+}
+
+#[derive(Default, Serialize)]
+struct HistoryRequest {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    episodes: Vec<HistoryItem>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    movies: Vec<HistoryItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryCounts {
+    episodes: u32,
+    movies: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryResponse {
+    added: HistoryCounts,
+}
+
+/// Trakt IDs from the external IDs of a Plex item. Since episode external IDs
+/// identify the episode, episodes are added without looking up the show.
+pub fn external_ids(guids: &[String]) -> Option<TraktIds> {
+    let mut ids = TraktIds::default();
+    for guid in guids {
+        if let Some(id) = guid.strip_prefix("tvdb://") {
+            ids.tvdb = id.parse().ok();
+        } else if let Some(id) = guid.strip_prefix("tmdb://") {
+            ids.tmdb = id.parse().ok();
+        } else if let Some(id) = guid.strip_prefix("imdb://") {
+            ids.imdb = Some(id.to_string());
+        }
+    }
+    if ids == TraktIds::default() {
+        return None;
+    }
+    return Some(ids);
+}
+
+impl TraktClient {
+    /// Add a watched episode or movie to the Trakt history.
+    pub async fn add_to_history(self: &Self, ids: TraktIds, movie: bool) -> Result<(), TraktError> {
+        let mut body = HistoryRequest::default();
+        match movie {
+            true => body.movies.push(HistoryItem { ids }),
+            false => body.episodes.push(HistoryItem { ids }),
+        }
+        let response = reqwest::Client::new()
+            .post(format!("{}sync/history", API_URL))
+            .header("Content-Type", "application/json")
+            .header("trakt-api-version", "2")
+            .header("trakt-api-key", &self.client_id)
+            .bearer_auth(&self.token)
+            .body(serde_json::to_string(&body).map_err(|_| TraktError::Parsing)?)
+            .send()
+            .await
+            .map_err(|_| TraktError::Connection)?;
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            return Err(TraktError::InvalidToken);
+        }
+        let response: HistoryResponse = response
+            .error_for_status()
+            .map_err(|_| TraktError::Connection)?
+            .json()
+            .await
+            .map_err(|_| TraktError::Parsing)?;
+        if response.added.episodes + response.added.movies == 0 {
+            return Err(TraktError::NotFound);
+        }
+        return Ok(());
+    }
+}
+
+/// Forward a scrobble to Trakt in the background, so that Trakt being slow or down does
+/// not hold back the Anilist update.
+pub fn forward(client: &TraktClient, webhook: &plex::Webhook) {
+    let metadata = &webhook.metadata;
+    let ids = match external_ids(&metadata.external_guids) {
+        Some(ids) => ids,
+        None => {
+            debug!(
+                "Not forwarding '{}' to Trakt without external IDs",
+                metadata.title
+            );
+            return;
+        }
+    };
+    let client = client.clone();
+    let title = metadata.title.clone();
+    let movie = metadata.is_movie();
+    tokio::spawn(trace::scope(trace::current(), async move {
+        match client.add_to_history(ids, movie).await {
+            Ok(()) => info!("Added '{}' to the Trakt history", title),
+            Err(error) => warn!("Could not add '{}' to Trakt: {:?}", title, error),
+        }
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn external_ids_parse() {
+        let guids = [
+            String::from("imdb://tt15765570"),
+            String::from("tmdb://3887662"),
+            String::from("tvdb://9469174"),
+        ];
+        assert_eq!(
+            external_ids(&guids),
+            Some(TraktIds {
+                tvdb: Some(9469174),
+                tmdb: Some(3887662),
+                imdb: Some(String::from("tt15765570")),
+            })
+        );
+        assert_eq!(external_ids(&[String::from("plex://episode/1")]), None);
+        assert_eq!(external_ids(&[]), None);
+    }
+
+    #[test]
+    fn history_request_serialize() {
+        let mut body = HistoryRequest::default();
+        body.episodes.push(HistoryItem {
+            ids: TraktIds {
+                tvdb: Some(9469174),
+                ..Default::default()
+            },
+        });
+        assert_eq!(
+            serde_json::to_string(&body).unwrap(),
+            "{\"episodes\":[{\"ids\":{\"tvdb\":9469174}}]}"
+        );
+    }
+}